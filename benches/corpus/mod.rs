@@ -0,0 +1,109 @@
+//! Deterministic corpus generation for the benchmarks, and the engine they
+//! scan it with.
+//!
+//! All inputs are derived from a fixed seed with a xorshift generator, so two
+//! machines running the same benchmark scan byte-identical data. The contents
+//! are pseudo-random and never match a signature, which keeps the scans on the
+//! "clean" path that dominates real-world workloads.
+
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clamav_rs::engine::Engine;
+
+/// Database loaded by [`example_engine`]; none of its signatures match the corpus.
+pub const EXAMPLE_DATABASE_PATH: &str = "test_data/database/example.cud";
+
+/// Compiled engine with the example database loaded.
+pub fn example_engine() -> Engine {
+    clamav_rs::initialize().expect("initialize should succeed");
+    let engine = Engine::new().expect("failed to create engine");
+    engine
+        .load_databases(EXAMPLE_DATABASE_PATH)
+        .expect("failed to load db");
+    engine.compile().expect("failed to compile");
+    engine
+}
+
+/// Seed used by all benchmarks unless stated otherwise.
+pub const DEFAULT_SEED: u64 = 0x5eed_c1a3_a5b1_0001;
+
+pub const ONE_MB: usize = 1024 * 1024;
+pub const FIFTY_MB: usize = 50 * ONE_MB;
+
+/// Minimal xorshift64* generator; good enough for filler data and stable across platforms.
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // xorshift must not be seeded with zero
+        XorShift(if seed == 0 { DEFAULT_SEED } else { seed })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Generates `len` deterministic bytes for the given seed.
+pub fn generate(seed: u64, len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    XorShift::new(seed).fill(&mut data);
+    data
+}
+
+/// Writes `len` deterministic bytes to `dir/name` and returns the path.
+pub fn write_file(dir: &Path, name: &str, seed: u64, len: usize) -> io::Result<PathBuf> {
+    let path = dir.join(name);
+    let mut file = File::create(&path)?;
+    file.write_all(&generate(seed, len))?;
+    file.sync_all()?;
+    Ok(path)
+}
+
+/// Writes `count` small files of `len` bytes each, seeded by their index.
+pub fn write_small_files(dir: &Path, count: usize, len: usize) -> io::Result<Vec<PathBuf>> {
+    (0..count)
+        .map(|i| write_file(dir, &format!("small_{:05}", i), DEFAULT_SEED ^ i as u64, len))
+        .collect()
+}
+
+/// Generates `len` bytes of deterministic log-like ASCII text.
+pub fn generate_text(seed: u64, len: usize) -> Vec<u8> {
+    const WORDS: &[&str] = &[
+        "INFO", "WARN", "request", "served", "in", "ms", "user", "id", "cache", "miss", "hit", "upstream",
+    ];
+    let mut rng = XorShift::new(seed);
+    let mut text = Vec::with_capacity(len + 16);
+    while text.len() < len {
+        let word = WORDS[(rng.next_u64() % WORDS.len() as u64) as usize];
+        text.extend_from_slice(word.as_bytes());
+        text.push(if rng.next_u64() & 7 == 0 { b'\n' } else { b' ' });
+    }
+    text.truncate(len);
+    text
+}
+
+/// Writes `len` bytes of deterministic text to `dir/name` and returns the path.
+pub fn write_text_file(dir: &Path, name: &str, seed: u64, len: usize) -> io::Result<PathBuf> {
+    let path = dir.join(name);
+    let mut file = File::create(&path)?;
+    file.write_all(&generate_text(seed, len))?;
+    file.sync_all()?;
+    Ok(path)
+}
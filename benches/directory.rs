@@ -0,0 +1,60 @@
+//! Compares sequential and pipelined directory scanning.
+//!
+//! The corpus is a tree of generated files from `corpus`. The pipelined scanner
+//! is measured without prefetching (openers hand files directly to scanners)
+//! and with the default prefetch depth, to show how much IO latency it hides.
+//!
+//! ```text
+//! cargo bench --bench directory
+//! ```
+
+use std::fs;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use clamav_rs::scan::{self, DirScanOptions};
+use clamav_rs::scan_settings::ScanSettings;
+
+mod corpus;
+
+const DIRECTORIES: usize = 20;
+const FILES_PER_DIRECTORY: usize = 100;
+const FILE_SIZE: usize = 64 * 1024;
+
+fn bench_directory(c: &mut Criterion) {
+    let engine = corpus::example_engine();
+
+    let root = tempfile::tempdir().expect("failed to create temp dir");
+    for d in 0..DIRECTORIES {
+        let dir = root.path().join(format!("dir_{:02}", d));
+        fs::create_dir(&dir).expect("failed to create corpus dir");
+        corpus::write_small_files(&dir, FILES_PER_DIRECTORY, FILE_SIZE).expect("failed to write corpus");
+    }
+
+    let mut group = c.benchmark_group("directory");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements((DIRECTORIES * FILES_PER_DIRECTORY) as u64));
+
+    group.bench_function("sequential", |b| {
+        let settings = ScanSettings::default();
+        b.iter(|| scan::scan_directory(&engine, root.path(), &settings).expect("scan failed"));
+    });
+
+    for &prefetch in &[0, DirScanOptions::default().prefetch] {
+        group.bench_with_input(BenchmarkId::new("pipelined", prefetch), &prefetch, |b, &prefetch| {
+            let settings = ScanSettings::default();
+            let options = DirScanOptions::builder()
+                .prefetch(prefetch)
+                .build()
+                .expect("invalid options");
+            b.iter(|| {
+                scan::scan_directory_parallel(&engine, root.path(), &settings, &options).expect("scan failed")
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_directory);
+criterion_main!(benches);
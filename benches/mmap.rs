@@ -0,0 +1,55 @@
+//! Compares `scan_file` with the memory-mapped `scan_file_mmap` fast path.
+//!
+//! The corpus is 10k small files generated deterministically by `corpus`; each
+//! iteration scans the whole corpus once. Requires the `mmap` feature:
+//!
+//! ```text
+//! cargo bench --features mmap --bench mmap
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use clamav_rs::scan_settings::ScanSettings;
+
+mod corpus;
+
+const FILE_COUNT: usize = 10_000;
+const FILE_SIZE: usize = 4096;
+
+fn bench_small_files(c: &mut Criterion) {
+    let engine = corpus::example_engine();
+
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let paths: Vec<String> = corpus::write_small_files(dir.path(), FILE_COUNT, FILE_SIZE)
+        .expect("failed to write corpus")
+        .into_iter()
+        .map(|path| path.to_str().unwrap().to_string())
+        .collect();
+
+    let mut group = c.benchmark_group("small_files");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(FILE_COUNT as u64));
+
+    group.bench_function("scan_file", |b| {
+        let settings = ScanSettings::default();
+        b.iter(|| {
+            for path in &paths {
+                engine.scan_file(path, &settings).expect("scan failed");
+            }
+        });
+    });
+
+    group.bench_function("scan_file_mmap", |b| {
+        let settings = ScanSettings::default();
+        b.iter(|| {
+            for path in &paths {
+                engine.scan_file_mmap(path, &settings).expect("scan failed");
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_small_files);
+criterion_main!(benches);
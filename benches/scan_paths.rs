@@ -0,0 +1,138 @@
+//! Benchmarks for the different scan entry points of the engine.
+//!
+//! The suite covers:
+//!
+//! * `scan_file` vs `scan_descriptor` vs `scan_map` on the same generated 1MB and 50MB inputs,
+//! * loading and compiling the example database,
+//! * throughput of parallel scanning with 1, 4 and 8 threads sharing one engine.
+//!
+//! Inputs are produced by the deterministic generator in `corpus`, so results are
+//! comparable across machines. Run with:
+//!
+//! ```text
+//! cargo bench --bench scan_paths
+//! ```
+//!
+//! CI only builds the benchmarks (`cargo bench --no-run`).
+
+use std::fs::File;
+use std::sync::Arc;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use clamav_rs::engine::Engine;
+use clamav_rs::fmap::Fmap;
+use clamav_rs::scan_settings::ScanSettings;
+
+mod corpus;
+
+use corpus::{example_engine, EXAMPLE_DATABASE_PATH};
+
+fn bench_scan_paths(c: &mut Criterion) {
+    let engine = example_engine();
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    let mut group = c.benchmark_group("scan_paths");
+    group.sample_size(10);
+
+    for &(label, size) in &[("1MB", corpus::ONE_MB), ("50MB", corpus::FIFTY_MB)] {
+        let path = corpus::write_file(dir.path(), label, corpus::DEFAULT_SEED, size)
+            .expect("failed to write corpus file");
+        let path_str = path.to_str().unwrap();
+        let data = corpus::generate(corpus::DEFAULT_SEED, size);
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("scan_file", label), &path_str, |b, path| {
+            let settings = ScanSettings::default();
+            b.iter(|| engine.scan_file(path, &settings).expect("scan failed"));
+        });
+
+        #[cfg(unix)]
+        group.bench_with_input(BenchmarkId::new("scan_descriptor", label), &path, |b, path| {
+            use std::os::unix::io::AsRawFd;
+
+            let settings = ScanSettings::default();
+            let file = File::open(path).expect("failed to open corpus file");
+            b.iter(|| {
+                engine
+                    .scan_descriptor(file.as_raw_fd(), &settings, Some(label))
+                    .expect("scan failed")
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("scan_map", label), &data, |b, data| {
+            let settings = ScanSettings::default();
+            b.iter(|| {
+                let map = Fmap::new_from_memory(data.as_ptr(), data.len() as u64)
+                    .expect("failed to map buffer");
+                engine.scan_map(&map, Some(label), &settings).expect("scan failed")
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_load_and_compile(c: &mut Criterion) {
+    clamav_rs::initialize().expect("initialize should succeed");
+
+    let mut group = c.benchmark_group("database");
+    group.sample_size(10);
+    group.bench_function("load_and_compile_example", |b| {
+        b.iter(|| {
+            let engine = Engine::new().expect("failed to create engine");
+            engine
+                .load_databases(EXAMPLE_DATABASE_PATH)
+                .expect("failed to load db");
+            engine.compile().expect("failed to compile");
+            engine
+        });
+    });
+    group.finish();
+}
+
+fn bench_parallel_throughput(c: &mut Criterion) {
+    const FILES_PER_ITERATION: usize = 64;
+
+    let engine = Arc::new(example_engine());
+    let data = Arc::new(corpus::generate(corpus::DEFAULT_SEED, corpus::ONE_MB));
+
+    let mut group = c.benchmark_group("parallel_scan");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes((FILES_PER_ITERATION * data.len()) as u64));
+
+    for &threads in &[1usize, 4, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            b.iter(|| {
+                let handles: Vec<_> = (0..threads)
+                    .map(|t| {
+                        let engine = Arc::clone(&engine);
+                        let data = Arc::clone(&data);
+                        thread::spawn(move || {
+                            let settings = ScanSettings::default();
+                            for _ in (t..FILES_PER_ITERATION).step_by(threads) {
+                                let map = Fmap::new_from_memory(data.as_ptr(), data.len() as u64)
+                                    .expect("failed to map buffer");
+                                engine.scan_map(&map, None, &settings).expect("scan failed");
+                            }
+                        })
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.join().expect("scan thread panicked");
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_scan_paths,
+    bench_load_and_compile,
+    bench_parallel_throughput
+);
+criterion_main!(benches);
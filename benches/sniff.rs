@@ -0,0 +1,61 @@
+//! Compares `scan_file` with the type-tailored `scan_file_auto`.
+//!
+//! The corpus mixes generated text files, which `scan_file_auto` scans with
+//! narrowed parse flags, and random binary files, which it scans unchanged.
+//!
+//! ```text
+//! cargo bench --bench sniff
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use clamav_rs::scan_settings::ScanSettings;
+use clamav_rs::sniff::SniffMode;
+
+mod corpus;
+
+const FILES_PER_KIND: usize = 200;
+const FILE_SIZE: usize = 256 * 1024;
+
+fn bench_mixed_corpus(c: &mut Criterion) {
+    let engine = corpus::example_engine();
+
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let mut paths = Vec::new();
+    for i in 0..FILES_PER_KIND {
+        let seed = corpus::DEFAULT_SEED ^ i as u64;
+        paths.push(corpus::write_text_file(dir.path(), &format!("text_{:04}", i), seed, FILE_SIZE).unwrap());
+        paths.push(corpus::write_file(dir.path(), &format!("binary_{:04}", i), seed, FILE_SIZE).unwrap());
+    }
+    let paths: Vec<String> = paths
+        .into_iter()
+        .map(|path| path.to_str().unwrap().to_string())
+        .collect();
+
+    let mut group = c.benchmark_group("mixed_corpus");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(paths.len() as u64));
+
+    group.bench_function("scan_file", |b| {
+        let settings = ScanSettings::default();
+        b.iter(|| {
+            for path in &paths {
+                engine.scan_file(path, &settings).expect("scan failed");
+            }
+        });
+    });
+
+    group.bench_function("scan_file_auto", |b| {
+        let settings = ScanSettings::default();
+        b.iter(|| {
+            for path in &paths {
+                engine.scan_file_auto(path, &settings, SniffMode::Narrow).expect("scan failed");
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_mixed_corpus);
+criterion_main!(benches);
@@ -0,0 +1,193 @@
+//! A minimal clamscan built on the public API of the crate.
+//!
+//! ```text
+//! cargo run --example clamscan_rs -- -d test_data/database/ -r test_data/files
+//! ```
+//!
+//! Prints one clamscan report line per file and exits with clamscan's status:
+//! 0 if nothing was found, 1 if a file was infected and 2 on errors.
+
+use std::env;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+use clamav_rs::prelude::*;
+use clamav_rs::report::{self, EXIT_CLEAN, EXIT_ERROR, EXIT_FOUND};
+use clamav_rs::scan::{self, DirScanEntry, DirScanOptions};
+use clamav_rs::sink::{JsonlSink, ResultSink, ScanRecord};
+
+const USAGE: &str = "\
+usage: clamscan_rs [options] <path>...
+
+  -d, --database=DIR    load the databases in DIR (default: the system directory)
+  -r, --recursive       scan directories recursively
+  -i, --infected        only print infected files
+      --include=GLOB    in directories, only scan files whose name matches GLOB
+      --exclude=GLOB    in directories, skip files whose name matches GLOB
+      --jsonl=FILE      also write each result as a JSON line to FILE
+  -h, --help            print this help";
+
+#[derive(Default)]
+struct Args {
+    database: Option<String>,
+    recursive: bool,
+    infected: bool,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    jsonl: Option<String>,
+    paths: Vec<String>,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("clamscan_rs: {}\n\n{}", message, USAGE);
+    process::exit(EXIT_ERROR);
+}
+
+/// Parses `--name=value`, `--name value`, `-n value` and flags.
+fn parse_args() -> Args {
+    let mut args = Args::default();
+    let mut raw = env::args().skip(1);
+    while let Some(arg) = raw.next() {
+        let (name, inline) = match arg.find('=') {
+            Some(pos) if arg.starts_with("--") => (arg[..pos].to_string(), Some(arg[pos + 1..].to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| raw.next())
+                .unwrap_or_else(|| usage_error(&format!("{} needs a value", name)))
+        };
+        match name.as_str() {
+            "-d" | "--database" => args.database = Some(value()),
+            "--include" => args.include.push(value()),
+            "--exclude" => args.exclude.push(value()),
+            "--jsonl" => args.jsonl = Some(value()),
+            "-r" | "--recursive" => args.recursive = true,
+            "-i" | "--infected" => args.infected = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(EXIT_CLEAN);
+            }
+            _ if name.starts_with('-') && name.len() > 1 => usage_error(&format!("unknown option {}", name)),
+            _ => args.paths.push(arg),
+        }
+    }
+    if args.paths.is_empty() {
+        usage_error("no paths to scan");
+    }
+    args
+}
+
+fn load_engine(args: &Args) -> Result<Engine, ClamError> {
+    let engine = Engine::new()?;
+    match &args.database {
+        Some(dir) => engine.load_databases(dir)?,
+        None => engine.load_databases(&clamav_rs::db::default_directory())?,
+    };
+    engine.compile()?;
+    Ok(engine)
+}
+
+/// Combines exit codes; detections take priority over errors, as in clamscan.
+fn combine(code: i32, other: i32) -> i32 {
+    if code == EXIT_FOUND || other == EXIT_FOUND {
+        EXIT_FOUND
+    } else {
+        code.max(other)
+    }
+}
+
+fn print_entry(args: &Args, entry: &DirScanEntry) {
+    let found = entry.result.as_ref().map(ScanResult::found).unwrap_or(false);
+    if found || !args.infected {
+        println!("{}", entry.format_line());
+    }
+}
+
+fn scan_file(args: &Args, engine: &Engine, path: &str, sink: Option<&Arc<JsonlSink>>) -> i32 {
+    let outcome = engine.scan_file_outcome(path, &ScanSettings::default());
+    let record = ScanRecord::new(Some(path.into()), outcome);
+    if let Some(sink) = sink {
+        sink.emit(&record);
+        sink.flush();
+    }
+    match record.outcome {
+        Ok(outcome) => {
+            let found = outcome.result.found();
+            if found || !args.infected {
+                println!("{}", outcome.format_line(Path::new(path)));
+            }
+            if found {
+                EXIT_FOUND
+            } else if let ScanResult::NotScanned(_) = outcome.result {
+                EXIT_ERROR
+            } else {
+                EXIT_CLEAN
+            }
+        }
+        Err(err) => {
+            println!("{}: {} ERROR", path, err.string_error());
+            EXIT_ERROR
+        }
+    }
+}
+
+fn scan_directory(args: &Args, engine: &Engine, path: &str, sink: Option<&Arc<JsonlSink>>) -> i32 {
+    let mut builder = DirScanOptions::builder();
+    builder.file_types(false);
+    for glob in &args.include {
+        builder.include(glob);
+    }
+    for glob in &args.exclude {
+        builder.exclude(glob);
+    }
+    if let Some(sink) = sink {
+        builder.sink(Arc::clone(sink) as Arc<dyn ResultSink>);
+    }
+    let options = builder.build().unwrap_or_else(|err| usage_error(&err.to_string()));
+    match scan::scan_directory_parallel(engine, Path::new(path), &ScanSettings::default(), &options) {
+        Ok(report) => {
+            for entry in &report.entries {
+                print_entry(args, entry);
+            }
+            report::exit_code(&report)
+        }
+        Err(err) => {
+            println!("{}: {} ERROR", path, err);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn main() {
+    let args = parse_args();
+    if let Err(err) = clamav_rs::initialize() {
+        eprintln!("clamscan_rs: failed to initialize libclamav: {}", err);
+        process::exit(EXIT_ERROR);
+    }
+    let engine = load_engine(&args).unwrap_or_else(|err| {
+        eprintln!("clamscan_rs: failed to load databases: {}", err);
+        process::exit(EXIT_ERROR);
+    });
+    let sink = args.jsonl.as_ref().map(|file| {
+        let sink = JsonlSink::create(file).unwrap_or_else(|err| usage_error(&format!("{}: {}", file, err)));
+        // walk order, so repeated scans of a tree give the same file
+        Arc::new(sink.ordered(1024))
+    });
+
+    let mut code = EXIT_CLEAN;
+    for path in &args.paths {
+        let result = if !Path::new(path).is_dir() {
+            scan_file(&args, &engine, path, sink.as_ref())
+        } else if args.recursive {
+            scan_directory(&args, &engine, path, sink.as_ref())
+        } else {
+            eprintln!("{}: Directory skipped, use -r to scan it", path);
+            EXIT_CLEAN
+        };
+        code = combine(code, result);
+    }
+    process::exit(code);
+}
@@ -0,0 +1,341 @@
+//! Limits on the memory buffered by streaming scans.
+//!
+//! `Scanner::scan_reader` and `ScannerPool::scan_reader` read their input
+//! into memory before handing it to libclamav. Without limits, a few large
+//! uploads can exhaust the memory of the host. [`BufferLimits`] caps both the
+//! bytes buffered by one scan and the bytes buffered by all scans sharing a
+//! scanner or pool at the same time. A stream which would exceed either limit
+//! fails with `ScanError::BufferLimitExceeded` while it is being read, before
+//! the excess is buffered.
+//!
+//! Buffered bytes are released as soon as the scan finishes, whether it
+//! succeeds or fails.
+//!
+//! [`BufferLimits`]: struct.BufferLimits.html
+
+use std::error;
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use clamav_sys::cl_error_t;
+
+use crate::error::ClamError;
+use crate::request::ScanError;
+
+/// Size of the chunks streams are read in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Maximum number of bytes buffered by streaming scans
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferLimits {
+    /// Bytes one scan may buffer. Defaults to no limit.
+    pub per_scan: u64,
+    /// Bytes all scans of a scanner or pool, including its clones, may
+    /// buffer at the same time. Defaults to no limit.
+    pub global: u64,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        BufferLimits {
+            per_scan: u64::MAX,
+            global: u64::MAX,
+        }
+    }
+}
+
+/// Which of the [`BufferLimits`] was exceeded
+///
+/// [`BufferLimits`]: struct.BufferLimits.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BufferScope {
+    /// The stream alone is larger than `BufferLimits::per_scan`
+    PerScan,
+    /// The stream and the other streams together are larger than
+    /// `BufferLimits::global`
+    Global,
+}
+
+/// A stream was refused because it would exceed its [`BufferLimits`]
+///
+/// [`BufferLimits`]: struct.BufferLimits.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferLimitError {
+    /// The exceeded limit
+    pub scope: BufferScope,
+    /// Bytes the stream needed buffered when it was refused
+    pub requested: u64,
+    /// Bytes buffered by the other streams at the time, or 0 for
+    /// `BufferScope::PerScan`
+    pub buffered: u64,
+    /// Value of the exceeded limit
+    pub limit: u64,
+}
+
+impl fmt::Display for BufferLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.scope {
+            BufferScope::PerScan => write!(
+                f,
+                "stream of at least {} bytes exceeds the per-scan buffer limit of {} bytes",
+                self.requested, self.limit
+            ),
+            BufferScope::Global => write!(
+                f,
+                "buffering {} bytes exceeds the global buffer limit of {} bytes, {} bytes in use",
+                self.requested, self.limit, self.buffered
+            ),
+        }
+    }
+}
+
+impl error::Error for BufferLimitError {}
+
+/// Budget shared by the clones of a scanner or pool
+#[derive(Debug, Clone)]
+pub(crate) struct BufferBudget {
+    limits: BufferLimits,
+    buffered: Arc<AtomicU64>,
+}
+
+impl BufferBudget {
+    pub(crate) fn new(limits: BufferLimits) -> Self {
+        BufferBudget {
+            limits,
+            buffered: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the bytes currently buffered by all streams.
+    pub(crate) fn buffered(&self) -> u64 {
+        self.buffered.load(Ordering::SeqCst)
+    }
+
+    /// Takes `bytes` more out of the global budget for `reservation`.
+    fn grow(&self, reservation: &mut Reservation, bytes: u64) -> Result<(), BufferLimitError> {
+        let limit = self.limits.global;
+        self.buffered
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |buffered| {
+                match buffered.checked_add(bytes) {
+                    Some(total) if total <= limit => Some(total),
+                    _ => None,
+                }
+            })
+            .map_err(|buffered| BufferLimitError {
+                scope: BufferScope::Global,
+                requested: reservation.bytes + bytes,
+                buffered: buffered - reservation.bytes,
+                limit,
+            })?;
+        reservation.bytes += bytes;
+        Ok(())
+    }
+
+    /// Reads `reader` to the end, holding the bytes read against the budget
+    /// until the returned buffer is dropped.
+    ///
+    /// Memory is reserved before it is allocated, so the buffers of all
+    /// streams never exceed the limits. Capacity is grown geometrically but
+    /// falls back to the exact size needed when the global budget is short.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ScanError::BufferLimitExceeded` if the stream exceeds a
+    /// limit, or `CL_EREAD` if reading fails.
+    pub(crate) fn read<R: Read>(&self, mut reader: R) -> Result<Buffered, ScanError> {
+        let mut buffered = Buffered {
+            data: Vec::new(),
+            reservation: Reservation {
+                buffered: self.buffered.clone(),
+                bytes: 0,
+            },
+        };
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => return Ok(buffered),
+                Ok(read) => read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return Err(ClamError::new(cl_error_t::CL_EREAD).into()),
+            };
+            let needed = buffered.data.len() as u64 + read as u64;
+            if needed > self.limits.per_scan {
+                return Err(ScanError::BufferLimitExceeded(BufferLimitError {
+                    scope: BufferScope::PerScan,
+                    requested: needed,
+                    buffered: 0,
+                    limit: self.limits.per_scan,
+                }));
+            }
+            let reserved = buffered.reservation.bytes;
+            if needed > reserved {
+                let wanted = reserved.saturating_mul(2).max(needed).min(self.limits.per_scan);
+                let reservation = &mut buffered.reservation;
+                self.grow(reservation, wanted - reserved)
+                    .or_else(|_| self.grow(reservation, needed - reserved))
+                    .map_err(ScanError::BufferLimitExceeded)?;
+                let capacity = (buffered.reservation.bytes - buffered.data.len() as u64) as usize;
+                buffered.data.reserve_exact(capacity);
+            }
+            buffered.data.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+/// Bytes of the global budget held by one stream
+struct Reservation {
+    buffered: Arc<AtomicU64>,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.buffered.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+/// Contents of a stream, released from the budget when dropped
+pub(crate) struct Buffered {
+    data: Vec<u8>,
+    reservation: Reservation,
+}
+
+impl Buffered {
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+    use std::sync::mpsc;
+    use std::sync::{Condvar, Mutex};
+    use std::thread;
+
+    /// Reader returning `data`, then blocking until the gate opens before
+    /// returning end of file
+    struct GatedReader {
+        data: Cursor<Vec<u8>>,
+        gate: Arc<(Mutex<bool>, Condvar)>,
+    }
+
+    impl Read for GatedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.data.read(buf)?;
+            if read == 0 {
+                let (open, opened) = &*self.gate;
+                let mut open = open.lock().unwrap();
+                while !*open {
+                    open = opened.wait(open).unwrap();
+                }
+            }
+            Ok(read)
+        }
+    }
+
+    /// Reader returning `data`, then failing
+    struct FailingReader(Cursor<Vec<u8>>);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::from(ErrorKind::ConnectionReset)),
+                read => Ok(read),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_streams_within_the_limits() {
+        let budget = BufferBudget::new(BufferLimits {
+            per_scan: 200_000,
+            global: 200_000,
+        });
+        let data: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        let buffered = budget.read(Cursor::new(data.clone())).unwrap();
+        assert_eq!(buffered.data(), &data[..]);
+        assert!(budget.buffered() >= 150_000);
+        assert!(buffered.data.capacity() as u64 <= budget.buffered());
+        drop(buffered);
+        assert_eq!(budget.buffered(), 0);
+    }
+
+    #[test]
+    fn stream_larger_than_per_scan_limit_fails() {
+        let budget = BufferBudget::new(BufferLimits {
+            per_scan: 100,
+            global: u64::MAX,
+        });
+        let err = budget.read(Cursor::new(vec![0; 101])).err().unwrap();
+        assert_eq!(
+            err,
+            ScanError::BufferLimitExceeded(BufferLimitError {
+                scope: BufferScope::PerScan,
+                requested: 101,
+                buffered: 0,
+                limit: 100
+            })
+        );
+        assert_eq!(budget.buffered(), 0);
+        assert!(budget.read(Cursor::new(vec![0; 100])).is_ok());
+    }
+
+    #[test]
+    fn failed_reads_release_the_budget() {
+        let budget = BufferBudget::new(Default::default());
+        let err = budget.read(FailingReader(Cursor::new(vec![0; 1000]))).err().unwrap();
+        assert_eq!(err, ScanError::Clam(ClamError::new(cl_error_t::CL_EREAD)));
+        assert_eq!(budget.buffered(), 0);
+    }
+
+    #[test]
+    fn only_streams_exceeding_the_global_limit_fail() {
+        let budget = BufferBudget::new(BufferLimits {
+            per_scan: 400,
+            global: 1000,
+        });
+        let gate = Arc::new((Mutex::new(false), Condvar::new()));
+        let (results, received) = mpsc::channel();
+        let streams: Vec<_> = (0..4)
+            .map(|_| {
+                let (budget, gate, results) = (budget.clone(), gate.clone(), results.clone());
+                thread::spawn(move || {
+                    let reader = GatedReader {
+                        data: Cursor::new(vec![0; 300]),
+                        gate,
+                    };
+                    let buffered = budget.read(reader).map(|buffered| buffered.data().len());
+                    results.send(buffered).unwrap();
+                })
+            })
+            .collect();
+
+        // Three streams fit and wait at the gate holding their buffers, so
+        // the first result is the stream which did not fit.
+        let err = received.recv().unwrap().err().unwrap();
+        assert_eq!(
+            err,
+            ScanError::BufferLimitExceeded(BufferLimitError {
+                scope: BufferScope::Global,
+                requested: 300,
+                buffered: 900,
+                limit: 1000
+            })
+        );
+        assert_eq!(budget.buffered(), 900);
+
+        *gate.0.lock().unwrap() = true;
+        gate.1.notify_all();
+        for stream in streams {
+            stream.join().unwrap();
+        }
+        let sizes: Vec<_> = received.try_iter().collect();
+        assert_eq!(sizes, vec![Ok(300), Ok(300), Ok(300)]);
+        assert_eq!(budget.buffered(), 0);
+    }
+}
@@ -0,0 +1,359 @@
+//! Command channel for operating long-running scanners.
+//!
+//! Services embedding a [`Scanner`] or [`ScannerPool`] need to reload
+//! definitions, change scan options or report their state, e.g. from an admin
+//! endpoint or a signal handler, without handing the scanner itself to that
+//! code. A [`ScannerControl`] is a cheap handle sending such commands to a
+//! control thread, which runs them one at a time in the order they arrived:
+//! a status request sent while a reload is in progress waits for the reload
+//! to finish and then reports its effect.
+//!
+//! Every command returns a [`Pending`] reply right away; [`Pending::wait`]
+//! blocks until the command completed. A command which panics fails with
+//! `CL_EARG` and the thread goes on with the next one. The control thread
+//! exits once all handles are dropped.
+//!
+//! [`Scanner`]: ../scanner/struct.Scanner.html
+//! [`ScannerPool`]: ../pool/struct.ScannerPool.html
+//! [`ScannerControl`]: struct.ScannerControl.html
+//! [`Pending`]: struct.Pending.html
+//! [`Pending::wait`]: struct.Pending.html#method.wait
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use clamav_sys::cl_error_t;
+
+use crate::error::ClamError;
+use crate::scan_settings::ScanSettings;
+
+/// Scanner operated by a control thread
+pub(crate) trait Controlled: Send + 'static {
+    /// Loads and compiles the databases again and publishes the new engine.
+    fn reload(&mut self) -> Result<(), ClamError>;
+    /// Drops the verdicts cached by the engine.
+    fn purge_cache(&mut self) -> Result<(), ClamError>;
+    /// Returns whether scans are answered without databases.
+    fn is_degraded(&self) -> bool;
+    /// Returns the scan options used for new scans.
+    fn settings(&self) -> ScanSettings;
+    /// Replaces the scan options used for new scans.
+    fn set_settings(&mut self, settings: ScanSettings);
+}
+
+/// State of a controlled scanner, as returned by [`ScannerControl::status`]
+///
+/// The counters cover the commands of all handles of one control thread.
+///
+/// [`ScannerControl::status`]: struct.ScannerControl.html#method.status
+#[derive(Clone, Debug)]
+pub struct ScannerStatus {
+    /// Reloads which published a new engine
+    pub reloads: u64,
+    /// Reloads which failed and left the previous engine in use
+    pub failed_reloads: u64,
+    /// Commands completed before this status request
+    pub commands: u64,
+    /// Whether the scanner has no databases, see
+    /// `ScannerBuilder::allow_missing_database`
+    pub degraded: bool,
+    /// Scan options used for new scans
+    pub settings: ScanSettings,
+}
+
+/// Reply to a command sent through a [`ScannerControl`]
+///
+/// [`ScannerControl`]: struct.ScannerControl.html
+#[must_use = "the command runs regardless, but its result is lost unless waited for"]
+pub struct Pending<T> {
+    reply: Receiver<Result<T, ClamError>>,
+}
+
+impl<T> Pending<T> {
+    /// Blocks until the command completed and returns its result.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the command, or `CL_EARG` if the command panicked.
+    pub fn wait(self) -> Result<T, ClamError> {
+        self.reply
+            .recv()
+            .unwrap_or_else(|_| Err(ClamError::new(cl_error_t::CL_EARG)))
+    }
+}
+
+/// State owned by the control thread
+struct Task {
+    target: Box<dyn Controlled>,
+    reloads: u64,
+    failed_reloads: u64,
+    commands: u64,
+}
+
+impl Task {
+    fn status(&self) -> ScannerStatus {
+        ScannerStatus {
+            reloads: self.reloads,
+            failed_reloads: self.failed_reloads,
+            commands: self.commands,
+            degraded: self.target.is_degraded(),
+            settings: self.target.settings(),
+        }
+    }
+
+    fn count_reload(&mut self, result: &Result<(), ClamError>) {
+        match result {
+            Ok(()) => self.reloads += 1,
+            Err(_) => self.failed_reloads += 1,
+        }
+    }
+}
+
+type Command = Box<dyn FnOnce(&mut Task) + Send>;
+
+/// Handle sending commands to the control thread of a scanner
+///
+/// Create it with `Scanner::control` or `ScannerPool::control`. Clones send
+/// to the same thread, so commands from all clones are serialized.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::prelude::*;
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let scanner = Scanner::builder()
+///     .database_dir("test_data/database/")
+///     .build()
+///     .expect("failed to build scanner");
+/// let control = scanner.control();
+///
+/// // e.g. from an admin endpoint
+/// control.reload().wait().expect("reload failed");
+/// let status = control.status().wait().expect("status failed");
+/// assert_eq!(status.reloads, 1);
+/// ```
+#[derive(Clone)]
+pub struct ScannerControl {
+    commands: Sender<Command>,
+}
+
+impl ScannerControl {
+    /// Starts the control thread operating `target`.
+    pub(crate) fn spawn<C: Controlled>(target: C) -> Self {
+        let (commands, queue) = mpsc::channel::<Command>();
+        let mut task = Task {
+            target: Box::new(target),
+            reloads: 0,
+            failed_reloads: 0,
+            commands: 0,
+        };
+        thread::spawn(move || {
+            for command in queue {
+                // the reply sender is dropped while unwinding, failing `wait`
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| command(&mut task)));
+                task.commands += 1;
+            }
+        });
+        ScannerControl { commands }
+    }
+
+    /// Queues `command` behind all commands sent before.
+    fn send<T, F>(&self, command: F) -> Pending<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Task) -> Result<T, ClamError> + Send + 'static,
+    {
+        let (reply, pending) = mpsc::channel();
+        // if the thread is gone, the reply sender is dropped and `wait` fails
+        let _ = self.commands.send(Box::new(move |task: &mut Task| {
+            let _ = reply.send(command(task));
+        }));
+        Pending { reply: pending }
+    }
+
+    /// Loads and compiles the databases again and publishes the new engine.
+    ///
+    /// Scans keep running on the previous engine meanwhile, and it stays in
+    /// use if the reload fails.
+    pub fn reload(&self) -> Pending<()> {
+        self.send(|task| {
+            let result = task.target.reload();
+            task.count_reload(&result);
+            result
+        })
+    }
+
+    /// Drops the verdicts the engine cached for clean files.
+    ///
+    /// libclamav cannot clear the cache of a compiled engine, so this builds
+    /// and publishes a new engine like [`reload`], and counts as a reload.
+    ///
+    /// [`reload`]: #method.reload
+    pub fn purge_cache(&self) -> Pending<()> {
+        self.send(|task| {
+            let result = task.target.purge_cache();
+            task.count_reload(&result);
+            result
+        })
+    }
+
+    /// Returns the state of the scanner once all earlier commands completed.
+    pub fn status(&self) -> Pending<ScannerStatus> {
+        self.send(|task| Ok(task.status()))
+    }
+
+    /// Replaces the scan options of the scanner for all scans started after
+    /// the command completed.
+    pub fn set_settings(&self, settings: ScanSettings) -> Pending<()> {
+        self.send(move |task| {
+            task.target.set_settings(settings);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_settings::ScanSettingsBuilder;
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::time::Duration;
+
+    /// Records the order of the commands it runs
+    struct Recorder {
+        log: Arc<Mutex<Vec<&'static str>>>,
+        /// Reloads wait at this barrier twice, once when started and once
+        /// before finishing
+        gate: Option<Arc<Barrier>>,
+        fail_reload: bool,
+        panic_on_purge: bool,
+        settings: ScanSettings,
+    }
+
+    impl Controlled for Recorder {
+        fn reload(&mut self) -> Result<(), ClamError> {
+            self.log.lock().unwrap().push("reload started");
+            if let Some(gate) = &self.gate {
+                gate.wait();
+                gate.wait();
+            }
+            self.log.lock().unwrap().push("reload finished");
+            if self.fail_reload {
+                Err(ClamError::new(cl_error_t::CL_EOPEN))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn purge_cache(&mut self) -> Result<(), ClamError> {
+            self.log.lock().unwrap().push("purge");
+            if self.panic_on_purge {
+                panic!("purge failed");
+            }
+            Ok(())
+        }
+
+        fn is_degraded(&self) -> bool {
+            false
+        }
+
+        fn settings(&self) -> ScanSettings {
+            self.log.lock().unwrap().push("status");
+            self.settings.clone()
+        }
+
+        fn set_settings(&mut self, settings: ScanSettings) {
+            self.log.lock().unwrap().push("set settings");
+            self.settings = settings;
+        }
+    }
+
+    fn recorder(gate: Option<Arc<Barrier>>) -> (ScannerControl, Arc<Mutex<Vec<&'static str>>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let control = ScannerControl::spawn(Recorder {
+            log: Arc::clone(&log),
+            gate,
+            fail_reload: false,
+            panic_on_purge: false,
+            settings: ScanSettings::default(),
+        });
+        (control, log)
+    }
+
+    #[test]
+    fn commands_during_reload_queue_behind_it() {
+        let gate = Arc::new(Barrier::new(2));
+        let (control, log) = recorder(Some(Arc::clone(&gate)));
+
+        let reload = control.reload();
+        gate.wait();
+        // the reload is in progress; these must wait for it
+        let other = control.clone();
+        let status = thread::spawn(move || other.status().wait());
+        let purge = control.purge_cache();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(*log.lock().unwrap(), vec!["reload started"]);
+
+        gate.wait();
+        reload.wait().expect("reload failed");
+        purge.wait().expect("purge failed");
+        let status = status.join().unwrap().expect("status failed");
+        assert!(status.commands >= 1);
+        assert_eq!(status.reloads, status.commands);
+
+        let log = log.lock().unwrap();
+        assert_eq!(log[..2], ["reload started", "reload finished"]);
+        assert_eq!(log.len(), 4);
+    }
+
+    #[test]
+    fn status_reflects_earlier_commands() {
+        let (control, _log) = recorder(None);
+        let settings = ScanSettingsBuilder::new().enable_pdf().build();
+
+        let reload = control.reload();
+        let set = control.set_settings(settings.clone());
+        let status = control.status();
+        reload.wait().expect("reload failed");
+        set.wait().expect("set_settings failed");
+        let status = status.wait().expect("status failed");
+        assert_eq!(status.reloads, 1);
+        assert_eq!(status.failed_reloads, 0);
+        assert_eq!(status.commands, 2);
+        assert_eq!(status.settings.to_string(), settings.to_string());
+    }
+
+    #[test]
+    fn failed_reload_is_reported_and_counted() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let control = ScannerControl::spawn(Recorder {
+            log,
+            gate: None,
+            fail_reload: true,
+            panic_on_purge: false,
+            settings: ScanSettings::default(),
+        });
+        let err = control.reload().wait().expect_err("reload should fail");
+        assert_eq!(err.code(), cl_error_t::CL_EOPEN as i32);
+        let status = control.status().wait().expect("status failed");
+        assert_eq!((status.reloads, status.failed_reloads), (0, 1));
+    }
+
+    #[test]
+    fn panicking_command_fails_without_stopping_the_thread() {
+        let control = ScannerControl::spawn(Recorder {
+            log: Arc::new(Mutex::new(Vec::new())),
+            gate: None,
+            fail_reload: false,
+            panic_on_purge: true,
+            settings: ScanSettings::default(),
+        });
+        let err = control.purge_cache().wait().expect_err("purge should fail");
+        assert_eq!(err.code(), cl_error_t::CL_EARG as i32);
+        control.reload().wait().expect("reload failed");
+        let status = control.status().wait().expect("status failed");
+        assert_eq!((status.reloads, status.commands), (1, 2));
+    }
+}
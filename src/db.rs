@@ -1,7 +1,146 @@
-use std::ffi::CStr;
+use std::collections::BTreeSet;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
 use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use clamav_sys::cl_retdbdir;
+use bitflags::bitflags;
+use clamav_sys::{
+    cl_countsigs,
+    cl_cvd,
+    cl_cvdfree,
+    cl_cvdhead,
+    cl_error_t,
+    cl_retdbdir,
+    CL_DB_PHISHING,
+    CL_DB_PHISHING_URLS,
+    CL_DB_PUA,
+    CL_DB_CVDNOTMP,
+    CL_DB_OFFICIAL,
+    CL_DB_PUA_MODE,
+    CL_DB_PUA_INCLUDE,
+    CL_DB_PUA_EXCLUDE,
+    CL_DB_COMPILED,
+    CL_DB_DIRECTORY,
+    CL_DB_OFFICIAL_ONLY,
+    CL_DB_BYTECODE,
+    CL_DB_SIGNED,
+    CL_DB_BYTECODE_UNSIGNED,
+    CL_DB_UNSIGNED,
+    CL_DB_BYTECODE_STATS,
+    CL_DB_ENHANCED,
+    CL_DB_PCRE_STATS,
+    CL_DB_YARA_EXCLUDE,
+    CL_DB_YARA_ONLY,
+    CL_DB_STDOPT,
+    CL_COUNTSIGS_OFFICIAL,
+    CL_COUNTSIGS_UNOFFICIAL,
+    CL_COUNTSIGS_ALL,
+};
+
+use crate::engine::Engine;
+use crate::error::ClamError;
+use crate::policy;
+
+bitflags! {
+    /// Options for loading databases
+    #[derive(Default)]
+    #[repr(C)]
+    pub struct DbFlags : u32 {
+        /// load phishing signatures
+        const CL_DB_PHISHING          = CL_DB_PHISHING;
+        /// load phishing URL signatures
+        const CL_DB_PHISHING_URLS     = CL_DB_PHISHING_URLS;
+        /// load "potentially unwanted application" signatures
+        const CL_DB_PUA               = CL_DB_PUA;
+        /// do not unpack cvd files to a temporary directory
+        const CL_DB_CVDNOTMP          = CL_DB_CVDNOTMP;
+        /// internal
+        const CL_DB_OFFICIAL          = CL_DB_OFFICIAL;
+        /// only load the PUA categories listed with the engine's pua_cats
+        const CL_DB_PUA_MODE          = CL_DB_PUA_MODE;
+        /// include the PUA categories in pua_cats
+        const CL_DB_PUA_INCLUDE       = CL_DB_PUA_INCLUDE;
+        /// exclude the PUA categories in pua_cats
+        const CL_DB_PUA_EXCLUDE       = CL_DB_PUA_EXCLUDE;
+        /// internal
+        const CL_DB_COMPILED          = CL_DB_COMPILED;
+        /// internal
+        const CL_DB_DIRECTORY         = CL_DB_DIRECTORY;
+        /// only load official signatures
+        const CL_DB_OFFICIAL_ONLY     = CL_DB_OFFICIAL_ONLY;
+        /// load bytecode signatures
+        const CL_DB_BYTECODE          = CL_DB_BYTECODE;
+        /// internal
+        const CL_DB_SIGNED            = CL_DB_SIGNED;
+        /// load unsigned bytecode
+        const CL_DB_BYTECODE_UNSIGNED = CL_DB_BYTECODE_UNSIGNED;
+        /// internal
+        const CL_DB_UNSIGNED          = CL_DB_UNSIGNED;
+        /// collect bytecode performance statistics
+        const CL_DB_BYTECODE_STATS    = CL_DB_BYTECODE_STATS;
+        /// internal
+        const CL_DB_ENHANCED          = CL_DB_ENHANCED;
+        /// collect PCRE performance statistics
+        const CL_DB_PCRE_STATS        = CL_DB_PCRE_STATS;
+        /// do not load yara rules
+        const CL_DB_YARA_EXCLUDE      = CL_DB_YARA_EXCLUDE;
+        /// only load yara rules
+        const CL_DB_YARA_ONLY         = CL_DB_YARA_ONLY;
+        /// recommended default options
+        const CL_DB_STDOPT            = CL_DB_STDOPT;
+    }
+}
+
+bitflags! {
+    /// Signatures counted by [`count_signatures`]
+    ///
+    /// [`count_signatures`]: fn.count_signatures.html
+    #[derive(Default)]
+    #[repr(C)]
+    pub struct CountFlags : u32 {
+        /// count the signatures of official `.cvd`, `.cld` and `.cud` databases
+        const CL_COUNTSIGS_OFFICIAL   = CL_COUNTSIGS_OFFICIAL;
+        /// count the signatures of other databases, e.g. `.ndb` files
+        const CL_COUNTSIGS_UNOFFICIAL = CL_COUNTSIGS_UNOFFICIAL;
+        /// count all signatures
+        const CL_COUNTSIGS_ALL        = CL_COUNTSIGS_ALL;
+    }
+}
+
+/// Counts the signatures in the database file or directory at `path`
+/// without loading them into an engine.
+///
+/// `.cvd`, `.cld` and `.cud` databases are counted from their header, other
+/// databases by their entries, so this is much cheaper than loading, but
+/// includes signatures an engine would skip, e.g. PUA signatures.
+///
+/// # Errors
+///
+/// Returns `CL_EARG` if `path` contains a NUL byte, `CL_EOPEN` if it cannot
+/// be read, or the error libclamav reports for a malformed database.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::db::{self, CountFlags};
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let count = db::count_signatures("test_data/database/", CountFlags::CL_COUNTSIGS_ALL).expect("failed to count");
+/// println!("{} signatures", count);
+/// ```
+pub fn count_signatures(path: &str, options: CountFlags) -> Result<u32, ClamError> {
+    let raw_path = CString::new(path).map_err(|_| ClamError::new(cl_error_t::CL_EARG))?;
+    let mut signatures = 0;
+    let result = unsafe { cl_countsigs(raw_path.as_ptr(), options.bits(), &mut signatures) };
+    match result {
+        cl_error_t::CL_SUCCESS => Ok(signatures),
+        _ => Err(ClamError::new(result)),
+    }
+}
 
 /// Gets the default database directory for clamav
 pub fn default_directory() -> String {
@@ -15,9 +154,340 @@ pub fn default_directory() -> String {
     }
 }
 
+/// Length of the header at the start of `.cvd`, `.cld` and `.cud` files
+const CVD_HEADER_LEN: u64 = 512;
+
+/// Header of a `.cvd`, `.cld` or `.cud` database, see [`read_cvd_header`]
+///
+/// [`read_cvd_header`]: fn.read_cvd_header.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CvdInfo {
+    /// Version of the database, e.g. the daily.cvd version
+    pub version: u32,
+    /// When the database was built
+    pub build_time: SystemTime,
+    /// Number of signatures in the database
+    pub signatures: u32,
+    /// Minimum functionality level of libclamav needed to load the database
+    pub functionality_level: u32,
+    /// MD5 of the compressed contents, as hex digits
+    pub md5: String,
+    /// Name of whoever built the database
+    pub builder: String,
+}
+
+impl CvdInfo {
+    /// Copies the fields of a header parsed by libclamav and frees it.
+    ///
+    /// # Safety
+    ///
+    /// `cvd` must be null or returned by `cl_cvdhead` or `cl_cvdparse`, and
+    /// must not be used afterwards.
+    unsafe fn from_raw(cvd: *mut cl_cvd) -> Result<CvdInfo, ClamError> {
+        let header = match cvd.as_ref() {
+            Some(header) => header,
+            None => return Err(ClamError::new(cl_error_t::CL_ECVD)),
+        };
+        let string = |raw: *mut c_char| {
+            if raw.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(raw).to_string_lossy().into_owned()
+            }
+        };
+        let info = CvdInfo {
+            version: header.version,
+            build_time: UNIX_EPOCH + Duration::from_secs(u64::from(header.stime)),
+            signatures: header.sigs,
+            functionality_level: header.fl,
+            md5: string(header.md5),
+            builder: string(header.builder),
+        };
+        cl_cvdfree(cvd);
+        Ok(info)
+    }
+}
+
+/// Reads the header of the `.cvd`, `.cld` or `.cud` database at `path`
+/// without loading the database.
+///
+/// Only the header is checked; the signature and the contents are not.
+///
+/// # Errors
+///
+/// Returns `CL_EOPEN` if the file cannot be read, `CL_EARG` if `path`
+/// contains a NUL byte, and `CL_ECVD` if the file is shorter than a header,
+/// e.g. from an interrupted download, or the header is malformed.
+///
+/// # Examples
+///
+/// ```
+/// let info = clamav_rs::db::read_cvd_header("test_data/database/example.cud").expect("bad header");
+/// println!("version {} with {} signatures by {}", info.version, info.signatures, info.builder);
+/// ```
+pub fn read_cvd_header(path: &str) -> Result<CvdInfo, ClamError> {
+    // libclamav reports a missing file and a truncated one alike
+    let metadata = fs::metadata(path).map_err(|_| ClamError::new(cl_error_t::CL_EOPEN))?;
+    if !metadata.is_file() {
+        return Err(ClamError::new(cl_error_t::CL_EOPEN));
+    }
+    if metadata.len() < CVD_HEADER_LEN {
+        return Err(ClamError::new(cl_error_t::CL_ECVD));
+    }
+    let raw_path = CString::new(path).map_err(|_| ClamError::new(cl_error_t::CL_EARG))?;
+    unsafe { CvdInfo::from_raw(cl_cvdhead(raw_path.as_ptr())) }
+}
+
+/// File extensions of the databases libclamav loads from a directory
+pub const DATABASE_EXTENSIONS: &[&str] = &[
+    "cvd", "cld", "cud", "hdb", "hsb", "hdu", "hsu", "mdb", "msb", "mdu", "msu", "ndb", "ndu",
+    "ldb", "ldu", "sdb", "zmd", "rmd", "idb", "fp", "sfp", "gdb", "pdb", "wdb", "cbc", "ftm",
+    "cfg", "cdb", "cat", "crb", "imp", "yar", "yara", "pwdb", "ign", "ign2", "info",
+];
+
+/// Returns whether `path` is named like a database libclamav loads.
+pub fn is_database_file(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => DATABASE_EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)),
+        None => false,
+    }
+}
+
+/// Returns whether `dir` contains at least one non-empty database.
+///
+/// Both the `.cvd` layout of fresh downloads and the `.cld` layout of
+/// incrementally updated databases count, as do plain signature files. A
+/// missing directory or one with only freshclam state files or empty files,
+/// e.g. left by an interrupted download, contains no databases.
+///
+/// # Errors
+///
+/// Returns the error of reading the directory, except for `NotFound`.
+pub fn contains_databases(dir: &Path) -> io::Result<bool> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if !is_database_file(&path) {
+            continue;
+        }
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() > 0 => return Ok(true),
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+/// Name of the file false positive hashes are written to by
+/// [`FalsePositiveDb::write_to`]
+///
+/// [`FalsePositiveDb::write_to`]: struct.FalsePositiveDb.html#method.write_to
+pub const FP_FILE_NAME: &str = "local.fp";
+/// Name of the file ignored signatures are written to by
+/// [`FalsePositiveDb::write_to`]
+///
+/// [`FalsePositiveDb::write_to`]: struct.FalsePositiveDb.html#method.write_to
+pub const IGN2_FILE_NAME: &str = "local.ign2";
+
+/// False positives in ClamAV's own database formats
+///
+/// Files added with [`add_file`] are listed by MD5 and size in a `.fp`
+/// database; libclamav reports them as clean whatever signature matches.
+/// Signatures added with [`add_signature`] are listed by name in a `.ign2`
+/// database; libclamav does not load them at all.
+///
+/// Unlike a `SuppressionList`, which is applied by this crate after each
+/// scan, these databases are read by libclamav itself, so [`write_to`] a
+/// database directory makes them apply to `clamscan`, `clamd` and every
+/// engine loading the directory. [`apply`] loads them into one engine
+/// without touching the directory.
+///
+/// [`add_file`]: #method.add_file
+/// [`add_signature`]: #method.add_signature
+/// [`write_to`]: #method.write_to
+/// [`apply`]: #method.apply
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FalsePositiveDb {
+    /// `.fp` lines, `md5:size:name`
+    hashes: BTreeSet<String>,
+    /// `.ign2` lines, signature names
+    signatures: BTreeSet<String>,
+}
+
+impl FalsePositiveDb {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Lists the contents of the file at `path` as a false positive.
+    ///
+    /// The entry is named after the file name, which is only used in
+    /// libclamav's debug output. Changing the file invalidates the entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of reading the file, or `InvalidData` if libclamav
+    /// fails to hash it.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let md5 = policy::md5_hex(&data)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "failed to hash file"))?;
+        let name: String = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c == ':' || c.is_whitespace() || c.is_control() { '_' } else { c })
+            .collect();
+        self.hashes.insert(format!("{}:{}:{}", md5, data.len(), name));
+        Ok(())
+    }
+
+    /// Lists the signature `name`, e.g. `Win.Test.EICAR_HDB-1`, as ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidInput` for an empty name or one containing `:` or
+    /// whitespace, which the `.ign2` format cannot express.
+    pub fn add_signature(&mut self, name: &str) -> io::Result<()> {
+        if name.is_empty() || name.contains(|c: char| c == ':' || c.is_whitespace() || c.is_control()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid signature name"));
+        }
+        self.signatures.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Returns true if neither files nor signatures were added.
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty() && self.signatures.is_empty()
+    }
+
+    /// Returns the contents of the `.fp` database.
+    pub fn fp_contents(&self) -> String {
+        lines(&self.hashes)
+    }
+
+    /// Returns the contents of the `.ign2` database.
+    pub fn ign2_contents(&self) -> String {
+        lines(&self.signatures)
+    }
+
+    /// Writes [`FP_FILE_NAME`] and [`IGN2_FILE_NAME`] to `dir`, replacing
+    /// existing files, and returns their paths. A file without entries is
+    /// not written. Engines loading `dir` afterwards, e.g. on
+    /// `Scanner::reload`, honour the entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of writing a file.
+    ///
+    /// [`FP_FILE_NAME`]: constant.FP_FILE_NAME.html
+    /// [`IGN2_FILE_NAME`]: constant.IGN2_FILE_NAME.html
+    pub fn write_to<P: AsRef<Path>>(&self, dir: P) -> io::Result<Vec<PathBuf>> {
+        let mut written = Vec::new();
+        for (name, entries) in &[(FP_FILE_NAME, &self.hashes), (IGN2_FILE_NAME, &self.signatures)] {
+            if entries.is_empty() {
+                continue;
+            }
+            let path = dir.as_ref().join(name);
+            fs::write(&path, lines(entries))?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
+    /// Loads the entries into `engine`, which must not be compiled yet.
+    ///
+    /// The databases are written to temporary files created with the
+    /// engine's `TempStrategy`, which are removed after loading. `.ign2`
+    /// entries only affect signatures loaded afterwards, so apply the
+    /// database before loading the signatures to ignore.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of `Engine::load_databases`, or `CL_ETMPFILE` if a
+    /// temporary file cannot be written.
+    pub fn apply(&self, engine: &Engine) -> Result<(), ClamError> {
+        if !self.hashes.is_empty() {
+            engine.load_database_contents(".fp", self.fp_contents().as_bytes())?;
+        }
+        if !self.signatures.is_empty() {
+            engine.load_database_contents(".ign2", self.ign2_contents().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn lines(entries: &BTreeSet<String>) -> String {
+    entries.iter().map(|entry| format!("{}\n", entry)).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{EXAMPLE_DATABASE_PATH, NAUGHTY_FILE_PATH};
+
+    #[test]
+    fn read_cvd_header_of_example_database() {
+        crate::initialize().expect("initialize should succeed");
+        let info = read_cvd_header("test_data/database/example.cud").expect("failed to read header");
+        assert_eq!(
+            info,
+            CvdInfo {
+                version: 1,
+                build_time: UNIX_EPOCH + Duration::from_secs(1528628750),
+                signatures: 1,
+                functionality_level: 85,
+                md5: "c6beb987961f898d85973fd1e521a99b".to_string(),
+                builder: "me".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn read_cvd_header_rejects_truncated_and_malformed_files() {
+        crate::initialize().expect("initialize should succeed");
+        let dir = tempfile::tempdir().unwrap();
+        let contents = fs::read("test_data/database/example.cud").unwrap();
+
+        let truncated = dir.path().join("truncated.cvd");
+        fs::write(&truncated, &contents[..100]).unwrap();
+        let err = read_cvd_header(truncated.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.code(), cl_error_t::CL_ECVD as i32);
+
+        let malformed = dir.path().join("malformed.cvd");
+        fs::write(&malformed, vec![b'x'; 1024]).unwrap();
+        let err = read_cvd_header(malformed.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.code(), cl_error_t::CL_ECVD as i32);
+
+        let missing = dir.path().join("missing.cvd");
+        let err = read_cvd_header(missing.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.code(), cl_error_t::CL_EOPEN as i32);
+    }
+
+    #[test]
+    fn count_signatures_of_test_databases() {
+        crate::initialize().expect("initialize should succeed");
+        let count = count_signatures("test_data/database/", CountFlags::CL_COUNTSIGS_ALL).expect("failed to count");
+        assert!(count > 0, "should count some signatures");
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("local.ndb"), "One.Marker:0:*:4f4e45\nTwo.Marker:0:*:54574f\n").unwrap();
+        let path = dir.path().to_str().unwrap();
+        assert_eq!(count_signatures(path, CountFlags::CL_COUNTSIGS_UNOFFICIAL), Ok(2));
+        assert_eq!(count_signatures(path, CountFlags::CL_COUNTSIGS_OFFICIAL), Ok(0));
+    }
+
+    #[test]
+    fn count_signatures_of_missing_path_fails() {
+        crate::initialize().expect("initialize should succeed");
+        assert!(count_signatures("test_data/does_not_exist", CountFlags::CL_COUNTSIGS_ALL).is_err());
+    }
 
     #[test]
     fn default_directory_success() {
@@ -27,4 +497,106 @@ mod tests {
             "should have a default db dir"
         );
     }
+
+    #[test]
+    fn stdopt_contains_defaults() {
+        let flags = DbFlags::CL_DB_STDOPT;
+        assert!(flags.contains(DbFlags::CL_DB_PHISHING | DbFlags::CL_DB_PHISHING_URLS | DbFlags::CL_DB_BYTECODE));
+        assert!(!flags.contains(DbFlags::CL_DB_PUA));
+        assert_eq!(DbFlags::default(), DbFlags::empty());
+    }
+
+    #[test]
+    fn missing_directory_contains_no_databases() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        assert!(!contains_databases(&missing).unwrap());
+    }
+
+    #[test]
+    fn empty_or_state_only_directory_contains_no_databases() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!contains_databases(dir.path()).unwrap());
+        fs::write(dir.path().join("freshclam.dat"), b"state").unwrap();
+        fs::write(dir.path().join("mirrors.dat"), b"state").unwrap();
+        fs::write(dir.path().join("main.cvd"), b"").unwrap();
+        assert!(!contains_databases(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn cvd_and_cld_layouts_contain_databases() {
+        for name in &["main.cvd", "daily.cld", "local.ndb", "DAILY.CLD"] {
+            let dir = tempfile::tempdir().unwrap();
+            fs::write(dir.path().join(name), b"ClamAV-VDB:").unwrap();
+            assert!(contains_databases(dir.path()).unwrap(), "{} should count", name);
+        }
+    }
+
+    #[test]
+    fn test_data_contains_databases() {
+        assert!(contains_databases(Path::new("test_data/database")).unwrap());
+    }
+
+    #[test]
+    fn false_positive_db_formats_entries() {
+        crate::initialize().expect("initialize should succeed");
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("odd name:1");
+        fs::write(&file, b"test").unwrap();
+
+        let mut db = FalsePositiveDb::new();
+        assert!(db.is_empty());
+        assert!(db.write_to(dir.path()).unwrap().is_empty());
+        db.add_file(&file).unwrap();
+        db.add_file(&file).unwrap();
+        db.add_signature("Win.Test.EICAR_HDB-1").unwrap();
+        for invalid in &["", "Eicar:1", "Eicar Test", "Eicar\n"] {
+            assert_eq!(
+                db.add_signature(invalid).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+        assert_eq!(db.fp_contents(), "098f6bcd4621d373cade4e832627b4f6:4:odd_name_1\n");
+        assert_eq!(db.ign2_contents(), "Win.Test.EICAR_HDB-1\n");
+
+        let written = db.write_to(dir.path()).unwrap();
+        assert_eq!(written, vec![dir.path().join(FP_FILE_NAME), dir.path().join(IGN2_FILE_NAME)]);
+        assert_eq!(fs::read_to_string(&written[0]).unwrap(), db.fp_contents());
+        assert_eq!(fs::read_to_string(&written[1]).unwrap(), db.ign2_contents());
+        assert!(db.add_file(dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn applied_false_positive_is_clean() {
+        crate::initialize().expect("initialize should succeed");
+        let mut db = FalsePositiveDb::new();
+        db.add_file(NAUGHTY_FILE_PATH).unwrap();
+        let engine = Engine::new().unwrap();
+        engine.load_databases(EXAMPLE_DATABASE_PATH).unwrap();
+        db.apply(&engine).unwrap();
+        engine.compile().unwrap();
+        let result = engine.scan_file(NAUGHTY_FILE_PATH, &Default::default()).unwrap();
+        assert_eq!(result, crate::engine::ScanResult::Clean);
+    }
+
+    #[test]
+    fn written_false_positive_is_clean_after_reload() {
+        crate::initialize().expect("initialize should succeed");
+        let dir = tempfile::tempdir().unwrap();
+        fs::copy(EXAMPLE_DATABASE_PATH, dir.path().join("example.cud")).unwrap();
+        let scanner = crate::scanner::Scanner::builder()
+            .database_dir(dir.path().to_str().unwrap())
+            .build()
+            .unwrap();
+        assert!(scanner.scan_path(NAUGHTY_FILE_PATH).unwrap().result.found());
+
+        let mut db = FalsePositiveDb::new();
+        db.add_file(NAUGHTY_FILE_PATH).unwrap();
+        db.write_to(dir.path()).unwrap();
+        scanner.reload().unwrap();
+        assert_eq!(
+            scanner.scan_path(NAUGHTY_FILE_PATH).unwrap().result,
+            crate::engine::ScanResult::Clean
+        );
+    }
 }
@@ -1,10 +1,21 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::ptr;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::ptr::{self, NonNull};
+use std::slice;
 use std::str;
 use std::mem;
 use std::time;
-use std::os::raw::{c_ulong, c_int};
+use std::thread;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::os::raw::{c_char, c_int, c_uchar, c_uint, c_ulong, c_ulonglong, c_void};
 
 use clamav_sys::{
     cl_engine_field,
@@ -15,362 +26,3248 @@ use clamav_sys::{
     cl_error_t,
     cl_load,
     time_t,
-    CL_DB_STDOPT,
+    CL_COUNT_PRECISION,
 };
 
 
-use crate::error::ClamError;
-use crate::scan_settings::ScanSettings;
-use crate::fmap::Fmap;
+use crate::db::{self, DbFlags};
+use crate::error::{ClamError, EngineStateError};
+use crate::scan_settings::{Overrides, ScanSettings};
+use crate::fmap::{AsFmap, Fmap};
+use crate::perf::{self, PerfInfo};
+use crate::policy::{self, SuppressionRecord};
+use crate::request::Interrupt;
+use crate::sniff::{self, SniffMode};
+use crate::temp::{TempSpace, TempStrategy};
+use crate::trace::ScanSpan;
 #[cfg(windows)]
 use crate::windows_fd::WindowsFd;
 
 /// Stats of a loaded database
+///
+/// New fields may be added in future releases. Use [`DatabaseStats::new`] or
+/// `Default` to build instances outside of this crate.
+///
+/// [`DatabaseStats::new`]: struct.DatabaseStats.html#method.new
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct DatabaseStats {
     /// The total number of loaded signatures
     pub signature_count: u32,
+    /// Wall-clock time spent in `cl_load`
+    pub load_duration: time::Duration,
+    /// The options the databases were loaded with
+    pub options: DbFlags,
+    /// Bytes held by the signature index after loading, or `None` if the
+    /// engine has no index, see [`Engine::with_signature_index`]
+    ///
+    /// [`Engine::with_signature_index`]: struct.Engine.html#method.with_signature_index
+    pub signature_index_bytes: Option<u64>,
+}
+
+impl DatabaseStats {
+    /// Creates stats from the signature count, load time and load options
+    pub fn new(signature_count: u32, load_duration: time::Duration, options: DbFlags) -> Self {
+        DatabaseStats {
+            signature_count,
+            load_duration,
+            options,
+            signature_index_bytes: None,
+        }
+    }
+}
+
+/// State of the databases loaded into an engine, see [`Engine::database_stats`]
+///
+/// [`Engine::database_stats`]: struct.Engine.html#method.database_stats
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct DatabaseStatus {
+    /// Version of the loaded databases, e.g. the daily.cvd version
+    pub version: u32,
+    /// Build time of the loaded databases
+    pub timestamp: Option<time::SystemTime>,
+    /// The total number of signatures loaded into the engine
+    pub signatures: u32,
+    /// Time since `timestamp`, zero if there is no timestamp or it is in the future
+    pub age: time::Duration,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ScanResult {
     /// Clean result
     Clean,
     /// Whitelisted result
     Whitelisted,
     /// Virus result, with detected name
-    Virus(String),
+    Virus(Detection),
+    /// Detection downgraded by a suppression rule, see the `policy` module
+    Suppressed { original: Detection },
+    /// A scan limit was exceeded, see [`LimitKind::classify`]
+    ///
+    /// [`LimitKind::classify`]: enum.LimitKind.html#method.classify
+    Suspicious(LimitKind),
+    /// The file is or holds encrypted content, see [`EncryptionKind::classify`]
+    ///
+    /// [`EncryptionKind::classify`]: enum.EncryptionKind.html#method.classify
+    Encrypted { container: EncryptionKind },
+    /// The file was not scanned, e.g. by a scanner without databases
+    NotScanned(NotScannedReason),
 }
 
-#[derive(Debug, PartialEq)]
-pub enum EngineValueType {
-    U32,
-    U64,
-    String,
-    Time,
+/// Why a file was not scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NotScannedReason {
+    /// No databases are loaded, see `ScannerBuilder::allow_missing_database`
+    NoDatabase,
+    /// The engine holds no signatures, see `Engine::set_allow_empty`
+    EmptyEngine,
+    /// Parts of a message failed to scan, see `MessageScanResult::errors`
+    PartsFailed,
 }
 
-pub struct ClamTime(time_t);
-
-impl ClamTime {
-    pub fn as_system_time(&self) -> time::SystemTime {
-        if self.0 >= 0 {
-            time::UNIX_EPOCH + time::Duration::from_secs(self.0 as u64)
-        }
-        else {
-            time::UNIX_EPOCH - time::Duration::from_secs((self.0 * -1) as u64)
+impl fmt::Display for NotScannedReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotScannedReason::NoDatabase => f.write_str("no database"),
+            NotScannedReason::EmptyEngine => f.write_str("no signatures loaded"),
+            NotScannedReason::PartsFailed => f.write_str("parts failed to scan"),
         }
     }
 }
 
-
-
-pub enum EngineValue {
-    U32(u32),
-    U64(u64),
-    String(String),
-    Time(ClamTime),
+impl ScanResult {
+    /// Returns true if a signature matched, a limit was exceeded or
+    /// encrypted content was flagged.
+    pub fn found(&self) -> bool {
+        matches!(self, ScanResult::Virus(_) | ScanResult::Suspicious(_) | ScanResult::Encrypted { .. })
+    }
 }
 
-/// Engine used for scanning files
-pub struct Engine {
-    handle: *mut clamav_sys::cl_engine,
+/// The scan limit a file exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+    /// `CL_ENGINE_MAX_FILES`, files extracted from the scanned file
+    Files,
+    /// `CL_ENGINE_MAX_RECURSION`, nesting depth of containers
+    Recursion,
+    /// `CL_ENGINE_MAX_FILESIZE`, size of a single extracted file
+    FileSize,
+    /// `CL_ENGINE_MAX_SCANSIZE`, total bytes scanned
+    ScanSize,
+    /// `CL_ENGINE_MAX_SCANTIME`
+    Time,
+    /// A limit libclamav did not name, as reported by older versions
+    Unspecified,
 }
 
-unsafe impl Send for Engine {}
-unsafe impl Sync for Engine {}
+const LIMITS_HEURISTIC: &str = "Heuristics.Limits.Exceeded";
 
-fn map_scan_result(result: cl_error_t, virname: *const i8) -> Result<ScanResult, ClamError> {
-    match result {
-        cl_error_t::CL_CLEAN => Ok(ScanResult::Clean),
-        cl_error_t::CL_BREAK => Ok(ScanResult::Whitelisted),
-        cl_error_t::CL_VIRUS => {
-            unsafe {
-                let bytes = CStr::from_ptr(virname).to_bytes();
-                let name = str::from_utf8(bytes).ok().unwrap_or_default().to_string();
-                Ok(ScanResult::Virus(name))
+impl LimitKind {
+    /// Returns the name of the heuristic libclamav reports for this limit
+    /// with `CL_SCAN_HEURISTIC_EXCEEDS_MAX`.
+    pub fn heuristic_name(&self) -> &'static str {
+        match self {
+            LimitKind::Files => "Heuristics.Limits.Exceeded.MaxFiles",
+            LimitKind::Recursion => "Heuristics.Limits.Exceeded.MaxRecursion",
+            LimitKind::FileSize => "Heuristics.Limits.Exceeded.MaxFileSize",
+            LimitKind::ScanSize => "Heuristics.Limits.Exceeded.MaxScanSize",
+            LimitKind::Time => "Heuristics.Limits.Exceeded.MaxScanTime",
+            LimitKind::Unspecified => LIMITS_HEURISTIC,
+        }
+    }
+
+    /// Recognizes the `Heuristics.Limits.Exceeded` detections.
+    pub fn from_detection(detection: &Detection) -> Option<LimitKind> {
+        let suffix = detection.name.strip_prefix(LIMITS_HEURISTIC)?;
+        Some(match suffix {
+            "" => LimitKind::Unspecified,
+            ".MaxFiles" => LimitKind::Files,
+            ".MaxRecursion" => LimitKind::Recursion,
+            ".MaxFileSize" => LimitKind::FileSize,
+            ".MaxScanSize" => LimitKind::ScanSize,
+            ".MaxScanTime" => LimitKind::Time,
+            _ if suffix.starts_with('.') => LimitKind::Unspecified,
+            _ => return None,
+        })
+    }
+
+    /// Recognizes the errors returned when a limit is exceeded without
+    /// `CL_SCAN_HEURISTIC_EXCEEDS_MAX`.
+    pub fn from_error(err: &ClamError) -> Option<LimitKind> {
+        [
+            (cl_error_t::CL_EMAXFILES, LimitKind::Files),
+            (cl_error_t::CL_EMAXREC, LimitKind::Recursion),
+            (cl_error_t::CL_EMAXSIZE, LimitKind::ScanSize),
+            (cl_error_t::CL_ETIMEOUT, LimitKind::Time),
+        ]
+        .iter()
+        .find(|(code, _)| err.code() == *code as i32)
+        .map(|&(_, kind)| kind)
+    }
+
+    /// Turns limit detections and limit errors of a scan into
+    /// `ScanResult::Suspicious`, leaving all other results untouched.
+    pub fn classify(outcome: Result<ScanOutcome, ClamError>) -> Result<ScanOutcome, ClamError> {
+        match outcome {
+            Ok(mut outcome) => {
+                if let ScanResult::Virus(detection) = &outcome.result {
+                    if let Some(kind) = LimitKind::from_detection(detection) {
+                        outcome.result = ScanResult::Suspicious(kind);
+                    }
+                }
+                Ok(outcome)
             }
+            Err(err) => match LimitKind::from_error(&err) {
+                Some(kind) => Ok(ScanOutcome {
+                    result: ScanResult::Suspicious(kind),
+                    bytes_scanned: 0,
+                    suppression: None,
+                    performance: None,
+                    from_cache: None,
+                }),
+                None => Err(err),
+            },
         }
-        _ => Err(ClamError::new(result)),
     }
 }
 
-impl Engine {
-    /// Initialises the engine
-    pub fn new() -> Self {
-        unsafe {
-            let handle = clamav_sys::cl_engine_new();
-            Engine { handle }
+impl fmt::Display for LimitKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.heuristic_name())
+    }
+}
+
+/// The kind of encrypted content flagged by the `ENCRYPTED_ARCHIVE` and
+/// `ENCRYPTED_DOC` heuristics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EncryptionKind {
+    /// Encrypted zip archive or zip member
+    Zip,
+    /// Encrypted RAR archive
+    Rar,
+    /// Encrypted 7-Zip archive
+    SevenZip,
+    /// PDF which cannot be opened without a password
+    Pdf,
+    /// Encrypted office document
+    Document,
+    /// Encrypted content libclamav named in a way this version does not know
+    Unspecified,
+}
+
+const ENCRYPTED_HEURISTIC: &str = "Heuristics.Encrypted";
+
+impl EncryptionKind {
+    /// Returns the name of the heuristic libclamav reports for this kind of
+    /// encrypted content.
+    pub fn heuristic_name(&self) -> &'static str {
+        match self {
+            EncryptionKind::Zip => "Heuristics.Encrypted.Zip",
+            EncryptionKind::Rar => "Heuristics.Encrypted.RAR",
+            EncryptionKind::SevenZip => "Heuristics.Encrypted.7Zip",
+            EncryptionKind::Pdf => "Heuristics.Encrypted.PDF",
+            EncryptionKind::Document => "Heuristics.Encrypted.Doc",
+            EncryptionKind::Unspecified => ENCRYPTED_HEURISTIC,
         }
     }
 
-    /// Compiles the loaded database definitions
-    ///
-    /// This function will compile the database definitions loaded
-    /// in this engine using the [`load_database`] function.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use clamav::{engine};
-    ///
-    /// clamav::initialize().expect("failed to initialize");
-    /// let scanner = engine::Engine::new();
-    /// scanner.compile().expect("failed to compile");
-    /// ```
-    ///
-    /// # Errors
-    ///
-    /// This function will return an error if compliation fails.
-    /// The [`ClamError`] returned will contain the error code.
-    ///
-    /// [`ClamError`]: struct.ClamError.html
-    pub fn compile(&self) -> Result<(), ClamError> {
-        unsafe {
-            let result = clamav_sys::cl_engine_compile(self.handle);
-            match result {
-                cl_error_t::CL_SUCCESS => Ok(()),
-                _ => Err(ClamError::new(result)),
+    /// Recognizes the `Heuristics.Encrypted` detections.
+    pub fn from_detection(detection: &Detection) -> Option<EncryptionKind> {
+        let suffix = detection.name.strip_prefix(ENCRYPTED_HEURISTIC)?;
+        Some(match suffix {
+            "" => EncryptionKind::Unspecified,
+            ".Zip" => EncryptionKind::Zip,
+            ".RAR" => EncryptionKind::Rar,
+            ".7Zip" => EncryptionKind::SevenZip,
+            ".PDF" => EncryptionKind::Pdf,
+            ".Doc" => EncryptionKind::Document,
+            _ if suffix.starts_with('.') => EncryptionKind::Unspecified,
+            _ => return None,
+        })
+    }
+
+    /// Turns encrypted content detections of a scan into
+    /// `ScanResult::Encrypted`, leaving all other results untouched.
+    pub fn classify(outcome: Result<ScanOutcome, ClamError>) -> Result<ScanOutcome, ClamError> {
+        outcome.map(|mut outcome| {
+            if let ScanResult::Virus(detection) = &outcome.result {
+                if let Some(container) = EncryptionKind::from_detection(detection) {
+                    outcome.result = ScanResult::Encrypted { container };
+                }
             }
+            outcome
+        })
+    }
+}
+
+impl fmt::Display for EncryptionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.heuristic_name())
+    }
+}
+
+/// Formats the verdict like clamscan: `OK` for clean, whitelisted and
+/// suppressed files, `<signature> FOUND` for detections, exceeded limits and
+/// encrypted content, and `NOT SCANNED (<reason>)` for files not scanned.
+impl fmt::Display for ScanResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanResult::Clean | ScanResult::Whitelisted | ScanResult::Suppressed { .. } => f.write_str("OK"),
+            ScanResult::Virus(detection) => write!(f, "{} FOUND", detection),
+            ScanResult::Suspicious(kind) => write!(f, "{} FOUND", kind),
+            ScanResult::Encrypted { container } => write!(f, "{} FOUND", container),
+            ScanResult::NotScanned(reason) => write!(f, "NOT SCANNED ({})", reason),
         }
     }
+}
 
-    /// Loads all of the definition databases (*.{cud, cvd}) in the specified directory.
-    ///
-    /// This function will load the definitions that can then be compiled with [`compile`].
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use clamav::{engine};
-    ///
-    /// clamav::initialize().expect("failed to initialize");
-    /// let scanner = engine::Engine::new();
-    /// scanner.load_databases("test_data/database/").expect("failed to load");
-    /// scanner.compile().expect("failed to compile");
-    /// ```
-    ///
-    /// # Errors
+/// Name of a detected signature
+///
+/// Signature names are not required to be valid UTF-8, e.g. in unofficial
+/// databases. `name` holds a lossy conversion for display and comparison,
+/// `raw_name` the bytes reported by libclamav.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Detection {
+    /// Signature name, with invalid UTF-8 sequences replaced by U+FFFD
+    pub name: String,
+    /// Signature name as reported by libclamav
+    pub raw_name: Vec<u8>,
+}
+
+impl Detection {
+    /// Name used for detections reported without a name
+    pub const UNKNOWN_NAME: &'static str = "<unknown>";
+
+    /// Prefix of the names of YARA rule matches
+    pub const YARA_PREFIX: &'static str = "YARA.";
+
+    /// Creates a detection from the raw signature name.
     ///
-    /// This function will return an error if compliation fails.
-    /// The [`ClamError`] returned will contain the error code.
+    /// An empty name is replaced by [`UNKNOWN_NAME`], so `name` is never empty.
     ///
-    /// [`ClamError`]: struct.ClamError.html
-    pub fn load_databases(
-        &self,
-        database_directory_path: &str,
-    ) -> Result<DatabaseStats, ClamError> {
-        // consider the rust-ish builder pattern as it allows options to be specified
-        let raw_path = CString::new(database_directory_path).unwrap();
-        unsafe {
-            let mut signature_count: u32 = 0;
-            let result = cl_load(
-                raw_path.as_ptr(),
-                self.handle,
-                &mut signature_count,
-                CL_DB_STDOPT,
-            );
-            match result {
-                cl_error_t::CL_SUCCESS => Ok(DatabaseStats { signature_count }),
-                _ => Err(ClamError::new(result)),
-            }
+    /// [`UNKNOWN_NAME`]: #associatedconstant.UNKNOWN_NAME
+    pub fn from_raw(raw_name: &[u8]) -> Self {
+        if raw_name.is_empty() {
+            return Self::unnamed();
+        }
+        Detection {
+            name: String::from_utf8_lossy(raw_name).into_owned(),
+            raw_name: raw_name.to_vec(),
         }
     }
 
-    /// Scans a file with the previously loaded and compiled definitions.
-    ///
-    /// This function will scan the given file with the the database definitions
-    /// loaded and compiled.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use clamav::{engine, engine::ScanResult, scan_settings::ScanSettings};
-    ///
-    /// clamav::initialize().expect("failed to initialize");
-    /// let scanner = engine::Engine::new();
-    /// scanner.load_databases("test_data/database/").expect("failed to load");
-    /// scanner.compile().expect("failed to compile");
-    ///
-    /// let settings: ScanSettings = Default::default();
-    /// let hit = scanner.scan_file("test_data/files/good_file", &settings).expect("expected scan to succeed");
-    ///
-    /// match hit {
-    ///     ScanResult::Virus(name) => println!("Virus {}", name),
-    ///     ScanResult::Clean => println!("Clean"),
-    ///     ScanResult::Whitelisted => println!("Whitelisted file")
-    /// }
-    /// ```
-    ///
-    /// ```
-    /// use clamav::{engine, engine::ScanResult, scan_settings::ScanSettingsBuilder};
-    ///
-    /// clamav::initialize().expect("failed to initialize");
-    /// let scanner = engine::Engine::new();
-    /// scanner.load_databases("test_data/database/").expect("failed to load");
-    /// scanner.compile().expect("failed to compile");
-    ///
-    /// let settings = ScanSettingsBuilder::new()
-    ///     .enable_pdf()
-    ///     .block_broken_executables()
-    ///     .build();
-    /// println!("Using settings {}", settings);
-    /// let hit = scanner.scan_file("test_data/files/good_file", &settings).expect("expected scan to succeed");
-    ///
-    /// match hit {
-    ///     ScanResult::Virus(name) => println!("Virus {}", name),
-    ///     ScanResult::Clean => println!("Clean"),
-    ///     ScanResult::Whitelisted => println!("Whitelisted file")
-    /// }
-    /// ```
+    /// Creates a detection for which libclamav reported no name.
     ///
-    /// # Errors
-    ///
-    /// This function will return an error if the scan fails.
-    /// The [`ClamError`] returned will contain the error code.
+    /// `name` is [`UNKNOWN_NAME`] and `raw_name` is empty.
     ///
-    /// [`ClamError`]: struct.ClamError.html
-    pub fn scan_file(&self, path: &str, settings: &mut ScanSettings) -> Result<ScanResult, ClamError> {
-        let raw_path = CString::new(path).unwrap();
-        unsafe {
-            let mut virname: *const i8 = ptr::null();
-            let result = clamav_sys::cl_scanfile(
-                raw_path.as_ptr(),
-                &mut virname,
-                ptr::null_mut(),
-                self.handle,
-                &mut settings.settings,
-            );
-            map_scan_result(result, virname)
+    /// [`UNKNOWN_NAME`]: #associatedconstant.UNKNOWN_NAME
+    pub fn unnamed() -> Self {
+        Detection {
+            name: Self::UNKNOWN_NAME.to_string(),
+            raw_name: Vec::new(),
         }
     }
 
-    /// Scans a descriptor with the previously loaded and compiled definitions.
-    ///
-    /// This function will scan the given descriptor with the the database definitions
-    /// loaded and compiled.
-    pub fn scan_descriptor(&self, descriptor: i32, settings: &mut ScanSettings, filename: Option< &str >) -> Result<ScanResult, ClamError> {
-        unsafe {
-            let mut virname: *const i8 = ptr::null();
-            let filename_cstr = filename.map(|x| CString::new(x).expect("CString::new failed"));
-            let mut scanned : c_ulong = 0;
-            let result = clamav_sys::cl_scandesc(
-                descriptor,
-                filename_cstr.map_or(ptr::null(), |x| x.as_ptr()),
-                &mut virname,
-                &mut scanned,
-                self.handle,
-                &mut settings.settings,
-            );
-            map_scan_result(result, virname)
-        }
+    /// Returns whether the detection is a match of a YARA rule.
+    pub fn is_yara(&self) -> bool {
+        self.name.starts_with(Self::YARA_PREFIX)
     }
 
-    #[cfg(unix)]
-    pub fn scan_fileobj<T: std::os::unix::io::AsRawFd>(&self, file: &T, settings: &mut ScanSettings, filename: Option< &str >) -> Result<ScanResult, ClamError>
-    {
-        self.scan_descriptor(file.as_raw_fd(), settings, filename)
+    /// Returns the name of the matching YARA rule, without the `YARA.` prefix
+    /// and the `.UNOFFICIAL` suffix libclamav adds to unsigned databases.
+    pub fn yara_rule(&self) -> Option<&str> {
+        let rule = self.name.strip_prefix(Self::YARA_PREFIX)?;
+        Some(rule.strip_suffix(".UNOFFICIAL").unwrap_or(rule))
     }
 
-    #[cfg(windows)]
-    pub fn scan_fileobj<T: std::os::windows::io::AsRawHandle>(&self, file: &T, settings: &mut ScanSettings, filename: Option< &str >) -> Result<ScanResult, ClamError> {
-        let fd = WindowsFd::new(file.as_raw_handle()).map_err(|_| ClamError::new(cl_error_t::CL_EARG))?; 
-        self.scan_descriptor(fd.raw(), settings, filename)
+    /// Returns whether libclamav reported no name for this detection.
+    pub fn is_unnamed(&self) -> bool {
+        self.raw_name.is_empty()
     }
 
-    /// @brief Scan custom data.
-    /// @param map           Buffer to be scanned, in form of a cl_fmap_t.
-    /// @param filename      Name of data origin. Does not need to be an actual
-    ///                      file on disk. May be None if a name is not available.
-    /// @param engine        The scanning engine.
-    /// @param scanoptions   The scanning options.
-    pub fn scan_map(&self, map : & Fmap, filename: Option<&str>, settings: &mut ScanSettings) -> Result<ScanResult, ClamError> {
-        let mut virname: *const i8 = ptr::null();
-        let c_filename = filename.map(|n| CString::new(n).expect("CString::new failed"));
-        let result = unsafe {
-            clamav_sys::cl_scanmap_callback(
-                map.raw(),
-                c_filename.map_or(ptr::null(), |n| n.as_ptr()),
-                &mut virname,
-                ptr::null_mut(),
-                self.handle,
-                &mut settings.settings,
-                ptr::null_mut())
-        };
-        map_scan_result(result, virname)
+    /// Returns the signature name
+    pub fn as_str(&self) -> &str {
+        &self.name
     }
 
-    fn get(&self, field: cl_engine_field) -> Result<EngineValue, ClamError> {
-        unsafe {
-            match get_field_type(field) {
-                EngineValueType::U32 => {
-                    let mut err: c_int = 0;
-                    let value = cl_engine_get_num(self.handle, field, &mut err) as u32;
-                    if err != 0 {
-                        Err(ClamError::new(mem::transmute(err)))
-                    }
-                    else {
-                        Ok(EngineValue::U32(value))
-                    }
-                },
-                EngineValueType::U64 => {
-                    let mut err: c_int = 0;
-                    let value = cl_engine_get_num(self.handle, field, &mut err) as u64;
-                    if err != 0 {
-                        Err(ClamError::new(mem::transmute(err)))
-                    }
-                    else {
-                        Ok(EngineValue::U64(value))
-                    }
-                },
-                EngineValueType::String => {
-                    let mut err = 0;
-                    let value = cl_engine_get_str(self.handle, field, &mut err);
-                    if err != 0 {
-                        Err(ClamError::new(mem::transmute(err)))
-                    }
-                    else {
-                        Ok(EngineValue::String(CStr::from_ptr(value).to_str().unwrap().to_string()))
-                    }
-                },
-                EngineValueType::Time => {
-                    let mut err = 0;
-                    let value = cl_engine_get_num(self.handle, field, &mut err) as time_t;
-                    if err != 0 {
-                        Err(ClamError::new(mem::transmute(err)))
-                    }
-                    else {
-                        Ok(EngineValue::Time(ClamTime(value)))
-                    }
-                },
-            }
-        }
+    /// Returns whether `name` is exactly the name reported by libclamav.
+    pub fn is_lossless(&self) -> bool {
+        self.name.as_bytes() == &self.raw_name[..]
     }
+}
 
-    fn set(&self, field: cl_engine_field, value: EngineValue) -> Result<(), ClamError> {
-        let expected_type = get_field_type(field);
-        let actual_type = match &value {
-            EngineValue::U32(_) => EngineValueType::U32,
-            EngineValue::U64(_) => EngineValueType::U64,
-            EngineValue::String(_) => EngineValueType::String,
-            EngineValue::Time(_) => EngineValueType::Time,
-        };
+impl From<&str> for Detection {
+    fn from(name: &str) -> Self {
+        Detection::from_raw(name.as_bytes())
+    }
+}
 
-        if expected_type != actual_type {
-            return Err(ClamError::new(cl_error_t::CL_EARG));
-        }
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
 
-        unsafe {
-            match value {
-                EngineValue::U32(val) => {
-                    let err = cl_engine_set_num(self.handle, field, val as i64);
-                    if err != cl_error_t::CL_SUCCESS {
-                        Err(ClamError::new(err))
-                    }
-                    else {
-                        Ok(())
-                    }
-                },
-                EngineValue::U64(val) => {
-                    let err = cl_engine_set_num(self.handle, field, val as i64);
+impl AsRef<str> for Detection {
+    fn as_ref(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PartialEq<str> for Detection {
+    fn eq(&self, other: &str) -> bool {
+        self.name == other
+    }
+}
+
+impl PartialEq<&str> for Detection {
+    fn eq(&self, other: &&str) -> bool {
+        self.name == *other
+    }
+}
+
+impl PartialEq<String> for Detection {
+    fn eq(&self, other: &String) -> bool {
+        &self.name == other
+    }
+}
+
+/// Name of a scanned object, safe to write to logs and to pass to libclamav
+///
+/// Control characters, including NUL and line breaks, are replaced by
+/// U+FFFD, and the name is cut to [`MAX_LEN`] bytes at a character boundary.
+///
+/// [`MAX_LEN`]: #associatedconstant.MAX_LEN
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SanitizedName {
+    name: String,
+}
+
+impl SanitizedName {
+    /// Maximum length of a name in bytes
+    pub const MAX_LEN: usize = 1024;
+
+    /// Sanitizes `name`.
+    pub fn new(name: &str) -> Self {
+        let mut sanitized = String::with_capacity(name.len().min(Self::MAX_LEN));
+        for c in name.chars() {
+            let c = if c.is_control() { char::REPLACEMENT_CHARACTER } else { c };
+            if sanitized.len() + c.len_utf8() > Self::MAX_LEN {
+                break;
+            }
+            sanitized.push(c);
+        }
+        SanitizedName { name: sanitized }
+    }
+
+    /// Returns the sanitized name
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the name for libclamav.
+    fn to_c_string(&self) -> CString {
+        CString::new(self.name.as_str()).expect("sanitized names contain no NUL")
+    }
+}
+
+impl From<&str> for SanitizedName {
+    fn from(name: &str) -> Self {
+        SanitizedName::new(name)
+    }
+}
+
+impl fmt::Display for SanitizedName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl AsRef<str> for SanitizedName {
+    fn as_ref(&self) -> &str {
+        &self.name
+    }
+}
+
+thread_local! {
+    /// Name of the memory scan running on this thread
+    static SCAN_NAME: RefCell<Option<SanitizedName>> = const { RefCell::new(None) };
+}
+
+/// Returns the name given to the memory scan running on the current thread.
+///
+/// [`Engine::scan_mem`] and [`Engine::scan_map`] pass their `filename` to
+/// libclamav, but libclamav reports objects to the engine callbacks by file
+/// descriptor only, which is -1 for memory. Callbacks set with e.g.
+/// [`Engine::set_virus_found_callback`] run on the scanning thread, so they
+/// can call this function to learn which buffer they are about. Returns
+/// `None` outside of such scans and for scans without a name.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::engine;
+/// use clamav_rs::prelude::*;
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let engine = Engine::new().expect("failed to create engine");
+/// engine.load_databases("test_data/database/").expect("failed to load");
+/// engine.compile().expect("failed to compile");
+/// engine
+///     .set_virus_found_callback(|_fd, virus| {
+///         let name = engine::scan_name().map(|name| name.to_string()).unwrap_or_default();
+///         println!("{}: {} FOUND", name, virus);
+///     })
+///     .expect("failed to set callback");
+///
+/// let settings = ScanSettings::default();
+/// engine.scan_mem(b"uploaded data", Some("tenant-1/upload.bin"), &settings).expect("scan failed");
+/// ```
+///
+/// [`Engine::scan_mem`]: struct.Engine.html#method.scan_mem
+/// [`Engine::scan_map`]: struct.Engine.html#method.scan_map
+/// [`Engine::set_virus_found_callback`]: struct.Engine.html#method.set_virus_found_callback
+pub fn scan_name() -> Option<SanitizedName> {
+    SCAN_NAME.with(|name| name.borrow().clone())
+}
+
+/// Sets the name returned by `scan_name` until dropped
+struct ScanNameGuard {
+    previous: Option<SanitizedName>,
+}
+
+impl ScanNameGuard {
+    fn enter(name: Option<SanitizedName>) -> Self {
+        // callbacks may start scans of their own
+        let previous = SCAN_NAME.with(|current| current.replace(name));
+        ScanNameGuard { previous }
+    }
+}
+
+impl Drop for ScanNameGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        SCAN_NAME.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+thread_local! {
+    /// Context given to `Engine::scan_file_with_context` for the scan running
+    /// on this thread, or null
+    static USER_CONTEXT: Cell<*mut c_void> = const { Cell::new(ptr::null_mut()) };
+}
+
+/// Calls `f` with the context given to [`Engine::scan_file_with_context`]
+/// for the scan running on the current thread.
+///
+/// Engine callbacks run on the scanning thread, so they can use this to read
+/// per-scan state such as a request ID. Returns `None` outside of such scans
+/// and when called from within `f`.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::engine;
+/// use clamav_rs::prelude::*;
+///
+/// struct Request {
+///     id: u64,
+/// }
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let engine = Engine::new().expect("failed to create engine");
+/// engine.load_databases("test_data/database/").expect("failed to load");
+/// engine.compile().expect("failed to compile");
+/// engine
+///     .set_virus_found_callback(|_fd, virus| {
+///         // only scans with a `Request` context are started below
+///         let id = unsafe { engine::with_scan_context(|request: &mut Request| request.id) };
+///         println!("request {:?}: {} FOUND", id, virus);
+///     })
+///     .expect("failed to set callback");
+///
+/// let settings = ScanSettings::default();
+/// let mut request = Request { id: 42 };
+/// engine
+///     .scan_file_with_context("test_data/files/naughty_file", &settings, &mut request)
+///     .expect("scan failed");
+/// ```
+///
+/// # Safety
+///
+/// The context is stored without its type. `C` must be the type of the
+/// context passed to the running `scan_file_with_context` call; the crate
+/// cannot check this, and a different type is undefined behaviour.
+///
+/// [`Engine::scan_file_with_context`]: struct.Engine.html#method.scan_file_with_context
+pub unsafe fn with_scan_context<C, R, F: FnOnce(&mut C) -> R>(f: F) -> Option<R> {
+    // taken out while `f` runs, so nested calls cannot alias it
+    let guard = UserContextGuard::enter(ptr::null_mut());
+    (guard.previous as *mut C).as_mut().map(f)
+}
+
+/// Sets the context returned by `with_scan_context` until dropped
+struct UserContextGuard {
+    previous: *mut c_void,
+}
+
+impl UserContextGuard {
+    fn enter(context: *mut c_void) -> Self {
+        // callbacks may start scans of their own
+        let previous = USER_CONTEXT.with(|current| current.replace(context));
+        UserContextGuard { previous }
+    }
+}
+
+impl Drop for UserContextGuard {
+    fn drop(&mut self) {
+        USER_CONTEXT.with(|current| current.set(self.previous));
+    }
+}
+
+thread_local! {
+    /// Buffer for the NUL-terminated path of the file scan running on this thread
+    static PATH_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Calls `f` with `path` as a C string, or fails with `CL_EARG` if the path
+/// contains a NUL byte.
+///
+/// The conversion reuses a per-thread buffer, so scanning paths of similar
+/// length allocates only once per thread. Scans started by callbacks while
+/// the buffer is in use get a buffer of their own.
+fn with_c_path<R, F: FnOnce(&CStr) -> R>(path: &str, f: F) -> Result<R, ClamError> {
+    PATH_BUFFER.with(|shared| {
+        let mut own = Vec::new();
+        let mut shared = shared.try_borrow_mut();
+        let buffer = match shared.as_deref_mut() {
+            Ok(buffer) => buffer,
+            Err(_) => &mut own,
+        };
+        buffer.clear();
+        buffer.extend_from_slice(path.as_bytes());
+        buffer.push(0);
+        // fails on a NUL byte within the path
+        let raw_path = CStr::from_bytes_with_nul(buffer).map_err(|_| ClamError::new(cl_error_t::CL_EARG))?;
+        Ok(f(raw_path))
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EngineValueType {
+    U32,
+    U64,
+    String,
+    Time,
+}
+
+/// Window layout for [`Engine::scan_file_windowed`]
+///
+/// [`Engine::scan_file_windowed`]: struct.Engine.html#method.scan_file_windowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowConfig {
+    /// Number of bytes scanned per window
+    pub window_size: u64,
+    /// Number of bytes shared by consecutive windows. Must be smaller than `window_size`.
+    pub overlap: u64,
+}
+
+/// A detection in one window of a windowed scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowDetection {
+    /// Offset of the window in the file
+    pub offset: u64,
+    /// Length of the window
+    pub len: u64,
+    /// Detected virus name
+    pub name: Detection,
+}
+
+/// Aggregated result of [`Engine::scan_file_windowed`]
+///
+/// [`Engine::scan_file_windowed`]: struct.Engine.html#method.scan_file_windowed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowedScanReport {
+    /// Number of windows scanned
+    pub windows: u64,
+    /// Detections, ordered by window offset
+    pub detections: Vec<WindowDetection>,
+}
+
+impl WindowedScanReport {
+    /// Returns the first detection as a [`ScanResult`], or `Clean`.
+    ///
+    /// [`ScanResult`]: enum.ScanResult.html
+    pub fn result(&self) -> ScanResult {
+        match self.detections.first() {
+            Some(detection) => ScanResult::Virus(detection.name.clone()),
+            None => ScanResult::Clean,
+        }
+    }
+}
+
+/// A `time_t` timestamp reported by libclamav, 0 meaning unset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClamTime(time_t);
+
+/// A [`ClamTime`] which cannot be represented as `SystemTime` on this platform
+///
+/// [`ClamTime`]: struct.ClamTime.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClamTimeError {
+    seconds: i64,
+}
+
+impl ClamTimeError {
+    /// The offending number of seconds since the UNIX epoch
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+}
+
+impl fmt::Display for ClamTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timestamp {} is out of range", self.seconds)
+    }
+}
+
+impl std::error::Error for ClamTimeError {}
+
+impl ClamTime {
+    pub fn new(value: time_t) -> Self {
+        ClamTime(value)
+    }
+
+    /// Seconds since the UNIX epoch
+    // time_t is 32 bits on some targets
+    #[allow(clippy::useless_conversion)]
+    pub fn seconds(&self) -> i64 {
+        i64::from(self.0)
+    }
+
+    /// Converts the timestamp to a `SystemTime`.
+    ///
+    /// Returns `Ok(None)` if the timestamp is unset, i.e. 0, and an error if
+    /// it lies outside the range of `SystemTime` on this platform.
+    pub fn as_system_time(&self) -> Result<Option<time::SystemTime>, ClamTimeError> {
+        let seconds = self.seconds();
+        if seconds == 0 {
+            return Ok(None);
+        }
+        let offset = time::Duration::from_secs(seconds.unsigned_abs());
+        let system_time = if seconds > 0 {
+            time::UNIX_EPOCH.checked_add(offset)
+        } else {
+            time::UNIX_EPOCH.checked_sub(offset)
+        };
+        system_time.map(Some).ok_or(ClamTimeError { seconds })
+    }
+}
+
+
+
+pub enum EngineValue {
+    U32(u32),
+    U64(u64),
+    String(String),
+    Time(ClamTime),
+}
+
+/// Lifecycle state of an [`Engine`]
+///
+/// Databases are loaded into a new engine, which is then compiled and can
+/// only scan from then on. Operations which are invalid in the current state
+/// fail with an [`EngineStateError`] before libclamav is called.
+///
+/// [`Engine`]: struct.Engine.html
+/// [`EngineStateError`]: ../enum.EngineStateError.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineState {
+    /// No databases were loaded yet
+    New,
+    /// Databases were loaded, but not compiled
+    Loaded,
+    /// The engine was compiled and can scan
+    Compiled,
+}
+
+/// Engine used for scanning files
+///
+/// # Lifecycle
+///
+/// Databases must be loaded, and settings used while loading or compiling
+/// such as `CL_ENGINE_AC_MAXDEPTH` changed, before [`compile`]; scanning is
+/// only possible afterwards. See [`EngineState`] and [`set_state_checks`].
+///
+/// [`compile`]: #method.compile
+/// [`EngineState`]: enum.EngineState.html
+/// [`set_state_checks`]: #method.set_state_checks
+///
+/// # Thread safety
+///
+/// The engine can be shared between threads for scanning. Loading databases,
+/// compiling and reading or changing settings are serialized by an internal
+/// lock, so they cannot corrupt each other, e.g. when a string setting is
+/// replaced while it is being read. They are not synchronized with running
+/// scans though: configure the engine completely before sharing it.
+pub struct Engine {
+    handle: *mut clamav_sys::cl_engine,
+    /// Serializes changes to and reads of the engine configuration
+    config_lock: Mutex<()>,
+    /// Whether databases were loaded successfully, for `Debug`
+    loaded: AtomicBool,
+    /// Whether the loaded databases were compiled, for `Debug`
+    compiled: AtomicBool,
+    /// Signatures loaded by all calls to `cl_load`, for `Debug` and to
+    /// tell scans of an empty engine from clean files
+    signature_count: AtomicU32,
+    /// Whether an engine without signatures scans, see `set_allow_empty`
+    allow_empty: AtomicBool,
+    /// Whether operations are checked against the lifecycle state
+    state_checks: AtomicBool,
+    /// Temporary files created by the crate for this engine
+    temp: TempSpace,
+    /// Callbacks set with `set_pre_scan_callback`, `set_post_scan_callback`,
+    /// `set_file_inspection_callback`, `set_hash_callback`,
+    /// `set_file_props_callback` and `set_virus_found_callback`. Scans hold a
+    /// clone until they finish, so they can be replaced while scanning.
+    hooks: Mutex<Hooks>,
+    /// Callback set with `set_progress_callback`. libclamav holds a pointer
+    /// to it, so it is only replaced while holding `config_lock`.
+    progress: Mutex<Option<Arc<Hook<ProgressFn>>>>,
+    /// Names of the loaded signatures, see `with_signature_index`.
+    /// libclamav holds a pointer to it.
+    signatures: Option<Arc<SignatureIndex>>,
+}
+
+unsafe impl Send for Engine {}
+unsafe impl Sync for Engine {}
+
+/// libclamav settings of an engine, see [`Engine::export_settings`]
+///
+/// [`Engine::export_settings`]: struct.Engine.html#method.export_settings
+pub struct EngineSettings {
+    raw: NonNull<clamav_sys::cl_settings>,
+}
+
+impl Drop for EngineSettings {
+    fn drop(&mut self) {
+        unsafe { clamav_sys::cl_engine_settings_free(self.raw.as_ptr()) };
+    }
+}
+
+// the settings are only read after the copy and are not tied to a thread
+unsafe impl Send for EngineSettings {}
+unsafe impl Sync for EngineSettings {}
+
+/// Result of a scan with additional information about the scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ScanOutcome {
+    /// The verdict
+    pub result: ScanResult,
+    /// Number of bytes scanned, in multiples of `CL_COUNT_PRECISION`
+    pub bytes_scanned: u64,
+    /// Why the detection was suppressed, if `result` is `Suppressed`
+    pub suppression: Option<SuppressionRecord>,
+    /// Parser timings, if the scan settings collect performance info
+    pub performance: Option<PerfInfo>,
+    /// Whether the verdict came from libclamav's cache of clean files
+    /// instead of a scan. `None` if this cannot be told: libclamav stopped
+    /// before the cache lookup, e.g. for empty files, the engine was not
+    /// compiled by this crate, or the outcome was not produced by a scan.
+    pub from_cache: Option<bool>,
+}
+
+impl ScanOutcome {
+    /// Formats the clamscan report line for `path`, e.g.
+    /// `/tmp/eicar.com: Eicar-Signature FOUND`.
+    pub fn format_line(&self, path: &Path) -> String {
+        format!("{}: {}", path.display(), self.result)
+    }
+}
+
+/// What libclamav does with a file after the pre-scan callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PreScanAction {
+    /// Scan the file, `CL_CLEAN`
+    Continue,
+    /// Skip the file and report it clean, `CL_BREAK`
+    Skip,
+    /// Skip the file and report it as `Detected.By.Callback`, `CL_VIRUS`
+    Detect,
+}
+
+impl PreScanAction {
+    fn to_raw(self) -> cl_error_t {
+        match self {
+            PreScanAction::Continue => cl_error_t::CL_CLEAN,
+            PreScanAction::Skip => cl_error_t::CL_BREAK,
+            PreScanAction::Detect => cl_error_t::CL_VIRUS,
+        }
+    }
+}
+
+/// A layer of a scanned file, reported by the file inspection callback
+///
+/// Strings from libclamav are converted lossily to UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FileInspection {
+    /// File descriptor of the layer, -1 for layers held in memory
+    pub fd: i32,
+    /// Type libclamav determined for the layer, e.g. `CL_TYPE_ZIP`
+    pub file_type: String,
+    /// Names of the containers of the layer, outermost first; empty for the
+    /// scanned file itself, and empty strings for containers without a name
+    pub ancestors: Vec<String>,
+    /// Size of the container holding the layer, 0 for the scanned file
+    pub parent_file_size: u64,
+    /// Name of the layer, e.g. of the archive member, if it has one
+    pub file_name: Option<String>,
+    /// Size of the layer
+    pub file_size: u64,
+    /// Hex encoded SHA-256 of the layer, if libclamav provided its contents
+    pub sha256: Option<String>,
+}
+
+type PreScanFn = dyn FnMut(i32, &str) -> PreScanAction + Send;
+type VirusFoundFn = dyn FnMut(i32, &str) + Send;
+type PostScanFn = dyn FnMut(i32, ScanResult, &str) + Send;
+type FileInspectionFn = dyn FnMut(FileInspection) + Send;
+type HashFn = dyn FnMut(i32, u64, &str, &str) + Send;
+type FilePropsFn = dyn FnMut(&str) + Send;
+type ProgressFn = dyn FnMut(u64, u64) + Send;
+
+/// Callback of an engine, called by one scanning thread at a time
+struct Hook<F: ?Sized>(Mutex<Box<F>>);
+
+impl<F: ?Sized> Hook<F> {
+    fn new(callback: Box<F>) -> Arc<Self> {
+        Arc::new(Hook(Mutex::new(callback)))
+    }
+
+    /// Runs `call` with the callback, returning `None` if it panics, as
+    /// panics must not unwind into libclamav.
+    fn run<T, C: FnOnce(&mut F) -> T>(&self, call: C) -> Option<T> {
+        // a panicking callback only poisons the lock, the callback itself stays usable
+        let mut callback = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        panic::catch_unwind(AssertUnwindSafe(|| call(&mut **callback))).ok()
+    }
+}
+
+/// Callbacks of an engine set by the user
+#[derive(Clone, Default)]
+struct Hooks {
+    pre_scan: Option<Arc<Hook<PreScanFn>>>,
+    virus_found: Option<Arc<Hook<VirusFoundFn>>>,
+    post_scan: Option<Arc<Hook<PostScanFn>>>,
+    file_inspection: Option<Arc<Hook<FileInspectionFn>>>,
+    hash: Option<Arc<Hook<HashFn>>>,
+    file_props: Option<Arc<Hook<FilePropsFn>>>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("pre_scan", &self.pre_scan.is_some())
+            .field("virus_found", &self.virus_found.is_some())
+            .field("post_scan", &self.post_scan.is_some())
+            .field("file_inspection", &self.file_inspection.is_some())
+            .field("hash", &self.hash.is_some())
+            .field("file_props", &self.file_props.is_some())
+            .finish()
+    }
+}
+
+/// Names of the signatures loaded into an engine
+#[derive(Debug, Default)]
+struct SignatureIndex {
+    names: Mutex<BTreeSet<String>>,
+}
+
+impl SignatureIndex {
+    /// Returns an estimate of the bytes held by the index.
+    fn memory_usage(&self) -> u64 {
+        let names = self.names.lock().unwrap();
+        let per_name = mem::size_of::<String>() as u64;
+        names.iter().map(|name| per_name + name.capacity() as u64).sum()
+    }
+}
+
+/// Signature load callback installed by `Engine::with_signature_index`
+///
+/// Unlike the scan callbacks, its context is the index itself.
+unsafe extern "C" fn index_signature(
+    _sig_type: *const c_char,
+    name: *const c_char,
+    custom: c_uint,
+    context: *mut c_void,
+) -> c_int {
+    if let (Some(index), Some(name)) = ((context as *const SignatureIndex).as_ref(), lossy_string(name)) {
+        // libclamav appends the suffix to the names of unofficial signatures
+        // when it stores them, so detections report them that way
+        let name = if custom != 0 { name + ".UNOFFICIAL" } else { name };
+        index.names.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(name);
+    }
+    // load the signature
+    0
+}
+
+/// Type of the `scanned` counter of the libclamav scan functions
+///
+/// The counter is `unsigned long`, i.e. 32 bits on Windows, and counts blocks
+/// of `CL_COUNT_PRECISION` bytes. Passing it to FFI fails to compile if the
+/// bindings declare a different type, so only this alias needs adjusting.
+pub(crate) type ScannedBlocks = c_ulong;
+
+// Fails to compile if the counter cannot be widened to u64 without loss
+const _: fn(ScannedBlocks) -> u64 = u64::from;
+
+/// Converts a libclamav `scanned` counter to bytes.
+// `ScannedBlocks` is u64 on some targets only
+#[allow(clippy::useless_conversion)]
+pub(crate) fn scanned_bytes(blocks: ScannedBlocks) -> u64 {
+    u64::from(blocks).saturating_mul(u64::from(CL_COUNT_PRECISION))
+}
+
+/// Data collected by the engine callbacks during one scan
+///
+/// A pointer to it is the context of the `cl_scan*_callback` functions. The
+/// callbacks ignore a null context, e.g. from scans through FFI.
+#[derive(Debug, Default)]
+pub(crate) struct ScanContext {
+    /// Whether to record `file_type`, which costs an allocation per scan
+    record_type: bool,
+    /// Type libclamav determined for the scanned file itself
+    pub(crate) file_type: Option<String>,
+    /// Whether libclamav looked up the file in its clean cache
+    cache_checked: bool,
+    /// Whether libclamav went on to scan the file
+    scanned: bool,
+    /// Callbacks of the engine at the start of the scan
+    hooks: Hooks,
+    /// Descriptors and types of the objects being scanned, innermost last,
+    /// for the post-scan callback
+    open_types: Vec<(c_int, String)>,
+    /// Detections reported by the virus-found callback, if collected
+    matches: Option<Vec<Detection>>,
+    /// Deadline and cancellation token of the request the scan belongs to
+    interrupt: Interrupt,
+}
+
+impl ScanContext {
+    fn as_raw(&mut self) -> *mut c_void {
+        self as *mut ScanContext as *mut c_void
+    }
+
+    /// Returns whether the verdict was taken from the clean cache, or `None`
+    /// if libclamav did not get as far as the cache lookup.
+    ///
+    /// libclamav calls the pre-cache callback before looking up a file in
+    /// the cache, and the pre-scan callback only if the lookup missed.
+    fn served_from_cache(&self) -> Option<bool> {
+        if self.cache_checked {
+            Some(!self.scanned)
+        } else {
+            None
+        }
+    }
+}
+
+/// Pre-cache callback of all engines compiled by this crate
+unsafe extern "C" fn pre_cache(fd: c_int, file_type: *const c_char, context: *mut c_void) -> cl_error_t {
+    if let Some(context) = (context as *mut ScanContext).as_mut() {
+        // skip the objects left once the request was given up on
+        if context.interrupt.is_due() {
+            return cl_error_t::CL_BREAK;
+        }
+        context.cache_checked = true;
+        // the first call is for the file itself, later ones for contained
+        // objects. Files served from the clean cache get no pre-scan call.
+        if context.record_type && context.file_type.is_none() && !file_type.is_null() {
+            let file_type = CStr::from_ptr(file_type).to_string_lossy();
+            if !file_type.is_empty() {
+                context.file_type = Some(file_type.into_owned());
+            }
+        }
+        // libclamav calls the post-scan callback for every object which got
+        // as far as the cache lookup, including cache hits
+        if context.hooks.post_scan.is_some() {
+            let file_type = if file_type.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(file_type).to_string_lossy().into_owned()
+            };
+            context.open_types.push((fd, file_type));
+        }
+    }
+    cl_error_t::CL_CLEAN
+}
+
+/// Pre-scan callback of all engines compiled by this crate
+unsafe extern "C" fn pre_scan(fd: c_int, file_type: *const c_char, context: *mut c_void) -> cl_error_t {
+    let context = match (context as *mut ScanContext).as_mut() {
+        Some(context) => context,
+        None => return cl_error_t::CL_CLEAN,
+    };
+    if context.interrupt.is_due() {
+        return cl_error_t::CL_BREAK;
+    }
+    let file_type = if file_type.is_null() {
+        Default::default()
+    } else {
+        CStr::from_ptr(file_type).to_string_lossy()
+    };
+    context.scanned = true;
+    match &context.hooks.pre_scan {
+        Some(hook) => hook
+            .run(|callback| callback(fd, &file_type))
+            .unwrap_or(PreScanAction::Continue)
+            .to_raw(),
+        None => cl_error_t::CL_CLEAN,
+    }
+}
+
+/// Post-scan callback installed by `Engine::set_post_scan_callback`
+unsafe extern "C" fn post_scan(
+    fd: c_int,
+    result: c_int,
+    virname: *const c_char,
+    context: *mut c_void,
+) -> cl_error_t {
+    let context = match (context as *mut ScanContext).as_mut() {
+        Some(context) => context,
+        None => return cl_error_t::CL_CLEAN,
+    };
+    let hook = match &context.hooks.post_scan {
+        Some(hook) => hook,
+        None => return cl_error_t::CL_CLEAN,
+    };
+    // libclamav skips the pre-cache callback for some objects, e.g. empty ones
+    let file_type = match context.open_types.last() {
+        Some((open, _)) if *open == fd => context.open_types.pop().map(|(_, file_type)| file_type),
+        _ => None,
+    }
+    .unwrap_or_default();
+    // results which are errors have no `ScanResult`; the raw code is only
+    // compared as it may not be a valid `cl_error_t`
+    let result = [cl_error_t::CL_CLEAN, cl_error_t::CL_BREAK, cl_error_t::CL_VIRUS]
+        .iter()
+        .find(|&&code| code as c_int == result)
+        .and_then(|&code| map_scan_result(code, virname).ok());
+    if let Some(result) = result {
+        hook.run(|callback| callback(fd, result, &file_type));
+    }
+    // anything else would replace the verdict
+    cl_error_t::CL_CLEAN
+}
+
+/// Converts a string from libclamav, which may be null.
+unsafe fn lossy_string(raw: *const c_char) -> Option<String> {
+    if raw.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(raw).to_string_lossy().into_owned())
+    }
+}
+
+/// File inspection callback installed by `Engine::set_file_inspection_callback`
+// `size_t` is u64 on some targets only
+#[allow(clippy::useless_conversion, clippy::too_many_arguments)]
+unsafe extern "C" fn file_inspection(
+    fd: c_int,
+    file_type: *const c_char,
+    ancestors: *mut *const c_char,
+    parent_file_size: clamav_sys::size_t,
+    file_name: *const c_char,
+    file_size: clamav_sys::size_t,
+    file_buffer: *const c_char,
+    recursion_level: u32,
+    _layer_attributes: u32,
+    context: *mut c_void,
+) -> cl_error_t {
+    let hook = match (context as *mut ScanContext).as_ref() {
+        Some(ScanContext { hooks: Hooks { file_inspection: Some(hook), .. }, .. }) => hook,
+        _ => return cl_error_t::CL_CLEAN,
+    };
+    // one entry per level above this layer, each of which may be null
+    let ancestors = if ancestors.is_null() {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(ancestors, recursion_level as usize)
+            .iter()
+            .map(|&name| lossy_string(name).unwrap_or_default())
+            .collect()
+    };
+    let file_size = u64::from(file_size);
+    let sha256 = if file_buffer.is_null() {
+        None
+    } else {
+        let contents = slice::from_raw_parts(file_buffer as *const u8, file_size as usize);
+        policy::sha256_hex(contents)
+    };
+    let inspection = FileInspection {
+        fd,
+        file_type: lossy_string(file_type).unwrap_or_default(),
+        ancestors,
+        parent_file_size: u64::from(parent_file_size),
+        file_name: lossy_string(file_name),
+        file_size,
+        sha256,
+    };
+    hook.run(|callback| callback(inspection));
+    // anything else would skip or detect the layer
+    cl_error_t::CL_CLEAN
+}
+
+/// Hash callback installed by `Engine::set_hash_callback`
+unsafe extern "C" fn hash(
+    fd: c_int,
+    size: c_ulonglong,
+    md5: *const c_uchar,
+    virname: *const c_char,
+    context: *mut c_void,
+) {
+    let hook = match (context as *mut ScanContext).as_ref() {
+        Some(ScanContext { hooks: Hooks { hash: Some(hook), .. }, .. }) => hook,
+        _ => return,
+    };
+    // both are nul-terminated strings, the hash in hex
+    let md5 = lossy_string(md5 as *const c_char).unwrap_or_default();
+    let virname = lossy_string(virname).unwrap_or_default();
+    hook.run(|callback| callback(fd, size, &md5, &virname));
+}
+
+/// File properties callback installed by `Engine::set_file_props_callback`
+///
+/// libclamav uses the returned value as the result of the scan, so `result`
+/// is passed on unchanged.
+unsafe extern "C" fn file_props(json: *const c_char, result: c_int, context: *mut c_void) -> c_int {
+    let hook = match (context as *mut ScanContext).as_ref() {
+        Some(ScanContext { hooks: Hooks { file_props: Some(hook), .. }, .. }) => hook,
+        _ => return result,
+    };
+    if let Some(json) = lossy_string(json) {
+        hook.run(|callback| callback(&json));
+    }
+    result
+}
+
+/// Progress callback installed by `Engine::set_progress_callback`
+///
+/// Unlike the scan callbacks, its context is the hook itself.
+// `size_t` is u64 on some targets only
+#[allow(clippy::useless_conversion)]
+unsafe extern "C" fn progress(
+    total: clamav_sys::size_t,
+    now: clamav_sys::size_t,
+    context: *mut c_void,
+) -> cl_error_t {
+    if let Some(hook) = (context as *const Hook<ProgressFn>).as_ref() {
+        hook.run(|callback| callback(u64::from(total), u64::from(now)));
+    }
+    cl_error_t::CL_SUCCESS
+}
+
+/// Virus-found callback of all engines compiled by this crate
+unsafe extern "C" fn virus_found(fd: c_int, virname: *const c_char, context: *mut c_void) {
+    let context = match (context as *mut ScanContext).as_mut() {
+        Some(context) => context,
+        None => return,
+    };
+    if let Some(matches) = &mut context.matches {
+        matches.push(if virname.is_null() {
+            Detection::unnamed()
+        } else {
+            Detection::from_raw(CStr::from_ptr(virname).to_bytes())
+        });
+    }
+    let hook = match &context.hooks.virus_found {
+        Some(hook) => hook,
+        None => return,
+    };
+    let name = if virname.is_null() {
+        Default::default()
+    } else {
+        CStr::from_ptr(virname).to_string_lossy()
+    };
+    hook.run(|callback| callback(fd, &name));
+}
+
+/// Converts the result of a libclamav scan.
+///
+/// `virname` is never owned by the caller, in single- and all-match mode alike:
+/// it points to a name owned by the engine, the signature name in its loaded
+/// databases or a static name for heuristic detections, and stays valid as
+/// long as the engine. All-match mode only reports the last name through it.
+/// The name is copied here, while the scan still borrows the engine, so the
+/// result does not depend on the engine outliving it; the pointer must
+/// neither be kept nor freed.
+fn map_scan_result(result: cl_error_t, virname: *const c_char) -> Result<ScanResult, ClamError> {
+    match result {
+        cl_error_t::CL_CLEAN => Ok(ScanResult::Clean),
+        cl_error_t::CL_BREAK => Ok(ScanResult::Whitelisted),
+        // some flows, e.g. with heuristic precedence, report a virus without a name
+        cl_error_t::CL_VIRUS if virname.is_null() => Ok(ScanResult::Virus(Detection::unnamed())),
+        cl_error_t::CL_VIRUS => {
+            unsafe {
+                let bytes = CStr::from_ptr(virname).to_bytes();
+                Ok(ScanResult::Virus(Detection::from_raw(bytes)))
+            }
+        }
+        _ => Err(ClamError::new(result)),
+    }
+}
+
+impl Engine {
+    /// Initialises the engine
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EMEM` if libclamav cannot allocate the engine.
+    pub fn new() -> Result<Self, ClamError> {
+        let handle = unsafe { clamav_sys::cl_engine_new() };
+        if handle.is_null() {
+            return Err(ClamError::new(cl_error_t::CL_EMEM));
+        }
+        let engine = unsafe { Engine::from_raw(handle) };
+        // enabling the checks is always sound, only disabling them is unsafe
+        engine.state_checks.store(true, Ordering::Relaxed);
+        engine.set_allow_empty(false);
+        Ok(engine)
+    }
+
+    /// Creates an engine from a raw libclamav engine handle.
+    ///
+    /// The engine takes ownership of `handle` and frees it when dropped. A
+    /// null `handle` is accepted; all operations on such an engine fail with
+    /// `CL_ENULLARG`.
+    ///
+    /// The crate cannot tell whether `handle` was loaded or compiled already,
+    /// so operations are not checked against the lifecycle state, see
+    /// [`set_state_checks`], and scans run even if no signatures were loaded
+    /// through this engine, see [`set_allow_empty`].
+    ///
+    /// [`set_state_checks`]: #method.set_state_checks
+    /// [`set_allow_empty`]: #method.set_allow_empty
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be null or a valid engine returned by `cl_engine_new`
+    /// which is not freed or owned elsewhere.
+    pub unsafe fn from_raw(handle: *mut clamav_sys::cl_engine) -> Self {
+        Engine {
+            handle,
+            config_lock: Mutex::new(()),
+            loaded: AtomicBool::new(false),
+            compiled: AtomicBool::new(false),
+            signature_count: AtomicU32::new(0),
+            allow_empty: AtomicBool::new(true),
+            state_checks: AtomicBool::new(false),
+            temp: TempSpace::new(),
+            hooks: Mutex::new(Hooks::default()),
+            progress: Mutex::new(None),
+            signatures: None,
+        }
+    }
+
+    /// Records the name of every signature loaded from now on, so that
+    /// [`find_signatures`] and [`has_signature`] can tell which signatures
+    /// the engine holds.
+    ///
+    /// The index is opt-in because it keeps a copy of every name, which for
+    /// the official databases amounts to tens of megabytes; the bytes held
+    /// are reported in [`DatabaseStats::signature_index_bytes`]. Call this
+    /// before loading databases, signatures loaded earlier are not indexed.
+    /// Names of unofficial signatures carry the `.UNOFFICIAL` suffix, as in
+    /// detections.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine").with_signature_index();
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// assert!(engine.has_signature("naughty_file.UNOFFICIAL").unwrap());
+    /// ```
+    ///
+    /// [`find_signatures`]: #method.find_signatures
+    /// [`has_signature`]: #method.has_signature
+    /// [`DatabaseStats::signature_index_bytes`]: struct.DatabaseStats.html#structfield.signature_index_bytes
+    pub fn with_signature_index(mut self) -> Self {
+        if let Ok(handle) = self.handle() {
+            let index = Arc::new(SignatureIndex::default());
+            let context = Arc::as_ptr(&index) as *mut c_void;
+            unsafe { clamav_sys::cl_engine_set_clcb_sigload(handle.as_ptr(), Some(index_signature), context) };
+            self.signatures = Some(index);
+        }
+        self
+    }
+
+    fn signature_index(&self) -> Result<&SignatureIndex, ClamError> {
+        self.signatures
+            .as_deref()
+            .ok_or_else(|| EngineStateError::NoSignatureIndex.into())
+    }
+
+    /// Returns the sorted names of the loaded signatures matching `pattern`,
+    /// a glob with `*` and `?` wildcards, e.g. `Win.Trojan.*`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EARG` with [`EngineStateError::NoSignatureIndex`] if the
+    /// engine was not created with [`with_signature_index`].
+    ///
+    /// [`EngineStateError::NoSignatureIndex`]: ../enum.EngineStateError.html#variant.NoSignatureIndex
+    /// [`with_signature_index`]: #method.with_signature_index
+    pub fn find_signatures(&self, pattern: &str) -> Result<Vec<String>, ClamError> {
+        let names = self.signature_index()?.names.lock().unwrap();
+        Ok(names
+            .iter()
+            .filter(|name| policy::glob_matches(pattern, name))
+            .cloned()
+            .collect())
+    }
+
+    /// Returns true if a signature named exactly `name` is loaded.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`find_signatures`].
+    ///
+    /// [`find_signatures`]: #method.find_signatures
+    pub fn has_signature(&self, name: &str) -> Result<bool, ClamError> {
+        Ok(self.signature_index()?.names.lock().unwrap().contains(name))
+    }
+
+    /// Returns the lifecycle state of the engine.
+    pub fn state(&self) -> EngineState {
+        if self.compiled.load(Ordering::Relaxed) {
+            EngineState::Compiled
+        } else if self.loaded.load(Ordering::Relaxed) {
+            EngineState::Loaded
+        } else {
+            EngineState::New
+        }
+    }
+
+    /// Enables or disables the lifecycle state checks.
+    ///
+    /// Engines created with [`new`] reject scans before [`compile`], and
+    /// loading databases, compiling or changing load-time settings after
+    /// it, with an [`EngineStateError`]. Disabling the checks passes these
+    /// calls on to libclamav, e.g. for an engine created with [`from_raw`]
+    /// whose state the crate does not know.
+    ///
+    /// # Safety
+    ///
+    /// With the checks disabled, the caller must uphold the lifecycle: some
+    /// libclamav versions crash when scanning with an engine which was not
+    /// compiled.
+    ///
+    /// [`new`]: #method.new
+    /// [`compile`]: #method.compile
+    /// [`from_raw`]: #method.from_raw
+    /// [`EngineStateError`]: ../enum.EngineStateError.html
+    pub unsafe fn set_state_checks(&self, enabled: bool) {
+        self.state_checks.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Sets whether an engine holding no signatures scans files.
+    ///
+    /// A compiled engine without signatures reports every file as clean,
+    /// which usually means the databases were missing or empty rather than
+    /// that the files are. Engines created with [`new`] therefore answer
+    /// scans with `ScanResult::NotScanned(NotScannedReason::EmptyEngine)` as
+    /// long as no signature was loaded; [`scan_file_quiet`] fails with
+    /// `EngineStateError::NoDatabase`. Allowing empty engines restores plain
+    /// libclamav behaviour, e.g. for engines relying on callbacks only.
+    ///
+    /// The check reads the count kept while loading databases, it makes no
+    /// libclamav call per scan.
+    ///
+    /// [`new`]: #method.new
+    /// [`scan_file_quiet`]: #method.scan_file_quiet
+    pub fn set_allow_empty(&self, allow: bool) {
+        self.allow_empty.store(allow, Ordering::Relaxed);
+    }
+
+    /// Returns the outcome of every scan while the engine holds no
+    /// signatures and empty engines are not allowed.
+    fn empty_outcome(&self) -> Option<ScanOutcome> {
+        if self.allow_empty.load(Ordering::Relaxed) || self.signature_count.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        Some(ScanOutcome {
+            result: ScanResult::NotScanned(NotScannedReason::EmptyEngine),
+            bytes_scanned: 0,
+            suppression: None,
+            performance: None,
+            from_cache: None,
+        })
+    }
+
+    /// Fails unless the engine is compiled, or state checks are disabled.
+    fn require_compiled(&self) -> Result<(), ClamError> {
+        if self.state_checks.load(Ordering::Relaxed) && !self.compiled.load(Ordering::Relaxed) {
+            return Err(EngineStateError::NotCompiled.into());
+        }
+        Ok(())
+    }
+
+    /// Fails if the engine is compiled and state checks are enabled.
+    fn require_uncompiled(&self) -> Result<(), ClamError> {
+        if self.state_checks.load(Ordering::Relaxed) && self.compiled.load(Ordering::Relaxed) {
+            return Err(EngineStateError::AlreadyCompiled.into());
+        }
+        Ok(())
+    }
+
+    /// Locks the engine configuration, see "Thread safety" above.
+    fn lock_config(&self) -> MutexGuard<'_, ()> {
+        // the lock guards no data, so a panic while holding it leaves nothing inconsistent
+        self.config_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns the libclamav handle, or `CL_ENULLARG` if there is none.
+    fn handle(&self) -> Result<NonNull<clamav_sys::cl_engine>, ClamError> {
+        NonNull::new(self.handle).ok_or_else(|| ClamError::new(cl_error_t::CL_ENULLARG))
+    }
+
+    /// Returns the recommended number of threads scanning concurrently with one engine
+    ///
+    /// A compiled engine is read-only and can be shared by any number of threads,
+    /// but scans do not scale linearly with threads. Known bottlenecks in libclamav are:
+    ///
+    /// * the clean-file cache, whose buckets are protected by mutexes that are
+    ///   taken for every scanned object (disable it with `CL_ENGINE_DISABLE_CACHE`
+    ///   if contention shows up in profiles),
+    /// * unpacking of archives and documents, which writes temporary files to
+    ///   the engine's temporary directory and is bound by that file system,
+    /// * bytecode signatures, which run in an interpreter unless the JIT is enabled.
+    ///
+    /// Since scanning is CPU bound otherwise, this returns the available
+    /// parallelism of the machine, or 1 if it cannot be determined.
+    pub fn recommended_concurrency() -> usize {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    }
+
+    /// Compiles the loaded database definitions
+    ///
+    /// This function will compile the database definitions loaded
+    /// in this engine using the [`load_database`] function.
+    ///
+    /// Compiling installs the crate's pre-scan callback, which directory
+    /// scans use to learn the type of each file, replacing any callback set
+    /// on a raw handle before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let scanner = Engine::new().expect("failed to create engine");
+    /// scanner.compile().expect("failed to compile");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if compliation fails.
+    /// The [`ClamError`] returned will contain the error code.
+    /// Compiling an engine twice fails with `EngineStateError::AlreadyCompiled`.
+    ///
+    /// [`ClamError`]: struct.ClamError.html
+    pub fn compile(&self) -> Result<(), ClamError> {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        self.require_uncompiled()?;
+        unsafe {
+            clamav_sys::cl_engine_set_clcb_pre_cache(handle.as_ptr(), Some(pre_cache));
+            clamav_sys::cl_engine_set_clcb_pre_scan(handle.as_ptr(), Some(pre_scan));
+            clamav_sys::cl_engine_set_clcb_virus_found(handle.as_ptr(), Some(virus_found));
+            let result = clamav_sys::cl_engine_compile(handle.as_ptr());
+            match result {
+                cl_error_t::CL_SUCCESS => {
+                    self.compiled.store(true, Ordering::Relaxed);
+                    Ok(())
+                }
+                _ => Err(ClamError::new(result)),
+            }
+        }
+    }
+
+    /// Compiles the loaded database definitions on a background thread
+    ///
+    /// Compiling full databases takes several seconds. This function moves the
+    /// engine to a spawned thread, compiles it there and returns immediately.
+    /// The compiled engine is handed back by [`CompilingEngine::wait`], so it is
+    /// not possible to scan with an engine that has not finished compiling.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let scanner = Engine::new().expect("failed to create engine");
+    /// scanner.load_databases("test_data/database/").expect("failed to load");
+    /// let compiling = scanner.compile_in_background();
+    /// // ... finish starting up the service ...
+    /// let scanner = compiling.wait().expect("failed to compile");
+    /// ```
+    ///
+    /// [`CompilingEngine::wait`]: struct.CompilingEngine.html#method.wait
+    pub fn compile_in_background(self) -> CompilingEngine {
+        let state = Arc::new((Mutex::new(None), Condvar::new()));
+        let thread_state = Arc::clone(&state);
+        thread::spawn(move || {
+            let result = self.compile().map(|_| self);
+            let (lock, cvar) = &*thread_state;
+            *lock.lock().unwrap() = Some(result);
+            cvar.notify_all();
+        });
+        CompilingEngine { state }
+    }
+
+    /// Loads all of the definition databases (*.{cud, cvd}) in the specified directory.
+    ///
+    /// This function will load the definitions that can then be compiled with [`compile`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let scanner = Engine::new().expect("failed to create engine");
+    /// scanner.load_databases("test_data/database/").expect("failed to load");
+    /// scanner.compile().expect("failed to compile");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if compliation fails.
+    /// The [`ClamError`] returned will contain the error code.
+    /// Loading into a compiled engine fails with
+    /// `EngineStateError::AlreadyCompiled`, and a path containing a NUL byte
+    /// with `CL_EARG`.
+    ///
+    /// [`ClamError`]: struct.ClamError.html
+    pub fn load_databases(
+        &self,
+        database_directory_path: &str,
+    ) -> Result<DatabaseStats, ClamError> {
+        self.load_databases_with_options(database_directory_path, DbFlags::CL_DB_STDOPT)
+    }
+
+    /// Same as [`load_databases`], loading with `options` instead of
+    /// `CL_DB_STDOPT`.
+    ///
+    /// Use it to load e.g. "potentially unwanted application" signatures with
+    /// `CL_DB_PUA`, or unsigned bytecode with `CL_DB_BYTECODE_UNSIGNED`.
+    /// `options` replace the standard options, so combine them with
+    /// `DbFlags::CL_DB_STDOPT` to keep loading phishing and bytecode
+    /// signatures.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::db::DbFlags;
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let scanner = Engine::new().expect("failed to create engine");
+    /// scanner
+    ///     .load_databases_with_options("test_data/database/", DbFlags::CL_DB_STDOPT | DbFlags::CL_DB_PUA)
+    ///     .expect("failed to load");
+    /// scanner.compile().expect("failed to compile");
+    /// ```
+    ///
+    /// [`load_databases`]: #method.load_databases
+    pub fn load_databases_with_options(&self, path: &str, options: DbFlags) -> Result<DatabaseStats, ClamError> {
+        self.load_with_flags(path, options)
+    }
+
+    /// Builds a new engine configured like this one from the databases in
+    /// `path`, e.g. after freshclam updated them.
+    ///
+    /// The new engine gets the libclamav settings of this engine, such as
+    /// scan limits and the temporary directory, as well as its callbacks,
+    /// temporary file strategy and quota, [`set_allow_empty`] and, if it has
+    /// one, a new signature index. Like any engine created with [`new`], it
+    /// checks its lifecycle state. It is loaded and compiled before it is
+    /// returned, which takes as long as building it from scratch.
+    ///
+    /// This engine is not modified and can keep scanning meanwhile, also
+    /// from other threads. Replacing it is up to the caller: keep the engine
+    /// in an `Arc` which scans clone when they start, or in a
+    /// [`SharedEngine`], and publish the new engine once it is ready. Scans
+    /// already running keep using this engine until they finish, and it is
+    /// freed when the last reference is dropped. If reloading fails, nothing
+    /// changes.
+    ///
+    /// Databases loaded into this engine with options other than the
+    /// standard ones are loaded with `CL_DB_STDOPT` into the new engine;
+    /// load other databases into an engine built from scratch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    /// use clamav_rs::shared::SharedEngine;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    /// let shared = SharedEngine::new(engine);
+    ///
+    /// // e.g. after freshclam ran
+    /// let reloaded = shared.get().reload_databases("test_data/database/").expect("failed to reload");
+    /// shared.swap(reloaded);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if this engine has no handle, `CL_EMEM` if the
+    /// settings cannot be copied, or the error of loading or compiling.
+    ///
+    /// [`set_allow_empty`]: #method.set_allow_empty
+    /// [`new`]: #method.new
+    /// [`SharedEngine`]: ../shared/struct.SharedEngine.html
+    pub fn reload_databases(&self, path: &str) -> Result<Engine, ClamError> {
+        let settings = self.export_settings()?;
+        let mut engine = Engine::new()?;
+        // shared, so a callback replaced on this engine stays on the new one
+        *engine.progress.lock().unwrap() = self.progress.lock().unwrap().clone();
+        engine.apply_settings(&settings)?;
+        *engine.hooks.lock().unwrap() = self.hooks();
+        engine.set_temp_strategy(self.temp_strategy());
+        engine.set_temp_quota(self.temp_quota());
+        engine.set_allow_empty(self.allow_empty.load(Ordering::Relaxed));
+        if self.signatures.is_some() {
+            // replaces the copied callback, which indexes into this engine
+            engine = engine.with_signature_index();
+        }
+        engine.load_databases(path)?;
+        engine.compile()?;
+        Ok(engine)
+    }
+
+    /// Copies the libclamav settings of this engine, such as scan limits,
+    /// load options and the temporary directory, to apply them to another
+    /// engine with [`apply_settings`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let tuned = Engine::new().expect("failed to create engine");
+    /// tuned.set_max_filesize(10 * 1024 * 1024).expect("failed to set limit");
+    ///
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine
+    ///     .apply_settings(&tuned.export_settings().expect("failed to export"))
+    ///     .expect("failed to apply");
+    /// assert_eq!(engine.max_filesize(), Ok(10 * 1024 * 1024));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle and `CL_EMEM` if
+    /// the settings cannot be copied.
+    ///
+    /// [`apply_settings`]: #method.apply_settings
+    pub fn export_settings(&self) -> Result<EngineSettings, ClamError> {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        let raw = unsafe { clamav_sys::cl_engine_settings_copy(handle.as_ptr()) };
+        NonNull::new(raw)
+            .map(|raw| EngineSettings { raw })
+            .ok_or_else(|| ClamError::new(cl_error_t::CL_EMEM))
+    }
+
+    /// Applies settings exported with [`export_settings`] to this engine,
+    /// replacing all of its libclamav settings.
+    ///
+    /// Only libclamav's settings are applied: the callbacks, temporary file
+    /// strategy and quota and signature index of this engine are kept, see
+    /// [`reload_databases`] to carry those over as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle, or
+    /// `EngineStateError::AlreadyCompiled` as load-time settings cannot
+    /// change after [`compile`].
+    ///
+    /// [`export_settings`]: #method.export_settings
+    /// [`reload_databases`]: #method.reload_databases
+    /// [`compile`]: #method.compile
+    pub fn apply_settings(&self, settings: &EngineSettings) -> Result<(), ClamError> {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        self.require_uncompiled()?;
+        unsafe {
+            let result = clamav_sys::cl_engine_settings_apply(handle.as_ptr(), settings.raw.as_ptr());
+            if result != cl_error_t::CL_SUCCESS {
+                return Err(ClamError::new(result));
+            }
+            // the copied callbacks with a context point into the exporting
+            // engine, which may be dropped first
+            match self.progress.lock().unwrap().as_ref() {
+                Some(hook) => {
+                    let context = Arc::as_ptr(hook) as *mut c_void;
+                    clamav_sys::cl_engine_set_clcb_sigload_progress(handle.as_ptr(), Some(progress), context);
+                    clamav_sys::cl_engine_set_clcb_engine_compile_progress(handle.as_ptr(), Some(progress), context);
+                }
+                None => {
+                    clamav_sys::cl_engine_set_clcb_sigload_progress(handle.as_ptr(), None, ptr::null_mut());
+                    clamav_sys::cl_engine_set_clcb_engine_compile_progress(handle.as_ptr(), None, ptr::null_mut());
+                }
+            }
+            match &self.signatures {
+                Some(index) => {
+                    let context = Arc::as_ptr(index) as *mut c_void;
+                    clamav_sys::cl_engine_set_clcb_sigload(handle.as_ptr(), Some(index_signature), context);
+                }
+                None => clamav_sys::cl_engine_set_clcb_sigload(handle.as_ptr(), None, ptr::null_mut()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the databases from the default database directory, if there are any.
+    ///
+    /// Returns `Ok(None)` without loading anything if the directory is missing
+    /// or contains no non-empty databases, e.g. because freshclam never ran.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EOPEN` if the directory cannot be read, or the error of
+    /// loading the databases.
+    pub fn try_load_system_databases(&self) -> Result<Option<DatabaseStats>, ClamError> {
+        let dir = db::default_directory();
+        match db::contains_databases(Path::new(&dir)) {
+            Ok(true) => self.load_databases(&dir).map(Some),
+            Ok(false) => Ok(None),
+            Err(_) => Err(ClamError::new(cl_error_t::CL_EOPEN)),
+        }
+    }
+
+    /// Loads YARA rules from a `.yar` or `.yara` file.
+    ///
+    /// Matches are reported with the rule name prefixed by `YARA.`, see
+    /// [`Detection::is_yara`]. The engine must be compiled afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EARG` if the file does not have a YARA extension, as
+    /// libclamav would silently skip it, or the error of `cl_load`.
+    ///
+    /// [`Detection::is_yara`]: struct.Detection.html#method.is_yara
+    pub fn load_yara_file(&self, path: &Path) -> Result<DatabaseStats, ClamError> {
+        if !is_yara_path(path) {
+            return Err(ClamError::new(cl_error_t::CL_EARG));
+        }
+        let path = path.to_str().ok_or_else(|| ClamError::new(cl_error_t::CL_EARG))?;
+        self.load_with_flags(path, DbFlags::CL_DB_STDOPT | DbFlags::CL_DB_YARA_ONLY)
+    }
+
+    /// Loads YARA rules from source text.
+    ///
+    /// libclamav only loads rules from files, so `rules` is written to a
+    /// temporary `.yara` file, created with the engine's [`TempStrategy`],
+    /// which is removed after loading.
+    ///
+    /// [`TempStrategy`]: ../temp/trait.TempStrategy.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let scanner = Engine::new().expect("failed to create engine");
+    /// scanner
+    ///     .load_yara_rules("rule marker { strings: $a = \"MARKER\" condition: $a }")
+    ///     .expect("failed to load");
+    /// scanner.compile().expect("failed to compile");
+    /// let result = scanner.scan_mem(b"...MARKER...", None, &ScanSettings::default());
+    /// if let Ok(ScanResult::Virus(detection)) = result {
+    ///     assert_eq!(detection.yara_rule(), Some("marker"));
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ETMPFILE` if the temporary file cannot be written or would
+    /// exceed the quota set with [`set_temp_quota`], or the error of
+    /// `cl_load`, e.g. for invalid rules.
+    ///
+    /// [`set_temp_quota`]: #method.set_temp_quota
+    pub fn load_yara_rules(&self, rules: &str) -> Result<DatabaseStats, ClamError> {
+        // libclamav picks the parser by the file extension
+        let file = self.temp.write(".yara", rules.as_bytes())?;
+        self.load_yara_file(file.path())
+    }
+
+    /// Loads a database from `contents` through a temporary file whose name
+    /// ends with `suffix`, e.g. `.fp`, which selects the parser.
+    pub(crate) fn load_database_contents(&self, suffix: &str, contents: &[u8]) -> Result<DatabaseStats, ClamError> {
+        let file = self.temp.write(suffix, contents)?;
+        let path = file.path().to_str().ok_or_else(|| ClamError::new(cl_error_t::CL_EARG))?;
+        self.load_with_flags(path, DbFlags::CL_DB_STDOPT)
+    }
+
+    /// Copies the `len` bytes of `reader` to a temporary file whose name ends
+    /// with `suffix`, held against the temporary file quota.
+    #[cfg(feature = "oci")]
+    pub(crate) fn spill<R: std::io::Read>(&self, suffix: &str, reader: R, len: u64) -> Result<crate::temp::SpillFile, ClamError> {
+        self.temp.copy(suffix, reader, len)
+    }
+
+    /// Sets how the crate creates temporary files for this engine, see the
+    /// [`temp`] module. Defaults to `DefaultTempStrategy::new()`.
+    ///
+    /// [`temp`]: ../temp/index.html
+    pub fn set_temp_strategy(&self, strategy: Arc<dyn TempStrategy>) {
+        self.temp.set_strategy(strategy);
+    }
+
+    /// Returns the strategy creating the temporary files of this engine.
+    pub fn temp_strategy(&self) -> Arc<dyn TempStrategy> {
+        self.temp.strategy()
+    }
+
+    /// Limits the bytes held in the crate's temporary files of this engine
+    /// at the same time. `None`, the default, disables the limit.
+    ///
+    /// Operations needing a temporary file which would exceed the quota fail
+    /// with `CL_ETMPFILE` and a [`TempQuotaError`] before the file is created.
+    ///
+    /// [`TempQuotaError`]: ../struct.TempQuotaError.html
+    pub fn set_temp_quota(&self, bytes: Option<u64>) {
+        self.temp.set_quota(bytes);
+    }
+
+    /// Returns the limit set with [`set_temp_quota`].
+    ///
+    /// [`set_temp_quota`]: #method.set_temp_quota
+    pub fn temp_quota(&self) -> Option<u64> {
+        self.temp.quota()
+    }
+
+    /// Returns the bytes currently held in the crate's temporary files of
+    /// this engine.
+    pub fn temp_bytes(&self) -> u64 {
+        self.temp.used()
+    }
+
+    /// Sets a callback reporting the progress of loading and compiling
+    /// databases, replacing the previous one.
+    ///
+    /// The callback receives the total number of items and the number of
+    /// items done so far. libclamav counts database files while loading and
+    /// compilation steps while compiling, so set the callback before
+    /// [`load_databases`] and expect one sequence of calls from each of
+    /// `load_databases` and [`compile`]. The engine keeps the callback until
+    /// it is replaced or the engine is dropped.
+    ///
+    /// Loading and compiling call the callback on the calling thread, or on
+    /// the compiling thread for [`compile_in_background`]. If it panics, the
+    /// panic is caught at the FFI boundary and loading continues.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine
+    ///     .set_progress_callback(|total, now| println!("{}/{}", now, total))
+    ///     .expect("failed to set callback");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle.
+    ///
+    /// [`load_databases`]: #method.load_databases
+    /// [`compile`]: #method.compile
+    /// [`compile_in_background`]: #method.compile_in_background
+    pub fn set_progress_callback<F>(&self, callback: F) -> Result<(), ClamError>
+    where
+        F: FnMut(u64, u64) + Send + 'static,
+    {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        let hook: Arc<Hook<ProgressFn>> = Hook::new(Box::new(callback));
+        let context = Arc::as_ptr(&hook) as *mut c_void;
+        unsafe {
+            clamav_sys::cl_engine_set_clcb_sigload_progress(handle.as_ptr(), Some(progress), context);
+            clamav_sys::cl_engine_set_clcb_engine_compile_progress(handle.as_ptr(), Some(progress), context);
+        }
+        // libclamav no longer points to the previous callback
+        *self.progress.lock().unwrap() = Some(hook);
+        Ok(())
+    }
+
+    /// Sets a callback called by libclamav before it scans a file, replacing
+    /// the previous one.
+    ///
+    /// The callback receives the file descriptor of the object about to be
+    /// scanned and the type libclamav determined for it, e.g. `CL_TYPE_PDF`.
+    /// It is called for the scanned file itself and for every object
+    /// extracted from it, and decides with the returned [`PreScanAction`]
+    /// whether the object is scanned.
+    ///
+    /// The callback may be called from several scanning threads, one at a
+    /// time. If it panics, the panic is caught at the FFI boundary and the
+    /// object is scanned as if it had returned `PreScanAction::Continue`.
+    /// Only scans through this crate call the callback; scans started through
+    /// FFI on the raw handle do not.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::engine::PreScanAction;
+    /// use clamav_rs::prelude::*;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    ///
+    /// let files = Arc::new(AtomicUsize::new(0));
+    /// let counter = files.clone();
+    /// engine
+    ///     .set_pre_scan_callback(move |_fd, _file_type| {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///         PreScanAction::Continue
+    ///     })
+    ///     .expect("failed to set callback");
+    ///
+    /// let settings = ScanSettings::default();
+    /// engine.scan_file("test_data/files/good_file", &settings).expect("scan failed");
+    /// println!("{} files about to be scanned", files.load(Ordering::Relaxed));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle.
+    ///
+    /// [`PreScanAction`]: enum.PreScanAction.html
+    pub fn set_pre_scan_callback<F>(&self, callback: F) -> Result<(), ClamError>
+    where
+        F: FnMut(i32, &str) -> PreScanAction + Send + 'static,
+    {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        // engines from `from_raw` may not have been compiled by this crate
+        unsafe { clamav_sys::cl_engine_set_clcb_pre_scan(handle.as_ptr(), Some(pre_scan)) };
+        self.hooks.lock().unwrap().pre_scan = Some(Hook::new(Box::new(callback)));
+        Ok(())
+    }
+
+    /// Sets a callback called by libclamav after it scanned a file, replacing
+    /// the previous one.
+    ///
+    /// The callback receives the file descriptor of the scanned object, its
+    /// result and the type libclamav determined for it, e.g. `CL_TYPE_ZIP`.
+    /// It is called for the scanned file itself and for every object
+    /// extracted from it, innermost objects first, so it can collect
+    /// statistics on archives and mails. Objects whose scan failed with an
+    /// error are left out, and objects libclamav did not type, e.g. empty
+    /// ones, have an empty type.
+    ///
+    /// The callback only observes the scan: there is no way for it to change
+    /// the verdict. As for [`set_pre_scan_callback`], it is called one at a
+    /// time, panics are caught at the FFI boundary, and only scans through
+    /// this crate call it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    ///
+    /// let objects = Arc::new(Mutex::new(Vec::new()));
+    /// let log = objects.clone();
+    /// engine
+    ///     .set_post_scan_callback(move |_fd, result, file_type| {
+    ///         log.lock().unwrap().push(format!("{}: {}", file_type, result));
+    ///     })
+    ///     .expect("failed to set callback");
+    ///
+    /// let settings = ScanSettings::default();
+    /// engine.scan_file("test_data/files/good_file", &settings).expect("scan failed");
+    /// println!("{:?}", objects.lock().unwrap());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle.
+    ///
+    /// [`set_pre_scan_callback`]: #method.set_pre_scan_callback
+    pub fn set_post_scan_callback<F>(&self, callback: F) -> Result<(), ClamError>
+    where
+        F: FnMut(i32, ScanResult, &str) + Send + 'static,
+    {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        unsafe {
+            // the pre-cache callback records the types of the objects
+            clamav_sys::cl_engine_set_clcb_pre_cache(handle.as_ptr(), Some(pre_cache));
+            clamav_sys::cl_engine_set_clcb_post_scan(handle.as_ptr(), Some(post_scan));
+        }
+        self.hooks.lock().unwrap().post_scan = Some(Hook::new(Box::new(callback)));
+        Ok(())
+    }
+
+    /// Sets a callback called by libclamav for every layer of a scanned file,
+    /// replacing the previous one.
+    ///
+    /// libclamav calls it for the scanned file itself and for every object
+    /// it extracts, e.g. archive members, before scanning them. Each call
+    /// describes one layer with a [`FileInspection`]: its type, the names of
+    /// the containers it was extracted from, its name and size, and the
+    /// SHA-256 of its contents, which is computed for every layer while a
+    /// callback is set. This gives a forensic record of nested containers.
+    ///
+    /// The callback only observes the scan. As for
+    /// [`set_pre_scan_callback`], it is called one at a time, panics are
+    /// caught at the FFI boundary, and only scans through this crate call it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    /// engine
+    ///     .set_file_inspection_callback(|layer| {
+    ///         println!("{}/{:?}: {}", layer.ancestors.join("/"), layer.file_name, layer.file_type);
+    ///     })
+    ///     .expect("failed to set callback");
+    ///
+    /// let settings = ScanSettingsBuilder::new().enable_archive().build();
+    /// engine.scan_file("test_data/files/nested.zip", &settings).expect("scan failed");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle.
+    ///
+    /// [`FileInspection`]: struct.FileInspection.html
+    /// [`set_pre_scan_callback`]: #method.set_pre_scan_callback
+    pub fn set_file_inspection_callback<F>(&self, callback: F) -> Result<(), ClamError>
+    where
+        F: FnMut(FileInspection) + Send + 'static,
+    {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        unsafe { clamav_sys::cl_engine_set_clcb_file_inspection(handle.as_ptr(), Some(file_inspection)) };
+        self.hooks.lock().unwrap().file_inspection = Some(Hook::new(Box::new(callback)));
+        Ok(())
+    }
+
+    /// Sets a callback called by libclamav with the hash of detected files,
+    /// replacing the previous one.
+    ///
+    /// The callback receives the file descriptor of the object which
+    /// matched, its size, its MD5 in lower case hex and the signature name.
+    /// libclamav calls it while checking a detection against its false
+    /// positive lists, so it is called for detected objects only, never for
+    /// clean files. To hash every scanned object, use
+    /// [`set_file_inspection_callback`].
+    ///
+    /// As for [`set_pre_scan_callback`], the callback is called one at a
+    /// time, panics are caught at the FFI boundary, and only scans through
+    /// this crate call it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    /// engine
+    ///     .set_hash_callback(|_fd, size, md5, virus| println!("{} {} bytes: {}", md5, size, virus))
+    ///     .expect("failed to set callback");
+    ///
+    /// let settings = ScanSettings::default();
+    /// engine.scan_file("test_data/files/naughty_file", &settings).expect("scan failed");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle.
+    ///
+    /// [`set_file_inspection_callback`]: #method.set_file_inspection_callback
+    /// [`set_pre_scan_callback`]: #method.set_pre_scan_callback
+    pub fn set_hash_callback<F>(&self, callback: F) -> Result<(), ClamError>
+    where
+        F: FnMut(i32, u64, &str, &str) + Send + 'static,
+    {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        unsafe { clamav_sys::cl_engine_set_clcb_hash(handle.as_ptr(), Some(hash)) };
+        self.hooks.lock().unwrap().hash = Some(Hook::new(Box::new(callback)));
+        Ok(())
+    }
+
+    /// Sets a callback called by libclamav for every detection, replacing
+    /// the previous one.
+    ///
+    /// The callback receives the file descriptor of the object which matched
+    /// and the signature name, converted lossily to UTF-8. Scans only return
+    /// the last detection; with `ScanSettingsBuilder::enable_all_matches`, the
+    /// callback sees every signature matching anywhere in the file, e.g. in
+    /// each member of an archive.
+    ///
+    /// As for [`set_pre_scan_callback`], the callback is called one at a
+    /// time, panics are caught at the FFI boundary, and only scans through
+    /// this crate call it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    ///
+    /// let found = Arc::new(Mutex::new(Vec::new()));
+    /// let names = found.clone();
+    /// engine
+    ///     .set_virus_found_callback(move |_fd, name| names.lock().unwrap().push(name.to_string()))
+    ///     .expect("failed to set callback");
+    ///
+    /// let settings = ScanSettingsBuilder::new().enable_all_matches().build();
+    /// engine.scan_file("test_data/files/naughty_file", &settings).expect("scan failed");
+    /// println!("found {:?}", found.lock().unwrap());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle.
+    ///
+    /// [`set_pre_scan_callback`]: #method.set_pre_scan_callback
+    pub fn set_virus_found_callback<F>(&self, callback: F) -> Result<(), ClamError>
+    where
+        F: FnMut(i32, &str) + Send + 'static,
+    {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        unsafe { clamav_sys::cl_engine_set_clcb_virus_found(handle.as_ptr(), Some(virus_found)) };
+        self.hooks.lock().unwrap().virus_found = Some(Hook::new(Box::new(callback)));
+        Ok(())
+    }
+
+    /// Sets a callback called with the properties libclamav collected about
+    /// a scanned file, replacing the previous one.
+    ///
+    /// The callback receives the properties as the raw JSON document
+    /// libclamav produces at the end of a scan, e.g. the PE sections or the
+    /// OLE2 streams of the file and its embedded objects; the `metadata`
+    /// feature provides a typed model for it. libclamav only collects the
+    /// properties for scans with `CL_SCAN_GENERAL_COLLECT_METADATA` and when
+    /// it was built with JSON support.
+    ///
+    /// As for [`set_pre_scan_callback`], the callback is called one at a
+    /// time, panics are caught at the FFI boundary, and only scans through
+    /// this crate call it. The callback cannot change the result of the scan.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    /// use clamav_rs::scan_settings::GeneralFlags;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    /// engine
+    ///     .set_file_props_callback(|json| println!("{}", json))
+    ///     .expect("failed to set callback");
+    ///
+    /// let mut settings = ScanSettings::default();
+    /// settings.set_general(settings.general() | GeneralFlags::CL_SCAN_GENERAL_COLLECT_METADATA);
+    /// engine.scan_file("test_data/files/good_file", &settings).expect("scan failed");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if the engine has no handle.
+    ///
+    /// [`set_pre_scan_callback`]: #method.set_pre_scan_callback
+    pub fn set_file_props_callback<F>(&self, callback: F) -> Result<(), ClamError>
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        unsafe { clamav_sys::cl_engine_set_clcb_file_props(handle.as_ptr(), Some(file_props)) };
+        self.hooks.lock().unwrap().file_props = Some(Hook::new(Box::new(callback)));
+        Ok(())
+    }
+
+    /// Returns the callbacks set by the user.
+    fn hooks(&self) -> Hooks {
+        self.hooks.lock().unwrap().clone()
+    }
+
+    fn load_with_flags(&self, path: &str, options: DbFlags) -> Result<DatabaseStats, ClamError> {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        self.require_uncompiled()?;
+        let raw_path = CString::new(path).map_err(|_| ClamError::new(cl_error_t::CL_EARG))?;
+        unsafe {
+            let mut signature_count: u32 = 0;
+            let start = time::Instant::now();
+            let result = cl_load(
+                raw_path.as_ptr(),
+                handle.as_ptr(),
+                &mut signature_count,
+                options.bits(),
+            );
+            let load_duration = start.elapsed();
+            match result {
+                cl_error_t::CL_SUCCESS => {
+                    self.loaded.store(true, Ordering::Relaxed);
+                    self.compiled.store(false, Ordering::Relaxed);
+                    self.signature_count.fetch_add(signature_count, Ordering::Relaxed);
+                    Ok(DatabaseStats {
+                        signature_index_bytes: self.signatures.as_ref().map(|index| index.memory_usage()),
+                        ..DatabaseStats::new(signature_count, load_duration, options)
+                    })
+                }
+                _ => Err(ClamError::new(result)),
+            }
+        }
+    }
+
+    /// Scans a file with the previously loaded and compiled definitions.
+    ///
+    /// This function will scan the given file with the the database definitions
+    /// loaded and compiled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let scanner = Engine::new().expect("failed to create engine");
+    /// scanner.load_databases("test_data/database/").expect("failed to load");
+    /// scanner.compile().expect("failed to compile");
+    ///
+    /// let settings: ScanSettings = Default::default();
+    /// let hit = scanner.scan_file("test_data/files/good_file", &settings).expect("expected scan to succeed");
+    ///
+    /// match hit {
+    ///     ScanResult::Virus(name) => println!("Virus {}", name),
+    ///     ScanResult::Clean => println!("Clean"),
+    ///     ScanResult::Whitelisted => println!("Whitelisted file"),
+    ///     ScanResult::Suppressed { original } => println!("Suppressed {}", original),
+    ///     ScanResult::Suspicious(limit) => println!("Suspicious {}", limit),
+    ///     ScanResult::Encrypted { container } => println!("Encrypted {}", container),
+    ///     ScanResult::NotScanned(reason) => println!("Not scanned: {}", reason),
+    ///     other => println!("{}", other),
+    /// }
+    /// ```
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let scanner = Engine::new().expect("failed to create engine");
+    /// scanner.load_databases("test_data/database/").expect("failed to load");
+    /// scanner.compile().expect("failed to compile");
+    ///
+    /// let settings = ScanSettingsBuilder::new()
+    ///     .enable_pdf()
+    ///     .block_broken_executables()
+    ///     .build();
+    /// println!("Using settings {}", settings.to_string());
+    /// let hit = scanner.scan_file("test_data/files/good_file", &settings).expect("expected scan to succeed");
+    ///
+    /// match hit {
+    ///     ScanResult::Virus(name) => println!("Virus {}", name),
+    ///     ScanResult::Clean => println!("Clean"),
+    ///     ScanResult::Whitelisted => println!("Whitelisted file"),
+    ///     ScanResult::Suppressed { original } => println!("Suppressed {}", original),
+    ///     ScanResult::Suspicious(limit) => println!("Suspicious {}", limit),
+    ///     ScanResult::Encrypted { container } => println!("Encrypted {}", container),
+    ///     ScanResult::NotScanned(reason) => println!("Not scanned: {}", reason),
+    ///     other => println!("{}", other),
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the scan fails.
+    /// The [`ClamError`] returned will contain the error code.
+    ///
+    /// [`ClamError`]: struct.ClamError.html
+    pub fn scan_file(&self, path: &str, settings: &ScanSettings) -> Result<ScanResult, ClamError> {
+        self.scan_file_outcome(path, settings)
+            .map(|outcome| outcome.result)
+    }
+
+    /// Same as [`scan_file`], making `context` available to the engine
+    /// callbacks through [`with_scan_context`] while the scan runs.
+    ///
+    /// The context is only borrowed for the duration of the call, so
+    /// callbacks never see it after the scan returned. Scans started by a
+    /// callback do not see the context unless they pass it on themselves.
+    ///
+    /// [`scan_file`]: #method.scan_file
+    /// [`with_scan_context`]: fn.with_scan_context.html
+    pub fn scan_file_with_context<C>(&self, path: &str, settings: &ScanSettings, context: &mut C) -> Result<ScanResult, ClamError> {
+        let _context = UserContextGuard::enter(context as *mut C as *mut c_void);
+        self.scan_file(path, settings)
+    }
+
+    /// Scans a file in all-match mode and returns the signature names of
+    /// every detection, in the order libclamav reported them, or an empty
+    /// list for clean files.
+    ///
+    /// `CL_SCAN_GENERAL_ALLMATCHES` is enabled for this scan regardless of
+    /// `settings`. The detections are collected per scan, so concurrent scans
+    /// and the callback set with [`set_virus_found_callback`] are not
+    /// affected. A file may be listed under the same name more than once,
+    /// e.g. if it holds several matching archive members. For engines not
+    /// compiled by this crate, only the last detection is returned. Names are
+    /// the lossy UTF-8 `Detection::name`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    ///
+    /// let settings = ScanSettings::default();
+    /// let found = engine.scan_file_all_matches("test_data/files/naughty_file", &settings).expect("scan failed");
+    /// assert_eq!(found, vec!["naughty_file.UNOFFICIAL"]);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the scan, or `EngineStateError::NoDatabase` if the
+    /// engine holds no signatures, see [`set_allow_empty`].
+    ///
+    /// [`set_virus_found_callback`]: #method.set_virus_found_callback
+    /// [`set_allow_empty`]: #method.set_allow_empty
+    pub fn scan_file_all_matches(&self, path: &str, settings: &ScanSettings) -> Result<Vec<String>, ClamError> {
+        let settings = Overrides::new().all_matches(true).apply(settings);
+        let mut context = ScanContext {
+            matches: Some(Vec::new()),
+            ..Default::default()
+        };
+        let outcome = self.scan_file_traced(path, &settings, Some(&mut context))?;
+        let mut matches = context.matches.take().unwrap_or_default();
+        match outcome.result {
+            ScanResult::Virus(detection) if matches.is_empty() => matches.push(detection),
+            ScanResult::Virus(_) => {}
+            ScanResult::NotScanned(_) => return Err(EngineStateError::NoDatabase.into()),
+            _ => matches.clear(),
+        }
+        Ok(matches.into_iter().map(|detection| detection.name).collect())
+    }
+
+    /// Scans a file like [`scan_file`], only telling whether it is infected.
+    ///
+    /// This is meant for hot loops over many mostly clean files: the
+    /// detection name is not read, no `tracing` span is opened and no
+    /// performance info is collected, so a clean scan makes no heap
+    /// allocations in this crate once the calling thread scanned a path of
+    /// similar length. Engine callbacks still run and may allocate.
+    ///
+    /// Whitelisted files are reported as not infected.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EARG` if `path` contains a NUL byte, or the error of the scan.
+    ///
+    /// [`scan_file`]: #method.scan_file
+    pub fn scan_file_quiet(&self, path: &str, settings: &ScanSettings) -> Result<bool, ClamError> {
+        let handle = self.handle()?;
+        self.require_compiled()?;
+        if self.empty_outcome().is_some() {
+            return Err(EngineStateError::NoDatabase.into());
+        }
+        let mut context = ScanContext {
+            hooks: self.hooks(),
+            ..Default::default()
+        };
+        let mut scan_options = settings.settings;
+        let mut virname: *const c_char = ptr::null();
+        let mut scanned: ScannedBlocks = 0;
+        let result = with_c_path(path, |raw_path| unsafe {
+            clamav_sys::cl_scanfile_callback(
+                raw_path.as_ptr(),
+                &mut virname,
+                &mut scanned,
+                handle.as_ptr(),
+                &mut scan_options,
+                context.as_raw(),
+            )
+        })?;
+        match result {
+            cl_error_t::CL_CLEAN | cl_error_t::CL_BREAK => Ok(false),
+            cl_error_t::CL_VIRUS => Ok(true),
+            _ => Err(ClamError::new(result)),
+        }
+    }
+
+    /// Same as [`scan_file`], additionally reporting the number of bytes scanned.
+    ///
+    /// [`scan_file`]: #method.scan_file
+    pub fn scan_file_outcome(&self, path: &str, settings: &ScanSettings) -> Result<ScanOutcome, ClamError> {
+        self.scan_file_traced(path, settings, None)
+    }
+
+    /// Same as [`scan_file_outcome`], scanning with `overrides` applied to a
+    /// copy of `settings`, see [`Overrides`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    /// use clamav_rs::scan_settings::Overrides;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    ///
+    /// let settings = ScanSettings::default();
+    /// let outcome = engine
+    ///     .scan_file_outcome_with("test_data/files/naughty_file", &settings, Overrides::new().all_matches(true))
+    ///     .expect("scan failed");
+    /// assert!(outcome.result.found());
+    /// ```
+    ///
+    /// [`scan_file_outcome`]: #method.scan_file_outcome
+    /// [`Overrides`]: ../scan_settings/struct.Overrides.html
+    pub fn scan_file_outcome_with(&self, path: &str, settings: &ScanSettings, overrides: Overrides) -> Result<ScanOutcome, ClamError> {
+        self.scan_file_outcome(path, &overrides.apply(settings))
+    }
+
+    fn scan_file_traced(&self, path: &str, settings: &ScanSettings, context: Option<&mut ScanContext>) -> Result<ScanOutcome, ClamError> {
+        let span = ScanSpan::for_path("scan_file", path, settings);
+        let outcome = span.in_scope(|| self.scan_file_untraced(path, settings, context));
+        span.finish_outcome(&outcome);
+        outcome
+    }
+
+    fn scan_file_untraced(&self, path: &str, settings: &ScanSettings, context: Option<&mut ScanContext>) -> Result<ScanOutcome, ClamError> {
+        let mut own_context = ScanContext::default();
+        let context = context.unwrap_or(&mut own_context);
+        context.hooks = self.hooks();
+        let handle = self.handle()?;
+        self.require_compiled()?;
+        if let Some(outcome) = self.empty_outcome() {
+            return Ok(outcome);
+        }
+        // libclamav takes a mutable pointer, but never modifies the options
+        let mut scan_options = settings.settings;
+        unsafe {
+            let mut virname: *const c_char = ptr::null();
+            let mut scanned: ScannedBlocks = 0;
+            let (result, performance) = with_c_path(path, |raw_path| {
+                perf::capture(settings, || {
+                    clamav_sys::cl_scanfile_callback(
+                        raw_path.as_ptr(),
+                        &mut virname,
+                        &mut scanned,
+                        handle.as_ptr(),
+                        &mut scan_options,
+                        context.as_raw(),
+                    )
+                })
+            })?;
+            Ok(ScanOutcome {
+                result: map_scan_result(result, virname)?,
+                bytes_scanned: scanned_bytes(scanned),
+                suppression: None,
+                performance,
+                from_cache: context.served_from_cache(),
+            })
+        }
+    }
+
+    /// Scans a file with parse flags tailored to its type.
+    ///
+    /// With [`SniffMode::Narrow`], the whole file is checked with the small
+    /// matcher in [`sniff`] and parse flags which cannot apply to the detected
+    /// type are removed from a copy of `settings` before scanning; e.g.
+    /// archive, PE and OLE2 parsing are skipped for plain text. Only a file
+    /// which is text throughout is narrowed, so a payload appended to a text
+    /// prefix is still parsed. If the file cannot be sniffed, or `mode` is
+    /// [`SniffMode::Off`], `settings` are used unchanged.
+    ///
+    /// The file is read once for sniffing and again for scanning, so a file
+    /// replaced in between may be scanned with the flags chosen for its
+    /// previous contents.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the scan fails.
+    /// The [`ClamError`] returned will contain the error code.
+    ///
+    /// [`sniff`]: ../sniff/index.html
+    /// [`SniffMode::Narrow`]: ../sniff/enum.SniffMode.html#variant.Narrow
+    /// [`SniffMode::Off`]: ../sniff/enum.SniffMode.html#variant.Off
+    /// [`ClamError`]: struct.ClamError.html
+    pub fn scan_file_auto(&self, path: &str, settings: &ScanSettings, mode: SniffMode) -> Result<ScanResult, ClamError> {
+        if mode == SniffMode::Off {
+            return self.scan_file(path, settings);
+        }
+        match sniff::sniff_file(Path::new(path)) {
+            Ok(kind) => {
+                let mut tailored = settings.clone();
+                tailored.settings.parse = kind.narrow(settings.settings.parse);
+                self.scan_file(path, &tailored)
+            }
+            Err(_) => self.scan_file(path, settings),
+        }
+    }
+
+    /// Scans a file by memory-mapping it and scanning the mapping.
+    ///
+    /// This avoids the descriptor handling of [`scan_file`] and is faster for large
+    /// numbers of small files. Empty files, special files (FIFOs, devices, ...) and
+    /// files which cannot be mapped are scanned through [`scan_file`] instead, so the
+    /// verdict is always the same as with the regular path.
+    ///
+    /// The file must not be truncated while it is being scanned, as accessing the
+    /// truncated part of the mapping raises `SIGBUS`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the scan fails.
+    /// The [`ClamError`] returned will contain the error code.
+    ///
+    /// [`scan_file`]: #method.scan_file
+    /// [`ClamError`]: struct.ClamError.html
+    #[cfg(feature = "mmap")]
+    pub fn scan_file_mmap(&self, path: &str, settings: &ScanSettings) -> Result<ScanResult, ClamError> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return self.scan_file(path, settings),
+        };
+        match file.metadata() {
+            Ok(metadata) if metadata.is_file() && metadata.len() > 0 => (),
+            _ => return self.scan_file(path, settings),
+        }
+        let mapping = match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(mapping) => mapping,
+            Err(_) => return self.scan_file(path, settings),
+        };
+        // `map` is declared after `mapping`, so it is closed before the memory is unmapped
+        let map = match Fmap::new_from_memory(mapping.as_ptr(), mapping.len() as u64) {
+            Ok(map) => map,
+            Err(_) => return self.scan_file(path, settings),
+        };
+        self.scan_map(&map, Some(path), settings)
+    }
+
+    /// Scans a file in successive, overlapping windows.
+    ///
+    /// Each window is mapped from the file with a handle-backed [`Fmap`] and
+    /// scanned separately, so engine limits such as the maximum scan size apply
+    /// per window instead of to the whole file. This allows covering very large
+    /// files, e.g. disk images, completely.
+    ///
+    /// # Limitations
+    ///
+    /// Every window is scanned as an independent object:
+    ///
+    /// * a signature spanning a window boundary is only found if it fits
+    ///   completely into the overlap, i.e. is at most `overlap` bytes long,
+    /// * a detection inside the overlap may be reported for both windows,
+    /// * signatures anchored to the file start or end, hash signatures, and
+    ///   parsing of containers spanning several windows only work for the
+    ///   window they happen to fall into.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EARG` if `overlap` is not smaller than `window_size`,
+    /// `CL_EOPEN`/`CL_ESTAT` if the file cannot be opened, `CL_EMAP` if a
+    /// window cannot be mapped, or the first error of a window scan.
+    ///
+    /// [`Fmap`]: ../fmap/struct.Fmap.html
+    pub fn scan_file_windowed(
+        &self,
+        path: &str,
+        settings: &ScanSettings,
+        config: WindowConfig,
+    ) -> Result<WindowedScanReport, ClamError> {
+        let file = File::open(path).map_err(|_| ClamError::new(cl_error_t::CL_EOPEN))?;
+        let size = file
+            .metadata()
+            .map_err(|_| ClamError::new(cl_error_t::CL_ESTAT))?
+            .len();
+        self.scan_region_windowed(&file, 0, size, Some(path), settings, config)
+    }
+
+    /// Scans `len` bytes of `file` starting at `start` in windows, see
+    /// [`scan_file_windowed`]. Detection offsets are relative to the file.
+    ///
+    /// [`scan_file_windowed`]: #method.scan_file_windowed
+    pub(crate) fn scan_region_windowed(
+        &self,
+        file: &File,
+        start: u64,
+        len: u64,
+        filename: Option<&str>,
+        settings: &ScanSettings,
+        config: WindowConfig,
+    ) -> Result<WindowedScanReport, ClamError> {
+        if config.overlap >= config.window_size {
+            return Err(ClamError::new(cl_error_t::CL_EARG));
+        }
+        #[cfg(unix)]
+        let handle = std::os::unix::io::AsRawFd::as_raw_fd(file);
+        #[cfg(windows)]
+        let handle = std::os::windows::io::AsRawHandle::as_raw_handle(file);
+
+        let end = start + len;
+        let step = config.window_size - config.overlap;
+        let mut report = WindowedScanReport::default();
+        let mut offset = start;
+        while offset < end {
+            let len = std::cmp::min(config.window_size, end - offset);
+            let map = Fmap::new_from_handle(handle, offset, len, false)
+                .map_err(|_| ClamError::new(cl_error_t::CL_EMAP))?;
+            if let ScanResult::Virus(name) = self.scan_map(&map, filename, settings)? {
+                report.detections.push(WindowDetection { offset, len, name });
+            }
+            report.windows += 1;
+            if offset + len >= end {
+                break;
+            }
+            offset += step;
+        }
+        Ok(report)
+    }
+
+    /// Scans a descriptor with the previously loaded and compiled definitions.
+    ///
+    /// This function will scan the given descriptor with the the database definitions
+    /// loaded and compiled.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EARG` if `filename` contains a NUL byte, or the error of
+    /// the scan.
+    pub fn scan_descriptor(&self, descriptor: i32, settings: &ScanSettings, filename: Option< &str >) -> Result<ScanResult, ClamError> {
+        self.scan_descriptor_outcome(descriptor, settings, filename)
+            .map(|outcome| outcome.result)
+    }
+
+    /// Same as [`scan_descriptor`], additionally reporting the number of bytes scanned.
+    ///
+    /// libclamav counts in blocks of `CL_COUNT_PRECISION` bytes, so the count
+    /// is rounded to whole blocks.
+    ///
+    /// [`scan_descriptor`]: #method.scan_descriptor
+    pub fn scan_descriptor_outcome(&self, descriptor: i32, settings: &ScanSettings, filename: Option< &str >) -> Result<ScanOutcome, ClamError> {
+        self.scan_descriptor_traced(descriptor, settings, filename, None)
+    }
+
+    /// Same as [`scan_descriptor_outcome`], scanning with `overrides` applied
+    /// to a copy of `settings`, see [`Overrides`].
+    ///
+    /// [`scan_descriptor_outcome`]: #method.scan_descriptor_outcome
+    /// [`Overrides`]: ../scan_settings/struct.Overrides.html
+    pub fn scan_descriptor_outcome_with(&self, descriptor: i32, settings: &ScanSettings, filename: Option<&str>, overrides: Overrides) -> Result<ScanOutcome, ClamError> {
+        self.scan_descriptor_outcome(descriptor, &overrides.apply(settings), filename)
+    }
+
+    fn scan_descriptor_traced(&self, descriptor: i32, settings: &ScanSettings, filename: Option<&str>, context: Option<&mut ScanContext>) -> Result<ScanOutcome, ClamError> {
+        let span = ScanSpan::new("scan_descriptor", filename, None, settings);
+        let outcome = span.in_scope(|| self.scan_descriptor_untraced(descriptor, settings, filename, context));
+        span.finish_outcome(&outcome);
+        outcome
+    }
+
+    fn scan_descriptor_untraced(&self, descriptor: i32, settings: &ScanSettings, filename: Option< &str >, context: Option<&mut ScanContext>) -> Result<ScanOutcome, ClamError> {
+        let mut own_context = ScanContext::default();
+        let context = context.unwrap_or(&mut own_context);
+        context.hooks = self.hooks();
+        let handle = self.handle()?;
+        self.require_compiled()?;
+        if let Some(outcome) = self.empty_outcome() {
+            return Ok(outcome);
+        }
+        let filename_cstr = match filename {
+            Some(name) => Some(CString::new(name).map_err(|_| ClamError::new(cl_error_t::CL_EARG))?),
+            None => None,
+        };
+        let mut scan_options = settings.settings;
+        unsafe {
+            let mut virname: *const c_char = ptr::null();
+            let mut scanned: ScannedBlocks = 0;
+            let (result, performance) = perf::capture(settings, || {
+                clamav_sys::cl_scandesc_callback(
+                    descriptor,
+                    filename_cstr.as_ref().map_or(ptr::null(), |x| x.as_ptr()),
+                    &mut virname,
+                    &mut scanned,
+                    handle.as_ptr(),
+                    &mut scan_options,
+                    context.as_raw(),
+                )
+            });
+            Ok(ScanOutcome {
+                result: map_scan_result(result, virname)?,
+                bytes_scanned: scanned_bytes(scanned),
+                suppression: None,
+                performance,
+                from_cache: context.served_from_cache(),
+            })
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn scan_fileobj<T: std::os::unix::io::AsRawFd>(&self, file: &T, settings: &ScanSettings, filename: Option< &str >) -> Result<ScanResult, ClamError>
+    {
+        self.scan_descriptor(file.as_raw_fd(), settings, filename)
+    }
+
+    #[cfg(windows)]
+    pub fn scan_fileobj<T: std::os::windows::io::AsRawHandle>(&self, file: &T, settings: &ScanSettings, filename: Option< &str >) -> Result<ScanResult, ClamError> {
+        self.scan_fileobj_outcome(file, settings, filename)
+            .map(|outcome| outcome.result)
+    }
+
+    /// Same as [`scan_fileobj`], additionally reporting the number of bytes scanned.
+    ///
+    /// [`scan_fileobj`]: #method.scan_fileobj
+    #[cfg(unix)]
+    pub fn scan_fileobj_outcome<T: std::os::unix::io::AsRawFd>(&self, file: &T, settings: &ScanSettings, filename: Option< &str >) -> Result<ScanOutcome, ClamError> {
+        self.scan_descriptor_outcome(file.as_raw_fd(), settings, filename)
+    }
+
+    /// Same as [`scan_fileobj`], additionally reporting the number of bytes scanned.
+    ///
+    /// [`scan_fileobj`]: #method.scan_fileobj
+    #[cfg(windows)]
+    pub fn scan_fileobj_outcome<T: std::os::windows::io::AsRawHandle>(&self, file: &T, settings: &ScanSettings, filename: Option< &str >) -> Result<ScanOutcome, ClamError> {
+        let fd = WindowsFd::new(file.as_raw_handle()).map_err(|_| ClamError::new(cl_error_t::CL_EARG))?; 
+        self.scan_descriptor_outcome(fd.raw(), settings, filename)
+    }
+
+    /// Same as [`scan_fileobj_outcome`], additionally returning the type
+    /// libclamav determined for the file, e.g. `CL_TYPE_PDF`.
+    ///
+    /// [`scan_fileobj_outcome`]: #method.scan_fileobj_outcome
+    #[cfg(unix)]
+    pub(crate) fn scan_fileobj_typed<T: std::os::unix::io::AsRawFd>(&self, file: &T, settings: &ScanSettings, filename: Option<&str>) -> (Result<ScanOutcome, ClamError>, Option<String>) {
+        let mut context = ScanContext {
+            record_type: true,
+            ..Default::default()
+        };
+        let outcome = self.scan_descriptor_traced(file.as_raw_fd(), settings, filename, Some(&mut context));
+        (outcome, context.file_type)
+    }
+
+    /// Same as [`scan_fileobj_outcome`], additionally returning the type
+    /// libclamav determined for the file, e.g. `CL_TYPE_PDF`.
+    ///
+    /// [`scan_fileobj_outcome`]: #method.scan_fileobj_outcome
+    #[cfg(windows)]
+    pub(crate) fn scan_fileobj_typed<T: std::os::windows::io::AsRawHandle>(&self, file: &T, settings: &ScanSettings, filename: Option<&str>) -> (Result<ScanOutcome, ClamError>, Option<String>) {
+        let fd = match WindowsFd::new(file.as_raw_handle()) {
+            Ok(fd) => fd,
+            Err(_) => return (Err(ClamError::new(cl_error_t::CL_EARG)), None),
+        };
+        let mut context = ScanContext {
+            record_type: true,
+            ..Default::default()
+        };
+        let outcome = self.scan_descriptor_traced(fd.raw(), settings, filename, Some(&mut context));
+        (outcome, context.file_type)
+    }
+
+    /// Same as [`scan_fileobj_typed`], skipping the objects left to scan
+    /// once `interrupt` is due. The verdict of an interrupted scan is
+    /// incomplete and must be discarded.
+    ///
+    /// [`scan_fileobj_typed`]: #method.scan_fileobj_typed
+    #[cfg(unix)]
+    pub(crate) fn scan_fileobj_interruptible<T: std::os::unix::io::AsRawFd>(&self, file: &T, settings: &ScanSettings, filename: Option<&str>, interrupt: Interrupt) -> (Result<ScanOutcome, ClamError>, Option<String>) {
+        let mut context = ScanContext {
+            record_type: true,
+            interrupt,
+            ..Default::default()
+        };
+        let outcome = self.scan_descriptor_traced(file.as_raw_fd(), settings, filename, Some(&mut context));
+        (outcome, context.file_type)
+    }
+
+    /// Same as [`scan_fileobj_typed`], skipping the objects left to scan
+    /// once `interrupt` is due. The verdict of an interrupted scan is
+    /// incomplete and must be discarded.
+    ///
+    /// [`scan_fileobj_typed`]: #method.scan_fileobj_typed
+    #[cfg(windows)]
+    pub(crate) fn scan_fileobj_interruptible<T: std::os::windows::io::AsRawHandle>(&self, file: &T, settings: &ScanSettings, filename: Option<&str>, interrupt: Interrupt) -> (Result<ScanOutcome, ClamError>, Option<String>) {
+        let fd = match WindowsFd::new(file.as_raw_handle()) {
+            Ok(fd) => fd,
+            Err(_) => return (Err(ClamError::new(cl_error_t::CL_EARG)), None),
+        };
+        let mut context = ScanContext {
+            record_type: true,
+            interrupt,
+            ..Default::default()
+        };
+        let outcome = self.scan_descriptor_traced(fd.raw(), settings, filename, Some(&mut context));
+        (outcome, context.file_type)
+    }
+
+    /// Scans a memory buffer with the previously loaded and compiled definitions.
+    ///
+    /// The buffer is mapped with [`Fmap::new_from_memory`] for the duration
+    /// of the call and scanned in place, e.g. the body of an upload, without
+    /// writing it to a temporary file first.
+    ///
+    /// `filename` names the buffer in libclamav, in the `tracing` span of the
+    /// scan and for callbacks, see [`scan_name`]. It is sanitized with
+    /// [`SanitizedName`] first.
+    ///
+    /// [`Fmap::new_from_memory`]: ../fmap/struct.Fmap.html#method.new_from_memory
+    /// [`scan_name`]: fn.scan_name.html
+    /// [`SanitizedName`]: struct.SanitizedName.html
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EMAP` if the buffer cannot be mapped, or the error of the scan.
+    pub fn scan_mem(&self, data: &[u8], filename: Option<&str>, settings: &ScanSettings) -> Result<ScanResult, ClamError> {
+        self.scan_mem_outcome(data, filename, settings)
+            .map(|outcome| outcome.result)
+    }
+
+    /// Same as [`scan_mem`], additionally reporting the number of bytes scanned.
+    ///
+    /// [`scan_mem`]: #method.scan_mem
+    pub fn scan_mem_outcome(&self, data: &[u8], filename: Option<&str>, settings: &ScanSettings) -> Result<ScanOutcome, ClamError> {
+        let map = Fmap::new_from_memory(data.as_ptr(), data.len() as u64)
+            .map_err(|_| ClamError::new(cl_error_t::CL_EMAP))?;
+        self.scan_map_traced("scan_mem", &map, filename, settings, Some(data.len() as u64), None)
+    }
+
+    /// Same as [`scan_mem_outcome`], additionally returning the type
+    /// libclamav determined for the buffer, e.g. `CL_TYPE_PDF`.
+    ///
+    /// [`scan_mem_outcome`]: #method.scan_mem_outcome
+    pub(crate) fn scan_mem_typed(&self, data: &[u8], filename: Option<&str>, settings: &ScanSettings) -> (Result<ScanOutcome, ClamError>, Option<String>) {
+        let map = match Fmap::new_from_memory(data.as_ptr(), data.len() as u64) {
+            Ok(map) => map,
+            Err(_) => return (Err(ClamError::new(cl_error_t::CL_EMAP)), None),
+        };
+        let mut context = ScanContext {
+            record_type: true,
+            ..Default::default()
+        };
+        let outcome = self.scan_map_traced("scan_mem", &map, filename, settings, Some(data.len() as u64), Some(&mut context));
+        (outcome, context.file_type)
+    }
+
+    /// Same as [`scan_mem_outcome`], scanning with `overrides` applied to a
+    /// copy of `settings`, see [`Overrides`].
+    ///
+    /// [`scan_mem_outcome`]: #method.scan_mem_outcome
+    /// [`Overrides`]: ../scan_settings/struct.Overrides.html
+    pub fn scan_mem_outcome_with(&self, data: &[u8], filename: Option<&str>, settings: &ScanSettings, overrides: Overrides) -> Result<ScanOutcome, ClamError> {
+        self.scan_mem_outcome(data, filename, &overrides.apply(settings))
+    }
+
+    /// Reads `reader` to its end and scans the contents, e.g. of a socket
+    /// or a decompressor.
+    ///
+    /// Streams of up to `spill_threshold` bytes are buffered in memory and
+    /// scanned like [`scan_mem`]. Longer streams are written to a temporary
+    /// file of the engine's [`TempStrategy`], held against the quota set
+    /// with [`set_temp_quota`], and scanned from there; the file is removed
+    /// when the scan finishes. An empty stream is reported clean without
+    /// calling libclamav, which cannot map empty buffers.
+    ///
+    /// `filename` names the stream as in [`scan_mem`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let engine = Engine::new().expect("failed to create engine");
+    /// engine.load_databases("test_data/database/").expect("failed to load");
+    /// engine.compile().expect("failed to compile");
+    ///
+    /// let mut stream = std::io::Cursor::new(b"streamed upload".to_vec());
+    /// let result = engine
+    ///     .scan_reader(&mut stream, Some("upload.bin"), &ScanSettings::default(), 16 * 1024 * 1024)
+    ///     .expect("scan failed");
+    /// assert_eq!(result, ScanResult::Clean);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EREAD` if reading fails, `CL_ETMPFILE` if the temporary
+    /// file cannot be written or would exceed the quota, or the error of the
+    /// scan.
+    ///
+    /// [`scan_mem`]: #method.scan_mem
+    /// [`TempStrategy`]: ../temp/trait.TempStrategy.html
+    /// [`set_temp_quota`]: #method.set_temp_quota
+    pub fn scan_reader<R: Read>(&self, reader: &mut R, filename: Option<&str>, settings: &ScanSettings, spill_threshold: u64) -> Result<ScanResult, ClamError> {
+        self.scan_reader_outcome(reader, filename, settings, spill_threshold)
+            .map(|outcome| outcome.result)
+    }
+
+    /// Same as [`scan_reader`], additionally reporting the number of bytes scanned.
+    ///
+    /// [`scan_reader`]: #method.scan_reader
+    pub fn scan_reader_outcome<R: Read>(&self, reader: &mut R, filename: Option<&str>, settings: &ScanSettings, spill_threshold: u64) -> Result<ScanOutcome, ClamError> {
+        let mut head = Vec::new();
+        // one byte past the threshold tells whether the stream goes on
+        reader
+            .by_ref()
+            .take(spill_threshold.saturating_add(1))
+            .read_to_end(&mut head)
+            .map_err(|_| ClamError::new(cl_error_t::CL_EREAD))?;
+        if head.is_empty() {
+            self.handle()?;
+            self.require_compiled()?;
+            return Ok(self.empty_outcome().unwrap_or(ScanOutcome {
+                result: ScanResult::Clean,
+                bytes_scanned: 0,
+                suppression: None,
+                performance: None,
+                from_cache: None,
+            }));
+        }
+        if head.len() as u64 <= spill_threshold {
+            return self.scan_mem_outcome(&head, filename, settings);
+        }
+        let spilled = self.temp.copy_stream(".stream", &head, reader)?;
+        drop(head);
+        let mut file = spilled.file();
+        file.seek(SeekFrom::Start(0))
+            .map_err(|_| ClamError::new(cl_error_t::CL_ESEEK))?;
+        self.scan_fileobj_outcome(file, settings, filename)
+    }
+
+    /// @brief Scan custom data.
+    /// @param map           Buffer to be scanned, in form of a cl_fmap_t.
+    /// @param filename      Name of data origin. Does not need to be an actual
+    ///                      file on disk. May be None if a name is not available.
+    ///                      Sanitized and made available to callbacks as for
+    ///                      `scan_mem`.
+    /// @param engine        The scanning engine.
+    /// @param scanoptions   The scanning options.
+    pub fn scan_map<M: AsFmap + ?Sized>(&self, map: &M, filename: Option<&str>, settings: &ScanSettings) -> Result<ScanResult, ClamError> {
+        self.scan_map_outcome(map, filename, settings)
+            .map(|outcome| outcome.result)
+    }
+
+    /// Same as [`scan_map`], additionally reporting the number of bytes scanned.
+    ///
+    /// [`scan_map`]: #method.scan_map
+    pub fn scan_map_outcome<M: AsFmap + ?Sized>(&self, map: &M, filename: Option<&str>, settings: &ScanSettings) -> Result<ScanOutcome, ClamError> {
+        self.scan_map_traced("scan_map", map.as_fmap(), filename, settings, None, None)
+    }
+
+    /// Same as [`scan_map_outcome`], scanning with `overrides` applied to a
+    /// copy of `settings`, see [`Overrides`].
+    ///
+    /// [`scan_map_outcome`]: #method.scan_map_outcome
+    /// [`Overrides`]: ../scan_settings/struct.Overrides.html
+    pub fn scan_map_outcome_with<M: AsFmap + ?Sized>(&self, map: &M, filename: Option<&str>, settings: &ScanSettings, overrides: Overrides) -> Result<ScanOutcome, ClamError> {
+        self.scan_map_outcome(map, filename, &overrides.apply(settings))
+    }
+
+    /// Same as [`scan_map_outcome`], skipping the objects left to scan once
+    /// `interrupt` is due, and additionally returning the type libclamav
+    /// determined for the map. The verdict of an interrupted scan is
+    /// incomplete and must be discarded.
+    ///
+    /// [`scan_map_outcome`]: #method.scan_map_outcome
+    pub(crate) fn scan_map_interruptible(&self, map: &Fmap, settings: &ScanSettings, interrupt: Interrupt) -> (Result<ScanOutcome, ClamError>, Option<String>) {
+        let mut context = ScanContext {
+            record_type: true,
+            interrupt,
+            ..Default::default()
+        };
+        let outcome = self.scan_map_traced("scan_map", map, None, settings, None, Some(&mut context));
+        (outcome, context.file_type)
+    }
+
+    fn scan_map_traced(&self, entry: &'static str, map: &Fmap, filename: Option<&str>, settings: &ScanSettings, size: Option<u64>, context: Option<&mut ScanContext>) -> Result<ScanOutcome, ClamError> {
+        let name = filename.map(SanitizedName::new);
+        let span = ScanSpan::new(entry, name.as_ref().map(SanitizedName::as_str), size, settings);
+        let outcome = span.in_scope(|| self.scan_map_untraced(map, name, settings, context));
+        span.finish_outcome(&outcome);
+        outcome
+    }
+
+    fn scan_map_untraced(&self, map: &Fmap, name: Option<SanitizedName>, settings: &ScanSettings, context: Option<&mut ScanContext>) -> Result<ScanOutcome, ClamError> {
+        let handle = self.handle()?;
+        self.require_compiled()?;
+        if let Some(outcome) = self.empty_outcome() {
+            return Ok(outcome);
+        }
+        let mut own_context = ScanContext::default();
+        let context = context.unwrap_or(&mut own_context);
+        context.hooks = self.hooks();
+        let mut virname: *const c_char = ptr::null();
+        let c_filename = name.as_ref().map(SanitizedName::to_c_string);
+        let _name = ScanNameGuard::enter(name);
+        let mut scan_options = settings.settings;
+        let mut scanned: ScannedBlocks = 0;
+        let (result, performance) = perf::capture(settings, || unsafe {
+            clamav_sys::cl_scanmap_callback(
+                map.raw(),
+                c_filename.as_ref().map_or(ptr::null(), |n| n.as_ptr()),
+                &mut virname,
+                &mut scanned,
+                handle.as_ptr(),
+                &mut scan_options,
+                context.as_raw())
+        });
+        Ok(ScanOutcome {
+            result: map_scan_result(result, virname)?,
+            bytes_scanned: scanned_bytes(scanned),
+            suppression: None,
+            performance,
+            from_cache: context.served_from_cache(),
+        })
+    }
+
+    fn get(&self, field: cl_engine_field) -> Result<EngineValue, ClamError> {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        unsafe {
+            match get_field_type(field) {
+                EngineValueType::U32 => {
+                    let mut err: c_int = 0;
+                    let value = cl_engine_get_num(handle.as_ptr(), field, &mut err) as u32;
+                    if err != 0 {
+                        Err(ClamError::from_code(err))
+                    }
+                    else {
+                        Ok(EngineValue::U32(value))
+                    }
+                },
+                EngineValueType::U64 => {
+                    let mut err: c_int = 0;
+                    let value = cl_engine_get_num(handle.as_ptr(), field, &mut err) as u64;
+                    if err != 0 {
+                        Err(ClamError::from_code(err))
+                    }
+                    else {
+                        Ok(EngineValue::U64(value))
+                    }
+                },
+                EngineValueType::String => {
+                    let mut err = 0;
+                    let value = cl_engine_get_str(handle.as_ptr(), field, &mut err);
+                    if err != 0 {
+                        Err(ClamError::from_code(err))
+                    }
+                    else if value.is_null() {
+                        // unset, e.g. CL_ENGINE_TMPDIR before it was set
+                        Ok(EngineValue::String(String::new()))
+                    }
+                    else {
+                        Ok(EngineValue::String(CStr::from_ptr(value).to_string_lossy().into_owned()))
+                    }
+                },
+                EngineValueType::Time => {
+                    let mut err = 0;
+                    let value = cl_engine_get_num(handle.as_ptr(), field, &mut err) as time_t;
+                    if err != 0 {
+                        Err(ClamError::from_code(err))
+                    }
+                    else {
+                        Ok(EngineValue::Time(ClamTime(value)))
+                    }
+                },
+            }
+        }
+    }
+
+    fn set(&self, field: cl_engine_field, value: EngineValue) -> Result<(), ClamError> {
+        let handle = self.handle()?;
+        let _config = self.lock_config();
+        if is_load_time_field(field) {
+            self.require_uncompiled()?;
+        }
+        let expected_type = get_field_type(field);
+        let actual_type = match &value {
+            EngineValue::U32(_) => EngineValueType::U32,
+            EngineValue::U64(_) => EngineValueType::U64,
+            EngineValue::String(_) => EngineValueType::String,
+            EngineValue::Time(_) => EngineValueType::Time,
+        };
+
+        if expected_type != actual_type {
+            return Err(ClamError::new(cl_error_t::CL_EARG));
+        }
+
+        unsafe {
+            match value {
+                EngineValue::U32(val) => {
+                    let err = cl_engine_set_num(handle.as_ptr(), field, val as i64);
+                    if err != cl_error_t::CL_SUCCESS {
+                        Err(ClamError::new(err))
+                    }
+                    else {
+                        Ok(())
+                    }
+                },
+                EngineValue::U64(val) => {
+                    let err = cl_engine_set_num(handle.as_ptr(), field, val as i64);
                     if err != cl_error_t::CL_SUCCESS {
                         Err(ClamError::new(err))
                     }
@@ -379,8 +3276,8 @@ impl Engine {
                     }
                 },
                 EngineValue::String(val) => {
-                    let val = CString::new(val).unwrap();
-                    let err = cl_engine_set_str(self.handle, field, val.as_ptr());
+                    let val = CString::new(val).map_err(|_| ClamError::new(cl_error_t::CL_EARG))?;
+                    let err = cl_engine_set_str(handle.as_ptr(), field, val.as_ptr());
                     if err != cl_error_t::CL_SUCCESS {
                         Err(ClamError::new(err))
                     }
@@ -389,122 +3286,1639 @@ impl Engine {
                     }
                 },
                 EngineValue::Time(ClamTime(val)) => {
-                    let err = cl_engine_set_num(self.handle, field, val as i64);
+                    let err = cl_engine_set_num(handle.as_ptr(), field, val);
                     if err != cl_error_t::CL_SUCCESS {
                         Err(ClamError::new(err))
                     }
                     else {
                         Ok(())
                     }
-                },
-            }
+                },
+            }
+        }
+    }
+
+    /// Returns the version, build time and signature count of the loaded databases
+    ///
+    /// The signature count is the sum reported by all loads into this engine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use clamav_rs::prelude::*;
+    ///
+    /// clamav_rs::initialize().expect("failed to initialize");
+    /// let scanner = Engine::new().expect("failed to create engine");
+    /// scanner.load_databases("test_data/database/").expect("failed to load");
+    /// let status = scanner.database_stats().expect("no databases loaded");
+    /// println!("version {} is {:?} old", status.version, status.age);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `EngineStateError::NoDatabase` if no versioned database is loaded.
+    pub fn database_stats(&self) -> Result<DatabaseStatus, ClamError> {
+        let version = self.database_version()?;
+        if version == 0 {
+            return Err(EngineStateError::NoDatabase.into());
+        }
+        let timestamp = self.database_timestamp()?;
+        let age = timestamp
+            .and_then(|timestamp| timestamp.elapsed().ok())
+            .unwrap_or_default();
+        Ok(DatabaseStatus {
+            version,
+            timestamp,
+            signatures: self.signature_count.load(Ordering::Relaxed),
+            age,
+        })
+    }
+
+    pub fn database_version(&self) -> Result<u32, ClamError> {
+        if let EngineValue::U32(value) = self.get(cl_engine_field::CL_ENGINE_DB_VERSION)? {
+            Ok(value)
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+
+    /// Returns the build time of the loaded databases, or `None` if no
+    /// database has set it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EFORMAT` if the timestamp is out of the range of `SystemTime`.
+    pub fn database_timestamp(&self) -> Result<Option<time::SystemTime>, ClamError> {
+        if let EngineValue::Time(value) = self.get(cl_engine_field::CL_ENGINE_DB_TIME)? {
+            value
+                .as_system_time()
+                .map_err(|_| ClamError::new(cl_error_t::CL_EFORMAT))
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+
+
+    pub fn set_max_filesize(&self, max_filesize: u64) -> Result<(), ClamError> {
+        self.set(cl_engine_field::CL_ENGINE_MAX_FILESIZE, EngineValue::U64(max_filesize))
+    }
+
+    pub fn max_filesize(&self) -> Result<u64, ClamError> {
+        if let EngineValue::U64(value) = self.get(cl_engine_field::CL_ENGINE_MAX_FILESIZE)? {
+            Ok(value)
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+
+    pub fn set_max_files(&self, max_files: u32) -> Result<(), ClamError> {
+        self.set(cl_engine_field::CL_ENGINE_MAX_FILES, EngineValue::U32(max_files))
+    }
+
+    pub fn max_files(&self) -> Result<u32, ClamError> {
+        if let EngineValue::U32(value) = self.get(cl_engine_field::CL_ENGINE_MAX_FILES)? {
+            Ok(value)
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+
+    pub fn set_max_recursion(&self, max_recursion: u32) -> Result<(), ClamError> {
+        self.set(cl_engine_field::CL_ENGINE_MAX_RECURSION, EngineValue::U32(max_recursion))
+    }
+
+    pub fn max_recursion(&self) -> Result<u32, ClamError> {
+        if let EngineValue::U32(value) = self.get(cl_engine_field::CL_ENGINE_MAX_RECURSION)? {
+            Ok(value)
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+
+    pub fn set_max_partitions(&self, max_partitions: u32) -> Result<(), ClamError> {
+        self.set(cl_engine_field::CL_ENGINE_MAX_PARTITIONS, EngineValue::U32(max_partitions))
+    }
+
+    pub fn max_partitions(&self) -> Result<u32, ClamError> {
+        if let EngineValue::U32(value) = self.get(cl_engine_field::CL_ENGINE_MAX_PARTITIONS)? {
+            Ok(value)
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+
+    pub fn set_max_scansize(&self, max_scansize: u64) -> Result<(), ClamError> {
+        self.set(cl_engine_field::CL_ENGINE_MAX_SCANSIZE, EngineValue::U64(max_scansize))
+    }
+
+    pub fn max_scansize(&self) -> Result<u64, ClamError> {
+        if let EngineValue::U64(value) = self.get(cl_engine_field::CL_ENGINE_MAX_SCANSIZE)? {
+            Ok(value)
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+
+    /// Limits the time libclamav spends on one scan, in milliseconds; 0
+    /// disables the limit. Scans exceeding it fail with `CL_ETIMEOUT`, or
+    /// report `Heuristics.Limits.Exceeded.MaxScanTime` with
+    /// `ScanSettingsBuilder::block_max_limit`.
+    pub fn set_max_scantime(&self, max_scantime: u32) -> Result<(), ClamError> {
+        self.set(cl_engine_field::CL_ENGINE_MAX_SCANTIME, EngineValue::U32(max_scantime))
+    }
+
+    pub fn max_scantime(&self) -> Result<u32, ClamError> {
+        if let EngineValue::U32(value) = self.get(cl_engine_field::CL_ENGINE_MAX_SCANTIME)? {
+            Ok(value)
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+
+    /// Sets the directory libclamav creates its temporary files in while
+    /// scanning, e.g. when unpacking archives, `CL_ENGINE_TMPDIR`. By
+    /// default libclamav uses the system's temporary directory.
+    ///
+    /// Temporary files of the crate itself are created by the engine's
+    /// [`TempStrategy`] instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EARG` if `path` is not valid UTF-8 or contains a NUL byte.
+    ///
+    /// [`TempStrategy`]: ../temp/trait.TempStrategy.html
+    pub fn set_tmp_dir(&self, path: &Path) -> Result<(), ClamError> {
+        let path = path.to_str().ok_or_else(|| ClamError::new(cl_error_t::CL_EARG))?;
+        self.set(cl_engine_field::CL_ENGINE_TMPDIR, EngineValue::String(path.to_string()))
+    }
+
+    /// Returns the directory set with [`set_tmp_dir`], or `None` if
+    /// libclamav uses the system's temporary directory.
+    ///
+    /// [`set_tmp_dir`]: #method.set_tmp_dir
+    pub fn tmp_dir(&self) -> Result<Option<PathBuf>, ClamError> {
+        match self.get(cl_engine_field::CL_ENGINE_TMPDIR)? {
+            EngineValue::String(dir) if dir.is_empty() => Ok(None),
+            EngineValue::String(dir) => Ok(Some(PathBuf::from(dir))),
+            _ => Err(ClamError::new(cl_error_t::CL_EARG)),
+        }
+    }
+
+    /// Sets whether libclamav keeps its temporary files after scanning,
+    /// `CL_ENGINE_KEEPTMP`, e.g. to inspect what it unpacked.
+    pub fn set_keep_temp_files(&self, keep: bool) -> Result<(), ClamError> {
+        self.set(cl_engine_field::CL_ENGINE_KEEPTMP, EngineValue::U32(keep.into()))
+    }
+
+    pub fn keep_temp_files(&self) -> Result<bool, ClamError> {
+        if let EngineValue::U32(value) = self.get(cl_engine_field::CL_ENGINE_KEEPTMP)? {
+            Ok(value != 0)
+        }
+        else {
+            Err(ClamError::new(cl_error_t::CL_EARG))
+        }
+    }
+}
+
+/// An engine which is being compiled on a background thread
+///
+/// Returned by [`Engine::compile_in_background`]. The engine itself is only
+/// available once compilation is done, through [`wait`] or [`wait_timeout`].
+///
+/// [`Engine::compile_in_background`]: struct.Engine.html#method.compile_in_background
+/// [`wait`]: #method.wait
+/// [`wait_timeout`]: #method.wait_timeout
+pub struct CompilingEngine {
+    state: Arc<(Mutex<CompileResult>, Condvar)>,
+}
+
+type CompileResult = Option<Result<Engine, ClamError>>;
+
+impl CompilingEngine {
+    /// Returns true once compilation has finished, successfully or not.
+    pub fn ready(&self) -> bool {
+        let (lock, _) = &*self.state;
+        lock.lock().unwrap().is_some()
+    }
+
+    /// Blocks until compilation has finished and returns the compiled engine.
+    ///
+    /// # Errors
+    ///
+    /// This function will return the error of [`Engine::compile`] if compilation failed.
+    ///
+    /// [`Engine::compile`]: struct.Engine.html#method.compile
+    pub fn wait(self) -> Result<Engine, ClamError> {
+        let (lock, cvar) = &*self.state;
+        let mut result = lock.lock().unwrap();
+        while result.is_none() {
+            result = cvar.wait(result).unwrap();
+        }
+        result.take().unwrap()
+    }
+
+    /// Blocks until compilation has finished or the timeout elapsed.
+    ///
+    /// If the timeout elapses first, the handle is given back in the `Err` variant
+    /// so that the caller can wait again later.
+    pub fn wait_timeout(self, timeout: time::Duration) -> Result<Result<Engine, ClamError>, CompilingEngine> {
+        let finished = {
+            let (lock, cvar) = &*self.state;
+            let (mut result, _) = cvar
+                .wait_timeout_while(lock.lock().unwrap(), timeout, |result| result.is_none())
+                .unwrap();
+            result.take()
+        };
+        match finished {
+            Some(result) => Ok(result),
+            None => Err(self),
+        }
+    }
+}
+
+fn is_yara_path(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("yar") || ext.eq_ignore_ascii_case("yara"),
+        None => false,
+    }
+}
+
+/// Shows the database state of the engine.
+///
+/// The signature count is the sum reported by all successful loads, so it is
+/// approximate when the same database was loaded twice. Settings which cannot
+/// be read, e.g. from an engine without handle, are shown as `None`.
+impl fmt::Debug for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Engine")
+            .field("handle", &self.handle)
+            .field("loaded", &self.loaded.load(Ordering::Relaxed))
+            .field("compiled", &self.compiled.load(Ordering::Relaxed))
+            .field("signature_count", &self.signature_count.load(Ordering::Relaxed))
+            .field("database_version", &self.database_version().ok())
+            .field("database_timestamp", &self.database_timestamp().ok().flatten())
+            .finish()
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe {
+                clamav_sys::cl_engine_free(self.handle);
+            }
+        }
+        // no scan can call the callbacks any more
+        *self.hooks.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = Hooks::default();
+        *self.progress.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+    }
+}
+
+
+/// Returns true for settings which libclamav only reads while loading or
+/// compiling databases, so changing them afterwards has no effect.
+fn is_load_time_field(field: cl_engine_field) -> bool {
+    matches!(
+        field,
+        cl_engine_field::CL_ENGINE_PUA_CATEGORIES
+            | cl_engine_field::CL_ENGINE_DB_OPTIONS
+            | cl_engine_field::CL_ENGINE_AC_ONLY
+            | cl_engine_field::CL_ENGINE_AC_MINDEPTH
+            | cl_engine_field::CL_ENGINE_AC_MAXDEPTH
+            | cl_engine_field::CL_ENGINE_BYTECODE_SECURITY
+            | cl_engine_field::CL_ENGINE_BYTECODE_MODE
+            | cl_engine_field::CL_ENGINE_DISABLE_CACHE
+    )
+}
+
+fn get_field_type(field: cl_engine_field) -> EngineValueType {
+    match field {
+        cl_engine_field::CL_ENGINE_MAX_SCANSIZE => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_MAX_FILESIZE => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_MAX_RECURSION => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_MAX_FILES => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_MIN_CC_COUNT => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_MIN_SSN_COUNT => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_PUA_CATEGORIES => EngineValueType::String,
+        cl_engine_field::CL_ENGINE_DB_OPTIONS => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_DB_VERSION => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_DB_TIME => EngineValueType::Time,
+        cl_engine_field::CL_ENGINE_AC_ONLY => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_AC_MINDEPTH => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_AC_MAXDEPTH => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_TMPDIR => EngineValueType::String,
+        cl_engine_field::CL_ENGINE_KEEPTMP => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_BYTECODE_SECURITY => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_BYTECODE_TIMEOUT => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_BYTECODE_MODE => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_MAX_EMBEDDEDPE => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_MAX_HTMLNORMALIZE => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_MAX_HTMLNOTAGS => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_MAX_SCRIPTNORMALIZE => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_MAX_ZIPTYPERCG => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_FORCETODISK => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_DISABLE_CACHE => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_DISABLE_PE_STATS => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_STATS_TIMEOUT => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_MAX_PARTITIONS => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_MAX_ICONSPE => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_MAX_RECHWP3 => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_MAX_SCANTIME => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_PCRE_MATCH_LIMIT => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_PCRE_RECMATCH_LIMIT => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_PCRE_MAX_FILESIZE => EngineValueType::U64,
+        cl_engine_field::CL_ENGINE_DISABLE_PE_CERTS => EngineValueType::U32,
+        cl_engine_field::CL_ENGINE_PE_DUMPCERTS => EngineValueType::U32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_settings::ScanSettingsBuilder;
+    use crate::test_support::{example_engine, EXAMPLE_DATABASE_PATH, GOOD_FILE_PATH, NAUGHTY_FILE_PATH};
+    use std::fs::File;
+    use std::io::Cursor;
+
+    const TEST_DATABASES_PATH: &str = "test_data/database/";
+    const NESTED_ZIP_PATH: &str = "test_data/files/nested.zip";
+
+    #[test]
+    fn compile_empty_engine_success() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        assert!(scanner.compile().is_ok(), "compile should succeed");
+    }
+
+    #[test]
+    fn empty_engine_does_not_report_clean() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
+        let not_scanned = ScanResult::NotScanned(NotScannedReason::EmptyEngine);
+
+        assert_eq!(scanner.scan_file(NAUGHTY_FILE_PATH, &settings), Ok(not_scanned.clone()));
+        let outcome = scanner.scan_file_outcome(GOOD_FILE_PATH, &settings).unwrap();
+        assert_eq!((outcome.result, outcome.bytes_scanned), (not_scanned.clone(), 0));
+        let contents = std::fs::read(GOOD_FILE_PATH).unwrap();
+        let map = Fmap::new_from_memory(contents.as_ptr(), contents.len() as u64).unwrap();
+        assert_eq!(scanner.scan_map(&map, None, &settings), Ok(not_scanned));
+        assert_state_error(scanner.scan_file_quiet(GOOD_FILE_PATH, &settings), EngineStateError::NoDatabase);
+    }
+
+    #[test]
+    fn allowed_empty_engine_scans_clean() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner.set_allow_empty(true);
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
+        assert_eq!(scanner.scan_file(NAUGHTY_FILE_PATH, &settings), Ok(ScanResult::Clean));
+        assert_eq!(scanner.scan_file_quiet(NAUGHTY_FILE_PATH, &settings), Ok(false));
+
+        // the check only applies while no signatures are loaded
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        assert_eq!(scanner.scan_file(GOOD_FILE_PATH, &settings), Ok(ScanResult::Clean));
+    }
+
+    #[test]
+    fn overrides_apply_to_one_scan_only() {
+        use crate::scan_settings::HeuristicFlags;
+
+        const ENCRYPTED_ZIP_PATH: &str = "test_data/files/encrypted.zip";
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        let base = ScanSettingsBuilder::hardened_upload().build();
+        let before = base.to_string();
+        let encrypted = ScanResult::Virus("Heuristics.Encrypted.Zip".into());
+
+        let quiet = Overrides::new().heuristic_alerts(HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE, false);
+        let outcome = scanner.scan_file_outcome_with(ENCRYPTED_ZIP_PATH, &base, quiet).unwrap();
+        assert_eq!(outcome.result, ScanResult::Clean);
+        assert_eq!(base.to_string(), before);
+        assert_eq!(scanner.scan_file_outcome(ENCRYPTED_ZIP_PATH, &base).unwrap().result, encrypted);
+
+        let contents = std::fs::read(ENCRYPTED_ZIP_PATH).unwrap();
+        let plain = quiet.apply(&base);
+        let loud = Overrides::new().heuristic_alerts(HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE, true);
+        assert_eq!(scanner.scan_mem_outcome_with(&contents, None, &plain, loud).unwrap().result, encrypted);
+        assert_eq!(scanner.scan_mem_outcome(&contents, None, &plain).unwrap().result, ScanResult::Clean);
+    }
+
+    #[test]
+    fn reload_databases_builds_configured_engine() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Arc::new(Engine::new().unwrap().with_signature_index());
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.set_max_filesize(1024 * 1024).expect("failed to set limit");
+        let detections = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&detections);
+        scanner
+            .set_virus_found_callback(move |_fd, name| recorded.lock().unwrap().push(name.to_string()))
+            .expect("failed to set callback");
+        scanner.compile().expect("failed to compile");
+
+        let in_flight = Arc::clone(&scanner);
+        let current = Arc::new(scanner.reload_databases(EXAMPLE_DATABASE_PATH).expect("reload failed"));
+        assert!(!Arc::ptr_eq(&current, &in_flight));
+        assert_eq!(current.state(), EngineState::Compiled);
+        assert_eq!(current.max_filesize(), Ok(1024 * 1024));
+        assert!(current.has_signature("naughty_file.UNOFFICIAL").unwrap());
+
+        let settings: ScanSettings = Default::default();
+        match current.scan_file(NAUGHTY_FILE_PATH, &settings) {
+            Ok(ScanResult::Virus(name)) => assert_eq!(name, "naughty_file.UNOFFICIAL"),
+            other => panic!("should have matched as a virus, got {:?}", other),
+        }
+        assert_eq!(current.scan_file(GOOD_FILE_PATH, &settings), Ok(ScanResult::Clean));
+        // the previous engine is untouched
+        assert!(matches!(in_flight.scan_file(NAUGHTY_FILE_PATH, &settings), Ok(ScanResult::Virus(_))));
+        assert_eq!(detections.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn exported_settings_apply_to_fresh_engine() {
+        crate::initialize().expect("initialize should succeed");
+        let source = Engine::new().unwrap().with_signature_index();
+        source.set_max_filesize(1234 * 1024).expect("failed to set limit");
+        let settings = source.export_settings().expect("failed to export");
+        drop(source);
+
+        let scanner = Engine::new().unwrap();
+        assert_ne!(scanner.max_filesize(), Ok(1234 * 1024));
+        scanner.apply_settings(&settings).expect("failed to apply");
+        assert_eq!(scanner.max_filesize(), Ok(1234 * 1024));
+        // the index callback of the dropped engine is not carried over
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+
+        let err = scanner.apply_settings(&settings).expect_err("compiled engine should be rejected");
+        assert_eq!(err.state_error(), Some(EngineStateError::AlreadyCompiled));
+    }
+
+    #[test]
+    fn reload_databases_failure_leaves_engine_usable() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        assert!(scanner.reload_databases("test_data/does_not_exist").is_err());
+        let result = scanner.scan_file(NAUGHTY_FILE_PATH, &Default::default());
+        assert!(matches!(result, Ok(ScanResult::Virus(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn compile_in_background_success() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        let compiling = scanner.compile_in_background();
+        while !compiling.ready() {
+            std::thread::sleep(time::Duration::from_millis(10));
+        }
+        let scanner = compiling.wait().expect("compile should succeed");
+        let settings: ScanSettings = Default::default();
+        match scanner.scan_file(NAUGHTY_FILE_PATH, &settings) {
+            Ok(ScanResult::Virus(name)) => assert_eq!(name, "naughty_file.UNOFFICIAL"),
+            _ => panic!("should have matched as a virus"),
+        }
+    }
+
+    #[test]
+    fn compile_in_background_wait_timeout_success() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        let mut compiling = scanner.compile_in_background();
+        let scanner = loop {
+            match compiling.wait_timeout(time::Duration::from_millis(100)) {
+                Ok(result) => break result.expect("compile should succeed"),
+                Err(pending) => compiling = pending,
+            }
+        };
+        let settings: ScanSettings = Default::default();
+        match scanner.scan_file(GOOD_FILE_PATH, &settings) {
+            Ok(ScanResult::Clean) => {}
+            _ => panic!("should have been clean"),
+        }
+    }
+
+    #[test]
+    fn recommended_concurrency_is_positive() {
+        assert!(Engine::recommended_concurrency() > 0);
+    }
+
+    #[test]
+    fn detection_from_invalid_utf8_keeps_raw_name() {
+        let raw = b"Unofficial.\xff\xfeName";
+        let detection = Detection::from_raw(raw);
+        assert_eq!(detection.name, "Unofficial.\u{fffd}\u{fffd}Name");
+        assert_eq!(detection.raw_name, raw.to_vec());
+        assert!(!detection.is_lossless());
+    }
+
+    #[test]
+    fn detection_is_never_empty() {
+        let detection = Detection::from_raw(b"");
+        assert_eq!(detection, Detection::UNKNOWN_NAME);
+        assert!(detection.raw_name.is_empty());
+    }
+
+    #[test]
+    fn map_scan_result_handles_null_virname() {
+        match map_scan_result(cl_error_t::CL_VIRUS, ptr::null()) {
+            Ok(ScanResult::Virus(detection)) => {
+                assert!(detection.is_unnamed());
+                assert_eq!(detection, Detection::unnamed());
+                assert_eq!(detection, Detection::UNKNOWN_NAME);
+            }
+            _ => panic!("should have been a virus"),
+        }
+    }
+
+    #[test]
+    fn map_scan_result_converts_invalid_utf8_lossily() {
+        let raw = CString::new(&b"Bad.\xc3"[..]).unwrap();
+        match map_scan_result(cl_error_t::CL_VIRUS, raw.as_ptr()) {
+            Ok(ScanResult::Virus(detection)) => {
+                assert_eq!(detection, "Bad.\u{fffd}");
+                assert_eq!(detection.raw_name, b"Bad.\xc3".to_vec());
+            }
+            _ => panic!("should have been a virus"),
+        }
+    }
+
+    #[test]
+    // `ScannedBlocks` is u64 on some targets only
+    #[allow(clippy::useless_conversion)]
+    fn scanned_bytes_widens_before_multiplying() {
+        assert_eq!(scanned_bytes(0), 0);
+        assert_eq!(scanned_bytes(3), 3 * u64::from(CL_COUNT_PRECISION));
+        let max = ScannedBlocks::MAX;
+        assert_eq!(
+            scanned_bytes(max),
+            u64::from(max).saturating_mul(u64::from(CL_COUNT_PRECISION))
+        );
+        assert!(scanned_bytes(max) > u64::from(u32::MAX));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn scan_descriptor_outcome_counts_scanned_bytes() {
+        use std::io::Write;
+        use std::os::unix::io::AsRawFd;
+
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&vec![b'x'; 256 * 1024]).unwrap();
+        let settings: ScanSettings = Default::default();
+        let outcome = scanner
+            .scan_descriptor_outcome(file.as_raw_fd(), &settings, None)
+            .expect("scan should succeed");
+        assert_eq!(outcome.result, ScanResult::Clean);
+        assert!(outcome.bytes_scanned > 0, "a non-empty file should count");
+        assert_eq!(outcome.bytes_scanned % u64::from(CL_COUNT_PRECISION), 0);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn nul_in_path_or_filename_is_rejected() {
+        use std::os::unix::io::AsRawFd;
+
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        let err = scanner
+            .load_databases("test_data/database/\0example.cud")
+            .expect_err("path with NUL should be rejected");
+        assert_eq!(err.code(), cl_error_t::CL_EARG as i32);
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+
+        let settings: ScanSettings = Default::default();
+        let err = scanner
+            .scan_file("test_data/files/good\0file", &settings)
+            .expect_err("path with NUL should be rejected");
+        assert_eq!(err.code(), cl_error_t::CL_EARG as i32);
+
+        let file = File::open(NAUGHTY_FILE_PATH).unwrap();
+        let err = scanner
+            .scan_descriptor(file.as_raw_fd(), &settings, Some("naughty\0file"))
+            .expect_err("filename with NUL should be rejected");
+        assert_eq!(err.code(), cl_error_t::CL_EARG as i32);
+        let result = scanner
+            .scan_descriptor(file.as_raw_fd(), &settings, Some("naughty_file"))
+            .expect("scan should succeed");
+        assert!(result.found());
+    }
+
+    #[test]
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    fn scan_descriptor_outcome_counts_multi_gb_file() {
+        use std::os::unix::io::AsRawFd;
+
+        // Just below 2 GiB, the largest file all supported libclamav versions scan
+        const SIZE: u64 = 2 * 1024 * 1024 * 1024 - CL_COUNT_PRECISION as u64;
+
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner
+            .set(cl_engine_field::CL_ENGINE_MAX_FILESIZE, EngineValue::U64(SIZE * 2))
+            .expect("failed to set max file size");
+        scanner.set_max_scansize(SIZE * 2).expect("failed to set max scan size");
+        scanner.compile().expect("failed to compile");
+
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(SIZE).unwrap();
+        let settings: ScanSettings = Default::default();
+        let outcome = scanner
+            .scan_descriptor_outcome(file.as_raw_fd(), &settings, None)
+            .expect("scan should succeed");
+        assert_eq!(outcome.result, ScanResult::Clean);
+        assert_eq!(outcome.bytes_scanned % u64::from(CL_COUNT_PRECISION), 0);
+        assert!(outcome.bytes_scanned > SIZE / 2, "scanned only {} bytes", outcome.bytes_scanned);
+        assert!(outcome.bytes_scanned <= SIZE + u64::from(CL_COUNT_PRECISION));
+    }
+
+    #[test]
+    fn limit_setters_read_back() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner.set_max_scansize(300 * 1024 * 1024).expect("failed to set max scan size");
+        scanner.set_max_filesize(100 * 1024 * 1024).expect("failed to set max file size");
+        scanner.set_max_files(5000).expect("failed to set max files");
+        scanner.set_max_recursion(12).expect("failed to set max recursion");
+        scanner.set_max_scantime(90_000).expect("failed to set max scan time");
+        assert_eq!(scanner.max_scansize(), Ok(300 * 1024 * 1024));
+        assert_eq!(scanner.max_filesize(), Ok(100 * 1024 * 1024));
+        assert_eq!(scanner.max_files(), Ok(5000));
+        assert_eq!(scanner.max_recursion(), Ok(12));
+        assert_eq!(scanner.max_scantime(), Ok(90_000));
+    }
+
+    #[test]
+    fn tmp_dir_and_keep_temp_files_read_back() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        assert_eq!(scanner.tmp_dir(), Ok(None));
+        assert_eq!(scanner.keep_temp_files(), Ok(false));
+
+        let dir = tempfile::tempdir().unwrap();
+        scanner.set_tmp_dir(dir.path()).expect("failed to set tmp dir");
+        scanner.set_keep_temp_files(true).expect("failed to set keep tmp");
+        assert_eq!(scanner.tmp_dir(), Ok(Some(dir.path().to_path_buf())));
+        assert_eq!(scanner.keep_temp_files(), Ok(true));
+
+        let err = scanner.set_tmp_dir(Path::new("/tmp/bad\0dir")).expect_err("NUL should be rejected");
+        assert_eq!(err.code(), cl_error_t::CL_EARG as i32);
+        assert_eq!(scanner.tmp_dir(), Ok(Some(dir.path().to_path_buf())));
+
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        let result = scanner.scan_file("test_data/files/nested.zip", &Default::default());
+        assert!(result.is_ok(), "{:?}", result);
+    }
+
+    #[test]
+    fn clam_time_zero_is_unset() {
+        assert_eq!(ClamTime::new(0).as_system_time(), Ok(None));
+    }
+
+    #[test]
+    fn clam_time_before_epoch() {
+        assert_eq!(
+            ClamTime::new(-1).as_system_time(),
+            Ok(Some(time::UNIX_EPOCH - time::Duration::from_secs(1)))
+        );
+    }
+
+    #[test]
+    fn clam_time_min_does_not_overflow() {
+        let min = ClamTime::new(time_t::MIN);
+        match min.as_system_time() {
+            Err(err) => assert_eq!(err.seconds(), min.seconds()),
+            // representable on platforms with a wider SystemTime
+            Ok(Some(system_time)) => assert!(system_time < time::UNIX_EPOCH),
+            Ok(None) => panic!("MIN is not unset"),
+        }
+    }
+
+    #[test]
+    fn clam_time_now_round_trips() {
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(
+            ClamTime::new(now as time_t).as_system_time(),
+            Ok(Some(time::UNIX_EPOCH + time::Duration::from_secs(now)))
+        );
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn clam_time_after_2106() {
+        let seconds: u64 = 4_354_000_000;
+        assert_eq!(
+            ClamTime::new(seconds as time_t).as_system_time(),
+            Ok(Some(time::UNIX_EPOCH + time::Duration::from_secs(seconds)))
+        );
+    }
+
+    #[test]
+    fn database_timestamp_unset_without_databases() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        assert_eq!(scanner.database_timestamp(), Ok(None));
+    }
+
+    #[test]
+    fn null_engine_fails_gracefully() {
+        crate::initialize().expect("initialize should succeed");
+        let null_error = Some(cl_error_t::CL_ENULLARG as i32);
+        let scanner = unsafe { Engine::from_raw(ptr::null_mut()) };
+        let settings: ScanSettings = Default::default();
+        assert_eq!(scanner.load_databases(EXAMPLE_DATABASE_PATH).err().map(|e| e.code()), null_error);
+        assert_eq!(scanner.compile().err().map(|e| e.code()), null_error);
+        assert_eq!(scanner.scan_file(GOOD_FILE_PATH, &settings).err().map(|e| e.code()), null_error);
+        let contents = std::fs::read(GOOD_FILE_PATH).unwrap();
+        let map = Fmap::new_from_memory(contents.as_ptr(), contents.len() as u64).unwrap();
+        assert_eq!(scanner.scan_map(&map, None, &settings).err().map(|e| e.code()), null_error);
+        assert_eq!(scanner.database_version().err().map(|e| e.code()), null_error);
+        assert_eq!(scanner.set_max_scansize(1024).err().map(|e| e.code()), null_error);
+        // dropping must not free the null handle
+        drop(scanner);
+    }
+
+    #[test]
+    fn scan_result_display_matches_clamscan() {
+        assert_eq!(ScanResult::Clean.to_string(), "OK");
+        assert_eq!(ScanResult::Whitelisted.to_string(), "OK");
+        assert_eq!(
+            ScanResult::Virus("Eicar-Signature".into()).to_string(),
+            "Eicar-Signature FOUND"
+        );
+        assert_eq!(
+            ScanResult::Virus(Detection::unnamed()).to_string(),
+            format!("{} FOUND", Detection::UNKNOWN_NAME)
+        );
+    }
+
+    #[test]
+    fn scan_result_found() {
+        assert!(!ScanResult::Clean.found());
+        assert!(!ScanResult::Whitelisted.found());
+        assert!(ScanResult::Virus("Eicar-Signature".into()).found());
+    }
+
+    #[test]
+    fn limit_kind_from_detection() {
+        let kind = |name: &str| LimitKind::from_detection(&Detection::from(name));
+        assert_eq!(kind("Heuristics.Limits.Exceeded"), Some(LimitKind::Unspecified));
+        assert_eq!(kind("Heuristics.Limits.Exceeded.MaxFiles"), Some(LimitKind::Files));
+        assert_eq!(kind("Heuristics.Limits.Exceeded.MaxRecursion"), Some(LimitKind::Recursion));
+        assert_eq!(kind("Heuristics.Limits.Exceeded.MaxFileSize"), Some(LimitKind::FileSize));
+        assert_eq!(kind("Heuristics.Limits.Exceeded.MaxScanSize"), Some(LimitKind::ScanSize));
+        assert_eq!(kind("Heuristics.Limits.Exceeded.MaxScanTime"), Some(LimitKind::Time));
+        assert_eq!(kind("Heuristics.Limits.Exceeded.MaxSomethingNew"), Some(LimitKind::Unspecified));
+        assert_eq!(kind("Heuristics.Limits.ExceededX"), None);
+        assert_eq!(kind("naughty_file.UNOFFICIAL"), None);
+        for &limit in &[LimitKind::Files, LimitKind::Recursion, LimitKind::FileSize, LimitKind::ScanSize, LimitKind::Time] {
+            assert_eq!(kind(limit.heuristic_name()), Some(limit));
+        }
+    }
+
+    #[test]
+    fn encryption_kind_from_detection() {
+        let kind = |name: &str| EncryptionKind::from_detection(&Detection::from(name));
+        assert_eq!(kind("Heuristics.Encrypted.Zip"), Some(EncryptionKind::Zip));
+        assert_eq!(kind("Heuristics.Encrypted.RAR"), Some(EncryptionKind::Rar));
+        assert_eq!(kind("Heuristics.Encrypted.PDF"), Some(EncryptionKind::Pdf));
+        assert_eq!(kind("Heuristics.Encrypted.Future"), Some(EncryptionKind::Unspecified));
+        assert_eq!(kind("Heuristics.EncryptedZip"), None);
+        assert_eq!(kind("naughty_file.UNOFFICIAL"), None);
+        for container in [EncryptionKind::Zip, EncryptionKind::SevenZip, EncryptionKind::Document] {
+            assert_eq!(kind(container.heuristic_name()), Some(container));
+        }
+    }
+
+    #[test]
+    fn encryption_kind_classify() {
+        let outcome = |result| Ok(ScanOutcome { result, bytes_scanned: 4096, suppression: None, performance: None, from_cache: None });
+        assert_eq!(
+            EncryptionKind::classify(outcome(ScanResult::Virus("Heuristics.Encrypted.Zip".into()))),
+            outcome(ScanResult::Encrypted { container: EncryptionKind::Zip })
+        );
+        assert_eq!(
+            EncryptionKind::classify(outcome(ScanResult::Virus("naughty_file.UNOFFICIAL".into()))),
+            outcome(ScanResult::Virus("naughty_file.UNOFFICIAL".into()))
+        );
+        let err = ClamError::new(cl_error_t::CL_EMAXREC);
+        assert_eq!(EncryptionKind::classify(Err(err.clone())), Err(err));
+    }
+
+    #[test]
+    fn limit_kind_classify() {
+        let outcome = |result| Ok(ScanOutcome { result, bytes_scanned: 4096, suppression: None, performance: None, from_cache: None });
+        assert_eq!(
+            LimitKind::classify(outcome(ScanResult::Virus("Heuristics.Limits.Exceeded.MaxFiles".into()))),
+            outcome(ScanResult::Suspicious(LimitKind::Files))
+        );
+        assert_eq!(
+            LimitKind::classify(outcome(ScanResult::Virus("naughty_file.UNOFFICIAL".into()))),
+            outcome(ScanResult::Virus("naughty_file.UNOFFICIAL".into()))
+        );
+        assert_eq!(LimitKind::classify(outcome(ScanResult::Clean)), outcome(ScanResult::Clean));
+        let classified = LimitKind::classify(Err(ClamError::new(cl_error_t::CL_EMAXREC))).unwrap();
+        assert_eq!(classified.result, ScanResult::Suspicious(LimitKind::Recursion));
+        let err = ClamError::new(cl_error_t::CL_EOPEN);
+        assert_eq!(LimitKind::classify(Err(err.clone())), Err(err));
+    }
+
+    #[test]
+    fn max_files_and_recursion_round_trip() {
+        crate::initialize().expect("initialize should succeed");
+        let engine = Engine::new().unwrap();
+        engine.set_max_files(123).unwrap();
+        engine.set_max_recursion(7).unwrap();
+        assert_eq!(engine.max_files(), Ok(123));
+        assert_eq!(engine.max_recursion(), Ok(7));
+    }
+
+    #[test]
+    fn scan_outcome_format_line_matches_clamscan() {
+        let outcome = |result| ScanOutcome { result, bytes_scanned: 0, suppression: None, performance: None, from_cache: None };
+        let path = Path::new("/tmp/files/eicar.com");
+        assert_eq!(outcome(ScanResult::Clean).format_line(path), "/tmp/files/eicar.com: OK");
+        assert_eq!(outcome(ScanResult::Whitelisted).format_line(path), "/tmp/files/eicar.com: OK");
+        assert_eq!(
+            outcome(ScanResult::Virus("Eicar-Signature".into())).format_line(path),
+            "/tmp/files/eicar.com: Eicar-Signature FOUND"
+        );
+        assert_eq!(
+            outcome(ScanResult::Suspicious(LimitKind::Files)).format_line(path),
+            "/tmp/files/eicar.com: Heuristics.Limits.Exceeded.MaxFiles FOUND"
+        );
+    }
+
+    #[test]
+    fn debug_follows_engine_lifecycle() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        let new = format!("{:?}", scanner);
+        assert!(new.contains("loaded: false"), "{}", new);
+        assert!(new.contains("compiled: false"), "{}", new);
+        assert!(new.contains("signature_count: 0"), "{}", new);
+
+        let stats = scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        let loaded = format!("{:?}", scanner);
+        assert!(loaded.contains("loaded: true"), "{}", loaded);
+        assert!(loaded.contains("compiled: false"), "{}", loaded);
+        assert!(
+            loaded.contains(&format!("signature_count: {}", stats.signature_count)),
+            "{}",
+            loaded
+        );
+
+        scanner.compile().expect("failed to compile");
+        let compiled = format!("{:?}", scanner);
+        assert!(compiled.contains("loaded: true"), "{}", compiled);
+        assert!(compiled.contains("compiled: true"), "{}", compiled);
+    }
+
+    fn assert_state_error<T: fmt::Debug>(result: Result<T, ClamError>, expected: EngineStateError) {
+        match result {
+            Err(err) => {
+                assert_eq!(err.state_error(), Some(expected), "{}", err);
+                assert_eq!(err.code(), cl_error_t::CL_EARG as i32);
+            }
+            Ok(value) => panic!("expected {:?}, got {:?}", expected, value),
+        }
+    }
+
+    #[test]
+    fn state_follows_engine_lifecycle() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        assert_eq!(scanner.state(), EngineState::New);
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        assert_eq!(scanner.state(), EngineState::Loaded);
+        scanner
+            .load_yara_rules("rule marker { strings: $a = \"MARKER\" condition: $a }")
+            .expect("failed to load rules");
+        assert_eq!(scanner.state(), EngineState::Loaded);
+        scanner.compile().expect("failed to compile");
+        assert_eq!(scanner.state(), EngineState::Compiled);
+    }
+
+    #[test]
+    fn scanning_requires_compiled_engine() {
+        crate::initialize().expect("initialize should succeed");
+        let settings = ScanSettings::default();
+        let file = File::open(GOOD_FILE_PATH).unwrap();
+        let new = Engine::new().unwrap();
+        let loaded = Engine::new().unwrap();
+        loaded
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        for scanner in &[new, loaded] {
+            assert_state_error(scanner.scan_file(GOOD_FILE_PATH, &settings), EngineStateError::NotCompiled);
+            assert_state_error(
+                scanner.scan_file_auto(GOOD_FILE_PATH, &settings, SniffMode::Narrow),
+                EngineStateError::NotCompiled,
+            );
+            assert_state_error(scanner.scan_fileobj(&file, &settings, None), EngineStateError::NotCompiled);
+            assert_state_error(scanner.scan_mem(b"data", None, &settings), EngineStateError::NotCompiled);
+            let config = WindowConfig { window_size: 4096, overlap: 0 };
+            assert_state_error(
+                scanner.scan_file_windowed(GOOD_FILE_PATH, &settings, config),
+                EngineStateError::NotCompiled,
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_clean_scan_is_served_from_cache() {
+        crate::initialize().expect("initialize should succeed");
+        let settings = ScanSettings::default();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"Nothing to see here, just some plain text for the clean cache.\n").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        for disable_cache in &[false, true] {
+            let scanner = Engine::new().unwrap();
+            scanner
+                .load_databases(EXAMPLE_DATABASE_PATH)
+                .expect("failed to load db");
+            if *disable_cache {
+                scanner
+                    .set(cl_engine_field::CL_ENGINE_DISABLE_CACHE, EngineValue::U32(1))
+                    .unwrap();
+            }
+            scanner.compile().expect("failed to compile");
+
+            let first = scanner.scan_file_outcome(path, &settings).unwrap();
+            assert_eq!(first.result, ScanResult::Clean);
+            assert_eq!(first.from_cache, Some(false));
+            let second = scanner.scan_file_outcome(path, &settings).unwrap();
+            assert_eq!(second.result, ScanResult::Clean);
+            assert_eq!(second.from_cache, Some(!disable_cache));
+
+            // only clean verdicts are cached
+            for _ in 0..2 {
+                let naughty = scanner.scan_file_outcome(NAUGHTY_FILE_PATH, &settings).unwrap();
+                assert_eq!(naughty.from_cache, Some(false));
+            }
+        }
+    }
+
+    #[test]
+    fn pre_scan_callback_sees_scanned_files() {
+        let scanner = example_engine();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let calls = seen.clone();
+        scanner
+            .set_pre_scan_callback(move |fd, file_type| {
+                assert!(fd >= 0);
+                calls.lock().unwrap().push(file_type.to_string());
+                PreScanAction::Continue
+            })
+            .unwrap();
+        let settings = ScanSettings::default();
+        let result = scanner.scan_file(NAUGHTY_FILE_PATH, &settings).unwrap();
+        assert_eq!(result, ScanResult::Virus("naughty_file.UNOFFICIAL".into()));
+        let data = std::fs::read(NAUGHTY_FILE_PATH).unwrap();
+        scanner.scan_mem(&data, None, &settings).unwrap();
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert!(seen.iter().all(|file_type| file_type.starts_with("CL_TYPE_")));
+    }
+
+    #[test]
+    fn pre_scan_callback_decides_the_verdict() {
+        let scanner = example_engine();
+        let settings = ScanSettings::default();
+        scanner.set_pre_scan_callback(|_, _| PreScanAction::Skip).unwrap();
+        assert_eq!(scanner.scan_file(NAUGHTY_FILE_PATH, &settings).unwrap(), ScanResult::Clean);
+        scanner.set_pre_scan_callback(|_, _| PreScanAction::Detect).unwrap();
+        assert_eq!(
+            scanner.scan_file(GOOD_FILE_PATH, &settings).unwrap(),
+            ScanResult::Virus("Detected.By.Callback".into())
+        );
+    }
+
+    #[test]
+    fn panicking_pre_scan_callback_continues_the_scan() {
+        let scanner = example_engine();
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        scanner
+            .set_pre_scan_callback(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                panic!("callback failed");
+            })
+            .unwrap();
+        let settings = ScanSettings::default();
+        for _ in 0..2 {
+            let result = scanner.scan_file(NAUGHTY_FILE_PATH, &settings).unwrap();
+            assert_eq!(result, ScanResult::Virus("naughty_file.UNOFFICIAL".into()));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn progress_callback_sees_database_loading() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let progress = calls.clone();
+        scanner
+            .set_progress_callback(move |total, now| progress.lock().unwrap().push((total, now)))
+            .unwrap();
+        scanner.load_databases(EXAMPLE_DATABASE_PATH).unwrap();
+        let loading = calls.lock().unwrap().len();
+        assert!(loading > 0);
+        scanner.compile().unwrap();
+        let calls = calls.lock().unwrap();
+        assert!(calls.len() > loading);
+        assert!(calls.iter().all(|&(total, now)| now <= total));
+    }
+
+    #[test]
+    fn sanitized_names() {
+        assert_eq!(SanitizedName::new("tenant-1/upload.bin").as_str(), "tenant-1/upload.bin");
+        assert_eq!(SanitizedName::new("a\0b\nc\u{1b}[31m").as_str(), "a\u{fffd}b\u{fffd}c\u{fffd}[31m");
+        let long = "é".repeat(SanitizedName::MAX_LEN);
+        let name = SanitizedName::new(&long);
+        assert_eq!(name.as_str().len(), SanitizedName::MAX_LEN);
+        assert!(name.to_c_string().as_bytes().len() <= SanitizedName::MAX_LEN);
+    }
+
+    #[test]
+    fn callbacks_see_the_name_of_memory_scans() {
+        let scanner = example_engine();
+        let found = Arc::new(Mutex::new(Vec::new()));
+        let names = found.clone();
+        scanner
+            .set_virus_found_callback(move |_, virus| {
+                let name = scan_name().map(|name| name.to_string());
+                names.lock().unwrap().push((name, virus.to_string()));
+            })
+            .unwrap();
+        let data = std::fs::read(NAUGHTY_FILE_PATH).unwrap();
+        let settings = ScanSettings::default();
+        let result = scanner.scan_mem(&data, Some("tenant-1/upload\n.bin"), &settings).unwrap();
+        assert_eq!(result, ScanResult::Virus("naughty_file.UNOFFICIAL".into()));
+        scanner.scan_mem(&data, None, &settings).unwrap();
+        assert_eq!(
+            *found.lock().unwrap(),
+            vec![
+                (Some("tenant-1/upload\u{fffd}.bin".to_string()), "naughty_file.UNOFFICIAL".to_string()),
+                (None, "naughty_file.UNOFFICIAL".to_string()),
+            ]
+        );
+        assert_eq!(scan_name(), None);
+    }
+
+    #[test]
+    fn file_inspection_callback_sees_nested_layers() {
+        let scanner = example_engine();
+        let layers = Arc::new(Mutex::new(Vec::new()));
+        let inspected = layers.clone();
+        scanner
+            .set_file_inspection_callback(move |layer| inspected.lock().unwrap().push(layer))
+            .unwrap();
+        let settings = ScanSettingsBuilder::new().enable_archive().build();
+        let result = scanner.scan_file(NESTED_ZIP_PATH, &settings).unwrap();
+        assert_eq!(result, ScanResult::Clean);
+
+        let layers = layers.lock().unwrap();
+        assert!(layers.len() > 1, "expected nested layers, got {:?}", layers);
+        let outer = &layers[0];
+        assert!(outer.ancestors.is_empty());
+        assert_eq!(outer.file_size, std::fs::metadata(NESTED_ZIP_PATH).unwrap().len());
+        let deepest = layers.iter().max_by_key(|layer| layer.ancestors.len()).unwrap();
+        assert!(!deepest.ancestors.is_empty());
+        assert!(deepest.parent_file_size > 0);
+        assert!(layers.iter().all(|layer| layer.file_type.starts_with("CL_TYPE_")));
+        let good_file = std::fs::read(GOOD_FILE_PATH).unwrap();
+        let good_file_sha256 = policy::sha256_hex(&good_file);
+        assert!(layers.iter().any(|layer| layer.sha256 == good_file_sha256));
+    }
+
+    #[test]
+    fn signature_index_finds_loaded_signatures() {
+        crate::initialize().expect("initialize should succeed");
+        let engine = Engine::new().unwrap().with_signature_index();
+        let stats = engine.load_databases(EXAMPLE_DATABASE_PATH).expect("failed to load db");
+        assert!(stats.signature_index_bytes.unwrap() > 0);
+
+        assert_eq!(engine.find_signatures("naughty*").unwrap(), vec!["naughty_file.UNOFFICIAL"]);
+        assert_eq!(engine.find_signatures("*.UNOFFICIAL").unwrap().len(), stats.signature_count as usize);
+        assert!(engine.find_signatures("Win.*").unwrap().is_empty());
+        assert!(engine.has_signature("naughty_file.UNOFFICIAL").unwrap());
+        assert!(!engine.has_signature("naughty_file").unwrap());
+    }
+
+    #[test]
+    fn signature_index_is_opt_in() {
+        crate::initialize().expect("initialize should succeed");
+        let engine = Engine::new().unwrap();
+        let stats = engine.load_databases(EXAMPLE_DATABASE_PATH).expect("failed to load db");
+        assert_eq!(stats.signature_index_bytes, None);
+        let err = engine.find_signatures("*").unwrap_err();
+        assert_eq!(err.state_error(), Some(EngineStateError::NoSignatureIndex));
+        let err = engine.has_signature("naughty_file.UNOFFICIAL").unwrap_err();
+        assert_eq!(err.state_error(), Some(EngineStateError::NoSignatureIndex));
+    }
+
+    #[test]
+    fn scan_context_reaches_callbacks() {
+        struct Request {
+            id: u64,
+            detections: Vec<String>,
+        }
+
+        let scanner = example_engine();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let ids = seen.clone();
+        scanner
+            .set_virus_found_callback(move |_, virus| {
+                let id = unsafe {
+                    with_scan_context(|request: &mut Request| {
+                        request.detections.push(virus.to_string());
+                        // nested lookups cannot alias the context
+                        assert!(with_scan_context(|_: &mut Request| ()).is_none());
+                        request.id
+                    })
+                };
+                ids.lock().unwrap().push(id);
+            })
+            .unwrap();
+
+        let settings = ScanSettings::default();
+        let mut request = Request { id: 7, detections: Vec::new() };
+        let result = scanner.scan_file_with_context(NAUGHTY_FILE_PATH, &settings, &mut request).unwrap();
+        assert!(result.found());
+        assert_eq!(request.detections, vec!["naughty_file.UNOFFICIAL"]);
+
+        // the context is gone once the scan returned
+        scanner.scan_file(NAUGHTY_FILE_PATH, &settings).unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![Some(7), None]);
+        assert!(unsafe { with_scan_context(|_: &mut Request| ()) }.is_none());
+    }
+
+    #[test]
+    fn hash_callback_sees_detected_files() {
+        let scanner = example_engine();
+        let hashes = Arc::new(Mutex::new(Vec::new()));
+        let seen = hashes.clone();
+        scanner
+            .set_hash_callback(move |_, size, md5, virus| {
+                seen.lock().unwrap().push((size, md5.to_string(), virus.to_string()));
+            })
+            .unwrap();
+        let settings = ScanSettings::default();
+        assert_eq!(scanner.scan_file(GOOD_FILE_PATH, &settings).unwrap(), ScanResult::Clean);
+        // libclamav only hashes detections
+        assert!(hashes.lock().unwrap().is_empty());
+
+        scanner.scan_file(NAUGHTY_FILE_PATH, &settings).unwrap();
+        let naughty = std::fs::read(NAUGHTY_FILE_PATH).unwrap();
+        assert_eq!(
+            *hashes.lock().unwrap(),
+            vec![(
+                naughty.len() as u64,
+                policy::md5_hex(&naughty).unwrap(),
+                "naughty_file.UNOFFICIAL".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn file_props_callback_receives_metadata_json() {
+        use crate::scan_settings::GeneralFlags;
+
+        let scanner = example_engine();
+        let documents = Arc::new(Mutex::new(Vec::new()));
+        let seen = documents.clone();
+        scanner
+            .set_file_props_callback(move |json| seen.lock().unwrap().push(json.to_string()))
+            .unwrap();
+
+        // A DOS header whose PE header is truncated, enough to be typed as an executable
+        let mut pe = vec![0u8; 256];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c] = 0x80;
+        pe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, &pe).unwrap();
+
+        let settings = ScanSettings::default();
+        scanner.scan_file(file.path().to_str().unwrap(), &settings).unwrap();
+        assert!(documents.lock().unwrap().is_empty(), "metadata is only collected on request");
+
+        let mut settings = ScanSettings::default();
+        settings.set_general(settings.general() | GeneralFlags::CL_SCAN_GENERAL_COLLECT_METADATA);
+        scanner.scan_file(file.path().to_str().unwrap(), &settings).unwrap();
+        let documents = documents.lock().unwrap();
+        assert_eq!(documents.len(), 1);
+        assert!(documents[0].contains("\"FileType\""), "no file type in {}", documents[0]);
+    }
+
+    #[test]
+    fn virus_found_callback_sees_detections() {
+        let scanner = example_engine();
+        let found = Arc::new(Mutex::new(Vec::new()));
+        let names = found.clone();
+        scanner
+            .set_virus_found_callback(move |_, name| names.lock().unwrap().push(name.to_string()))
+            .unwrap();
+        let settings = ScanSettings::default();
+        assert_eq!(scanner.scan_file(GOOD_FILE_PATH, &settings).unwrap(), ScanResult::Clean);
+        assert!(found.lock().unwrap().is_empty());
+        let result = scanner.scan_file(NAUGHTY_FILE_PATH, &settings).unwrap();
+        assert_eq!(result, ScanResult::Virus("naughty_file.UNOFFICIAL".into()));
+        assert_eq!(*found.lock().unwrap(), vec!["naughty_file.UNOFFICIAL".to_string()]);
+    }
+
+    #[test]
+    fn post_scan_callback_sees_results() {
+        let scanner = example_engine();
+        let scanned = Arc::new(Mutex::new(Vec::new()));
+        let results = scanned.clone();
+        scanner
+            .set_post_scan_callback(move |fd, result, file_type| {
+                assert!(fd >= 0);
+                results.lock().unwrap().push((result, file_type.to_string()));
+            })
+            .unwrap();
+        let settings = ScanSettings::default();
+        let result = scanner.scan_file(NAUGHTY_FILE_PATH, &settings).unwrap();
+        assert_eq!(result, ScanResult::Virus("naughty_file.UNOFFICIAL".into()));
+        let scanned = scanned.lock().unwrap();
+        let (last, file_type) = scanned.last().unwrap();
+        assert_eq!(*last, result);
+        assert!(file_type.starts_with("CL_TYPE_"));
+    }
+
+    #[test]
+    fn compiled_engine_rejects_loading() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+
+        assert_state_error(scanner.load_databases(EXAMPLE_DATABASE_PATH), EngineStateError::AlreadyCompiled);
+        assert_state_error(
+            scanner.load_yara_rules("rule marker { strings: $a = \"MARKER\" condition: $a }"),
+            EngineStateError::AlreadyCompiled,
+        );
+        assert_state_error(scanner.compile(), EngineStateError::AlreadyCompiled);
+        assert_eq!(scanner.state(), EngineState::Compiled);
+        let result = scanner.scan_file(NAUGHTY_FILE_PATH, &ScanSettings::default());
+        assert_eq!(result, Ok(ScanResult::Virus("naughty_file.UNOFFICIAL".into())));
+    }
+
+    #[test]
+    fn compiled_engine_rejects_load_time_settings() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .set(cl_engine_field::CL_ENGINE_AC_MAXDEPTH, EngineValue::U32(4))
+            .expect("setting before compile should succeed");
+        scanner.compile().expect("failed to compile");
+
+        assert_state_error(
+            scanner.set(cl_engine_field::CL_ENGINE_AC_MAXDEPTH, EngineValue::U32(4)),
+            EngineStateError::AlreadyCompiled,
+        );
+        assert_state_error(
+            scanner.set(cl_engine_field::CL_ENGINE_DISABLE_CACHE, EngineValue::U32(1)),
+            EngineStateError::AlreadyCompiled,
+        );
+        // limits are read while scanning and may change at any time
+        scanner.set_max_filesize(1024 * 1024).expect("limits can change after compile");
+        scanner.set_max_recursion(4).expect("limits can change after compile");
+    }
+
+    #[test]
+    fn unchecked_engine_reaches_libclamav() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner.compile().expect("failed to compile");
+        unsafe { scanner.set_state_checks(false) };
+        // libclamav refuses this itself, just without a description
+        let err = scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect_err("libclamav should refuse to load into a compiled engine");
+        assert_eq!(err.state_error(), None, "{}", err);
+
+        unsafe { scanner.set_state_checks(true) };
+        assert_state_error(scanner.load_databases(EXAMPLE_DATABASE_PATH), EngineStateError::AlreadyCompiled);
+    }
+
+    #[test]
+    fn database_stats_match_getters() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        let stats = scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        let status = scanner.database_stats().expect("databases should be loaded");
+        assert_eq!(status.version, scanner.database_version().unwrap());
+        assert_eq!(status.timestamp, scanner.database_timestamp().unwrap());
+        assert_eq!(status.signatures, stats.signature_count);
+        let timestamp = status.timestamp.expect("example database has a build time");
+        assert!(status.age > time::Duration::from_secs(0));
+        assert!(status.age <= timestamp.elapsed().unwrap());
+    }
+
+    #[test]
+    fn database_stats_without_databases_fails() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        assert_state_error(scanner.database_stats(), EngineStateError::NoDatabase);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn database_status_serializes_fields() {
+        let status = DatabaseStatus {
+            version: 26000,
+            timestamp: None,
+            signatures: 5,
+            age: time::Duration::from_secs(0),
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["version"], 26000);
+        assert_eq!(json["signatures"], 5);
+        assert!(json["timestamp"].is_null());
+    }
+
+    #[test]
+    fn debug_tolerates_null_engine() {
+        let scanner = unsafe { Engine::from_raw(ptr::null_mut()) };
+        let debug = format!("{:?}", scanner);
+        assert!(debug.contains("database_version: None"), "{}", debug);
+        assert!(debug.contains("database_timestamp: None"), "{}", debug);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn null_engine_fails_descriptor_scan() {
+        let scanner = unsafe { Engine::from_raw(ptr::null_mut()) };
+        let settings: ScanSettings = Default::default();
+        let file = File::open(GOOD_FILE_PATH).unwrap();
+        let result = scanner.scan_fileobj(&file, &settings, None);
+        assert_eq!(result.err().map(|e| e.code()), Some(cl_error_t::CL_ENULLARG as i32));
+    }
+
+    #[test]
+    fn concurrent_string_settings_are_serialized() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Arc::new(Engine::new().unwrap());
+        let values: Vec<String> = (0..4).map(|i| format!("/tmp/clamav-rs-{}", "x".repeat(i * 100))).collect();
+        let handles: Vec<_> = (0..values.len())
+            .map(|i| {
+                let scanner = Arc::clone(&scanner);
+                let values = values.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        scanner
+                            .set(cl_engine_field::CL_ENGINE_TMPDIR, EngineValue::String(values[i].clone()))
+                            .expect("set should succeed");
+                        match scanner.get(cl_engine_field::CL_ENGINE_TMPDIR) {
+                            Ok(EngineValue::String(value)) => assert!(values.contains(&value), "torn value {:?}", value),
+                            _ => panic!("get should return a string"),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("setter thread panicked");
         }
     }
 
-    pub fn database_version(&self) -> Result<u32, ClamError> {
-        if let EngineValue::U32(value) = self.get(cl_engine_field::CL_ENGINE_DB_VERSION)? {
-            Ok(value)
-        }
-        else {
-            Err(ClamError::new(cl_error_t::CL_EARG))
-        }
+    const EICAR: &[u8] = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+    /// Engine with a body signature `Mem.Test.Eicar` matching EICAR at any offset
+    fn eicar_engine() -> (Engine, tempfile::TempDir) {
+        let db_dir = tempfile::tempdir().unwrap();
+        let hex: String = EICAR.iter().map(|b| format!("{:02x}", b)).collect();
+        std::fs::write(db_dir.path().join("mem.ndb"), format!("Mem.Test.Eicar:0:*:{}\n", hex)).unwrap();
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(db_dir.path().to_str().unwrap())
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        (scanner, db_dir)
     }
 
-    pub fn database_timestamp(&self) -> Result<time::SystemTime, ClamError> {
-        if let EngineValue::Time(value) = self.get(cl_engine_field::CL_ENGINE_DB_TIME)? {
-            Ok(value.as_system_time())
-        }
-        else {
-            Err(ClamError::new(cl_error_t::CL_EARG))
-        }
+    fn eicar_found() -> ScanResult {
+        ScanResult::Virus("Mem.Test.Eicar.UNOFFICIAL".into())
     }
 
+    #[test]
+    fn scan_file_all_matches_returns_every_detection() {
+        let db_dir = tempfile::tempdir().unwrap();
+        std::fs::copy(EXAMPLE_DATABASE_PATH, db_dir.path().join("example.cud")).unwrap();
+        let hex: String = b"naughty file".iter().map(|b| format!("{:02x}", b)).collect();
+        std::fs::write(db_dir.path().join("second.ndb"), format!("Second.Naughty:0:*:{}\n", hex)).unwrap();
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(db_dir.path().to_str().unwrap())
+            .expect("failed to load db");
+        let seen = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&seen);
+        scanner
+            .set_virus_found_callback(move |_fd, _name| *counter.lock().unwrap() += 1)
+            .expect("failed to set callback");
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
 
-    pub fn set_max_scansize(&self, max_scansize: u64) -> Result<(), ClamError> {
-        self.set(cl_engine_field::CL_ENGINE_MAX_SCANSIZE, EngineValue::U64(max_scansize))
+        let mut found = scanner.scan_file_all_matches(NAUGHTY_FILE_PATH, &settings).unwrap();
+        found.sort();
+        assert_eq!(found, vec!["Second.Naughty.UNOFFICIAL", "naughty_file.UNOFFICIAL"]);
+        assert_eq!(scanner.scan_file_all_matches(GOOD_FILE_PATH, &settings), Ok(vec![]));
+        // the callback of the engine still sees the detections
+        assert_eq!(*seen.lock().unwrap(), found.len());
+        // single-match scans are unchanged
+        assert!(matches!(scanner.scan_file(NAUGHTY_FILE_PATH, &settings), Ok(ScanResult::Virus(_))));
     }
 
-    pub fn max_scansize(&self) -> Result<u64, ClamError> {
-        if let EngineValue::U64(value) = self.get(cl_engine_field::CL_ENGINE_MAX_SCANSIZE)? {
-            Ok(value)
-        }
-        else {
-            Err(ClamError::new(cl_error_t::CL_EARG))
-        }
+    #[test]
+    fn scan_mem_detects_eicar_in_slice() {
+        let (scanner, _db_dir) = eicar_engine();
+        let upload: Vec<u8> = [&b"uploaded "[..], EICAR, &b" bytes"[..]].concat();
+        let result = scanner.scan_mem(&upload, Some("upload.txt"), &Default::default());
+        assert_eq!(result, Ok(eicar_found()));
     }
-}
 
-impl Drop for Engine {
-    fn drop(&mut self) {
-        unsafe {
-            clamav_sys::cl_engine_free(self.handle);
-        }
+    #[test]
+    fn scan_reader_buffers_or_spills_streams() {
+        let (scanner, _db_dir) = eicar_engine();
+        let settings: ScanSettings = Default::default();
+        let buffered = scanner.scan_reader(&mut Cursor::new(EICAR), Some("eicar.com"), &settings, 1024);
+        assert_eq!(buffered, Ok(eicar_found()));
+
+        let strategy = Arc::new(crate::temp::tests::CountingStrategy::default());
+        scanner.set_temp_strategy(strategy.clone());
+        let mut stream: Vec<u8> = vec![b' '; 4096];
+        stream.extend_from_slice(EICAR);
+        let spilled = scanner.scan_reader(&mut Cursor::new(&stream), None, &settings, 1024);
+        assert_eq!(spilled, Ok(eicar_found()));
+        assert_eq!(strategy.created.load(Ordering::SeqCst), 1);
+        assert_eq!(scanner.temp_bytes(), 0, "the spilled file should be released");
+
+        let mut clean = Cursor::new(vec![b' '; 4096]);
+        assert_eq!(scanner.scan_reader(&mut clean, None, &settings, 1024), Ok(ScanResult::Clean));
     }
-}
 
+    #[test]
+    fn scan_reader_of_empty_stream_is_clean() {
+        let (scanner, _db_dir) = eicar_engine();
+        let outcome = scanner
+            .scan_reader_outcome(&mut std::io::empty(), None, &Default::default(), 1024)
+            .expect("empty stream should scan");
+        assert_eq!((outcome.result, outcome.bytes_scanned), (ScanResult::Clean, 0));
+    }
 
-fn get_field_type(field: cl_engine_field) -> EngineValueType {
-    match field {
-        cl_engine_field::CL_ENGINE_MAX_SCANSIZE => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_MAX_FILESIZE => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_MAX_RECURSION => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_MAX_FILES => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_MIN_CC_COUNT => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_MIN_SSN_COUNT => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_PUA_CATEGORIES => EngineValueType::String,
-        cl_engine_field::CL_ENGINE_DB_OPTIONS => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_DB_VERSION => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_DB_TIME => EngineValueType::Time,
-        cl_engine_field::CL_ENGINE_AC_ONLY => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_AC_MINDEPTH => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_AC_MAXDEPTH => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_TMPDIR => EngineValueType::String,
-        cl_engine_field::CL_ENGINE_KEEPTMP => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_BYTECODE_SECURITY => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_BYTECODE_TIMEOUT => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_BYTECODE_MODE => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_MAX_EMBEDDEDPE => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_MAX_HTMLNORMALIZE => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_MAX_HTMLNOTAGS => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_MAX_SCRIPTNORMALIZE => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_MAX_ZIPTYPERCG => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_FORCETODISK => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_DISABLE_CACHE => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_DISABLE_PE_STATS => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_STATS_TIMEOUT => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_MAX_PARTITIONS => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_MAX_ICONSPE => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_MAX_RECHWP3 => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_MAX_SCANTIME => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_PCRE_MATCH_LIMIT => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_PCRE_RECMATCH_LIMIT => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_PCRE_MAX_FILESIZE => EngineValueType::U64,
-        cl_engine_field::CL_ENGINE_DISABLE_PE_CERTS => EngineValueType::U32,
-        cl_engine_field::CL_ENGINE_PE_DUMPCERTS => EngineValueType::U32,
+    #[test]
+    fn scan_reader_spill_respects_temp_quota() {
+        let (scanner, _db_dir) = eicar_engine();
+        scanner.set_temp_quota(Some(2048));
+        let mut stream = Cursor::new(vec![0u8; 4096]);
+        let err = scanner
+            .scan_reader(&mut stream, None, &Default::default(), 1024)
+            .expect_err("spill should exceed the quota");
+        assert_eq!(err.code(), cl_error_t::CL_ETMPFILE as i32);
+        assert_eq!(scanner.temp_bytes(), 0);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::File;
+    const YARA_RULE: &str = "rule clamav_rs_marker { strings: $a = \"CLAMAV-RS-YARA-MAGIC\" condition: $a }";
+
+    #[test]
+    fn load_yara_rules_detects_marker() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        let stats = scanner.load_yara_rules(YARA_RULE).expect("failed to load rules");
+        assert!(stats.signature_count > 0, "should load the rule");
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
 
-    const TEST_DATABASES_PATH: &'static str = "test_data/database/";
-    const EXAMPLE_DATABASE_PATH: &'static str = "test_data/database/example.cud";
-    const GOOD_FILE_PATH: &'static str = "test_data/files/good_file";
-    const NAUGHTY_FILE_PATH: &'static str = "test_data/files/naughty_file";
+        let data = b"some padding CLAMAV-RS-YARA-MAGIC more padding";
+        match scanner.scan_mem(data, Some("buffer"), &settings) {
+            Ok(ScanResult::Virus(detection)) => {
+                assert!(detection.is_yara(), "{} should be a YARA match", detection);
+                assert_eq!(detection.yara_rule(), Some("clamav_rs_marker"));
+            }
+            other => panic!("should have matched the rule, got {:?}", other),
+        }
+        let clean = scanner.scan_mem(b"nothing to see", None, &settings);
+        assert_eq!(clean, Ok(ScanResult::Clean));
+    }
 
     #[test]
-    fn compile_empty_engine_success() {
+    fn load_yara_rules_uses_temp_strategy_and_quota() {
+        use crate::temp::tests::CountingStrategy;
+
         crate::initialize().expect("initialize should succeed");
-        let scanner = Engine::new();
-        assert!(scanner.compile().is_ok(), "compile should succeed");
+        let scanner = Engine::new().unwrap();
+        let strategy = Arc::new(CountingStrategy::default());
+        scanner.set_temp_strategy(strategy.clone());
+        scanner.set_temp_quota(Some(YARA_RULE.len() as u64 - 1));
+
+        let err = scanner.load_yara_rules(YARA_RULE).expect_err("quota should be exceeded");
+        assert_eq!(err.code(), cl_error_t::CL_ETMPFILE as i32);
+        let quota = err.temp_quota_error().expect("should be a quota error");
+        assert_eq!(quota.requested, YARA_RULE.len() as u64);
+        assert_eq!(strategy.created.load(Ordering::SeqCst), 0);
+
+        scanner.set_temp_quota(Some(YARA_RULE.len() as u64));
+        scanner.load_yara_rules(YARA_RULE).expect("failed to load rules");
+        assert_eq!(strategy.created.load(Ordering::SeqCst), 1);
+        assert_eq!(scanner.temp_bytes(), 0);
+    }
+
+    #[test]
+    fn load_yara_file_checks_extension() {
+        crate::initialize().expect("initialize should succeed");
+        let dir = tempfile::tempdir().unwrap();
+        let scanner = Engine::new().unwrap();
+        for name in &["rules.yar", "rules.YARA"] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, YARA_RULE).unwrap();
+            assert!(scanner.load_yara_file(&path).is_ok(), "{} should load", name);
+        }
+        let path = dir.path().join("rules.txt");
+        std::fs::write(&path, YARA_RULE).unwrap();
+        let result = scanner.load_yara_file(&path);
+        assert_eq!(result.err().map(|e| e.code()), Some(cl_error_t::CL_EARG as i32));
+    }
+
+    #[test]
+    fn detection_recognizes_yara_names() {
+        let yara = Detection::from("YARA.some_rule.UNOFFICIAL");
+        assert!(yara.is_yara());
+        assert_eq!(yara.yara_rule(), Some("some_rule"));
+        let signature = Detection::from("Win.Test.EICAR_HDB-1");
+        assert!(!signature.is_yara());
+        assert_eq!(signature.yara_rule(), None);
     }
 
     #[test]
     fn load_databases_success() {
         crate::initialize().expect("initialize should succeed");
-        let scanner = Engine::new();
+        let scanner = Engine::new().unwrap();
         let result = scanner.load_databases(TEST_DATABASES_PATH);
         assert!(result.is_ok(), "load should succeed");
         assert!(
@@ -516,19 +4930,55 @@ mod tests {
     #[test]
     fn load_databases_with_file_success() {
         crate::initialize().expect("initialize should succeed");
-        let scanner = Engine::new();
+        let scanner = Engine::new().unwrap();
         let result = scanner.load_databases(EXAMPLE_DATABASE_PATH);
         assert!(result.is_ok(), "load should succeed");
-        assert!(
-            result.unwrap().signature_count > 0,
-            "should load some signatures"
-        );
+        let stats = result.unwrap();
+        assert!(stats.signature_count > 0, "should load some signatures");
+        assert_eq!(stats.options, DbFlags::CL_DB_STDOPT);
+    }
+
+    #[test]
+    fn load_databases_with_options_loads_pua_signatures() {
+        crate::initialize().expect("initialize should succeed");
+        let db_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            db_dir.path().join("pua.ndb"),
+            "Test.Marker:0:*:4d41524b4552\nPUA.Tool.Marker:0:*:50554d41524b4552\n",
+        )
+        .unwrap();
+        let path = db_dir.path().to_str().unwrap();
+
+        let standard = Engine::new().unwrap().load_databases(path).expect("failed to load db");
+        let options = DbFlags::CL_DB_STDOPT | DbFlags::CL_DB_PUA;
+        let pua = Engine::new()
+            .unwrap()
+            .load_databases_with_options(path, options)
+            .expect("failed to load db");
+        assert_eq!(pua.options, options);
+        assert!(pua.signature_count > standard.signature_count);
+    }
+
+    #[test]
+    fn load_databases_records_duration() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        let stats = scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        assert!(stats.load_duration > time::Duration::from_secs(0));
+    }
+
+    #[test]
+    fn database_stats_default_is_empty() {
+        let stats = DatabaseStats::default();
+        assert_eq!(stats, DatabaseStats::new(0, time::Duration::from_secs(0), DbFlags::empty()));
     }
 
     #[test]
     fn load_databases_fake_path_fails() {
         crate::initialize().expect("initialize should succeed");
-        let scanner = Engine::new();
+        let scanner = Engine::new().unwrap();
         assert!(
             scanner.load_databases("/dev/null").is_err(),
             "should fail to load invalid databases"
@@ -538,13 +4988,13 @@ mod tests {
     #[test]
     fn scan_naughty_file_matches() {
         crate::initialize().expect("initialize should succeed");
-        let scanner = Engine::new();
+        let scanner = Engine::new().unwrap();
         scanner
             .load_databases(EXAMPLE_DATABASE_PATH)
             .expect("failed to load db");
         scanner.compile().expect("failed to compile");
-        let mut settings: ScanSettings = Default::default();
-        let result = scanner.scan_file(NAUGHTY_FILE_PATH, &mut settings);
+        let settings: ScanSettings = Default::default();
+        let result = scanner.scan_file(NAUGHTY_FILE_PATH, &settings);
         assert!(result.is_ok(), "scan should succeed");
         let hit = result.unwrap();
         match hit {
@@ -558,13 +5008,13 @@ mod tests {
     #[test]
     fn scan_good_file_success() {
         crate::initialize().expect("initialize should succeed");
-        let scanner = Engine::new();
+        let scanner = Engine::new().unwrap();
         scanner
             .load_databases(EXAMPLE_DATABASE_PATH)
             .expect("failed to load db");
         scanner.compile().expect("failed to compile");
-        let mut settings: ScanSettings = Default::default();
-        let result = scanner.scan_file(GOOD_FILE_PATH, &mut settings);
+        let settings: ScanSettings = Default::default();
+        let result = scanner.scan_file(GOOD_FILE_PATH, &settings);
         assert!(result.is_ok(), "scan should succeed");
         let hit = result.unwrap();
         match hit {
@@ -573,18 +5023,156 @@ mod tests {
         }
     }
 
+    #[test]
+    fn scan_file_auto_matches_scan_file() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
+        for path in &[GOOD_FILE_PATH, NAUGHTY_FILE_PATH, "/nonexistent/file"] {
+            let expected = scanner.scan_file(path, &settings);
+            for &mode in &[SniffMode::Narrow, SniffMode::Off] {
+                let actual = scanner.scan_file_auto(path, &settings, mode);
+                assert_eq!(actual, expected, "verdict for {} with {:?} should not change", path, mode);
+            }
+        }
+        assert_eq!(
+            settings.settings,
+            clamav_sys::cl_scan_options::default(),
+            "settings should not be modified"
+        );
+    }
+
+    #[test]
+    fn scan_file_auto_parses_archive_after_text_prefix() {
+        use std::io::Write;
+
+        let scanner = example_engine();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let prefix = b"2021-01-01 12:00:00 INFO service started\n".repeat(2 * sniff::SNIFF_LEN);
+        file.write_all(&prefix).unwrap();
+        file.write_all(&std::fs::read(NESTED_ZIP_PATH).unwrap()).unwrap();
+        file.flush().unwrap();
+        let path = file.path().to_str().unwrap();
+
+        assert_eq!(sniff::sniff_file(file.path()).unwrap(), sniff::FileKind::Other);
+        let settings = ScanSettings::default();
+        let expected = scanner.scan_file(path, &settings);
+        assert_eq!(scanner.scan_file_auto(path, &settings, SniffMode::Narrow), expected);
+    }
+
+    #[test]
+    fn scan_file_windowed_whole_file_matches() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
+        let config = WindowConfig { window_size: 1024 * 1024, overlap: 1024 };
+        let report = scanner
+            .scan_file_windowed(NAUGHTY_FILE_PATH, &settings, config)
+            .expect("scan should succeed");
+        assert_eq!(report.windows, 1);
+        assert_eq!(report.detections.len(), 1);
+        assert_eq!(report.detections[0].offset, 0);
+        assert_eq!(report.result(), ScanResult::Virus("naughty_file.UNOFFICIAL".into()));
+
+        let report = scanner
+            .scan_file_windowed(GOOD_FILE_PATH, &settings, config)
+            .expect("scan should succeed");
+        assert_eq!(report.result(), ScanResult::Clean);
+    }
+
+    #[test]
+    fn scan_file_windowed_rejects_overlap_larger_than_window() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
+        let config = WindowConfig { window_size: 4096, overlap: 4096 };
+        let result = scanner.scan_file_windowed(GOOD_FILE_PATH, &settings, config);
+        assert_eq!(result.err().map(|e| e.code()), Some(cl_error_t::CL_EARG as i32));
+    }
+
+    // Scans a 2 GiB file, which takes minutes and needs a file system with
+    // sparse files; run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    #[cfg(unix)]
+    fn scan_file_windowed_finds_eicar_in_sparse_file() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        const EICAR: &[u8] = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+        const WINDOW: u64 = 16 * 1024 * 1024;
+        const OVERLAP: u64 = 4096;
+        const SIZE: u64 = 2 * 1024 * 1024 * 1024 + 12345;
+
+        // A body signature matching EICAR at any offset, unlike the hash
+        // signature in the official databases which only matches the whole file
+        let db_dir = tempfile::tempdir().unwrap();
+        let hex: String = EICAR.iter().map(|b| format!("{:02x}", b)).collect();
+        std::fs::write(db_dir.path().join("windowed.ndb"), format!("Windowed.Test.Eicar:0:*:{}\n", hex)).unwrap();
+
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(db_dir.path().to_str().unwrap())
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+
+        let step = WINDOW - OVERLAP;
+        let offsets = [
+            0,
+            1024 * 1024 * 1024 + 777,
+            // straddles the end of window 10, but lies within the overlap
+            10 * step + WINDOW - 30,
+            SIZE - EICAR.len() as u64,
+        ];
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.as_file().set_len(SIZE).unwrap();
+        for &offset in &offsets {
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.write_all(EICAR).unwrap();
+        }
+        file.flush().unwrap();
+
+        let settings: ScanSettings = Default::default();
+        let config = WindowConfig { window_size: WINDOW, overlap: OVERLAP };
+        let report = scanner
+            .scan_file_windowed(file.path().to_str().unwrap(), &settings, config)
+            .expect("scan should succeed");
+        assert_eq!(report.windows, (SIZE - OVERLAP).div_ceil(step));
+        for &offset in &offsets {
+            assert!(
+                report.detections.iter().any(|d| {
+                    d.name == "Windowed.Test.Eicar"
+                        && d.offset <= offset
+                        && offset + EICAR.len() as u64 <= d.offset + d.len
+                }),
+                "EICAR at offset {} not found in {:?}",
+                offset,
+                report.detections
+            );
+        }
+    }
+
     #[test]
     #[cfg(unix)]
     fn scan_naughty_fd_matches() {
         crate::initialize().expect("initialize should succeed");
-        let scanner = Engine::new();
+        let scanner = Engine::new().unwrap();
         scanner
             .load_databases(EXAMPLE_DATABASE_PATH)
             .expect("failed to load db");
         scanner.compile().expect("failed to compile");
         let settings: ScanSettings = Default::default();
         let file = File::open(NAUGHTY_FILE_PATH).unwrap();
-        let result = scanner.scan_fileobj(&file, &settings);
+        let result = scanner.scan_fileobj(&file, &settings, None);
         assert!(result.is_ok(), "scan should succeed");
         let hit = result.unwrap();
         match hit {
@@ -598,14 +5186,14 @@ mod tests {
     #[test]
     fn scan_good_fd_success() {
         crate::initialize().expect("initialize should succeed");
-        let scanner = Engine::new();
+        let scanner = Engine::new().unwrap();
         scanner
             .load_databases(EXAMPLE_DATABASE_PATH)
             .expect("failed to load db");
         scanner.compile().expect("failed to compile");
-        let mut settings: ScanSettings = Default::default();
+        let settings: ScanSettings = Default::default();
         let file = File::open(GOOD_FILE_PATH).unwrap();
-        let result = scanner.scan_fileobj(&file, &mut settings, Some(GOOD_FILE_PATH));
+        let result = scanner.scan_fileobj(&file, &settings, Some(GOOD_FILE_PATH));
         assert!(result.is_ok(), "scan should succeed");
         let hit = result.unwrap();
         match hit {
@@ -613,4 +5201,61 @@ mod tests {
             _ => panic!("should have matched as a virus"),
         }
     }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn scan_naughty_file_mmap_matches() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
+        let result = scanner.scan_file_mmap(NAUGHTY_FILE_PATH, &settings);
+        assert!(result.is_ok(), "scan should succeed");
+        match result.unwrap() {
+            ScanResult::Virus(name) => {
+                assert_eq!(name, "naughty_file.UNOFFICIAL");
+            }
+            _ => panic!("should have matched as a virus"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn scan_good_file_mmap_success() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
+        let result = scanner.scan_file_mmap(GOOD_FILE_PATH, &settings);
+        assert!(result.is_ok(), "scan should succeed");
+        match result.unwrap() {
+            ScanResult::Clean => {}
+            _ => panic!("should have been clean"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn scan_empty_file_mmap_falls_back() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Engine::new().unwrap();
+        scanner
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        scanner.compile().expect("failed to compile");
+        let settings: ScanSettings = Default::default();
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        let result = scanner.scan_file_mmap(empty.path().to_str().unwrap(), &settings);
+        assert!(result.is_ok(), "scan should succeed");
+        match result.unwrap() {
+            ScanResult::Clean => {}
+            _ => panic!("should have been clean"),
+        }
+    }
 }
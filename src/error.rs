@@ -1,24 +1,70 @@
 use std::ffi::CStr;
 use std::fmt;
+use std::io;
 use std::str;
 use std::error;
+use std::os::raw::c_int;
 
 use clamav_sys::cl_error_t;
 
+/// Why the crate failed an operation before reaching libclamav
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Detail {
+    State(EngineStateError),
+    TempQuota(TempQuotaError),
+}
+
 /// An error indicating a clam failure.
 #[derive(Clone, PartialEq, Eq)]
 pub struct ClamError {
     code: i32,
+    detail: Option<Detail>,
 }
 
 impl ClamError {
     pub fn new(native_err: cl_error_t) -> Self {
         ClamError {
             code: native_err as i32,
+            detail: None,
+        }
+    }
+
+    /// Creates an error from a code libclamav returned as a plain integer.
+    ///
+    /// Codes outside of `cl_error_t` are kept as `CL_ELAST_ERROR` rather
+    /// than reinterpreted.
+    pub(crate) fn from_code(code: c_int) -> Self {
+        let last = cl_error_t::CL_ELAST_ERROR as c_int;
+        ClamError {
+            code: if (0..=last).contains(&code) { code } else { last },
+            detail: None,
+        }
+    }
+
+    /// Returns why the operation was invalid for the engine's lifecycle
+    /// state, if it was rejected before reaching libclamav for that reason.
+    pub fn state_error(&self) -> Option<EngineStateError> {
+        match self.detail {
+            Some(Detail::State(state)) => Some(state),
+            _ => None,
+        }
+    }
+
+    /// Returns the exceeded quota, if a temporary file was refused because
+    /// of the engine's temporary file quota.
+    pub fn temp_quota_error(&self) -> Option<TempQuotaError> {
+        match self.detail {
+            Some(Detail::TempQuota(quota)) => Some(quota),
+            _ => None,
         }
     }
 
     pub fn string_error(&self) -> String {
+        match self.detail {
+            Some(Detail::State(state)) => return state.to_string(),
+            Some(Detail::TempQuota(quota)) => return quota.to_string(),
+            None => (),
+        }
         unsafe {
             let ptr = clamav_sys::cl_strerror(self.code);
             let bytes = CStr::from_ptr(ptr).to_bytes();
@@ -32,8 +78,137 @@ impl ClamError {
     pub fn code(&self) -> i32 {
         self.code
     }
+
+    /// Returns the kind of the error, to match on instead of [`code`].
+    ///
+    /// [`code`]: #method.code
+    pub fn kind(&self) -> ClamErrorKind {
+        KINDS
+            .iter()
+            .find(|(native, _)| *native as i32 == self.code)
+            .map_or(ClamErrorKind::Unknown, |(_, kind)| *kind)
+    }
 }
 
+/// The kind of a [`ClamError`], one per `cl_error_t` code
+///
+/// [`ClamError`]: struct.ClamError.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ClamErrorKind {
+    /// `CL_CLEAN`, also `CL_SUCCESS`
+    Clean,
+    /// `CL_VIRUS`
+    Virus,
+    /// `CL_ENULLARG`, a null argument
+    NullArgument,
+    /// `CL_EARG`, an invalid argument or an operation invalid in the
+    /// engine's state
+    InvalidArgument,
+    /// `CL_EMALFDB`, a malformed database
+    MalformedDatabase,
+    /// `CL_ECVD`, a broken or invalid CVD container
+    Cvd,
+    /// `CL_EVERIFY`, a failed signature verification of a database
+    Verify,
+    /// `CL_EUNPACK`, a failure unpacking a database
+    Unpack,
+    /// `CL_EOPEN`
+    Open,
+    /// `CL_ECREAT`
+    Create,
+    /// `CL_EUNLINK`
+    Unlink,
+    /// `CL_ESTAT`
+    Stat,
+    /// `CL_EREAD`
+    Read,
+    /// `CL_ESEEK`
+    Seek,
+    /// `CL_EWRITE`
+    Write,
+    /// `CL_EDUP`
+    Dup,
+    /// `CL_EACCES`
+    Access,
+    /// `CL_ETMPFILE`
+    TempFile,
+    /// `CL_ETMPDIR`
+    TempDir,
+    /// `CL_EMAP`
+    Map,
+    /// `CL_EMEM`
+    Memory,
+    /// `CL_ETIMEOUT`
+    Timeout,
+    /// `CL_BREAK`
+    Break,
+    /// `CL_EMAXREC`
+    MaxRecursion,
+    /// `CL_EMAXSIZE`
+    MaxSize,
+    /// `CL_EMAXFILES`
+    MaxFiles,
+    /// `CL_EFORMAT`, a malformed file
+    Format,
+    /// `CL_EPARSE`
+    Parse,
+    /// `CL_EBYTECODE`
+    Bytecode,
+    /// `CL_EBYTECODE_TESTFAIL`
+    BytecodeTestFail,
+    /// `CL_ELOCK`
+    Lock,
+    /// `CL_EBUSY`
+    Busy,
+    /// `CL_ESTATE`
+    State,
+    /// `CL_VERIFIED`, content deemed trusted
+    Verified,
+    /// `CL_ERROR`, an unspecified error
+    Unspecified,
+    /// `CL_ELAST_ERROR`, or a code libclamav does not define
+    Unknown,
+}
+
+const KINDS: [(cl_error_t, ClamErrorKind); 35] = [
+    (cl_error_t::CL_CLEAN, ClamErrorKind::Clean),
+    (cl_error_t::CL_VIRUS, ClamErrorKind::Virus),
+    (cl_error_t::CL_ENULLARG, ClamErrorKind::NullArgument),
+    (cl_error_t::CL_EARG, ClamErrorKind::InvalidArgument),
+    (cl_error_t::CL_EMALFDB, ClamErrorKind::MalformedDatabase),
+    (cl_error_t::CL_ECVD, ClamErrorKind::Cvd),
+    (cl_error_t::CL_EVERIFY, ClamErrorKind::Verify),
+    (cl_error_t::CL_EUNPACK, ClamErrorKind::Unpack),
+    (cl_error_t::CL_EOPEN, ClamErrorKind::Open),
+    (cl_error_t::CL_ECREAT, ClamErrorKind::Create),
+    (cl_error_t::CL_EUNLINK, ClamErrorKind::Unlink),
+    (cl_error_t::CL_ESTAT, ClamErrorKind::Stat),
+    (cl_error_t::CL_EREAD, ClamErrorKind::Read),
+    (cl_error_t::CL_ESEEK, ClamErrorKind::Seek),
+    (cl_error_t::CL_EWRITE, ClamErrorKind::Write),
+    (cl_error_t::CL_EDUP, ClamErrorKind::Dup),
+    (cl_error_t::CL_EACCES, ClamErrorKind::Access),
+    (cl_error_t::CL_ETMPFILE, ClamErrorKind::TempFile),
+    (cl_error_t::CL_ETMPDIR, ClamErrorKind::TempDir),
+    (cl_error_t::CL_EMAP, ClamErrorKind::Map),
+    (cl_error_t::CL_EMEM, ClamErrorKind::Memory),
+    (cl_error_t::CL_ETIMEOUT, ClamErrorKind::Timeout),
+    (cl_error_t::CL_BREAK, ClamErrorKind::Break),
+    (cl_error_t::CL_EMAXREC, ClamErrorKind::MaxRecursion),
+    (cl_error_t::CL_EMAXSIZE, ClamErrorKind::MaxSize),
+    (cl_error_t::CL_EMAXFILES, ClamErrorKind::MaxFiles),
+    (cl_error_t::CL_EFORMAT, ClamErrorKind::Format),
+    (cl_error_t::CL_EPARSE, ClamErrorKind::Parse),
+    (cl_error_t::CL_EBYTECODE, ClamErrorKind::Bytecode),
+    (cl_error_t::CL_EBYTECODE_TESTFAIL, ClamErrorKind::BytecodeTestFail),
+    (cl_error_t::CL_ELOCK, ClamErrorKind::Lock),
+    (cl_error_t::CL_EBUSY, ClamErrorKind::Busy),
+    (cl_error_t::CL_ESTATE, ClamErrorKind::State),
+    (cl_error_t::CL_VERIFIED, ClamErrorKind::Verified),
+    (cl_error_t::CL_ERROR, ClamErrorKind::Unspecified),
+];
+
 impl fmt::Display for ClamError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "cl_error {}: {}", self.code, self.string_error())
@@ -52,10 +227,199 @@ impl error::Error for ClamError {
     }
 }
 
+/// Converts the error for code mixing file system access and scans.
+///
+/// File system codes map to the matching [`io::ErrorKind`], e.g. `CL_EACCES`
+/// to `PermissionDenied` and `CL_EOPEN` to `NotFound`; codes without a
+/// counterpart map to `Other`. The `ClamError` is kept as the inner error, so
+/// the message is unchanged and [`io::Error::into_inner`] returns it.
+///
+/// [`io::ErrorKind`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html
+/// [`io::Error::into_inner`]: https://doc.rust-lang.org/std/io/struct.Error.html#method.into_inner
+impl From<ClamError> for io::Error {
+    fn from(err: ClamError) -> Self {
+        io::Error::new(io_error_kind(err.kind()), err)
+    }
+}
+
+fn io_error_kind(kind: ClamErrorKind) -> io::ErrorKind {
+    match kind {
+        ClamErrorKind::Open => io::ErrorKind::NotFound,
+        ClamErrorKind::Access => io::ErrorKind::PermissionDenied,
+        ClamErrorKind::NullArgument | ClamErrorKind::InvalidArgument => io::ErrorKind::InvalidInput,
+        ClamErrorKind::Format | ClamErrorKind::MalformedDatabase | ClamErrorKind::Cvd => io::ErrorKind::InvalidData,
+        ClamErrorKind::Timeout => io::ErrorKind::TimedOut,
+        ClamErrorKind::Memory => io::ErrorKind::OutOfMemory,
+        _ => io::ErrorKind::Other,
+    }
+}
+
+/// An operation which is invalid in the current lifecycle state of an engine
+///
+/// Returned inside a [`ClamError`] with code `CL_EARG`, see
+/// [`ClamError::state_error`].
+///
+/// [`ClamError`]: struct.ClamError.html
+/// [`ClamError::state_error`]: struct.ClamError.html#method.state_error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EngineStateError {
+    /// The engine must be compiled first, e.g. to scan
+    NotCompiled,
+    /// The engine was already compiled, so databases cannot be loaded and
+    /// load-time settings cannot be changed anymore
+    AlreadyCompiled,
+    /// No databases were found yet, see `ScannerBuilder::allow_missing_database`,
+    /// the engine holds no signatures, see `Engine::set_allow_empty`, or no
+    /// versioned database is loaded, see `Engine::database_stats`
+    NoDatabase,
+    /// The engine keeps no index of its signatures, see
+    /// `Engine::with_signature_index`
+    NoSignatureIndex,
+}
+
+impl fmt::Display for EngineStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineStateError::NotCompiled => write!(f, "engine is not compiled, call compile() first"),
+            EngineStateError::AlreadyCompiled => write!(
+                f,
+                "engine is already compiled, load databases and change load-time settings before compile()"
+            ),
+            EngineStateError::NoDatabase => write!(f, "no databases loaded yet, reload() once they are available"),
+            EngineStateError::NoSignatureIndex => {
+                write!(f, "engine has no signature index, create it with with_signature_index()")
+            }
+        }
+    }
+}
+
+impl error::Error for EngineStateError {}
+
+impl From<EngineStateError> for ClamError {
+    fn from(err: EngineStateError) -> Self {
+        ClamError {
+            code: cl_error_t::CL_EARG as i32,
+            detail: Some(Detail::State(err)),
+        }
+    }
+}
+
+/// A temporary file which would have exceeded the engine's quota, see
+/// `Engine::set_temp_quota`
+///
+/// Returned inside a [`ClamError`] with code `CL_ETMPFILE`, see
+/// [`ClamError::temp_quota_error`].
+///
+/// [`ClamError`]: struct.ClamError.html
+/// [`ClamError::temp_quota_error`]: struct.ClamError.html#method.temp_quota_error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TempQuotaError {
+    /// Size of the refused file
+    pub requested: u64,
+    /// Bytes held in other temporary files at the time
+    pub used: u64,
+    /// The quota
+    pub quota: u64,
+}
+
+impl fmt::Display for TempQuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "temporary file of {} bytes exceeds the quota of {} bytes, {} bytes in use",
+            self.requested, self.quota, self.used
+        )
+    }
+}
+
+impl error::Error for TempQuotaError {}
+
+impl From<TempQuotaError> for ClamError {
+    fn from(err: TempQuotaError) -> Self {
+        ClamError {
+            code: cl_error_t::CL_ETMPFILE as i32,
+            detail: Some(Detail::TempQuota(err)),
+        }
+    }
+}
+
+/// An error returned by [`initialize`]
+///
+/// [`initialize`]: fn.initialize.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InitError {
+    /// `cl_init` failed
+    Clam(ClamError),
+    /// `cl_initialize_crypto` failed with the given return code. CVD
+    /// signature verification will not work.
+    Crypto(i32),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InitError::Clam(err) => write!(f, "failed to initialize libclamav: {}", err),
+            InitError::Crypto(code) => write!(f, "failed to initialize crypto, code {}", code),
+        }
+    }
+}
+
+impl error::Error for InitError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            InitError::Clam(err) => Some(err),
+            InitError::Crypto(_) => None,
+        }
+    }
+}
+
+impl From<ClamError> for InitError {
+    fn from(err: ClamError) -> Self {
+        InitError::Clam(err)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn kind_matches_code() {
+        assert_eq!(ClamError::new(cl_error_t::CL_EMALFDB).kind(), ClamErrorKind::MalformedDatabase);
+        assert_eq!(ClamError::from(EngineStateError::NotCompiled).kind(), ClamErrorKind::InvalidArgument);
+        assert_eq!(ClamError::from_code(-1).kind(), ClamErrorKind::Unknown);
+        for (native, kind) in KINDS.iter() {
+            assert_eq!(ClamError::new(*native).kind(), *kind);
+        }
+    }
+
+    #[test]
+    fn converts_into_io_error() {
+        let err = ClamError::new(cl_error_t::CL_EACCES);
+        let message = err.to_string();
+        let io_err = io::Error::from(err.clone());
+        assert_eq!(io_err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(io_err.to_string(), message);
+        let inner = io_err.into_inner().unwrap().downcast::<ClamError>().unwrap();
+        assert_eq!(*inner, err);
+
+        let io_err = io::Error::from(ClamError::from(EngineStateError::NotCompiled));
+        assert_eq!(io_err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(io::Error::from(ClamError::new(cl_error_t::CL_EBUSY)).kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn unknown_codes_map_to_last_error() {
+        let known = ClamError::from_code(cl_error_t::CL_EMEM as c_int);
+        assert_eq!(known.code(), cl_error_t::CL_EMEM as i32);
+        for code in &[-1, cl_error_t::CL_ELAST_ERROR as c_int + 1, c_int::MAX, c_int::MIN] {
+            let err = ClamError::from_code(*code);
+            assert_eq!(err.code(), cl_error_t::CL_ELAST_ERROR as i32);
+            // must not panic
+            let _ = err.string_error();
+        }
+    }
+
     #[test]
     fn error_as_string_success() {
         let err = ClamError::new(cl_error_t::CL_EFORMAT);
@@ -65,4 +429,14 @@ mod tests {
             "error description should contain string error"
         );
     }
+
+    #[test]
+    fn state_error_is_described() {
+        let err = ClamError::from(EngineStateError::NotCompiled);
+        assert_eq!(err.code(), cl_error_t::CL_EARG as i32);
+        assert_eq!(err.state_error(), Some(EngineStateError::NotCompiled));
+        assert!(err.to_string().contains("not compiled"), "{}", err);
+        assert_ne!(err, ClamError::new(cl_error_t::CL_EARG));
+        assert_eq!(ClamError::new(cl_error_t::CL_EARG).state_error(), None);
+    }
 }
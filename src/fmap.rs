@@ -16,9 +16,16 @@
 // MA 02110-1301, USA.
 //
 
+use std::cmp;
+use std::convert::TryFrom;
 use std::fmt;
+use std::fs::File;
+#[cfg(unix)]
+use std::io;
 use std::result;
+use std::ops;
 use std::os;
+use std::path::Path;
 use std::error;
 
 #[cfg(windows)]
@@ -65,8 +72,37 @@ impl MapError {
 
 pub type Result<T> = result::Result<T, MapError>;
 
+/// Validates the arguments libclamav passes to the pread callback.
+///
+/// Returns the number of bytes to request from the OS, or `None` if the call
+/// must fail. A zero count succeeds without touching the buffer, so a null
+/// buffer is only an error when bytes were requested. The count is clamped so
+/// that the byte count read always fits the callback's return type.
+fn pread_request(buf: *mut os::raw::c_void, count: u64, offset: os::raw::c_long) -> Option<usize> {
+    if offset < 0 {
+        return None;
+    }
+    if count == 0 {
+        return Some(0);
+    }
+    if buf.is_null() {
+        return None;
+    }
+    let max = cmp::min(isize::MAX as u64, os::raw::c_long::MAX as u64);
+    Some(cmp::min(count, max) as usize)
+}
+
+/// Reads up to `count` bytes at `offset` into `buf`.
+///
+/// Returns the number of bytes read, which is 0 at or past the end of the
+/// file, or -1 on error.
 #[cfg(windows)]
-extern fn cl_pread(handle: *mut os::raw::c_void, buf: *mut os::raw::c_void, count: os::raw::c_ulonglong, offset: os::raw::c_long) -> os::raw::c_long {
+extern "C" fn cl_pread(handle: *mut os::raw::c_void, buf: *mut os::raw::c_void, count: os::raw::c_ulonglong, offset: os::raw::c_long) -> os::raw::c_long {
+    let count = match pread_request(buf, count, offset) {
+        Some(0) => return 0,
+        Some(count) => cmp::min(count, u32::MAX as usize) as u32,
+        None => return -1,
+    };
     let mut read_bytes = 0;
 
     unsafe {
@@ -74,7 +110,7 @@ extern fn cl_pread(handle: *mut os::raw::c_void, buf: *mut os::raw::c_void, coun
         overlapped.InternalHigh = (offset as usize) >> 32;
         overlapped.Internal = (offset as usize) & 0xffffffff;
 
-        if !ReadFile(std::mem::transmute::<_, HANDLE>(handle), buf, count as u32, &mut read_bytes, &mut overlapped).as_bool() {
+        if !ReadFile(std::mem::transmute::<_, HANDLE>(handle), buf, count, &mut read_bytes, &mut overlapped).as_bool() {
             let err = GetLastError();
             if err != ERROR_HANDLE_EOF {
                 return -1;
@@ -82,46 +118,351 @@ extern fn cl_pread(handle: *mut os::raw::c_void, buf: *mut os::raw::c_void, coun
         }
     }
 
-    read_bytes as i32
+    read_bytes as os::raw::c_long
 }
 
+/// Reads up to `count` bytes at `offset` into `buf`.
+///
+/// Returns the number of bytes read, which is 0 at or past the end of the
+/// file, or -1 on error. Reads interrupted by a signal are retried.
 #[cfg(unix)]
-extern fn cl_pread(handle: *mut os::raw::c_void, buf: *mut os::raw::c_void, count: os::raw::c_ulonglong, offset: os::raw::c_long) -> os::raw::c_long {
-    use std::convert::TryInto;
-    unsafe {
-        libc::pread(handle as i32, buf, count.try_into().unwrap(), offset).try_into().unwrap()
+extern "C" fn cl_pread(handle: *mut os::raw::c_void, buf: *mut os::raw::c_void, count: os::raw::c_ulonglong, offset: os::raw::c_long) -> os::raw::c_long {
+    let count = match pread_request(buf, count, offset) {
+        Some(0) => return 0,
+        Some(count) => count,
+        None => return -1,
+    };
+
+    loop {
+        // The handle is the file descriptor given to `Fmap::new_from_handle`
+        let read = unsafe { libc::pread(handle as RawOsHandle, buf, count, offset as libc::off_t) };
+        if read >= 0 {
+            return read as os::raw::c_long;
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return -1;
+        }
     }
 }
 
-#[allow(dead_code)]
-pub struct Fmap(*mut cl_fmap_t);
+/// A libclamav map of a memory buffer or file
+pub struct Fmap {
+    map: *mut cl_fmap_t,
+    /// Duplicate of the mapped file when created from a `File`, closed after the map
+    _file: Option<File>,
+}
 
 impl Fmap {
     pub fn new_from_memory(start: *const u8, len: u64) -> Result< Fmap > {
         let map = unsafe { cl_fmap_open_memory(start as *const os::raw::c_void, len) };
-        if map.is_null() {
-            Err(MapError::new())
-        }
-        else {
-            Ok(Fmap(map))
-        }
+        Self::from_raw_map(map, None)
+    }
+
+    /// Maps `data`, which the returned map owns and frees after the map.
+    ///
+    /// Unlike [`new_from_memory`], the buffer cannot be dropped while the
+    /// map is in use.
+    ///
+    /// [`new_from_memory`]: #method.new_from_memory
+    pub fn from_bytes(data: Vec<u8>) -> Result<OwnedFmap> {
+        let map = Fmap::new_from_memory(data.as_ptr(), data.len() as u64)?;
+        Ok(OwnedFmap { map, _data: Some(data) })
     }
 
     pub fn new_from_handle(handle: RawOsHandle, offset: u64, len: u64, use_ageing: bool) -> Result< Fmap > {
         let map = unsafe { cl_fmap_open_handle(handle as *mut os::raw::c_void, offset, len, Some(cl_pread), use_ageing.into() ) };
+        Self::from_raw_map(map, None)
+    }
+
+    /// Maps `len` bytes of `file` starting at `offset`.
+    ///
+    /// Like `TryFrom<&File>`, the map reads through a duplicate of the
+    /// handle of `file`, so it may outlive `file` and does not move its
+    /// position. Prefer it over [`new_from_handle`], which leaves it to the
+    /// caller to keep the handle open while the map is in use.
+    ///
+    /// [`new_from_handle`]: #method.new_from_handle
+    pub fn from_file(file: &File, offset: u64, len: u64, use_ageing: bool) -> Result<Fmap> {
+        let file = file.try_clone().map_err(|_| MapError::new())?;
+        let map = unsafe {
+            cl_fmap_open_handle(
+                raw_os_handle(&file) as *mut os::raw::c_void,
+                offset,
+                len,
+                Some(cl_pread),
+                use_ageing.into(),
+            )
+        };
+        Fmap::from_raw_map(map, Some(file))
+    }
+
+    fn from_raw_map(map: *mut cl_fmap_t, file: Option<File>) -> Result< Fmap > {
         if map.is_null() {
             Err(MapError::new())
         }
         else {
-            Ok(Fmap(map))
+            Ok(Fmap { map, _file: file })
         }
     }
 
-    pub fn raw(& self) -> *mut cl_fmap_t {self.0}
+    pub fn raw(& self) -> *mut cl_fmap_t {self.map}
 }
 
 impl Drop for Fmap {
     fn drop(&mut self) -> () {
-        unsafe {cl_fmap_close(self.0)};
+        unsafe {cl_fmap_close(self.map)};
+        // the duplicated handle, if any, is closed when `_file` is dropped
+    }
+}
+
+#[cfg(unix)]
+fn raw_os_handle(file: &File) -> RawOsHandle {
+    use std::os::unix::io::AsRawFd;
+    file.as_raw_fd()
+}
+
+#[cfg(windows)]
+fn raw_os_handle(file: &File) -> RawOsHandle {
+    use std::os::windows::io::AsRawHandle;
+    file.as_raw_handle()
+}
+
+/// Maps the whole file through a duplicate of its handle.
+///
+/// The map reads at explicit offsets, so it neither uses nor moves the
+/// position of `file`, and `file` may be closed while the map is in use.
+impl TryFrom<&File> for Fmap {
+    type Error = MapError;
+
+    fn try_from(file: &File) -> Result<Fmap> {
+        let len = file.metadata().map_err(|_| MapError::new())?.len();
+        Fmap::from_file(file, 0, len, false)
+    }
+}
+
+/// A map owning what it maps, a file opened by path, see `TryFrom<&Path>`,
+/// or a buffer, see `Fmap::from_bytes`
+pub struct OwnedFmap {
+    map: Fmap,
+    /// Mapped buffer, dropped after `map`
+    _data: Option<Vec<u8>>,
+}
+
+impl OwnedFmap {
+    /// Opens and maps the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<OwnedFmap> {
+        let file = File::open(path).map_err(|_| MapError::new())?;
+        Ok(OwnedFmap {
+            map: Fmap::try_from(&file)?,
+            _data: None,
+        })
+    }
+}
+
+impl TryFrom<&Path> for OwnedFmap {
+    type Error = MapError;
+
+    fn try_from(path: &Path) -> Result<OwnedFmap> {
+        OwnedFmap::open(path)
+    }
+}
+
+impl ops::Deref for OwnedFmap {
+    type Target = Fmap;
+
+    fn deref(&self) -> &Fmap {
+        &self.map
+    }
+}
+
+/// Types which can be scanned as a map, accepted by `Engine::scan_map`
+pub trait AsFmap {
+    fn as_fmap(&self) -> &Fmap;
+}
+
+impl AsFmap for Fmap {
+    fn as_fmap(&self) -> &Fmap {
+        self
+    }
+}
+
+impl AsFmap for OwnedFmap {
+    fn as_fmap(&self) -> &Fmap {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn pread_request_accepts_zero_count_without_buffer() {
+        assert_eq!(pread_request(ptr::null_mut(), 0, 0), Some(0));
+    }
+
+    #[test]
+    fn pread_request_rejects_null_buffer() {
+        assert_eq!(pread_request(ptr::null_mut(), 1, 0), None);
+    }
+
+    #[test]
+    fn pread_request_rejects_negative_offset() {
+        let mut buf = [0u8; 4];
+        assert_eq!(pread_request(buf.as_mut_ptr().cast(), 4, -1), None);
+    }
+
+    #[test]
+    fn pread_request_clamps_count() {
+        let mut buf = [0u8; 4];
+        let count = pread_request(buf.as_mut_ptr().cast(), u64::MAX, 0).unwrap();
+        assert!(count as u64 <= isize::MAX as u64);
+        assert!(count as u64 <= os::raw::c_long::MAX as u64);
+        assert_eq!(pread_request(buf.as_mut_ptr().cast(), 4, 0), Some(4));
+    }
+
+    mod conversions {
+        use super::*;
+        use crate::engine::ScanResult;
+        use crate::scan_settings::ScanSettings;
+        use crate::test_support::{example_engine, GOOD_FILE_PATH, NAUGHTY_FILE_PATH};
+        use std::convert::TryInto;
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn fmap_from_file_outlives_file() {
+            let engine = example_engine();
+            let file = File::open(NAUGHTY_FILE_PATH).unwrap();
+            let map: Fmap = (&file).try_into().expect("failed to map file");
+            drop(file);
+            let result = engine.scan_map(&map, None, &ScanSettings::default());
+            assert_eq!(result, Ok(ScanResult::Virus("naughty_file.UNOFFICIAL".into())));
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn fmap_from_file_maps_full_length() {
+            let engine = example_engine();
+            let settings = ScanSettings::default();
+            let file = File::open(GOOD_FILE_PATH).unwrap();
+            let len = file.metadata().unwrap().len();
+            let map = Fmap::from_file(&file, 0, len, true).expect("failed to map file");
+            assert_eq!(engine.scan_map(&map, None, &settings), Ok(ScanResult::Clean));
+
+            let file = File::open(NAUGHTY_FILE_PATH).unwrap();
+            let len = file.metadata().unwrap().len();
+            let map = Fmap::from_file(&file, 0, len, false).expect("failed to map file");
+            drop(file);
+            assert_eq!(
+                engine.scan_map(&map, None, &settings),
+                Ok(ScanResult::Virus("naughty_file.UNOFFICIAL".into()))
+            );
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn owned_fmap_from_path_scans() {
+            let engine = example_engine();
+            let settings = ScanSettings::default();
+            let good = OwnedFmap::try_from(Path::new(GOOD_FILE_PATH)).expect("failed to map file");
+            assert_eq!(engine.scan_map(&good, None, &settings), Ok(ScanResult::Clean));
+            let naughty = OwnedFmap::try_from(Path::new(NAUGHTY_FILE_PATH)).expect("failed to map file");
+            assert_eq!(
+                engine.scan_map(&naughty, None, &settings),
+                Ok(ScanResult::Virus("naughty_file.UNOFFICIAL".into()))
+            );
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn fmap_from_bytes_owns_buffer() {
+            let engine = example_engine();
+            let data = std::fs::read(NAUGHTY_FILE_PATH).unwrap();
+            let map = Fmap::from_bytes(data).expect("failed to map buffer");
+            assert_eq!(
+                engine.scan_map(&map, None, &ScanSettings::default()),
+                Ok(ScanResult::Virus("naughty_file.UNOFFICIAL".into()))
+            );
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn owned_fmap_missing_path_fails() {
+            assert!(OwnedFmap::try_from(Path::new("test_data/files/does_not_exist")).is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    mod unix {
+        use super::*;
+        use crate::test_support::GOOD_FILE_PATH;
+        use std::fs::{self, File};
+        use std::os::unix::io::AsRawFd;
+
+        fn read_at(file: &File, buf: &mut [u8], offset: os::raw::c_long) -> os::raw::c_long {
+            cl_pread(file.as_raw_fd() as *mut os::raw::c_void, buf.as_mut_ptr().cast(), buf.len() as u64, offset)
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn reads_whole_file() {
+            let contents = fs::read(GOOD_FILE_PATH).unwrap();
+            let file = File::open(GOOD_FILE_PATH).unwrap();
+            let mut buf = vec![0u8; contents.len()];
+            assert_eq!(read_at(&file, &mut buf, 0), contents.len() as os::raw::c_long);
+            assert_eq!(buf, contents);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn short_read_returns_bytes_before_eof() {
+            let contents = fs::read(GOOD_FILE_PATH).unwrap();
+            let file = File::open(GOOD_FILE_PATH).unwrap();
+            let offset = contents.len() - 1;
+            let mut buf = [0u8; 16];
+            assert_eq!(read_at(&file, &mut buf, offset as os::raw::c_long), 1);
+            assert_eq!(buf[0], contents[offset]);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn offset_past_eof_returns_zero() {
+            let len = fs::metadata(GOOD_FILE_PATH).unwrap().len();
+            let file = File::open(GOOD_FILE_PATH).unwrap();
+            let mut buf = [0u8; 16];
+            assert_eq!(read_at(&file, &mut buf, len as os::raw::c_long), 0);
+            assert_eq!(read_at(&file, &mut buf, len as os::raw::c_long + 4096), 0);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn zero_count_returns_zero() {
+            let file = File::open(GOOD_FILE_PATH).unwrap();
+            assert_eq!(cl_pread(file.as_raw_fd() as *mut os::raw::c_void, ptr::null_mut(), 0, 0), 0);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn null_buffer_returns_error() {
+            let file = File::open(GOOD_FILE_PATH).unwrap();
+            assert_eq!(cl_pread(file.as_raw_fd() as *mut os::raw::c_void, ptr::null_mut(), 16, 0), -1);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn negative_offset_returns_error() {
+            let file = File::open(GOOD_FILE_PATH).unwrap();
+            let mut buf = [0u8; 16];
+            assert_eq!(read_at(&file, &mut buf, -1), -1);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn bad_handle_returns_error() {
+            let mut buf = [0u8; 16];
+            let bad = -1 as RawOsHandle;
+            assert_eq!(cl_pread(bad as *mut os::raw::c_void, buf.as_mut_ptr().cast(), 16, 0), -1);
+        }
     }
 }
@@ -1,50 +1,176 @@
-use std::sync::Once;
+use std::sync::{Mutex, MutexGuard, Once, PoisonError};
 
+pub mod buffer;
+pub mod control;
 pub mod db;
 pub mod engine;
+#[cfg(feature = "metadata")]
+pub mod metadata;
 mod error;
+mod message;
+mod partition;
+pub mod perf;
+pub mod policy;
+#[cfg(target_os = "linux")]
+mod procmaps;
+pub mod scan;
 pub mod scan_settings;
+pub mod scanner;
+pub mod shared;
+pub mod sink;
+pub mod sniff;
+pub mod temp;
+mod trace;
 pub mod version;
 pub mod fmap;
+pub mod pool;
+pub mod prelude;
+pub mod report;
+pub mod request;
+pub mod warmup;
 #[cfg(windows)]
 pub mod windows_fd;
+#[cfg(test)]
+pub(crate) mod test_support;
+
+pub use engine::ScanResult;
+pub use error::{ClamError, ClamErrorKind, EngineStateError, InitError, TempQuotaError};
+pub use message::{clear_message_callback, set_debug, set_message_callback, Severity};
+pub use scan_settings::ScanSettings;
 
-pub use error::ClamError;
+use std::os::raw::c_int;
 
 use clamav_sys::{
+    cl_cleanup_crypto,
     cl_error_t,
     cl_init,
     cl_initialize_crypto,
 };
 
+/// The process-wide initialization steps, replaceable in tests
+trait Initializer {
+    fn init(&self) -> cl_error_t;
+    fn init_crypto(&self) -> c_int;
+    fn cleanup_crypto(&self);
+}
+
+struct Libclamav;
+
+impl Initializer for Libclamav {
+    fn init(&self) -> cl_error_t {
+        unsafe { cl_init(clamav_sys::CL_INIT_DEFAULT) }
+    }
+
+    fn init_crypto(&self) -> c_int {
+        unsafe { cl_initialize_crypto() }
+    }
+
+    fn cleanup_crypto(&self) {
+        unsafe { cl_cleanup_crypto() }
+    }
+}
+
+/// Outcome of the initialization steps run so far
+struct InitState {
+    /// Code of `cl_init`, which runs at most once per process
+    init: Option<cl_error_t>,
+    /// Code of `cl_initialize_crypto`, `None` before it ran and after
+    /// `cleanup`
+    crypto: Option<c_int>,
+}
+
+impl InitState {
+    const fn new() -> Self {
+        InitState { init: None, crypto: None }
+    }
+}
+
+static STATE: Mutex<InitState> = Mutex::new(InitState::new());
+
+fn lock_state() -> MutexGuard<'static, InitState> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs the initialization steps which did not run yet. Crypto is not
+/// initialized if `cl_init` fails.
+fn initialize_with<I: Initializer>(state: &mut InitState, initializer: &I) -> Result<(), InitError> {
+    let result = *state.init.get_or_insert_with(|| initializer.init());
+    if result != cl_error_t::CL_SUCCESS {
+        return init_outcome(result, 0);
+    }
+    let crypto_result = *state.crypto.get_or_insert_with(|| initializer.init_crypto());
+    init_outcome(result, crypto_result)
+}
+
+/// Cleans up crypto if it was initialized, so the next initialization
+/// runs it again.
+fn cleanup_with<I: Initializer>(state: &mut InitState, initializer: &I) {
+    if state.crypto.take() == Some(0) {
+        initializer.cleanup_crypto();
+    }
+}
+
+fn init_outcome(result: cl_error_t, crypto_result: c_int) -> Result<(), InitError> {
+    match (result, crypto_result) {
+        (cl_error_t::CL_SUCCESS, 0) => Ok(()),
+        (cl_error_t::CL_SUCCESS, code) => Err(InitError::Crypto(code)),
+        _ => Err(InitError::Clam(ClamError::new(result))),
+    }
+}
+
 /// Initializes clamav
 ///
-/// This must be called once per process. This is safe to call multiple times.
-pub fn initialize() -> Result<(), ClamError> {
+/// This must be called once per process. This is safe to call multiple times
+/// and from multiple threads; later calls return the outcome of the first
+/// one, unless [`cleanup`] ran in between, in which case crypto is
+/// initialized again. Crypto is cleaned up when the process exits.
+///
+/// # Errors
+///
+/// Returns [`InitError::Clam`] if `cl_init` fails and [`InitError::Crypto`] if
+/// the crypto library, which is needed to verify CVD signatures, cannot be
+/// initialized.
+///
+/// [`cleanup`]: fn.cleanup.html
+/// [`InitError::Clam`]: enum.InitError.html#variant.Clam
+/// [`InitError::Crypto`]: enum.InitError.html#variant.Crypto
+pub fn initialize() -> Result<(), InitError> {
+    extern "C" fn cleanup_at_exit() {
+        // SAFETY: atexit handlers run once main has returned, when no engine
+        // loads or scans anymore.
+        unsafe { cleanup() }
+    }
+
+    static REGISTER_CLEANUP: Once = Once::new();
     // the cl_init implementation isn't thread-safe, which is painful for tests
-    static ONCE: Once = Once::new();
-    static mut RESULT: cl_error_t = cl_error_t::CL_SUCCESS;
-    unsafe {
-        ONCE.call_once(|| {
-            RESULT = cl_init(clamav_sys::CL_INIT_DEFAULT);
-            // this function always returns OK
-            if RESULT == cl_error_t::CL_SUCCESS {
-                cl_initialize_crypto();
-                libc::atexit(cleanup);
-            }
+    let outcome = initialize_with(&mut lock_state(), &Libclamav);
+    if outcome.is_ok() {
+        REGISTER_CLEANUP.call_once(|| unsafe {
+            libc::atexit(cleanup_at_exit);
         });
-
-        extern "C" fn cleanup() {
-            unsafe {
-                clamav_sys::cl_cleanup_crypto();
-            }
-        }
-
-        match RESULT {
-            cl_error_t::CL_SUCCESS => Ok(()),
-            _ => Err(ClamError::new(RESULT)),
-        }
     }
+    outcome
+}
+
+/// Cleans up the crypto library set up by [`initialize`].
+///
+/// Use it to tear down before unloading the crate, e.g. with `dlclose`, or
+/// between tests. A later [`initialize`] sets up crypto again; `cl_init`
+/// itself runs only once per process. Calling it again, or without a
+/// successful [`initialize`], does nothing.
+///
+/// It may be called from any thread. Engines stay valid, but loading signed
+/// databases fails until crypto is initialized again.
+///
+/// # Safety
+///
+/// No engine may load databases or scan, on any thread, while this runs, as
+/// libclamav verifies signatures with the crypto library without any locking
+/// of its own.
+///
+/// [`initialize`]: fn.initialize.html
+pub unsafe fn cleanup() {
+    cleanup_with(&mut lock_state(), &Libclamav);
 }
 
 pub fn version() -> String {
@@ -63,8 +189,96 @@ pub fn version() -> String {
 mod tests {
     use super::*;
 
+    use std::cell::Cell;
+
+    struct MockInitializer {
+        result: cl_error_t,
+        crypto_result: c_int,
+        crypto_calls: Cell<usize>,
+        cleanup_calls: Cell<usize>,
+    }
+
+    impl MockInitializer {
+        fn new(result: cl_error_t, crypto_result: c_int) -> Self {
+            MockInitializer {
+                result,
+                crypto_result,
+                crypto_calls: Cell::new(0),
+                cleanup_calls: Cell::new(0),
+            }
+        }
+    }
+
+    impl Initializer for MockInitializer {
+        fn init(&self) -> cl_error_t {
+            self.result
+        }
+
+        fn init_crypto(&self) -> c_int {
+            self.crypto_calls.set(self.crypto_calls.get() + 1);
+            self.crypto_result
+        }
+
+        fn cleanup_crypto(&self) {
+            self.cleanup_calls.set(self.cleanup_calls.get() + 1);
+        }
+    }
+
+    fn outcome_of(initializer: &MockInitializer) -> Result<(), InitError> {
+        initialize_with(&mut InitState::new(), initializer)
+    }
+
     #[test]
     fn initialize_success() {
         assert!(initialize().is_ok(), "initialize should succeed");
+        assert!(initialize().is_ok(), "repeated initialize should succeed");
+    }
+
+    #[test]
+    fn crypto_failure_is_reported() {
+        let initializer = MockInitializer::new(cl_error_t::CL_SUCCESS, -1);
+        assert_eq!(outcome_of(&initializer), Err(InitError::Crypto(-1)));
+        assert_eq!(initializer.crypto_calls.get(), 1);
+    }
+
+    #[test]
+    fn crypto_is_skipped_if_init_fails() {
+        let initializer = MockInitializer::new(cl_error_t::CL_EMEM, -1);
+        assert_eq!(
+            outcome_of(&initializer),
+            Err(InitError::Clam(ClamError::new(cl_error_t::CL_EMEM)))
+        );
+        assert_eq!(initializer.crypto_calls.get(), 0);
+    }
+
+    #[test]
+    fn cleanup_is_idempotent_and_allows_reinitializing() {
+        let initializer = MockInitializer::new(cl_error_t::CL_SUCCESS, 0);
+        let mut state = InitState::new();
+        assert_eq!(initialize_with(&mut state, &initializer), Ok(()));
+        assert_eq!(initialize_with(&mut state, &initializer), Ok(()));
+        assert_eq!(initializer.crypto_calls.get(), 1);
+
+        cleanup_with(&mut state, &initializer);
+        cleanup_with(&mut state, &initializer);
+        assert_eq!(initializer.cleanup_calls.get(), 1);
+
+        assert_eq!(initialize_with(&mut state, &initializer), Ok(()));
+        assert_eq!(initializer.crypto_calls.get(), 2);
+    }
+
+    #[test]
+    fn failed_crypto_is_not_cleaned_up() {
+        let initializer = MockInitializer::new(cl_error_t::CL_SUCCESS, -1);
+        let mut state = InitState::new();
+        assert_eq!(initialize_with(&mut state, &initializer), Err(InitError::Crypto(-1)));
+        cleanup_with(&mut state, &initializer);
+        assert_eq!(initializer.cleanup_calls.get(), 0);
+    }
+
+    #[test]
+    fn mock_success() {
+        let initializer = MockInitializer::new(cl_error_t::CL_SUCCESS, 0);
+        assert_eq!(outcome_of(&initializer), Ok(()));
     }
 }
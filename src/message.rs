@@ -0,0 +1,195 @@
+//! Routing of libclamav's log messages.
+//!
+//! libclamav reports warnings, errors and verbose info messages through a
+//! single process-wide callback. The crate registers it once, the first time
+//! a message callback is set or a scan collects performance info, and then
+//! dispatches each message:
+//!
+//! - info messages of a scan collecting performance info are captured for
+//!   its `PerfInfo`, see the [`perf`] module,
+//! - every message is passed to the closure set with
+//!   [`set_message_callback`], if any,
+//! - otherwise warnings and errors are written to stderr as libclamav does
+//!   by default, and info messages are dropped.
+//!
+//! Debug messages, enabled with [`set_debug`], are info messages which are
+//! written to stderr if no closure is set.
+//!
+//! [`perf`]: ../perf/index.html
+//! [`set_message_callback`]: fn.set_message_callback.html
+//! [`set_debug`]: fn.set_debug.html
+
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once, PoisonError};
+
+use clamav_sys::cl_msg;
+
+use crate::perf;
+
+/// Severity of a libclamav message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Verbose information, `CL_MSG_INFO_VERBOSE`
+    Info,
+    /// `CL_MSG_WARN`
+    Warning,
+    /// `CL_MSG_ERROR`
+    Error,
+}
+
+impl Severity {
+    fn from_raw(severity: cl_msg) -> Severity {
+        match severity {
+            cl_msg::CL_MSG_INFO_VERBOSE => Severity::Info,
+            cl_msg::CL_MSG_WARN => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+type MessageCallback = Box<dyn FnMut(Severity, &str) + Send>;
+
+static CALLBACK: Mutex<Option<MessageCallback>> = Mutex::new(None);
+
+/// Whether libclamav's debug messages are passed on
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// Prefix libclamav gives its debug messages
+const DEBUG_PREFIX: &[u8] = b"LibClamAV debug";
+
+thread_local! {
+    /// Set while the message callback runs on this thread
+    static DISPATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Routes libclamav's messages to `callback` instead of stderr.
+///
+/// The callback receives the full message as libclamav formats it, e.g.
+/// `LibClamAV Warning: ...` including the trailing newline. It replaces a
+/// previously set callback and is called for the messages of all engines
+/// and threads of the process, one message at a time.
+///
+/// Messages libclamav emits while the callback runs on the same thread are
+/// written to stderr instead, so a callback cannot recurse into itself. The
+/// callback must not set or clear the message callback. A panicking
+/// callback drops the message.
+pub fn set_message_callback<F>(callback: F)
+where
+    F: FnMut(Severity, &str) + Send + 'static,
+{
+    *CALLBACK.lock().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(callback));
+    register();
+}
+
+/// Removes the callback set with [`set_message_callback`], so warnings and
+/// errors are written to stderr again.
+///
+/// [`set_message_callback`]: fn.set_message_callback.html
+pub fn clear_message_callback() {
+    CALLBACK.lock().unwrap_or_else(PoisonError::into_inner).take();
+}
+
+/// Turns libclamav's debug messages on or off, for all engines of the
+/// process.
+///
+/// They are routed like the other messages: passed to the callback set with
+/// [`set_message_callback`], or written to stderr if there is none.
+///
+/// libclamav cannot turn its debug messages off again, so turning them off
+/// drops them in the crate instead; libclamav keeps formatting them, which
+/// slows down loading and scanning.
+///
+/// [`set_message_callback`]: fn.set_message_callback.html
+pub fn set_debug(enabled: bool) {
+    DEBUG.store(enabled, Ordering::Relaxed);
+    register();
+    if enabled {
+        unsafe { clamav_sys::cl_debug() };
+    }
+}
+
+/// Installs the crate's message callback in libclamav, once per process.
+pub(crate) fn register() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| unsafe { clamav_sys::cl_set_clcb_msg(Some(on_message)) });
+}
+
+/// Passes `fullmsg` to the message callback. Returns false if there is none
+/// or it is already running on this thread.
+fn dispatch(severity: Severity, fullmsg: &str) -> bool {
+    if DISPATCHING.with(Cell::get) {
+        return false;
+    }
+    let mut callback = CALLBACK.lock().unwrap_or_else(PoisonError::into_inner);
+    let callback = match callback.as_mut() {
+        Some(callback) => callback,
+        None => return false,
+    };
+    DISPATCHING.with(|dispatching| dispatching.set(true));
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| callback(severity, fullmsg)));
+    DISPATCHING.with(|dispatching| dispatching.set(false));
+    true
+}
+
+unsafe extern "C" fn on_message(severity: cl_msg, fullmsg: *const c_char, msg: *const c_char, _context: *mut c_void) {
+    let severity = Severity::from_raw(severity);
+    if severity == Severity::Info && !msg.is_null() {
+        perf::capture_message(&CStr::from_ptr(msg).to_string_lossy());
+    }
+    if fullmsg.is_null() {
+        return;
+    }
+    let fullmsg = CStr::from_ptr(fullmsg);
+    let debug = fullmsg.to_bytes().starts_with(DEBUG_PREFIX);
+    if debug && !DEBUG.load(Ordering::Relaxed) {
+        return;
+    }
+    if !dispatch(severity, &fullmsg.to_string_lossy()) && (severity != Severity::Info || debug) {
+        // the default callback's behaviour for warnings and errors
+        let _ = io::stderr().write_all(fullmsg.to_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use std::sync::Arc;
+
+    #[test]
+    fn malformed_database_reports_messages() {
+        crate::initialize().expect("initialize should succeed");
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let sink = messages.clone();
+        set_message_callback(move |severity, message| {
+            sink.lock().unwrap().push((severity, message.to_string()));
+        });
+
+        let db_dir = tempfile::tempdir().unwrap();
+        std::fs::write(db_dir.path().join("broken.ndb"), "Broken.Sig:0:*:zz\n").unwrap();
+        let engine = Engine::new().unwrap();
+        assert!(engine.load_databases(db_dir.path().to_str().unwrap()).is_err());
+        clear_message_callback();
+
+        let messages = messages.lock().unwrap();
+        assert!(
+            messages
+                .iter()
+                .any(|(severity, message)| *severity >= Severity::Warning && message.contains("LibClamAV")),
+            "no warning or error in {:?}",
+            messages
+        );
+    }
+
+    #[test]
+    fn severity_from_raw() {
+        assert_eq!(Severity::from_raw(cl_msg::CL_MSG_INFO_VERBOSE), Severity::Info);
+        assert_eq!(Severity::from_raw(cl_msg::CL_MSG_WARN), Severity::Warning);
+        assert_eq!(Severity::from_raw(cl_msg::CL_MSG_ERROR), Severity::Error);
+    }
+}
@@ -0,0 +1,300 @@
+//! Typed view of the scan metadata JSON.
+//!
+//! With `CL_SCAN_GENERAL_COLLECT_METADATA`, libclamav describes every scanned
+//! object as JSON: its type, size and hash, format specific properties such
+//! as the PE header, and the objects extracted from it. [`Metadata::parse`]
+//! models the stable parts of this document. Fields are optional, values of
+//! an unexpected type are treated as missing, and unknown fields are kept in
+//! [`Object::other`], so documents from newer ClamAV versions still parse.
+//!
+//! This module requires the `metadata` feature.
+//!
+//! [`Metadata::parse`]: struct.Metadata.html#method.parse
+//! [`Object::other`]: struct.Object.html#structfield.other
+
+use std::error;
+use std::fmt;
+
+use serde::de::{DeserializeOwned, Deserializer};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Deserializes a field, treating a value of an unexpected type as missing
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+fn lenient_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    lenient(deserializer).map(Option::unwrap_or_default)
+}
+
+/// A scanned object: the scanned file itself or an object extracted from it
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Object {
+    /// Name of the object, e.g. the member name inside an archive
+    #[serde(rename = "FileName", default, deserialize_with = "lenient")]
+    pub file_name: Option<String>,
+    /// libclamav type, e.g. `CL_TYPE_MSEXE`
+    #[serde(rename = "FileType", default, deserialize_with = "lenient")]
+    pub file_type: Option<String>,
+    #[serde(rename = "FileSize", default, deserialize_with = "lenient")]
+    pub file_size: Option<u64>,
+    /// Hex encoded MD5 of the object
+    #[serde(rename = "FileMD5", default, deserialize_with = "lenient")]
+    pub file_md5: Option<String>,
+    /// Set on OLE2 and OOXML documents containing VBA macros
+    #[serde(rename = "HasMacros", default, deserialize_with = "lenient")]
+    pub has_macros: Option<bool>,
+    /// Names of the signatures which matched this object
+    #[serde(rename = "Viruses", default, deserialize_with = "lenient_vec")]
+    pub viruses: Vec<String>,
+    /// PE header properties of executables
+    #[serde(rename = "PE", default, deserialize_with = "lenient")]
+    pub pe: Option<Map<String, Value>>,
+    /// OLE2 properties of legacy Office documents
+    #[serde(rename = "OLE2", default, deserialize_with = "lenient")]
+    pub ole2: Option<Map<String, Value>>,
+    /// Objects extracted from this one
+    #[serde(rename = "ContainedObjects", default, deserialize_with = "lenient_vec")]
+    pub contained_objects: Vec<Object>,
+    /// All other properties
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl Object {
+    /// Returns this object and all objects below it, depth first.
+    pub fn objects(&self) -> Vec<&Object> {
+        let mut objects = Vec::new();
+        let mut pending = vec![self];
+        while let Some(object) = pending.pop() {
+            objects.push(object);
+            pending.extend(object.contained_objects.iter().rev());
+        }
+        objects
+    }
+}
+
+/// The metadata document of one scan
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::metadata::Metadata;
+///
+/// let json = r#"{
+///     "Magic": "CLAMJSONv0",
+///     "RootFileType": "CL_TYPE_ZIP",
+///     "FileType": "CL_TYPE_ZIP",
+///     "ContainedObjects": [{ "FileName": "a.txt", "FileType": "CL_TYPE_TEXT_ASCII" }]
+/// }"#;
+/// let metadata = Metadata::parse(json).expect("invalid metadata");
+/// assert_eq!(metadata.contained_types(), vec!["CL_TYPE_TEXT_ASCII"]);
+/// assert!(!metadata.has_macros());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Metadata {
+    /// Format marker, `CLAMJSONv0` for all current versions
+    #[serde(rename = "Magic", default, deserialize_with = "lenient")]
+    pub magic: Option<String>,
+    #[serde(rename = "RootFileType", default, deserialize_with = "lenient")]
+    pub root_file_type: Option<String>,
+    /// The scanned file
+    #[serde(flatten)]
+    pub root: Object,
+}
+
+impl Metadata {
+    /// Parses a metadata document.
+    ///
+    /// # Errors
+    ///
+    /// Fails only if `json` is not a JSON object.
+    pub fn parse(json: &str) -> Result<Metadata, ParseMetadataError> {
+        serde_json::from_str(json).map_err(|source| ParseMetadataError { source })
+    }
+
+    /// Returns the scanned file and all objects extracted from it, depth first.
+    pub fn objects(&self) -> Vec<&Object> {
+        self.root.objects()
+    }
+
+    /// Returns the types of all objects extracted from the scanned file,
+    /// depth first.
+    pub fn contained_types(&self) -> Vec<&str> {
+        self.objects()
+            .into_iter()
+            .skip(1)
+            .filter_map(|object| object.file_type.as_deref())
+            .collect()
+    }
+
+    /// Returns true if the scanned file or any object in it has VBA macros.
+    pub fn has_macros(&self) -> bool {
+        self.objects()
+            .iter()
+            .any(|object| object.has_macros == Some(true))
+    }
+}
+
+/// Error returned by [`Metadata::parse`]
+///
+/// [`Metadata::parse`]: struct.Metadata.html#method.parse
+#[derive(Debug)]
+pub struct ParseMetadataError {
+    source: serde_json::Error,
+}
+
+impl fmt::Display for ParseMetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid scan metadata: {}", self.source)
+    }
+}
+
+impl error::Error for ParseMetadataError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_settings::{GeneralFlags, ScanSettings};
+    use crate::test_support::example_engine;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    // Written by hand after the layout of ClamAV 0.103 output, see
+    // test_data/metadata/README.md; `parse_libclamav_output` checks the model
+    // against the documents of the linked libclamav.
+    const PE_METADATA_PATH: &str = "test_data/metadata/pe.json";
+    const DOCX_METADATA_PATH: &str = "test_data/metadata/docx.json";
+    const NESTED_ZIP_PATH: &str = "test_data/files/nested.zip";
+
+    fn parse_fixture(path: &str) -> Metadata {
+        let json = fs::read_to_string(path).expect("failed to read fixture");
+        Metadata::parse(&json).expect("fixture should parse")
+    }
+
+    #[test]
+    fn parse_pe() {
+        let metadata = parse_fixture(PE_METADATA_PATH);
+        assert_eq!(metadata.magic.as_deref(), Some("CLAMJSONv0"));
+        assert_eq!(metadata.root_file_type.as_deref(), Some("CL_TYPE_MSEXE"));
+        let root = &metadata.root;
+        assert_eq!(root.file_type.as_deref(), Some("CL_TYPE_MSEXE"));
+        assert_eq!(root.file_size, Some(73802));
+        assert_eq!(root.file_md5.as_deref(), Some("5a0b8f8d1e7a9f2b3c4d6e8f01234567"));
+        let pe = root.pe.as_ref().expect("PE properties");
+        assert_eq!(pe.get("NumberOfSections").and_then(Value::as_u64), Some(4));
+        assert_eq!(pe.get("EntryPoint").and_then(Value::as_str), Some("0x4014e0"));
+        assert!(metadata.contained_types().is_empty());
+        assert!(!metadata.has_macros());
+    }
+
+    #[test]
+    fn parse_docx() {
+        let metadata = parse_fixture(DOCX_METADATA_PATH);
+        assert_eq!(metadata.root_file_type.as_deref(), Some("CL_TYPE_OOXML_WORD"));
+        assert_eq!(metadata.root.file_size, Some(12151));
+        assert!(metadata.root.pe.is_none());
+        assert!(metadata.root.other.contains_key("CoreProperties"));
+        assert_eq!(
+            metadata.contained_types(),
+            vec![
+                "CL_TYPE_XML_WORD",
+                "CL_TYPE_XML_WORD",
+                "CL_TYPE_XML_WORD",
+                "CL_TYPE_PNG",
+            ]
+        );
+        assert!(!metadata.has_macros());
+    }
+
+    #[test]
+    fn parse_libclamav_output() {
+        let scanner = example_engine();
+        let documents = Arc::new(Mutex::new(Vec::new()));
+        let seen = documents.clone();
+        scanner
+            .set_file_props_callback(move |json| seen.lock().unwrap().push(json.to_string()))
+            .unwrap();
+        let mut settings = ScanSettings::default();
+        settings.set_general(settings.general() | GeneralFlags::CL_SCAN_GENERAL_COLLECT_METADATA);
+        scanner.scan_file(NESTED_ZIP_PATH, &settings).unwrap();
+
+        let documents = documents.lock().unwrap();
+        assert_eq!(documents.len(), 1);
+        let metadata = Metadata::parse(&documents[0]).expect("libclamav output should parse");
+        assert!(matches!(metadata.magic.as_deref(), Some(magic) if magic.starts_with("CLAMJSON")));
+        assert_eq!(metadata.root_file_type.as_deref(), Some("CL_TYPE_ZIP"));
+        assert_eq!(metadata.root.file_size, Some(fs::metadata(NESTED_ZIP_PATH).unwrap().len()));
+        assert!(!metadata.contained_types().is_empty(), "no contained objects in {}", documents[0]);
+    }
+
+    #[test]
+    fn has_macros_in_nested_object() {
+        let json = r#"{
+            "FileType": "CL_TYPE_ZIP",
+            "ContainedObjects": [
+                { "FileType": "CL_TYPE_TEXT_ASCII" },
+                { "FileType": "CL_TYPE_MSOLE2", "HasMacros": true }
+            ]
+        }"#;
+        let metadata = Metadata::parse(json).unwrap();
+        assert!(metadata.has_macros());
+        assert_eq!(metadata.contained_types(), vec!["CL_TYPE_TEXT_ASCII", "CL_TYPE_MSOLE2"]);
+    }
+
+    #[test]
+    fn contained_types_are_depth_first() {
+        let json = r#"{
+            "FileType": "CL_TYPE_ZIP",
+            "ContainedObjects": [
+                { "FileType": "CL_TYPE_ZIP", "ContainedObjects": [{ "FileType": "CL_TYPE_MSEXE" }] },
+                { "FileType": "CL_TYPE_PDF" }
+            ]
+        }"#;
+        let metadata = Metadata::parse(json).unwrap();
+        assert_eq!(
+            metadata.contained_types(),
+            vec!["CL_TYPE_ZIP", "CL_TYPE_MSEXE", "CL_TYPE_PDF"]
+        );
+    }
+
+    #[test]
+    fn unknown_fields_and_types_are_tolerated() {
+        let json = r#"{
+            "Magic": "CLAMJSONv1",
+            "FileType": "CL_TYPE_MSEXE",
+            "FileSize": "large",
+            "HasMacros": 1,
+            "Viruses": "not a list",
+            "ContainedObjects": {},
+            "FutureProperty": { "Nested": [1, 2, 3] }
+        }"#;
+        let metadata = Metadata::parse(json).expect("unexpected fields should be tolerated");
+        assert_eq!(metadata.magic.as_deref(), Some("CLAMJSONv1"));
+        assert_eq!(metadata.root.file_size, None);
+        assert_eq!(metadata.root.has_macros, None);
+        assert!(metadata.root.viruses.is_empty());
+        assert!(metadata.root.contained_objects.is_empty());
+        assert!(metadata.root.other.contains_key("FutureProperty"));
+    }
+
+    #[test]
+    fn parse_rejects_non_objects() {
+        assert!(Metadata::parse("[]").is_err());
+        assert!(Metadata::parse("{").is_err());
+    }
+}
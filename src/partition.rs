@@ -0,0 +1,309 @@
+//! Minimal MBR and GPT partition table reader for disk image scanning.
+//!
+//! Only what is needed to find the partitions of a raw image is read: the
+//! primary MBR entries, or the GPT entries behind a protective MBR. Logical
+//! partitions inside extended MBR partitions are not followed and GPT
+//! checksums are not verified; libclamav's own partition parsers see the
+//! whole image anyway. A GPT header announcing more than 128 entries, or
+//! entries larger than 4 KiB, is treated like a missing table, as no tool
+//! writes such tables and reading them would cost up to 2^32 reads.
+
+use std::cmp;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::scan::PartitionScheme;
+
+const SECTOR_SIZE: u64 = 512;
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MIN_ENTRY_SIZE: u32 = 128;
+const GPT_MAX_ENTRY_SIZE: u32 = 4096;
+/// Entries in the minimum 16 KiB entry array, which all partitioning tools use
+const GPT_MAX_ENTRIES: u32 = 128;
+
+/// A partition found in a disk image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Partition {
+    pub(crate) scheme: PartitionScheme,
+    /// Position in the partition table
+    pub(crate) index: usize,
+    /// Offset of the first byte in the image
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+}
+
+fn read_at(file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// Builds a partition from a sector range, clipped to the image.
+fn clipped(scheme: PartitionScheme, index: usize, first_sector: u64, sectors: u64, image_len: u64) -> Option<Partition> {
+    let offset = first_sector.checked_mul(SECTOR_SIZE)?;
+    if sectors == 0 || offset >= image_len {
+        return None;
+    }
+    let len = cmp::min(sectors.saturating_mul(SECTOR_SIZE), image_len - offset);
+    Some(Partition { scheme, index, offset, len })
+}
+
+/// Reads the partitions of a raw disk image, in table order.
+///
+/// Returns an empty list if the image has no recognizable partition table.
+/// At most `max_partitions` partitions are returned.
+pub(crate) fn read_partitions(file: &File, max_partitions: usize) -> io::Result<Vec<Partition>> {
+    let image_len = file.metadata()?.len();
+    if image_len < SECTOR_SIZE {
+        return Ok(Vec::new());
+    }
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    read_at(file, 0, &mut mbr)?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let entries: Vec<&[u8]> = (0..4)
+        .map(|i| &mbr[MBR_ENTRIES_OFFSET + 16 * i..MBR_ENTRIES_OFFSET + 16 * (i + 1)])
+        .collect();
+    if entries.iter().any(|entry| entry[4] == MBR_TYPE_GPT_PROTECTIVE) {
+        return read_gpt(file, image_len, max_partitions);
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let kind = entry[4];
+        if kind == MBR_TYPE_EMPTY || MBR_TYPE_EXTENDED.contains(&kind) {
+            continue;
+        }
+        let first = u64::from(u32_at(entry, 8));
+        let sectors = u64::from(u32_at(entry, 12));
+        partitions.extend(clipped(PartitionScheme::Mbr, index, first, sectors, image_len));
+    }
+    partitions.truncate(max_partitions);
+    Ok(partitions)
+}
+
+fn read_gpt(file: &File, image_len: u64, max_partitions: usize) -> io::Result<Vec<Partition>> {
+    if image_len < 2 * SECTOR_SIZE {
+        return Ok(Vec::new());
+    }
+    let mut header = [0u8; SECTOR_SIZE as usize];
+    read_at(file, SECTOR_SIZE, &mut header)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Ok(Vec::new());
+    }
+    let entries_offset = match u64_at(&header, 72).checked_mul(SECTOR_SIZE) {
+        Some(offset) => offset,
+        None => return Ok(Vec::new()),
+    };
+    let count = u32_at(&header, 80);
+    let entry_size = u32_at(&header, 84);
+    if !(GPT_MIN_ENTRY_SIZE..=GPT_MAX_ENTRY_SIZE).contains(&entry_size) || count > GPT_MAX_ENTRIES {
+        return Ok(Vec::new());
+    }
+
+    let mut partitions = Vec::new();
+    let mut entry = vec![0u8; entry_size as usize];
+    for index in 0..count as usize {
+        if partitions.len() >= max_partitions {
+            break;
+        }
+        let offset = entries_offset + index as u64 * u64::from(entry_size);
+        if offset + u64::from(entry_size) > image_len {
+            break;
+        }
+        read_at(file, offset, &mut entry)?;
+        // an all-zero type GUID marks an unused entry
+        if entry[0..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first = u64_at(&entry, 32);
+        let last = u64_at(&entry, 40);
+        if last < first {
+            continue;
+        }
+        partitions.extend(clipped(PartitionScheme::Gpt, index, first, last - first + 1, image_len));
+    }
+    Ok(partitions)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+
+    pub(crate) const PARTITION_FIRST_SECTOR: u64 = 64;
+    pub(crate) const PARTITION_SECTORS: u64 = 256;
+
+    /// A FAT12 file system with a single file `name` (8.3, space padded)
+    pub(crate) fn fat12(name: &[u8; 11], contents: &[u8]) -> Vec<u8> {
+        const RESERVED: usize = 1;
+        const FATS: usize = 2;
+        const FAT_SECTORS: usize = 1;
+        const ROOT_SECTORS: usize = 1;
+        let sector = SECTOR_SIZE as usize;
+        let mut fs = vec![0u8; PARTITION_SECTORS as usize * sector];
+
+        // boot sector with BIOS parameter block
+        fs[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        fs[3..11].copy_from_slice(b"MSWIN4.1");
+        fs[11..13].copy_from_slice(&(sector as u16).to_le_bytes());
+        fs[13] = 1; // sectors per cluster
+        fs[14..16].copy_from_slice(&(RESERVED as u16).to_le_bytes());
+        fs[16] = FATS as u8;
+        fs[17..19].copy_from_slice(&16u16.to_le_bytes()); // root entries
+        fs[19..21].copy_from_slice(&(PARTITION_SECTORS as u16).to_le_bytes());
+        fs[21] = 0xf8; // media descriptor
+        fs[22..24].copy_from_slice(&(FAT_SECTORS as u16).to_le_bytes());
+        fs[54..62].copy_from_slice(b"FAT12   ");
+        fs[510..512].copy_from_slice(&MBR_SIGNATURE);
+
+        // both FATs: media entry, reserved entry, cluster 2 is the end of the file
+        for fat in 0..FATS {
+            let start = (RESERVED + fat * FAT_SECTORS) * sector;
+            fs[start..start + 5].copy_from_slice(&[0xf8, 0xff, 0xff, 0xff, 0x0f]);
+        }
+
+        // root directory entry pointing at cluster 2
+        let root = (RESERVED + FATS * FAT_SECTORS) * sector;
+        fs[root..root + 11].copy_from_slice(name);
+        fs[root + 11] = 0x20; // archive attribute
+        fs[root + 26..root + 28].copy_from_slice(&2u16.to_le_bytes());
+        fs[root + 28..root + 32].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+
+        let data = root + ROOT_SECTORS * sector;
+        fs[data..data + contents.len()].copy_from_slice(contents);
+        fs
+    }
+
+    fn mbr_entry(mbr: &mut [u8], index: usize, kind: u8, first: u32, sectors: u32) {
+        let entry = MBR_ENTRIES_OFFSET + 16 * index;
+        mbr[entry + 4] = kind;
+        mbr[entry + 8..entry + 12].copy_from_slice(&first.to_le_bytes());
+        mbr[entry + 12..entry + 16].copy_from_slice(&sectors.to_le_bytes());
+    }
+
+    fn image_with(partition: &[u8], tables: Vec<(u64, Vec<u8>)>) -> tempfile::NamedTempFile {
+        let mut image = vec![0u8; ((PARTITION_FIRST_SECTOR + PARTITION_SECTORS + 34) * SECTOR_SIZE) as usize];
+        for (offset, table) in tables {
+            image[offset as usize..offset as usize + table.len()].copy_from_slice(&table);
+        }
+        let start = (PARTITION_FIRST_SECTOR * SECTOR_SIZE) as usize;
+        image[start..start + partition.len()].copy_from_slice(partition);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&image).unwrap();
+        file
+    }
+
+    /// A raw image with an MBR and one FAT12 partition
+    pub(crate) fn mbr_image(partition: &[u8]) -> tempfile::NamedTempFile {
+        let mut mbr = vec![0u8; SECTOR_SIZE as usize];
+        mbr_entry(&mut mbr, 0, 0x01, PARTITION_FIRST_SECTOR as u32, PARTITION_SECTORS as u32);
+        mbr[510..512].copy_from_slice(&MBR_SIGNATURE);
+        image_with(partition, vec![(0, mbr)])
+    }
+
+    /// A raw image with a protective MBR, a GPT and one FAT12 partition in entry 1
+    pub(crate) fn gpt_image(partition: &[u8]) -> tempfile::NamedTempFile {
+        let mut mbr = vec![0u8; SECTOR_SIZE as usize];
+        mbr_entry(&mut mbr, 0, MBR_TYPE_GPT_PROTECTIVE, 1, u32::MAX);
+        mbr[510..512].copy_from_slice(&MBR_SIGNATURE);
+
+        let mut header = vec![0u8; SECTOR_SIZE as usize];
+        header[0..8].copy_from_slice(GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&[0x00, 0x00, 0x01, 0x00]); // revision 1.0
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[72..80].copy_from_slice(&2u64.to_le_bytes()); // entries start at LBA 2
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+
+        // entry 0 is unused, entry 1 holds the partition
+        let mut entries = vec![0u8; 4 * 128];
+        let entry = &mut entries[128..256];
+        entry[0..16].copy_from_slice(&[0xa2, 0xa0, 0xd0, 0xeb, 0xe5, 0xb9, 0x33, 0x44, 0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7]);
+        entry[16..32].copy_from_slice(&[0x11; 16]);
+        entry[32..40].copy_from_slice(&PARTITION_FIRST_SECTOR.to_le_bytes());
+        entry[40..48].copy_from_slice(&(PARTITION_FIRST_SECTOR + PARTITION_SECTORS - 1).to_le_bytes());
+
+        image_with(partition, vec![(0, mbr), (SECTOR_SIZE, header), (2 * SECTOR_SIZE, entries)])
+    }
+
+    fn expected(scheme: PartitionScheme, index: usize) -> Vec<Partition> {
+        vec![Partition {
+            scheme,
+            index,
+            offset: PARTITION_FIRST_SECTOR * SECTOR_SIZE,
+            len: PARTITION_SECTORS * SECTOR_SIZE,
+        }]
+    }
+
+    #[test]
+    fn reads_mbr_partitions() {
+        let image = mbr_image(&fat12(b"README  TXT", b"hello"));
+        let partitions = read_partitions(image.as_file(), 16).unwrap();
+        assert_eq!(partitions, expected(PartitionScheme::Mbr, 0));
+    }
+
+    #[test]
+    fn reads_gpt_partitions() {
+        let image = gpt_image(&fat12(b"README  TXT", b"hello"));
+        let partitions = read_partitions(image.as_file(), 16).unwrap();
+        assert_eq!(partitions, expected(PartitionScheme::Gpt, 1));
+    }
+
+    #[test]
+    fn gpt_with_too_many_entries_is_rejected() {
+        for &(count, entry_size) in &[(GPT_MAX_ENTRIES + 1, 128), (u32::MAX, 128), (4, GPT_MAX_ENTRY_SIZE * 2)] {
+            let mut image = gpt_image(&fat12(b"README  TXT", b"hello"));
+            image.seek(SeekFrom::Start(SECTOR_SIZE + 80)).unwrap();
+            image.write_all(&count.to_le_bytes()).unwrap();
+            image.write_all(&entry_size.to_le_bytes()).unwrap();
+            image.flush().unwrap();
+            let partitions = read_partitions(image.as_file(), 16).unwrap();
+            assert!(partitions.is_empty(), "{} entries of {} bytes: {:?}", count, entry_size, partitions);
+        }
+    }
+
+    #[test]
+    fn image_without_table_has_no_partitions() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[0x42; 4096]).unwrap();
+        assert!(read_partitions(file.as_file(), 16).unwrap().is_empty());
+    }
+
+    #[test]
+    fn partitions_are_clipped_to_image() {
+        let mut mbr = vec![0u8; 2048];
+        mbr_entry(&mut mbr, 0, 0x83, 2, 1000);
+        mbr_entry(&mut mbr, 1, 0x83, 100, 10);
+        mbr[510..512].copy_from_slice(&MBR_SIGNATURE);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&mbr).unwrap();
+        let partitions = read_partitions(file.as_file(), 16).unwrap();
+        assert_eq!(
+            partitions,
+            vec![Partition { scheme: PartitionScheme::Mbr, index: 0, offset: 1024, len: 1024 }]
+        );
+    }
+
+    #[test]
+    fn partition_count_is_limited() {
+        let image = mbr_image(&fat12(b"README  TXT", b"hello"));
+        assert!(read_partitions(image.as_file(), 0).unwrap().is_empty());
+    }
+}
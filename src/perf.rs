@@ -0,0 +1,236 @@
+//! Performance information of single scans.
+//!
+//! With `CL_SCAN_DEV_COLLECT_PERFORMANCE_INFO`, set by
+//! `ScanSettingsBuilder::collect_performance_info`, libclamav measures the
+//! time spent in each parser and reports it as an info message. The scan
+//! functions returning a `ScanOutcome` capture these messages and attach
+//! them as [`PerfInfo`] to the outcome.
+//!
+//! Messages are captured through libclamav's process-wide message callback,
+//! which the crate shares with [`set_message_callback`]. It is registered by
+//! the first scan collecting performance info, if no message callback was
+//! set before.
+//!
+//! [`PerfInfo`]: struct.PerfInfo.html
+//! [`set_message_callback`]: ../fn.set_message_callback.html
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use clamav_sys::CL_SCAN_DEV_COLLECT_PERFORMANCE_INFO;
+
+use crate::message;
+use crate::scan_settings::ScanSettings;
+
+/// Marker libclamav puts in front of the timings
+const PERFORMANCE_MARKER: &str = "performance:";
+
+/// Timings of one scan
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PerfInfo {
+    /// Time spent per parser or scan stage, in the order libclamav reports them
+    pub per_parser: Vec<(String, Duration)>,
+    /// Time spent in libclamav, measured around the scan call
+    pub total: Duration,
+    /// Captured messages, or parts of them, which could not be parsed
+    pub raw: Vec<String>,
+}
+
+/// Parses a duration such as `12.345ms`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let seconds = match unit.trim() {
+        "s" => number,
+        "ms" => number / 1e3,
+        "us" | "µs" => number / 1e6,
+        "ns" => number / 1e9,
+        _ => return None,
+    };
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(Duration::from_secs_f64(seconds))
+    } else {
+        None
+    }
+}
+
+/// Parses one `name: duration` entry.
+fn parse_entry(entry: &str) -> Option<(String, Duration)> {
+    let (name, value) = entry.rsplit_once(':')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), parse_duration(value)?))
+}
+
+impl PerfInfo {
+    /// Parses the info messages captured during a scan.
+    ///
+    /// Timings are read from messages of the form
+    /// `performance: <name>: <duration>, <name>: <duration>, ...`. Anything
+    /// else is kept in `raw`, so a changed format loses timings but no data.
+    pub(crate) fn parse(messages: &[String], total: Duration) -> PerfInfo {
+        let mut info = PerfInfo {
+            total,
+            ..PerfInfo::default()
+        };
+        for message in messages {
+            let message = message.trim();
+            let timings = match message.find(PERFORMANCE_MARKER) {
+                Some(start) => &message[start + PERFORMANCE_MARKER.len()..],
+                None => {
+                    if !message.is_empty() {
+                        info.raw.push(message.to_string());
+                    }
+                    continue;
+                }
+            };
+            for entry in timings.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+                match parse_entry(entry) {
+                    Some(timing) => info.per_parser.push(timing),
+                    None => info.raw.push(entry.to_string()),
+                }
+            }
+        }
+        info
+    }
+}
+
+thread_local! {
+    /// Info messages of the performance collecting scan running on this thread
+    static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Captures an info message for the performance collecting scan running on
+/// this thread, if any.
+pub(crate) fn capture_message(msg: &str) {
+    CAPTURED.with(|captured| {
+        if let Ok(mut captured) = captured.try_borrow_mut() {
+            if let Some(messages) = captured.as_mut() {
+                messages.push(msg.to_string());
+            }
+        }
+    });
+}
+
+/// Returns true if `settings` ask for performance info.
+fn wanted(settings: &ScanSettings) -> bool {
+    settings.settings.dev & CL_SCAN_DEV_COLLECT_PERFORMANCE_INFO != 0
+}
+
+/// Runs `scan`, capturing its performance info if `settings` ask for it.
+///
+/// libclamav reports messages on the scanning thread, so `scan` must call
+/// libclamav on the current thread.
+pub(crate) fn capture<T, F: FnOnce() -> T>(settings: &ScanSettings, scan: F) -> (T, Option<PerfInfo>) {
+    if !wanted(settings) {
+        return (scan(), None);
+    }
+    message::register();
+    let previous = CAPTURED.with(|captured| captured.replace(Some(Vec::new())));
+    let start = Instant::now();
+    let result = scan();
+    let total = start.elapsed();
+    let messages = CAPTURED.with(|captured| captured.replace(previous)).unwrap_or_default();
+    (result, Some(PerfInfo::parse(&messages, total)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::scan_settings::ScanSettingsBuilder;
+    use crate::test_support::EXAMPLE_DATABASE_PATH;
+
+    fn ms(millis: u64, micros: u64) -> Duration {
+        Duration::from_micros(millis * 1000 + micros)
+    }
+
+    #[test]
+    fn parse_timings() {
+        let messages = vec!["performance: scan: 1.250ms, magic_scan: 0.003ms, zip: 12.5ms, \n".to_string()];
+        let info = PerfInfo::parse(&messages, ms(20, 0));
+        assert_eq!(
+            info.per_parser,
+            vec![
+                ("scan".to_string(), ms(1, 250)),
+                ("magic_scan".to_string(), ms(0, 3)),
+                ("zip".to_string(), ms(12, 500)),
+            ]
+        );
+        assert_eq!(info.total, ms(20, 0));
+        assert!(info.raw.is_empty());
+    }
+
+    #[test]
+    fn parse_keeps_unknown_formats_raw() {
+        let messages = vec![
+            "LibClamAV info: unrelated message\n".to_string(),
+            "performance: db load: 2s, scan: lots, bytecode: 7 fortnights, : 1ms, raw_scan: 10us".to_string(),
+            "   ".to_string(),
+        ];
+        let info = PerfInfo::parse(&messages, Duration::from_secs(3));
+        assert_eq!(
+            info.per_parser,
+            vec![
+                ("db load".to_string(), Duration::from_secs(2)),
+                ("raw_scan".to_string(), Duration::from_micros(10)),
+            ]
+        );
+        assert_eq!(
+            info.raw,
+            vec!["LibClamAV info: unrelated message", "scan: lots", "bytecode: 7 fortnights", ": 1ms"]
+        );
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration(" 250us "), Some(Duration::from_micros(250)));
+        assert_eq!(parse_duration("42ns"), Some(Duration::from_nanos(42)));
+        assert_eq!(parse_duration("3 ms"), Some(Duration::from_millis(3)));
+        assert_eq!(parse_duration("ms"), None);
+        assert_eq!(parse_duration("12"), None);
+        assert_eq!(parse_duration("1.2.3ms"), None);
+    }
+
+    #[test]
+    fn capture_is_skipped_without_flag() {
+        let (value, info) = capture(&ScanSettings::default(), || 42);
+        assert_eq!(value, 42);
+        assert_eq!(info, None);
+    }
+
+    #[test]
+    fn archive_scan_reports_parser_timings() {
+        crate::initialize().expect("initialize should succeed");
+        let engine = Engine::new().unwrap();
+        engine
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        engine.compile().expect("failed to compile");
+
+        let archive = crate::test_support::zip_bomb(64 * 1024);
+        let map = crate::fmap::Fmap::new_from_memory(archive.as_ptr(), archive.len() as u64).unwrap();
+        let settings = ScanSettingsBuilder::new()
+            .enable_archive()
+            .collect_performance_info()
+            .build();
+        let outcome = engine
+            .scan_map_outcome(&map, Some("archive.zip"), &settings)
+            .expect("scan should succeed");
+        let info = outcome.performance.expect("performance info should be attached");
+        assert!(!info.per_parser.is_empty(), "no parser timings in {:?}", info);
+        assert!(info.total > Duration::from_secs(0));
+
+        let outcome = engine
+            .scan_map_outcome(&map, None, &ScanSettingsBuilder::new().enable_archive().build())
+            .expect("scan should succeed");
+        assert_eq!(outcome.performance, None);
+    }
+}
@@ -0,0 +1,553 @@
+//! Suppression of triaged detections.
+//!
+//! Deployments accumulate detections they have reviewed and accepted, e.g.
+//! `Heuristics.OLE2.ContainsMacros` on a signed internal template. A
+//! [`SuppressionList`] names them by signature glob, optionally restricted to
+//! files with a given SHA-256. Scanning through a [`Scanner`] with a list
+//! downgrades matching detections to `ScanResult::Suppressed` and records the
+//! matching rule in `ScanOutcome::suppression`.
+//!
+//! Lists are read from text files with one rule per line:
+//!
+//! ```text
+//! # accepted everywhere
+//! Heuristics.OLE2.ContainsMacros
+//! # accepted for one file only
+//! Heuristics.Encrypted.* sha256=9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//! ```
+//!
+//! Globs match the whole signature name; `*` matches any number of
+//! characters and `?` matches one character. Empty lines and lines starting
+//! with `#` are ignored.
+//!
+//! [`SuppressionList`]: struct.SuppressionList.html
+//! [`Scanner`]: ../scanner/struct.Scanner.html
+
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::mem;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::engine::{Detection, ScanOutcome, ScanResult};
+
+const SHA256_LEN: usize = 32;
+const MD5_LEN: usize = 16;
+/// Size of the chunks streamed into a hash
+const HASH_CHUNK: usize = 64 * 1024;
+
+/// Returns the hex encoded SHA-256 of `data`, computed by libclamav.
+pub(crate) fn sha256_hex(data: &[u8]) -> Option<String> {
+    digest_hex(b"sha256\0", SHA256_LEN, data)
+}
+
+/// Returns the hex encoded MD5 of `data`, computed by libclamav.
+pub(crate) fn md5_hex(data: &[u8]) -> Option<String> {
+    digest_hex(b"md5\0", MD5_LEN, data)
+}
+
+/// Hashes `data` with the nul-terminated `algorithm`, whose digests are
+/// `size` bytes long.
+fn digest_hex(algorithm: &[u8], size: usize, data: &[u8]) -> Option<String> {
+    // the longest of the digests above
+    let mut digest = [0u8; SHA256_LEN];
+    let mut len: c_uint = size as c_uint;
+    let result = unsafe {
+        clamav_sys::cl_hash_data(
+            algorithm.as_ptr() as *const c_char,
+            data.as_ptr() as *const c_void,
+            data.len() as clamav_sys::size_t,
+            digest.as_mut_ptr(),
+            &mut len,
+        )
+    };
+    if result.is_null() || len as usize != size {
+        return None;
+    }
+    Some(hex(&digest[..size]))
+}
+
+/// Returns the hex encoded SHA-256 of everything `reader` yields, streamed
+/// through libclamav in chunks.
+pub(crate) fn sha256_reader_hex<R: Read>(mut reader: R) -> Option<String> {
+    let ctx = unsafe { clamav_sys::cl_hash_init(b"sha256\0".as_ptr() as *const c_char) };
+    if ctx.is_null() {
+        return None;
+    }
+    let context = HashContext(ctx);
+    let mut chunk = vec![0u8; HASH_CHUNK];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        };
+        let result = unsafe {
+            clamav_sys::cl_update_hash(context.0, chunk.as_ptr() as *const c_void, read as clamav_sys::size_t)
+        };
+        if result != 0 {
+            return None;
+        }
+    }
+    let mut digest = [0u8; SHA256_LEN];
+    if !context.finish(&mut digest) {
+        return None;
+    }
+    Some(hex(&digest))
+}
+
+/// Returns the hex encoded SHA-256 of the contents of `file`, read from the
+/// start through the same descriptor.
+pub(crate) fn sha256_file_hex(mut file: &File) -> Option<String> {
+    file.seek(SeekFrom::Start(0)).ok()?;
+    sha256_reader_hex(file)
+}
+
+/// Hash context of libclamav, destroyed unless it was finished
+struct HashContext(*mut c_void);
+
+impl HashContext {
+    /// Writes the digest to `digest`, which must be as long as the digests
+    /// of the algorithm, and frees the context.
+    fn finish(self, digest: &mut [u8]) -> bool {
+        let ctx = self.0;
+        // cl_finish_hash frees the context, also on failure
+        mem::forget(self);
+        unsafe { clamav_sys::cl_finish_hash(ctx, digest.as_mut_ptr() as *mut c_void) == 0 }
+    }
+}
+
+impl Drop for HashContext {
+    fn drop(&mut self) {
+        unsafe { clamav_sys::cl_hash_destroy(self.0) }
+    }
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Matches `name` against a glob with `*` and `?` wildcards.
+pub(crate) fn glob_matches(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut g, mut n) = (0, 0);
+    // position after the last `*` and the name position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                g += 1;
+                backtrack = Some((g, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_g, star_n)) => {
+                    g = star_g;
+                    n = star_n + 1;
+                    backtrack = Some((star_g, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// A single suppression rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuppressionRule {
+    /// Glob over the signature name
+    pub signature: String,
+    /// Lower case hex SHA-256 the scanned data must have, or `None` for any data
+    pub sha256: Option<String>,
+}
+
+impl SuppressionRule {
+    /// Creates a rule suppressing `signature` for any data.
+    pub fn new(signature: &str) -> Self {
+        SuppressionRule {
+            signature: signature.to_string(),
+            sha256: None,
+        }
+    }
+
+    /// Restricts the rule to data with the given hex encoded SHA-256.
+    pub fn with_sha256(mut self, sha256: &str) -> Self {
+        self.sha256 = Some(sha256.to_ascii_lowercase());
+        self
+    }
+}
+
+/// Formats the rule as a line of a suppression file.
+impl fmt::Display for SuppressionRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.signature)?;
+        if let Some(sha256) = &self.sha256 {
+            write!(f, " sha256={}", sha256)?;
+        }
+        Ok(())
+    }
+}
+
+/// Audit record of a suppressed detection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuppressionRecord {
+    /// The detection which was suppressed
+    pub detection: Detection,
+    /// The rule which suppressed it
+    pub rule: SuppressionRule,
+    /// SHA-256 of the scanned data, if the rule required it
+    pub sha256: Option<String>,
+}
+
+/// Error of parsing or loading a suppression list
+#[derive(Debug)]
+pub enum SuppressionError {
+    /// The file could not be read
+    Io(io::Error),
+    /// A line is not a valid rule
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for SuppressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SuppressionError::Io(err) => write!(f, "failed to read suppression list: {}", err),
+            SuppressionError::Parse { line, message } => {
+                write!(f, "invalid suppression rule on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl error::Error for SuppressionError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SuppressionError::Io(err) => Some(err),
+            SuppressionError::Parse { .. } => None,
+        }
+    }
+}
+
+fn parse_rule(line: &str) -> Result<SuppressionRule, String> {
+    let mut fields = line.split_whitespace();
+    let signature = fields.next().ok_or_else(|| "missing signature".to_string())?;
+    let mut rule = SuppressionRule::new(signature);
+    for field in fields {
+        match field.strip_prefix("sha256=") {
+            Some(hash) if hash.len() == 2 * SHA256_LEN && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+                rule = rule.with_sha256(hash);
+            }
+            Some(hash) => return Err(format!("invalid sha256 {}", hash)),
+            None => return Err(format!("unknown field {}", field)),
+        }
+    }
+    Ok(rule)
+}
+
+fn parse_rules(text: &str) -> Result<Vec<SuppressionRule>, SuppressionError> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, text)| parse_rule(text).map_err(|message| SuppressionError::Parse { line, message }))
+        .collect()
+}
+
+/// Reloadable list of suppression rules
+///
+/// The list is shared by reference: wrap it in an `Arc`, hand it to
+/// `ScannerBuilder::suppressions` and keep a clone to call [`reload`] when
+/// the file changes. Scans started after the reload use the new rules.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::policy::SuppressionList;
+/// use clamav_rs::prelude::*;
+/// use std::str::FromStr;
+///
+/// let list = SuppressionList::from_str("naughty_file.*").expect("invalid rules");
+/// let (result, record) = list.apply(ScanResult::Virus("naughty_file.UNOFFICIAL".into()), || None);
+/// assert!(!result.found());
+/// assert_eq!(record.unwrap().rule.signature, "naughty_file.*");
+/// ```
+///
+/// [`reload`]: #method.reload
+#[derive(Debug, Default)]
+pub struct SuppressionList {
+    path: Option<PathBuf>,
+    rules: Mutex<Arc<Vec<SuppressionRule>>>,
+}
+
+impl SuppressionList {
+    /// Creates a list from rules, which cannot be reloaded.
+    pub fn new(rules: Vec<SuppressionRule>) -> Self {
+        SuppressionList {
+            path: None,
+            rules: Mutex::new(Arc::new(rules)),
+        }
+    }
+
+    /// Reads a suppression file. [`reload`] reads the same file again.
+    ///
+    /// [`reload`]: #method.reload
+    pub fn load(path: &Path) -> Result<Self, SuppressionError> {
+        let rules = parse_rules(&fs::read_to_string(path).map_err(SuppressionError::Io)?)?;
+        Ok(SuppressionList {
+            path: Some(path.to_path_buf()),
+            rules: Mutex::new(Arc::new(rules)),
+        })
+    }
+
+    /// Reads the file the list was loaded from again and replaces the rules.
+    ///
+    /// If the file cannot be read or parsed, the previous rules stay in use.
+    /// Lists not created with [`load`] keep their rules.
+    ///
+    /// [`load`]: #method.load
+    pub fn reload(&self) -> Result<(), SuppressionError> {
+        if let Some(path) = &self.path {
+            let rules = parse_rules(&fs::read_to_string(path).map_err(SuppressionError::Io)?)?;
+            self.replace(rules);
+        }
+        Ok(())
+    }
+
+    /// Replaces the rules.
+    pub fn replace(&self, rules: Vec<SuppressionRule>) {
+        *self.rules.lock().unwrap() = Arc::new(rules);
+    }
+
+    /// Returns the current rules.
+    pub fn rules(&self) -> Arc<Vec<SuppressionRule>> {
+        Arc::clone(&self.rules.lock().unwrap())
+    }
+
+    /// Downgrades `result` to `ScanResult::Suppressed` if a rule matches it.
+    ///
+    /// `data` returns the scanned data; it is only called if a matching rule
+    /// is restricted to a SHA-256, and at most once. Rules restricted to a
+    /// SHA-256 never match if it returns `None`.
+    pub fn apply<F>(&self, result: ScanResult, data: F) -> (ScanResult, Option<SuppressionRecord>)
+    where
+        F: FnOnce() -> Option<Vec<u8>>,
+    {
+        self.apply_hashed(result, || data().and_then(|data| sha256_hex(&data)))
+    }
+
+    /// Same as [`apply`], with `sha256` returning the hex encoded SHA-256 of
+    /// the scanned data instead of the data, so that callers can stream it.
+    ///
+    /// [`apply`]: #method.apply
+    pub(crate) fn apply_hashed<F>(&self, result: ScanResult, sha256: F) -> (ScanResult, Option<SuppressionRecord>)
+    where
+        F: FnOnce() -> Option<String>,
+    {
+        let detection = match result {
+            ScanResult::Virus(detection) => detection,
+            other => return (other, None),
+        };
+        let rules = self.rules();
+        let mut hash = Some(sha256);
+        let mut actual: Option<Option<String>> = None;
+        for rule in rules.iter().filter(|rule| glob_matches(&rule.signature, &detection.name)) {
+            if let Some(expected) = &rule.sha256 {
+                let actual = actual.get_or_insert_with(|| hash.take().and_then(|hash| hash()));
+                if actual.as_ref() != Some(expected) {
+                    continue;
+                }
+            }
+            let record = SuppressionRecord {
+                detection: detection.clone(),
+                rule: rule.clone(),
+                sha256: actual.flatten(),
+            };
+            return (ScanResult::Suppressed { original: detection }, Some(record));
+        }
+        (ScanResult::Virus(detection), None)
+    }
+
+    /// Applies the list to a scan outcome, see [`apply`].
+    ///
+    /// [`apply`]: #method.apply
+    pub fn apply_outcome<F>(&self, outcome: ScanOutcome, data: F) -> ScanOutcome
+    where
+        F: FnOnce() -> Option<Vec<u8>>,
+    {
+        self.apply_outcome_hashed(outcome, || data().and_then(|data| sha256_hex(&data)))
+    }
+
+    /// Applies the list to a scan outcome, see [`apply_hashed`].
+    ///
+    /// [`apply_hashed`]: #method.apply_hashed
+    pub(crate) fn apply_outcome_hashed<F>(&self, mut outcome: ScanOutcome, sha256: F) -> ScanOutcome
+    where
+        F: FnOnce() -> Option<String>,
+    {
+        let (result, suppression) = self.apply_hashed(outcome.result, sha256);
+        outcome.result = result;
+        outcome.suppression = suppression;
+        outcome
+    }
+}
+
+/// Parses the contents of a suppression file. The list cannot be reloaded.
+impl FromStr for SuppressionList {
+    type Err = SuppressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_rules(s).map(SuppressionList::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::NAUGHTY_FILE_PATH;
+
+    fn naughty() -> ScanResult {
+        ScanResult::Virus("naughty_file.UNOFFICIAL".into())
+    }
+
+    fn naughty_sha256() -> String {
+        crate::initialize().expect("initialize should succeed");
+        sha256_hex(&fs::read(NAUGHTY_FILE_PATH).unwrap()).expect("hashing should succeed")
+    }
+
+    #[test]
+    fn digests_of_known_data() {
+        crate::initialize().expect("initialize should succeed");
+        assert_eq!(
+            sha256_hex(b"test").as_deref(),
+            Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")
+        );
+        assert_eq!(md5_hex(b"test").as_deref(), Some("098f6bcd4621d373cade4e832627b4f6"));
+    }
+
+    #[test]
+    fn streamed_digest_matches_digest_of_data() {
+        crate::initialize().expect("initialize should succeed");
+        // spans several chunks and ends in a partial one
+        let data: Vec<u8> = (0..3 * HASH_CHUNK + 17).map(|i| i as u8).collect();
+        assert_eq!(sha256_reader_hex(&data[..]), sha256_hex(&data));
+    }
+
+    #[test]
+    fn file_digest_starts_at_the_beginning() {
+        let expected = naughty_sha256();
+        let mut file = File::open(NAUGHTY_FILE_PATH).unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        assert_eq!(sha256_file_hex(&file), Some(expected));
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_matches("naughty_file.UNOFFICIAL", "naughty_file.UNOFFICIAL"));
+        assert!(glob_matches("naughty_file.*", "naughty_file.UNOFFICIAL"));
+        assert!(glob_matches("*.UNOFFICIAL", "naughty_file.UNOFFICIAL"));
+        assert!(glob_matches("*file*", "naughty_file.UNOFFICIAL"));
+        assert!(glob_matches("naughty_fil?.UNOFFICIAL", "naughty_file.UNOFFICIAL"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("naughty_file", "naughty_file.UNOFFICIAL"));
+        assert!(!glob_matches("*.OFFICIAL?", "naughty_file.UNOFFICIAL"));
+        assert!(!glob_matches("Heuristics.*", "naughty_file.UNOFFICIAL"));
+    }
+
+    #[test]
+    fn parse_rules_and_display() {
+        let text = "\n# comment\nHeuristics.OLE2.ContainsMacros\n  naughty_file.* sha256=9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08  \n";
+        let rules = parse_rules(text).expect("rules should parse");
+        assert_eq!(
+            rules,
+            vec![
+                SuppressionRule::new("Heuristics.OLE2.ContainsMacros"),
+                SuppressionRule::new("naughty_file.*")
+                    .with_sha256("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"),
+            ]
+        );
+        let lines: Vec<String> = rules.iter().map(ToString::to_string).collect();
+        assert_eq!(parse_rules(&lines.join("\n")).unwrap(), rules);
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        match parse_rules("ok\nbad sha256=1234\n") {
+            Err(SuppressionError::Parse { line: 2, .. }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        match parse_rules("bad colour=blue") {
+            Err(SuppressionError::Parse { line: 1, message }) => assert_eq!(message, "unknown field colour=blue"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn global_suppression() {
+        let list = SuppressionList::new(vec![SuppressionRule::new("naughty_file.*")]);
+        let (result, record) = list.apply(naughty(), || panic!("data should not be needed"));
+        assert_eq!(result, ScanResult::Suppressed { original: "naughty_file.UNOFFICIAL".into() });
+        let record = record.expect("suppression should be recorded");
+        assert_eq!(record.detection, "naughty_file.UNOFFICIAL");
+        assert_eq!(record.rule, SuppressionRule::new("naughty_file.*"));
+        assert_eq!(record.sha256, None);
+    }
+
+    #[test]
+    fn suppression_by_hash() {
+        let sha256 = naughty_sha256();
+        let list = SuppressionList::new(vec![SuppressionRule::new("naughty_file.UNOFFICIAL").with_sha256(&sha256)]);
+        let (result, record) = list.apply(naughty(), || fs::read(NAUGHTY_FILE_PATH).ok());
+        assert!(!result.found());
+        let record = record.expect("suppression should be recorded");
+        assert_eq!(record.sha256.as_deref(), Some(sha256.as_str()));
+
+        // other data with the same detection is not suppressed
+        let (result, record) = list.apply(naughty(), || Some(b"other data".to_vec()));
+        assert_eq!(result, naughty());
+        assert_eq!(record, None);
+        let (result, _) = list.apply(naughty(), || None);
+        assert_eq!(result, naughty());
+    }
+
+    #[test]
+    fn clean_results_are_untouched() {
+        let list = SuppressionList::new(vec![SuppressionRule::new("*")]);
+        assert_eq!(list.apply(ScanResult::Clean, || None), (ScanResult::Clean, None));
+        assert_eq!(list.apply(ScanResult::Whitelisted, || None), (ScanResult::Whitelisted, None));
+    }
+
+    #[test]
+    fn reload_replaces_rules() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        io::Write::write_all(&mut file, b"Heuristics.*\n").unwrap();
+        let list = SuppressionList::load(file.path()).expect("list should load");
+        assert_eq!(list.apply(naughty(), || None).0, naughty());
+
+        fs::write(file.path(), "naughty_file.*\n").unwrap();
+        list.reload().expect("reload should succeed");
+        assert!(!list.apply(naughty(), || None).0.found());
+
+        // a broken file keeps the previous rules
+        fs::write(file.path(), "naughty_file.* sha256=zz\n").unwrap();
+        assert!(list.reload().is_err());
+        assert_eq!(*list.rules(), vec![SuppressionRule::new("naughty_file.*")]);
+    }
+
+    #[test]
+    fn load_missing_file_fails() {
+        match SuppressionList::load(Path::new("test_data/does_not_exist")) {
+            Err(SuppressionError::Io(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
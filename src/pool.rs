@@ -0,0 +1,888 @@
+//! Shared engine with admission control for concurrent scans.
+//!
+//! A [`ScannerPool`] lets any number of threads submit scans against one
+//! engine, but only admits a bounded number of scans at a time. Further scans
+//! wait in a FIFO queue, so early requests are not starved by later ones, and
+//! scans arriving while the queue is full are rejected with `CL_EBUSY` instead
+//! of piling onto the OS scheduler.
+//!
+//! [`ScannerPool`]: struct.ScannerPool.html
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use clamav_sys::{cl_error_t, cl_scan_options};
+
+use crate::buffer::{BufferBudget, BufferLimits};
+use crate::control::{Controlled, ScannerControl};
+use crate::engine::{Engine, ScanOutcome, ScanResult};
+use crate::error::ClamError;
+use crate::policy::{self, SuppressionList};
+use crate::request::{ScanError, ScanRequest, ScanSource};
+use crate::scan::{self, TypeStats};
+use crate::scan_settings::ScanSettings;
+use crate::shared::SharedEngine;
+use crate::sink::{ResultSink, ScanRecord};
+
+/// Admission limits of a [`ScannerPool`]
+///
+/// [`ScannerPool`]: struct.ScannerPool.html
+#[derive(Debug, Clone)]
+pub struct PoolOptions {
+    /// Maximum number of scans running at the same time. Values below 1 are
+    /// treated as 1. Defaults to [`Engine::recommended_concurrency`].
+    ///
+    /// [`Engine::recommended_concurrency`]: ../engine/struct.Engine.html#method.recommended_concurrency
+    pub max_in_flight: usize,
+    /// Maximum number of scans waiting for admission; further scans are
+    /// rejected with `CL_EBUSY`. Defaults to 1024.
+    pub max_queued: usize,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            max_in_flight: Engine::recommended_concurrency(),
+            max_queued: 1024,
+        }
+    }
+}
+
+/// Snapshot of the admission state of a [`ScannerPool`]
+///
+/// [`ScannerPool`]: struct.ScannerPool.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// Scans currently running
+    pub in_flight: usize,
+    /// Scans currently waiting for admission
+    pub queue_depth: usize,
+    /// Total number of admitted scans
+    pub admitted: u64,
+    /// Total number of scans rejected because the queue was full
+    pub rejected: u64,
+    /// Total time admitted scans spent waiting in the queue
+    pub total_wait: Duration,
+    /// Longest time a single scan spent waiting in the queue
+    pub max_wait: Duration,
+}
+
+struct LimiterState {
+    in_flight: usize,
+    // Scans holding a ticket in next_admit..next_ticket are queued, in order
+    next_ticket: u64,
+    next_admit: u64,
+    // Queued tickets whose scans gave up waiting
+    abandoned: BTreeSet<u64>,
+    admitted: u64,
+    rejected: u64,
+    total_wait: Duration,
+    max_wait: Duration,
+}
+
+struct Limiter {
+    state: Mutex<LimiterState>,
+    released: Condvar,
+    max_in_flight: usize,
+    max_queued: usize,
+}
+
+struct Permit<'a> {
+    limiter: &'a Limiter,
+}
+
+impl Limiter {
+    fn new(options: &PoolOptions) -> Self {
+        Limiter {
+            state: Mutex::new(LimiterState {
+                in_flight: 0,
+                next_ticket: 0,
+                next_admit: 0,
+                abandoned: BTreeSet::new(),
+                admitted: 0,
+                rejected: 0,
+                total_wait: Duration::from_secs(0),
+                max_wait: Duration::from_secs(0),
+            }),
+            released: Condvar::new(),
+            max_in_flight: cmp::max(options.max_in_flight, 1),
+            max_queued: options.max_queued,
+        }
+    }
+
+    fn acquire(&self) -> Result<Permit<'_>, ClamError> {
+        self.acquire_until(None).map(|permit| permit.expect("admission without deadline"))
+    }
+
+    /// Waits for admission until `deadline`, returning `None` if it passes first.
+    fn acquire_until(&self, deadline: Option<Instant>) -> Result<Option<Permit<'_>>, ClamError> {
+        let mut state = self.state.lock().unwrap();
+        let queued = state.next_ticket - state.next_admit;
+        if queued == 0 && state.in_flight < self.max_in_flight {
+            state.next_ticket += 1;
+            state.next_admit += 1;
+            state.in_flight += 1;
+            state.admitted += 1;
+            return Ok(Some(Permit { limiter: self }));
+        }
+        if queued - state.abandoned.len() as u64 >= self.max_queued as u64 {
+            state.rejected += 1;
+            return Err(ClamError::new(cl_error_t::CL_EBUSY));
+        }
+
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        let start = Instant::now();
+        while ticket != state.next_admit || state.in_flight >= self.max_in_flight {
+            state = match deadline {
+                None => self.released.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.abandoned.insert(ticket);
+                        Self::skip_abandoned(&mut state);
+                        self.released.notify_all();
+                        return Ok(None);
+                    }
+                    self.released.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+        let waited = start.elapsed();
+        state.next_admit += 1;
+        Self::skip_abandoned(&mut state);
+        state.in_flight += 1;
+        state.admitted += 1;
+        state.total_wait += waited;
+        state.max_wait = cmp::max(state.max_wait, waited);
+        // The next scan in line may be admissible as well
+        self.released.notify_all();
+        Ok(Some(Permit { limiter: self }))
+    }
+
+    /// Moves the head of the queue past tickets which are no longer waiting.
+    fn skip_abandoned(state: &mut LimiterState) {
+        while state.abandoned.remove(&state.next_admit) {
+            state.next_admit += 1;
+        }
+    }
+
+    fn metrics(&self) -> PoolMetrics {
+        let state = self.state.lock().unwrap();
+        PoolMetrics {
+            in_flight: state.in_flight,
+            queue_depth: (state.next_ticket - state.next_admit) as usize - state.abandoned.len(),
+            admitted: state.admitted,
+            rejected: state.rejected,
+            total_wait: state.total_wait,
+            max_wait: state.max_wait,
+        }
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        state.in_flight -= 1;
+        self.limiter.released.notify_all();
+    }
+}
+
+/// Engine shared between threads with bounded, fair admission of scans
+///
+/// The pool is cheap to clone; clones share the engine and the limits. Pools
+/// created with [`with_shared`] pick up engines published to the
+/// [`SharedEngine`] with the next admitted scan.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::prelude::*;
+/// use clamav_rs::pool::{PoolOptions, ScannerPool};
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let engine = Engine::new().expect("failed to create engine");
+/// engine.load_databases("test_data/database/").expect("failed to load");
+/// engine.compile().expect("failed to compile");
+///
+/// let options = PoolOptions { max_in_flight: 4, max_queued: 64 };
+/// let pool = ScannerPool::new(engine, Default::default(), options);
+/// let result = pool.scan_file("test_data/files/good_file").expect("scan failed");
+/// println!("{:?}", pool.metrics());
+/// ```
+///
+/// [`with_shared`]: #method.with_shared
+/// [`SharedEngine`]: ../shared/struct.SharedEngine.html
+#[derive(Clone)]
+pub struct ScannerPool {
+    engine: SharedEngine,
+    /// Shared by all clones, replaced through a `ScannerControl`
+    settings: Arc<Mutex<cl_scan_options>>,
+    limiter: Arc<Limiter>,
+    sink: Option<Arc<dyn ResultSink>>,
+    buffers: BufferBudget,
+    suppressions: Option<Arc<SuppressionList>>,
+    /// Shared by all clones
+    by_type: Arc<Mutex<BTreeMap<String, TypeStats>>>,
+}
+
+impl ScannerPool {
+    /// Creates a pool scanning with the given compiled engine and settings.
+    pub fn new(engine: Engine, settings: ScanSettings, options: PoolOptions) -> Self {
+        Self::with_shared(SharedEngine::new(engine), settings, options)
+    }
+
+    /// Creates a pool scanning with whichever engine is currently published
+    /// to `engine`.
+    ///
+    /// Each scan holds on to the engine it was admitted with, so swapping the
+    /// engine never interrupts or fails running scans.
+    pub fn with_shared(engine: SharedEngine, settings: ScanSettings, options: PoolOptions) -> Self {
+        ScannerPool {
+            engine,
+            settings: Arc::new(Mutex::new(settings.settings)),
+            limiter: Arc::new(Limiter::new(&options)),
+            sink: None,
+            buffers: BufferBudget::new(BufferLimits::default()),
+            suppressions: None,
+            by_type: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Emits the results of `scan_file`, `scan_mem` and `scan_request` to
+    /// `sink`, including scans rejected with `CL_EBUSY`. Requests which are
+    /// cancelled or past their deadline are not emitted.
+    ///
+    /// Clones made afterwards share the sink.
+    pub fn with_sink(mut self, sink: Arc<dyn ResultSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Limits the bytes buffered by [`scan_reader`], per scan and across all
+    /// scans of the pool. Defaults to no limits.
+    ///
+    /// Clones made afterwards share the global budget.
+    ///
+    /// [`scan_reader`]: #method.scan_reader
+    pub fn with_buffer_limits(mut self, limits: BufferLimits) -> Self {
+        self.buffers = BufferBudget::new(limits);
+        self
+    }
+
+    /// Applies `suppressions` to the results of `scan_file`, `scan_mem`,
+    /// `scan_reader` and `scan_request`, see the [`policy`] module. Results
+    /// are emitted to the sink after the suppressions were applied.
+    ///
+    /// Clones made afterwards share the list.
+    ///
+    /// [`policy`]: ../policy/index.html
+    pub fn with_suppressions(mut self, suppressions: Arc<SuppressionList>) -> Self {
+        self.suppressions = Some(suppressions);
+        self
+    }
+
+    /// Applies the suppression list, if any, to `outcome`. `sha256` returns
+    /// the hash of the scanned data.
+    fn suppress<F>(&self, outcome: Result<ScanOutcome, ClamError>, sha256: F) -> Result<ScanOutcome, ClamError>
+    where
+        F: FnOnce() -> Option<String>,
+    {
+        match &self.suppressions {
+            Some(suppressions) => outcome.map(|outcome| suppressions.apply_outcome_hashed(outcome, sha256)),
+            None => outcome,
+        }
+    }
+
+    /// Applies the suppression list to the `scanned` outcome and type of a
+    /// scan started at `started`, and adds it to the statistics by type.
+    fn finish<F>(
+        &self,
+        scanned: (Result<ScanOutcome, ClamError>, Option<String>),
+        started: Instant,
+        sha256: F,
+    ) -> Result<ScanOutcome, ClamError>
+    where
+        F: FnOnce() -> Option<String>,
+    {
+        let (outcome, file_type) = scanned;
+        let outcome = self.suppress(outcome, sha256);
+        self.count_type(file_type, outcome.as_ref().ok(), started.elapsed());
+        outcome
+    }
+
+    fn count_type(&self, file_type: Option<String>, outcome: Option<&ScanOutcome>, time: Duration) {
+        scan::count_type(&mut self.by_type.lock().unwrap(), file_type, outcome, time);
+    }
+
+    /// Emits `outcome` to the sink, if any, and passes it on.
+    fn emit(&self, path: Option<&Path>, outcome: Result<ScanOutcome, ClamError>) -> Result<ScanOutcome, ClamError> {
+        match &self.sink {
+            Some(sink) => {
+                let record = ScanRecord::new(path.map(Path::to_path_buf), outcome);
+                sink.emit(&record);
+                record.outcome
+            }
+            None => outcome,
+        }
+    }
+
+    /// Runs `scan` once it is admitted by the pool.
+    ///
+    /// This is the building block of the `scan_*` methods and can be used to
+    /// run any other engine operation under the pool's limits.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EBUSY` if the queue is full, or the error returned by `scan`.
+    pub fn run<T, F>(&self, scan: F) -> Result<T, ClamError>
+    where
+        F: FnOnce(&Engine, &ScanSettings) -> Result<T, ClamError>,
+    {
+        let _permit = self.limiter.acquire()?;
+        let engine = self.engine.get();
+        scan(&engine, &self.settings())
+    }
+
+    /// Scans a file once admitted by the pool.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`run`], and `CL_EOPEN` if the file cannot be opened.
+    ///
+    /// [`run`]: #method.run
+    pub fn scan_file(&self, path: &str) -> Result<ScanResult, ClamError> {
+        let outcome = self.run(|engine, settings| {
+            let file = File::open(path).map_err(|_| ClamError::new(cl_error_t::CL_EOPEN))?;
+            let started = Instant::now();
+            let scanned = engine.scan_fileobj_typed(&file, settings, Some(path));
+            self.finish(scanned, started, || policy::sha256_file_hex(&file))
+        });
+        self.emit(Some(Path::new(path)), outcome)
+            .map(|outcome| outcome.result)
+    }
+
+    /// Scans a memory buffer once admitted by the pool.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`run`], and `CL_EMAP` if the buffer cannot be mapped.
+    ///
+    /// [`run`]: #method.run
+    pub fn scan_mem(&self, data: &[u8], filename: Option<&str>) -> Result<ScanResult, ClamError> {
+        let outcome = self.run(|engine, settings| {
+            let started = Instant::now();
+            let scanned = engine.scan_mem_typed(data, filename, settings);
+            self.finish(scanned, started, || policy::sha256_hex(data))
+        });
+        self.emit(filename.map(Path::new), outcome)
+            .map(|outcome| outcome.result)
+    }
+
+    /// Reads `reader` to the end and scans its contents once admitted by the
+    /// pool.
+    ///
+    /// The contents are buffered within the pool's [`BufferLimits`] before
+    /// waiting for admission, and released once the scan finishes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ScanError::BufferLimitExceeded` if the contents would exceed
+    /// the buffer limits, `CL_EREAD` if reading fails, otherwise the same
+    /// errors as [`scan_mem`].
+    ///
+    /// [`BufferLimits`]: ../buffer/struct.BufferLimits.html
+    /// [`scan_mem`]: #method.scan_mem
+    pub fn scan_reader<R: Read>(&self, reader: R, filename: Option<&str>) -> Result<ScanResult, ScanError> {
+        let buffered = self.buffers.read(reader)?;
+        Ok(self.scan_mem(buffered.data(), filename)?)
+    }
+
+    /// Scans the source of `request` once admitted by the pool.
+    ///
+    /// The deadline also bounds the time spent waiting for admission, and
+    /// requests which are cancelled or past their deadline fail without
+    /// waiting. See the [`request`] module for how interruptions are handled.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ScanError::DeadlineExceeded` or `ScanError::Cancelled` if the
+    /// request is interrupted, otherwise the errors of [`run`], `CL_EOPEN` if
+    /// a file cannot be opened and `CL_EMAP` if a buffer cannot be mapped.
+    ///
+    /// [`request`]: ../request/index.html
+    /// [`run`]: #method.run
+    pub fn scan_request(&self, request: &ScanRequest) -> Result<ScanOutcome, ScanError> {
+        let path = match &request.source {
+            ScanSource::Path(path) => Some(path.as_path()),
+            ScanSource::Bytes(_) => None,
+        };
+        match self.admit_request(request) {
+            Ok(outcome) => self.emit(path, Ok(outcome)).map_err(ScanError::from),
+            Err(ScanError::Clam(err)) => self.emit(path, Err(err)).map_err(ScanError::from),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn admit_request(&self, request: &ScanRequest) -> Result<ScanOutcome, ScanError> {
+        let started = Instant::now();
+        if let Some(err) = request.interrupted(started, None) {
+            return Err(err);
+        }
+        let _permit = match self.limiter.acquire_until(request.deadline)? {
+            Some(permit) => permit,
+            None => return Err(request.interrupted(started, None).expect("deadline passed")),
+        };
+        let engine = self.engine.get();
+        let settings = request.settings.clone().unwrap_or_else(|| self.settings());
+        let mut scanned = None;
+        let outcome = request.run(started, || {
+            let source = request.open()?;
+            let started = Instant::now();
+            let (outcome, file_type) = source.scan(&engine, &settings, request.interrupt());
+            scanned = Some((file_type, started.elapsed()));
+            self.suppress(outcome, || source.sha256())
+        });
+        // the outcome of an interrupted scan is discarded by `run`
+        if let (Ok(outcome), Some((file_type, time))) = (&outcome, scanned) {
+            self.count_type(file_type, Some(outcome), time);
+        }
+        outcome
+    }
+
+    /// Returns the engine currently used for newly admitted scans.
+    pub fn engine(&self) -> Arc<Engine> {
+        self.engine.get()
+    }
+
+    /// Returns the handle used to publish new engines to the pool.
+    pub fn shared_engine(&self) -> &SharedEngine {
+        &self.engine
+    }
+
+    /// Returns the bytes currently buffered by `scan_reader` on this pool and
+    /// its clones.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.buffers.buffered()
+    }
+
+    /// Returns a snapshot of the pool's admission metrics.
+    pub fn metrics(&self) -> PoolMetrics {
+        self.limiter.metrics()
+    }
+
+    /// Returns the statistics by file type of the scans of this pool and its
+    /// clones, as in [`DirScanReport::by_type`]. Interrupted requests are not
+    /// counted.
+    ///
+    /// [`DirScanReport::by_type`]: ../scan/struct.DirScanReport.html#structfield.by_type
+    pub fn type_stats(&self) -> BTreeMap<String, TypeStats> {
+        self.by_type.lock().unwrap().clone()
+    }
+
+    /// Returns the options used for newly admitted scans.
+    pub fn settings(&self) -> ScanSettings {
+        ScanSettings {
+            settings: *self.settings.lock().unwrap(),
+        }
+    }
+
+    /// Starts a control thread operating this pool and its clones, and
+    /// returns a handle to it, see the [`control`] module.
+    ///
+    /// Reloads publish the engine returned by `build`, which must be
+    /// compiled. Scans already admitted finish on their engine.
+    ///
+    /// [`control`]: ../control/index.html
+    pub fn control<F>(&self, build: F) -> ScannerControl
+    where
+        F: FnMut() -> Result<Engine, ClamError> + Send + 'static,
+    {
+        ScannerControl::spawn(PoolTarget {
+            pool: self.clone(),
+            build,
+        })
+    }
+}
+
+/// A pool operated by a control thread, with the function building its engines
+struct PoolTarget<F> {
+    pool: ScannerPool,
+    build: F,
+}
+
+impl<F> Controlled for PoolTarget<F>
+where
+    F: FnMut() -> Result<Engine, ClamError> + Send + 'static,
+{
+    fn reload(&mut self) -> Result<(), ClamError> {
+        let engine = (self.build)()?;
+        self.pool.engine.swap(engine);
+        Ok(())
+    }
+
+    fn purge_cache(&mut self) -> Result<(), ClamError> {
+        // a new engine starts with an empty cache
+        self.reload()
+    }
+
+    fn is_degraded(&self) -> bool {
+        false
+    }
+
+    fn settings(&self) -> ScanSettings {
+        self.pool.settings()
+    }
+
+    fn set_settings(&mut self, settings: ScanSettings) {
+        *self.pool.settings.lock().unwrap() = settings.settings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan_settings::ScanSettingsBuilder;
+    use crate::test_support::{EXAMPLE_DATABASE_PATH, GOOD_FILE_PATH, NAUGHTY_FILE_PATH};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn example_pool(max_in_flight: usize, max_queued: usize) -> ScannerPool {
+        crate::initialize().expect("initialize should succeed");
+        let engine = Engine::new().unwrap();
+        engine
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        engine.compile().expect("failed to compile");
+        let options = PoolOptions { max_in_flight, max_queued };
+        ScannerPool::new(engine, Default::default(), options)
+    }
+
+    fn wait_until<F: Fn(&PoolMetrics) -> bool>(pool: &ScannerPool, condition: F) {
+        while !condition(&pool.metrics()) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Starts a scan which holds its permit until the returned sender is used.
+    fn blocked_scan(pool: &ScannerPool) -> (thread::JoinHandle<Result<(), ClamError>>, mpsc::Sender<()>) {
+        let (release, released) = mpsc::channel();
+        let pool = pool.clone();
+        let handle = thread::spawn(move || {
+            pool.run(|_, _| {
+                released.recv().unwrap();
+                Ok(())
+            })
+        });
+        (handle, release)
+    }
+
+    #[test]
+    fn control_publishes_built_engines_and_settings() {
+        let pool = example_pool(2, 8);
+        let before = pool.engine();
+        let control = pool.control(|| {
+            let engine = Engine::new()?;
+            engine.load_databases(EXAMPLE_DATABASE_PATH)?;
+            engine.compile()?;
+            Ok(engine)
+        });
+
+        control.purge_cache().wait().expect("purge failed");
+        assert!(!Arc::ptr_eq(&before, &pool.engine()));
+        let settings = ScanSettingsBuilder::new().enable_pdf().build();
+        control.set_settings(settings.clone()).wait().expect("set_settings failed");
+        assert_eq!(pool.clone().settings().to_string(), settings.to_string());
+        assert_eq!(pool.scan_file(NAUGHTY_FILE_PATH).unwrap(), ScanResult::Virus("naughty_file.UNOFFICIAL".into()));
+    }
+
+    #[test]
+    fn pool_scans_fixtures() {
+        let pool = example_pool(2, 2);
+        match pool.scan_file(NAUGHTY_FILE_PATH) {
+            Ok(ScanResult::Virus(name)) => assert_eq!(name, "naughty_file.UNOFFICIAL"),
+            _ => panic!("should have matched as a virus"),
+        }
+        let contents = std::fs::read(GOOD_FILE_PATH).unwrap();
+        match pool.scan_mem(&contents, Some(GOOD_FILE_PATH)) {
+            Ok(ScanResult::Clean) => {}
+            _ => panic!("should have been clean"),
+        }
+        assert_eq!(pool.metrics().admitted, 2);
+    }
+
+    #[test]
+    fn queued_streams_hold_the_global_buffer_budget() {
+        let pool = example_pool(1, 4).with_buffer_limits(BufferLimits {
+            per_scan: 64,
+            global: 64,
+        });
+        let (blocker, release) = blocked_scan(&pool);
+        wait_until(&pool, |metrics| metrics.in_flight == 1);
+
+        let queued = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.scan_reader(&[0u8; 40][..], None))
+        };
+        wait_until(&pool, |metrics| metrics.queue_depth == 1);
+        assert_eq!(pool.buffered_bytes(), 40);
+
+        match pool.scan_reader(&[0u8; 40][..], None) {
+            Err(ScanError::BufferLimitExceeded(err)) => {
+                assert_eq!(err.scope, crate::buffer::BufferScope::Global);
+                assert_eq!(err.buffered, 40);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        release.send(()).unwrap();
+        blocker.join().unwrap().unwrap();
+        assert_eq!(queued.join().unwrap(), Ok(ScanResult::Clean));
+        assert_eq!(pool.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn pool_rejects_only_past_queue_limit() {
+        let pool = example_pool(2, 2);
+        let mut scans: Vec<_> = (0..2).map(|_| blocked_scan(&pool)).collect();
+        wait_until(&pool, |m| m.in_flight == 2);
+        scans.extend((0..2).map(|_| blocked_scan(&pool)));
+        wait_until(&pool, |m| m.queue_depth == 2);
+
+        let rejected = pool.scan_file(GOOD_FILE_PATH).expect_err("scan should be rejected");
+        assert_eq!(rejected.code(), cl_error_t::CL_EBUSY as i32);
+
+        for (_, release) in &scans {
+            release.send(()).unwrap();
+        }
+        for (handle, _) in scans {
+            assert!(handle.join().unwrap().is_ok(), "queued scan should succeed");
+        }
+        let metrics = pool.metrics();
+        assert_eq!(metrics.admitted, 4);
+        assert_eq!(metrics.rejected, 1);
+        assert_eq!(metrics.in_flight, 0);
+        assert_eq!(metrics.queue_depth, 0);
+    }
+
+    #[test]
+    fn pool_honors_in_flight_cap() {
+        let pool = example_pool(3, 100);
+        let current = Arc::new(AtomicUsize::new(0));
+        let highest = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..12)
+            .map(|_| {
+                let pool = pool.clone();
+                let current = Arc::clone(&current);
+                let highest = Arc::clone(&highest);
+                thread::spawn(move || {
+                    pool.run(|engine, settings| {
+                        let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                        highest.fetch_max(now, Ordering::SeqCst);
+                        let result = engine.scan_file(NAUGHTY_FILE_PATH, settings);
+                        thread::sleep(Duration::from_millis(5));
+                        current.fetch_sub(1, Ordering::SeqCst);
+                        result
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap().is_ok(), "scan should succeed");
+        }
+        assert!(highest.load(Ordering::SeqCst) <= 3);
+        assert_eq!(pool.metrics().rejected, 0);
+    }
+
+    #[test]
+    fn pool_admits_in_fifo_order() {
+        let pool = example_pool(1, 16);
+        let (first, release) = blocked_scan(&pool);
+        wait_until(&pool, |m| m.in_flight == 1);
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let handles: Vec<_> = (0..5)
+            .map(|i| {
+                let pool_clone = pool.clone();
+                let order = Arc::clone(&order);
+                let handle = thread::spawn(move || {
+                    pool_clone.run(|_, _| {
+                        order.lock().unwrap().push(i);
+                        Ok(())
+                    })
+                });
+                wait_until(&pool, |m| m.queue_depth == i + 1);
+                handle
+            })
+            .collect();
+
+        release.send(()).unwrap();
+        assert!(first.join().unwrap().is_ok());
+        for handle in handles {
+            assert!(handle.join().unwrap().is_ok());
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+        assert!(pool.metrics().max_wait > Duration::from_secs(0));
+    }
+
+    #[test]
+    fn pool_uses_swapped_engine_for_new_scans() {
+        let pool = example_pool(2, 16);
+        let (running, release) = blocked_scan(&pool);
+        wait_until(&pool, |m| m.in_flight == 1);
+        let old = Arc::downgrade(&pool.engine());
+
+        let engine = Engine::new().unwrap();
+        engine
+            .load_databases(EXAMPLE_DATABASE_PATH)
+            .expect("failed to load db");
+        engine.compile().expect("failed to compile");
+        drop(pool.shared_engine().swap(engine));
+
+        assert!(old.upgrade().is_some(), "running scan should keep the old engine");
+        match pool.scan_file(NAUGHTY_FILE_PATH) {
+            Ok(ScanResult::Virus(name)) => assert_eq!(name, "naughty_file.UNOFFICIAL"),
+            _ => panic!("should have matched as a virus"),
+        }
+        release.send(()).unwrap();
+        assert!(running.join().unwrap().is_ok());
+        assert!(old.upgrade().is_none(), "old engine should be freed after the last scan");
+    }
+
+    #[test]
+    fn pool_scan_request_fixtures() {
+        let pool = example_pool(2, 2);
+        let outcome = pool
+            .scan_request(&ScanRequest::path(NAUGHTY_FILE_PATH).timeout(Duration::from_secs(60)))
+            .expect("scan should succeed");
+        assert_eq!(outcome.result, ScanResult::Virus("naughty_file.UNOFFICIAL".into()));
+        let contents = std::fs::read(GOOD_FILE_PATH).unwrap();
+        let outcome = pool.scan_request(&ScanRequest::bytes(contents)).expect("scan should succeed");
+        assert_eq!(outcome.result, ScanResult::Clean);
+    }
+
+    #[test]
+    fn pool_emits_results_to_sink() {
+        use crate::sink::{ChannelSink, Overflow};
+
+        let (sink, receiver) = ChannelSink::new(8, Overflow::Drop);
+        let pool = example_pool(2, 2).with_sink(Arc::new(sink));
+        pool.scan_file(NAUGHTY_FILE_PATH).expect("scan should succeed");
+        let contents = std::fs::read(GOOD_FILE_PATH).unwrap();
+        pool.scan_mem(&contents, None).expect("scan should succeed");
+        pool.scan_request(&ScanRequest::path(GOOD_FILE_PATH)).expect("scan should succeed");
+        pool.scan_request(&ScanRequest::path(GOOD_FILE_PATH).deadline(Instant::now()))
+            .expect_err("request should not be admitted");
+        pool.scan_file("/nonexistent/file").expect_err("scan should fail");
+
+        let records: Vec<_> = receiver.try_iter().collect();
+        let paths: Vec<_> = records.iter().map(|r| r.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Some(NAUGHTY_FILE_PATH.into()),
+                None,
+                Some(GOOD_FILE_PATH.into()),
+                Some("/nonexistent/file".into()),
+            ]
+        );
+        let results: Vec<_> = records.iter().map(|r| r.outcome.clone().ok().map(|o| o.result)).collect();
+        assert_eq!(
+            results,
+            vec![
+                Some(ScanResult::Virus("naughty_file.UNOFFICIAL".into())),
+                Some(ScanResult::Clean),
+                Some(ScanResult::Clean),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn pool_applies_suppressions_before_emitting() {
+        use crate::policy::SuppressionRule;
+        use crate::sink::{ChannelSink, Overflow};
+
+        let (sink, receiver) = ChannelSink::new(8, Overflow::Drop);
+        let contents = std::fs::read(NAUGHTY_FILE_PATH).unwrap();
+        crate::initialize().expect("initialize should succeed");
+        let sha256 = policy::sha256_hex(&contents).unwrap();
+        let rule = SuppressionRule::new("naughty_file.UNOFFICIAL").with_sha256(&sha256);
+        let pool = example_pool(2, 2)
+            .with_sink(Arc::new(sink))
+            .with_suppressions(Arc::new(SuppressionList::new(vec![rule])));
+        let suppressed = ScanResult::Suppressed { original: "naughty_file.UNOFFICIAL".into() };
+        assert_eq!(pool.scan_file(NAUGHTY_FILE_PATH), Ok(suppressed.clone()));
+        assert_eq!(pool.scan_mem(&contents, None), Ok(suppressed.clone()));
+        let outcome = pool.scan_request(&ScanRequest::path(NAUGHTY_FILE_PATH)).expect("scan should succeed");
+        assert_eq!(outcome.result, suppressed);
+        assert_eq!(outcome.suppression.unwrap().sha256, Some(sha256));
+
+        let emitted: Vec<_> = receiver.try_iter().map(|r| r.outcome.unwrap().result).collect();
+        assert_eq!(emitted, vec![suppressed.clone(), suppressed.clone(), suppressed]);
+    }
+
+    #[test]
+    fn pool_counts_scans_by_type_including_cache_hits() {
+        let pool = example_pool(2, 2);
+        let contents = std::fs::read(GOOD_FILE_PATH).unwrap();
+        assert_eq!(pool.scan_file(GOOD_FILE_PATH), Ok(ScanResult::Clean));
+        // served from the clean cache
+        assert_eq!(pool.scan_mem(&contents, None), Ok(ScanResult::Clean));
+        pool.scan_request(&ScanRequest::path(NAUGHTY_FILE_PATH)).expect("scan should succeed");
+
+        let by_type = pool.clone().type_stats();
+        assert_eq!(by_type.keys().collect::<Vec<_>>(), vec!["CL_TYPE_TEXT_ASCII"]);
+        let stats = by_type["CL_TYPE_TEXT_ASCII"];
+        assert_eq!((stats.scanned, stats.infected), (3, 1));
+    }
+
+    #[test]
+    fn pool_scan_request_past_deadline_is_not_admitted() {
+        let pool = example_pool(2, 2);
+        let request = ScanRequest::path(GOOD_FILE_PATH).deadline(Instant::now());
+        match pool.scan_request(&request) {
+            Err(ScanError::DeadlineExceeded(progress)) => assert_eq!(progress.bytes_scanned, None),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(pool.metrics().admitted, 0);
+    }
+
+    #[test]
+    fn pool_scan_request_deadline_bounds_queueing() {
+        let pool = example_pool(1, 16);
+        let (holder, release_holder) = blocked_scan(&pool);
+        wait_until(&pool, |m| m.in_flight == 1);
+        let (first, release_first) = blocked_scan(&pool);
+        wait_until(&pool, |m| m.queue_depth == 1);
+
+        let request = ScanRequest::path(GOOD_FILE_PATH).timeout(Duration::from_millis(50));
+        let started = Instant::now();
+        match pool.scan_request(&request) {
+            Err(ScanError::DeadlineExceeded(progress)) => assert_eq!(progress.bytes_scanned, None),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(pool.metrics().queue_depth, 1);
+
+        // scans queued behind the abandoned request are still admitted
+        let (last, release_last) = blocked_scan(&pool);
+        wait_until(&pool, |m| m.queue_depth == 2);
+        for release in &[release_holder, release_first, release_last] {
+            release.send(()).unwrap();
+        }
+        for handle in [holder, first, last] {
+            assert!(handle.join().unwrap().is_ok(), "queued scan should succeed");
+        }
+        let metrics = pool.metrics();
+        assert_eq!(metrics.admitted, 3);
+        assert_eq!(metrics.queue_depth, 0);
+        assert_eq!(metrics.in_flight, 0);
+    }
+}
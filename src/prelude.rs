@@ -0,0 +1,23 @@
+//! The types needed by most programs, for glob importing.
+//!
+//! ```
+//! use clamav_rs::prelude::*;
+//!
+//! clamav_rs::initialize().expect("failed to initialize");
+//! let scanner = Engine::new().expect("failed to create engine");
+//! scanner.load_databases("test_data/database/").expect("failed to load");
+//! scanner.compile().expect("failed to compile");
+//!
+//! let settings = ScanSettingsBuilder::new().enable_pdf().build();
+//! match scanner.scan_file("test_data/files/good_file", &settings) {
+//!     Ok(ScanResult::Virus(name)) => println!("Virus {}", name),
+//!     Ok(_) => println!("Clean"),
+//!     Err(err) => println!("Scan failed: {}", err),
+//! }
+//! ```
+
+pub use crate::engine::{Detection, Engine, ScanOutcome, ScanResult};
+pub use crate::error::{ClamError, ClamErrorKind};
+pub use crate::fmap::{AsFmap, Fmap, MapError, OwnedFmap};
+pub use crate::scan_settings::{ScanSettings, ScanSettingsBuilder};
+pub use crate::scanner::Scanner;
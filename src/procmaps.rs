@@ -0,0 +1,145 @@
+//! Minimal `/proc/<pid>/maps` reader for process memory scanning.
+//!
+//! Reading unmapped or inaccessible addresses of `/proc/<pid>/mem` fails
+//! with `EIO`, which would fail the whole scan. The mappings are read up
+//! front instead, to find how much of a requested region can be read, and
+//! the pages of that region are probed for the ones which fail anyway.
+
+use std::fs::{self, File};
+use std::io;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+
+/// A mapping of a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Mapping {
+    pub(crate) range: Range<u64>,
+    /// Whether the mapping has read permission
+    pub(crate) readable: bool,
+}
+
+/// Parses the contents of a maps file, e.g.
+/// `7f0c1a2b3000-7f0c1a2b5000 r--p 00000000 08:01 1234 /usr/lib/libc.so.6`.
+/// Lines which cannot be parsed are skipped.
+pub(crate) fn parse_maps(contents: &str) -> Vec<Mapping> {
+    let mut mappings: Vec<_> = contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let perms = fields.next()?;
+            let start = u64::from_str_radix(start, 16).ok()?;
+            let end = u64::from_str_radix(end, 16).ok()?;
+            if start >= end {
+                return None;
+            }
+            Some(Mapping {
+                range: start..end,
+                readable: perms.starts_with('r'),
+            })
+        })
+        .collect();
+    mappings.sort_by_key(|mapping| mapping.range.start);
+    mappings
+}
+
+/// Reads the mappings of process `pid`.
+pub(crate) fn read_maps(pid: u32) -> io::Result<Vec<Mapping>> {
+    Ok(parse_maps(&fs::read_to_string(format!("/proc/{}/maps", pid))?))
+}
+
+/// Returns the end of the readable prefix of `range`: the first address of
+/// `range` which is not covered by a readable mapping, or `range.end`.
+pub(crate) fn readable_end(mappings: &[Mapping], range: &Range<u64>) -> u64 {
+    let mut end = range.start;
+    for mapping in mappings {
+        if end >= range.end || mapping.range.start > end {
+            break;
+        }
+        if mapping.range.end <= end {
+            continue;
+        }
+        if !mapping.readable {
+            break;
+        }
+        end = mapping.range.end;
+    }
+    end.min(range.end)
+}
+
+/// Returns the end of the prefix of `range` which can be read from `mem`,
+/// the `/proc/<pid>/mem` of a process: the start of the first page of
+/// `range` whose first byte fails with `EIO`, or `range.end`.
+///
+/// Readable mappings can still fail, e.g. device memory, or pages unmapped
+/// after the maps were read.
+pub(crate) fn probe_readable_end(mem: &File, range: &Range<u64>, page_size: u64) -> io::Result<u64> {
+    let mut byte = [0u8; 1];
+    let mut at = range.start;
+    while at < range.end {
+        match mem.read_at(&mut byte, at) {
+            Ok(0) => return Ok(at),
+            Ok(_) => at = (at / page_size + 1) * page_size,
+            Err(err) if err.raw_os_error() == Some(libc::EIO) => return Ok(at),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(range.end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::GuardedRegion;
+
+    const MAPS: &str = "\
+00400000-00452000 r-xp 00000000 08:02 173521      /usr/bin/dbus-daemon
+00651000-00652000 r--p 00051000 08:02 173521      /usr/bin/dbus-daemon
+00652000-00655000 rw-p 00052000 08:02 173521      /usr/bin/dbus-daemon
+00e03000-00e24000 rw-p 00000000 00:00 0           [heap]
+00e24000-00e25000 ---p 00000000 00:00 0
+00e25000-00e26000 rw-p 00000000 00:00 0
+not a mapping
+ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0  [vsyscall]
+";
+
+    #[test]
+    fn parse_maps_reads_ranges_and_permissions() {
+        let mappings = parse_maps(MAPS);
+        assert_eq!(mappings.len(), 7);
+        assert_eq!(
+            mappings[0],
+            Mapping {
+                range: 0x400000..0x452000,
+                readable: true
+            }
+        );
+        assert!(!mappings[4].readable);
+        assert!(!mappings[6].readable);
+    }
+
+    #[test]
+    fn readable_end_stops_at_gaps_and_unreadable_mappings() {
+        let mappings = parse_maps(MAPS);
+        // contiguous readable mappings
+        assert_eq!(readable_end(&mappings, &(0x651800..0x654000)), 0x654000);
+        assert_eq!(readable_end(&mappings, &(0x651800..0x700000)), 0x655000);
+        // guard page after the heap
+        assert_eq!(readable_end(&mappings, &(0xe03000..0xe26000)), 0xe24000);
+        // unmapped start
+        assert_eq!(readable_end(&mappings, &(0x300000..0x500000)), 0x300000);
+        assert_eq!(readable_end(&mappings, &(0xe24000..0xe25000)), 0xe24000);
+    }
+
+    #[test]
+    fn probe_stops_at_the_first_page_failing_with_eio() {
+        let region = GuardedRegion::new();
+        let mem = File::open("/proc/self/mem").unwrap();
+        let (start, guard) = (region.start(), region.guard());
+        let page = guard - start;
+        assert_eq!(probe_readable_end(&mem, &(start + 1..guard), page).unwrap(), guard);
+        assert_eq!(probe_readable_end(&mem, &(start + 1..guard + page), page).unwrap(), guard);
+        assert_eq!(probe_readable_end(&mem, &(guard + 8..guard + 16), page).unwrap(), guard + 8);
+    }
+}
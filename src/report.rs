@@ -0,0 +1,122 @@
+//! clamscan-compatible reporting for command line tools.
+//!
+//! Together with [`ScanOutcome::format_line`] and [`DirScanEntry::format_line`],
+//! this lets a wrapper print the same lines and exit with the same status as
+//! `clamscan`, so scripts written for it keep working.
+//!
+//! [`ScanOutcome::format_line`]: ../engine/struct.ScanOutcome.html#method.format_line
+//! [`DirScanEntry::format_line`]: ../scan/struct.DirScanEntry.html#method.format_line
+
+use crate::engine::ScanResult;
+use crate::scan::DirScanReport;
+
+/// Exit code when no file was infected and no error occurred
+pub const EXIT_CLEAN: i32 = 0;
+/// Exit code when at least one file was infected
+pub const EXIT_FOUND: i32 = 1;
+/// Exit code when an error occurred and no file was infected
+pub const EXIT_ERROR: i32 = 2;
+
+/// Returns the clamscan exit code for a directory scan.
+///
+/// As in clamscan, detections take priority over errors: a scan which found
+/// a virus exits with [`EXIT_FOUND`] even if other files failed to scan. A
+/// cancelled scan, one stopped by its budget, or one which returned
+/// `ScanResult::NotScanned` for any file counts as an error, since not all
+/// files were checked.
+///
+/// [`EXIT_FOUND`]: constant.EXIT_FOUND.html
+pub fn exit_code(results: &DirScanReport) -> i32 {
+    if results.infected().next().is_some() {
+        EXIT_FOUND
+    } else if results.cancelled
+        || results.budget_exhausted.is_some()
+        || results.entries.iter().any(|entry| {
+            matches!(entry.result, Err(_) | Ok(ScanResult::NotScanned(_)))
+        }) {
+        EXIT_ERROR
+    } else {
+        EXIT_CLEAN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::NotScannedReason;
+    use crate::error::ClamError;
+    use crate::scan::DirScanEntry;
+    use clamav_sys::cl_error_t;
+    use std::path::PathBuf;
+
+    fn report(results: Vec<Result<ScanResult, ClamError>>, cancelled: bool) -> DirScanReport {
+        let mut report = DirScanReport::default();
+        report.entries = results
+            .into_iter()
+            .enumerate()
+            .map(|(i, result)| DirScanEntry {
+                path: PathBuf::from(format!("file{}", i)),
+                result,
+                cached: false,
+                unstable: false,
+            })
+            .collect();
+        report.cancelled = cancelled;
+        report
+    }
+
+    fn virus() -> Result<ScanResult, ClamError> {
+        Ok(ScanResult::Virus("Eicar-Signature".into()))
+    }
+
+    fn error() -> Result<ScanResult, ClamError> {
+        Err(ClamError::new(cl_error_t::CL_EOPEN))
+    }
+
+    #[test]
+    fn empty_report_is_clean() {
+        assert_eq!(exit_code(&report(vec![], false)), EXIT_CLEAN);
+    }
+
+    #[test]
+    fn clean_and_whitelisted_are_clean() {
+        let results = vec![Ok(ScanResult::Clean), Ok(ScanResult::Whitelisted)];
+        assert_eq!(exit_code(&report(results, false)), EXIT_CLEAN);
+    }
+
+    #[test]
+    fn detection_is_found() {
+        assert_eq!(exit_code(&report(vec![Ok(ScanResult::Clean), virus()], false)), EXIT_FOUND);
+    }
+
+    #[test]
+    fn error_is_error() {
+        assert_eq!(exit_code(&report(vec![Ok(ScanResult::Clean), error()], false)), EXIT_ERROR);
+    }
+
+    #[test]
+    fn detection_takes_priority_over_error() {
+        assert_eq!(exit_code(&report(vec![error(), virus()], false)), EXIT_FOUND);
+        assert_eq!(exit_code(&report(vec![virus()], true)), EXIT_FOUND);
+    }
+
+    #[test]
+    fn not_scanned_is_error() {
+        for reason in &[NotScannedReason::NoDatabase, NotScannedReason::EmptyEngine] {
+            let results = vec![Ok(ScanResult::Clean), Ok(ScanResult::NotScanned(*reason))];
+            assert_eq!(exit_code(&report(results, false)), EXIT_ERROR);
+        }
+    }
+
+    #[test]
+    fn cancelled_is_error() {
+        assert_eq!(exit_code(&report(vec![Ok(ScanResult::Clean)], true)), EXIT_ERROR);
+    }
+
+    #[test]
+    fn budget_exhausted_is_error() {
+        let mut results = report(vec![Ok(ScanResult::Clean)], false);
+        results.budget_exhausted = Some(crate::scan::BudgetLimit::Files);
+        assert_eq!(exit_code(&results), EXIT_ERROR);
+    }
+}
@@ -0,0 +1,337 @@
+//! Scans bounded by a deadline or cancellation token.
+//!
+//! Services handling requests with deadlines hand a [`ScanRequest`] to
+//! `Scanner::scan_request` or `ScannerPool::scan_request` instead of wiring
+//! timeouts around every scan. Async callers run these blocking calls on a
+//! blocking thread pool, e.g. with `tokio::task::spawn_blocking`.
+//!
+//! libclamav offers no way to interrupt a running scan from another thread,
+//! so the deadline and the token are checked before the engine is touched,
+//! while waiting for admission to a pool, by the engine callbacks before
+//! each object of the scan, and when the scan returns. Once the request is
+//! given up on, the callbacks skip the objects left, e.g. the remaining
+//! members of an archive, so the scan returns early; a single large object
+//! is still scanned to its end. A scan which finishes after its deadline or
+//! after being cancelled fails with [`ScanError`] and its verdict is
+//! discarded, so callers never act on a result they have already given up
+//! on. To bound the time a single object can take, set
+//! `CL_ENGINE_MAX_SCANTIME` on the engine.
+//!
+//! [`ScanRequest`]: struct.ScanRequest.html
+//! [`ScanError`]: enum.ScanError.html
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clamav_sys::cl_error_t;
+
+use crate::buffer::BufferLimitError;
+use crate::engine::{Engine, ScanOutcome};
+use crate::error::ClamError;
+use crate::fmap::Fmap;
+use crate::policy;
+use crate::scan::ScanToken;
+use crate::scan_settings::ScanSettings;
+
+/// What a [`ScanRequest`] scans
+///
+/// [`ScanRequest`]: struct.ScanRequest.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanSource {
+    /// A file
+    Path(PathBuf),
+    /// A memory buffer
+    Bytes(Vec<u8>),
+}
+
+/// A scan with an optional deadline and cancellation token
+#[derive(Clone)]
+pub struct ScanRequest {
+    pub source: ScanSource,
+    /// Scan options overriding those of the scanner or pool
+    pub settings: Option<ScanSettings>,
+    /// Point in time after which the result is no longer wanted
+    pub deadline: Option<Instant>,
+    /// Token to give up on the scan from another thread
+    pub cancellation: Option<ScanToken>,
+}
+
+impl ScanRequest {
+    /// Creates a request without deadline or cancellation token.
+    pub fn new(source: ScanSource) -> Self {
+        ScanRequest {
+            source,
+            settings: None,
+            deadline: None,
+            cancellation: None,
+        }
+    }
+
+    /// Creates a request scanning the file at `path`.
+    pub fn path<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(ScanSource::Path(path.into()))
+    }
+
+    /// Creates a request scanning `data`.
+    pub fn bytes<B: Into<Vec<u8>>>(data: B) -> Self {
+        Self::new(ScanSource::Bytes(data.into()))
+    }
+
+    /// Sets the deadline to `timeout` from now.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Sets the deadline.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the cancellation token.
+    pub fn cancellation(mut self, token: ScanToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Sets the scan options.
+    pub fn settings(mut self, settings: ScanSettings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Returns the deadline and token for the engine callbacks.
+    pub(crate) fn interrupt(&self) -> Interrupt {
+        Interrupt {
+            deadline: self.deadline,
+            cancellation: self.cancellation.clone(),
+        }
+    }
+
+    /// Opens the source for scanning.
+    pub(crate) fn open(&self) -> Result<OpenedSource<'_>, ClamError> {
+        match &self.source {
+            ScanSource::Path(path) => {
+                let file = File::open(path).map_err(|_| ClamError::new(cl_error_t::CL_EOPEN))?;
+                Ok(OpenedSource::File(file, path))
+            }
+            ScanSource::Bytes(data) => Ok(OpenedSource::Bytes(data)),
+        }
+    }
+
+    /// Returns the error to fail with if the request is cancelled or past
+    /// its deadline.
+    pub(crate) fn interrupted(&self, started: Instant, progress: Option<u64>) -> Option<ScanError> {
+        let progress = || ScanProgress {
+            elapsed: started.elapsed(),
+            bytes_scanned: progress,
+        };
+        if matches!(&self.cancellation, Some(token) if token.is_cancelled()) {
+            return Some(ScanError::Cancelled(progress()));
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Some(ScanError::DeadlineExceeded(progress())),
+            _ => None,
+        }
+    }
+
+    /// Runs `scan` unless the request is already interrupted, and discards
+    /// its result if the request was interrupted while it ran.
+    pub(crate) fn run<F>(&self, started: Instant, scan: F) -> Result<ScanOutcome, ScanError>
+    where
+        F: FnOnce() -> Result<ScanOutcome, ClamError>,
+    {
+        if let Some(err) = self.interrupted(started, None) {
+            return Err(err);
+        }
+        let outcome = scan()?;
+        match self.interrupted(started, Some(outcome.bytes_scanned)) {
+            Some(err) => Err(err),
+            None => Ok(outcome),
+        }
+    }
+}
+
+/// The deadline and cancellation token of a request, checked by the engine
+/// callbacks while its scan runs
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interrupt {
+    deadline: Option<Instant>,
+    cancellation: Option<ScanToken>,
+}
+
+impl Interrupt {
+    /// Returns whether the request was cancelled or is past its deadline.
+    pub(crate) fn is_due(&self) -> bool {
+        matches!(&self.cancellation, Some(token) if token.is_cancelled())
+            || matches!(self.deadline, Some(deadline) if Instant::now() >= deadline)
+    }
+}
+
+/// The source of a [`ScanRequest`], opened for scanning
+///
+/// [`ScanRequest`]: struct.ScanRequest.html
+pub(crate) enum OpenedSource<'a> {
+    File(File, &'a Path),
+    Bytes(&'a [u8]),
+}
+
+impl OpenedSource<'_> {
+    /// Scans the source with `engine`, skipping the objects left once
+    /// `interrupt` is due. Also returns the type libclamav determined for
+    /// the source, e.g. `CL_TYPE_PDF`.
+    pub(crate) fn scan(&self, engine: &Engine, settings: &ScanSettings, interrupt: Interrupt) -> (Result<ScanOutcome, ClamError>, Option<String>) {
+        match self {
+            OpenedSource::File(file, path) => engine.scan_fileobj_interruptible(file, settings, path.to_str(), interrupt),
+            OpenedSource::Bytes(data) => match Fmap::new_from_memory(data.as_ptr(), data.len() as u64) {
+                Ok(map) => engine.scan_map_interruptible(&map, settings, interrupt),
+                Err(_) => (Err(ClamError::new(cl_error_t::CL_EMAP)), None),
+            },
+        }
+    }
+
+    /// Returns the hex encoded SHA-256 of the scanned data. Files are hashed
+    /// through the descriptor they were scanned through.
+    pub(crate) fn sha256(&self) -> Option<String> {
+        match self {
+            OpenedSource::File(file, _) => policy::sha256_file_hex(file),
+            OpenedSource::Bytes(data) => policy::sha256_hex(data),
+        }
+    }
+}
+
+/// How far a scan got before its request was interrupted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanProgress {
+    /// Time since the request was submitted
+    pub elapsed: Duration,
+    /// Bytes libclamav scanned, or `None` if the engine was not called
+    pub bytes_scanned: Option<u64>,
+}
+
+/// Error of a [`ScanRequest`]
+///
+/// [`ScanRequest`]: struct.ScanRequest.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanError {
+    /// The scan itself failed
+    Clam(ClamError),
+    /// The deadline passed before the scan could finish
+    DeadlineExceeded(ScanProgress),
+    /// The cancellation token was cancelled before the scan could finish
+    Cancelled(ScanProgress),
+    /// A streamed input would have exceeded the scanner's `BufferLimits`
+    BufferLimitExceeded(BufferLimitError),
+}
+
+impl ScanError {
+    /// Returns the progress of an interrupted scan.
+    pub fn progress(&self) -> Option<&ScanProgress> {
+        match self {
+            ScanError::Clam(_) | ScanError::BufferLimitExceeded(_) => None,
+            ScanError::DeadlineExceeded(progress) | ScanError::Cancelled(progress) => Some(progress),
+        }
+    }
+}
+
+impl From<ClamError> for ScanError {
+    fn from(err: ClamError) -> Self {
+        ScanError::Clam(err)
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::Clam(err) => write!(f, "{}", err),
+            ScanError::DeadlineExceeded(progress) => {
+                write!(f, "scan deadline exceeded after {:?}", progress.elapsed)
+            }
+            ScanError::Cancelled(progress) => write!(f, "scan cancelled after {:?}", progress.elapsed),
+            ScanError::BufferLimitExceeded(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for ScanError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ScanError::Clam(err) => Some(err),
+            ScanError::BufferLimitExceeded(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome() -> Result<ScanOutcome, ClamError> {
+        Ok(ScanOutcome {
+            result: crate::engine::ScanResult::Clean,
+            bytes_scanned: 4096,
+            suppression: None,
+            performance: None,
+            from_cache: None,
+        })
+    }
+
+    #[test]
+    fn request_without_limits_runs() {
+        let request = ScanRequest::bytes(vec![0u8; 16]);
+        assert_eq!(request.run(Instant::now(), outcome), outcome().map_err(ScanError::from));
+    }
+
+    #[test]
+    fn past_deadline_fails_fast() {
+        let request = ScanRequest::bytes(vec![0u8; 16]).deadline(Instant::now());
+        match request.run(Instant::now(), || panic!("engine should not be called")) {
+            Err(ScanError::DeadlineExceeded(progress)) => assert_eq!(progress.bytes_scanned, None),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cancelled_token_fails_fast() {
+        let token = ScanToken::new();
+        token.cancel();
+        let request = ScanRequest::bytes(vec![0u8; 16]).cancellation(token);
+        match request.run(Instant::now(), || panic!("engine should not be called")) {
+            Err(ScanError::Cancelled(progress)) => assert_eq!(progress.bytes_scanned, None),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn interruption_during_scan_discards_result() {
+        let token = ScanToken::new();
+        let request = ScanRequest::bytes(vec![0u8; 16]).cancellation(token.clone());
+        let result = request.run(Instant::now(), || {
+            token.cancel();
+            outcome()
+        });
+        match result {
+            Err(ScanError::Cancelled(progress)) => assert_eq!(progress.bytes_scanned, Some(4096)),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let request = ScanRequest::bytes(vec![0u8; 16]).timeout(Duration::from_millis(20));
+        let result = request.run(Instant::now(), || {
+            std::thread::sleep(Duration::from_millis(40));
+            outcome()
+        });
+        assert!(matches!(result, Err(ScanError::DeadlineExceeded(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn scan_errors_are_passed_through() {
+        let request = ScanRequest::bytes(vec![0u8; 16]);
+        let err = ClamError::new(cl_error_t::CL_EMAP);
+        assert_eq!(request.run(Instant::now(), || Err(err.clone())), Err(ScanError::Clam(err)));
+    }
+}
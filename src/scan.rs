@@ -0,0 +1,3227 @@
+//! Scanning of directory trees and raw disk images.
+
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+#[cfg(target_os = "linux")]
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use clamav_sys::{cl_error_t, CL_SCAN_HEURISTIC_PARTITION_INTXN};
+
+use crate::engine::{Engine, NotScannedReason, ScanOutcome, ScanResult, WindowConfig, WindowedScanReport};
+use crate::error::ClamError;
+#[cfg(target_os = "linux")]
+use crate::fmap::Fmap;
+use crate::partition;
+use crate::policy::{self, SuppressionList, SuppressionRecord};
+#[cfg(target_os = "linux")]
+use crate::procmaps;
+use crate::scan_settings::ScanSettings;
+use crate::sink::{ResultSink, ScanRecord};
+use crate::trace;
+
+/// Result of scanning a single file during a directory scan
+#[derive(Debug, Clone)]
+pub struct DirScanEntry {
+    /// Path of the scanned file
+    pub path: PathBuf,
+    /// Verdict, or the error which prevented scanning the file
+    pub result: Result<ScanResult, ClamError>,
+    /// True if the verdict was taken from a cache instead of scanning the file
+    pub cached: bool,
+    /// True if the file changed size or modification time while it was
+    /// scanned, so the verdict may not match its current contents
+    pub unstable: bool,
+}
+
+impl DirScanEntry {
+    /// Formats the clamscan report line of this file: `<path>: OK`,
+    /// `<path>: <signature> FOUND` or `<path>: <error> ERROR`.
+    pub fn format_line(&self) -> String {
+        match &self.result {
+            Ok(result) => format!("{}: {}", self.path.display(), result),
+            Err(err) => format!("{}: {} ERROR", self.path.display(), err.string_error()),
+        }
+    }
+}
+
+/// Why a file found by the directory walk was not scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// The file was deleted or renamed before it could be opened
+    Vanished,
+    /// The path no longer refers to a regular file, e.g. it was replaced by
+    /// a symbolic link
+    Replaced,
+    /// A [`BatchBudget`] ran out before the file's turn came
+    ///
+    /// [`BatchBudget`]: struct.BatchBudget.html
+    BudgetExhausted,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::Vanished => write!(f, "vanished"),
+            SkipReason::Replaced => write!(f, "replaced"),
+            SkipReason::BudgetExhausted => write!(f, "budget exhausted"),
+        }
+    }
+}
+
+/// A file found by the directory walk which was not scanned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// A file whose detection was downgraded by a suppression rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuppressedFile {
+    pub path: PathBuf,
+    /// The suppressed detection and the rule which matched it
+    pub record: SuppressionRecord,
+}
+
+/// Key in [`DirScanReport::by_type`] for files libclamav reported no type
+/// for, e.g. empty files
+///
+/// [`DirScanReport::by_type`]: struct.DirScanReport.html#structfield.by_type
+pub const UNKNOWN_FILE_TYPE: &str = "CL_TYPE_ANY";
+
+/// Scan statistics of the files of one type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeStats {
+    /// Number of files scanned successfully, including files libclamav
+    /// served from its clean cache
+    pub scanned: u64,
+    /// Number of files detected as viruses or exceeding a limit
+    pub infected: u64,
+    /// Bytes libclamav reported as scanned, in multiples of `CL_COUNT_PRECISION`
+    pub bytes: u64,
+    /// Time spent scanning, summed over all scanning threads
+    pub time: Duration,
+}
+
+impl TypeStats {
+    /// Returns the fraction of scanned files which were infected, or 0 if
+    /// none were scanned.
+    pub fn detection_rate(&self) -> f64 {
+        if self.scanned == 0 {
+            0.0
+        } else {
+            self.infected as f64 / self.scanned as f64
+        }
+    }
+
+    fn merge(&mut self, other: &TypeStats) {
+        self.scanned += other.scanned;
+        self.infected += other.infected;
+        self.bytes += other.bytes;
+        self.time += other.time;
+    }
+}
+
+/// Formats the statistics as `3 scanned, 1 infected, 12288 bytes in 1.5ms`
+impl fmt::Display for TypeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} scanned, {} infected, {} bytes in {:?}",
+            self.scanned, self.infected, self.bytes, self.time
+        )
+    }
+}
+
+/// Adds a scanned file to the statistics of its type. Failed scans, passed
+/// as `None`, are not counted.
+///
+/// Files served from libclamav's clean cache are counted by the type
+/// reported before the cache lookup, with no bytes scanned.
+pub(crate) fn count_type(
+    by_type: &mut BTreeMap<String, TypeStats>,
+    file_type: Option<String>,
+    outcome: Option<&ScanOutcome>,
+    time: Duration,
+) {
+    if let Some(outcome) = outcome {
+        let stats = by_type
+            .entry(file_type.unwrap_or_else(|| UNKNOWN_FILE_TYPE.to_string()))
+            .or_default();
+        stats.scanned += 1;
+        stats.infected += u64::from(outcome.result.found());
+        stats.bytes += outcome.bytes_scanned;
+        stats.time += time;
+    }
+}
+
+/// Results of a directory scan
+///
+/// When the scan finishes, `entries` and `skipped` are sorted by path, so
+/// scanning the same unchanged tree gives the same report however many
+/// threads scan it and in whichever order they finish. The order in which
+/// the files finished is still available from [`iter_completion_order`].
+///
+/// [`iter_completion_order`]: #method.iter_completion_order
+#[derive(Debug, Clone, Default)]
+pub struct DirScanReport {
+    pub entries: Vec<DirScanEntry>,
+    /// True if the scan was cancelled before all files were scanned
+    pub cancelled: bool,
+    /// Statistics of the scanned files by the type libclamav determined,
+    /// e.g. `CL_TYPE_PDF`. Verdicts reused by an [`IncrementalScanner`] are
+    /// not included.
+    ///
+    /// [`IncrementalScanner`]: struct.IncrementalScanner.html
+    pub by_type: BTreeMap<String, TypeStats>,
+    /// Files which disappeared or were replaced between the walk and the
+    /// scan. These are neither errors nor verdicts.
+    pub skipped: Vec<SkippedFile>,
+    /// Audit records of the detections downgraded by a suppression list,
+    /// sorted by path. The entries of these files report
+    /// `ScanResult::Suppressed`.
+    pub suppressed: Vec<SuppressedFile>,
+    /// The limit of the [`BatchBudget`] which stopped the scan, if any. The
+    /// files left are listed in `skipped` as
+    /// [`SkipReason::BudgetExhausted`].
+    ///
+    /// [`BatchBudget`]: struct.BatchBudget.html
+    /// [`SkipReason::BudgetExhausted`]: enum.SkipReason.html#variant.BudgetExhausted
+    pub budget_exhausted: Option<BudgetLimit>,
+    /// Where to continue a scan stopped by its budget, see
+    /// [`scan_directory_with_budget`].
+    ///
+    /// [`scan_directory_with_budget`]: fn.scan_directory_with_budget.html
+    pub resume: Option<ResumeToken>,
+    /// Completion number of each entry of `entries`
+    completion: Vec<u64>,
+}
+
+impl DirScanReport {
+    /// Paths of the files which were actually scanned, i.e. not served from a cache.
+    pub fn scanned(&self) -> impl Iterator<Item = &Path> {
+        self.entries.iter().filter(|e| !e.cached).map(|e| e.path.as_path())
+    }
+
+    /// Entries which were detected as viruses or exceeded a limit.
+    pub fn infected(&self) -> impl Iterator<Item = &DirScanEntry> {
+        self.entries
+            .iter()
+            .filter(|e| matches!(&e.result, Ok(result) if result.found()))
+    }
+
+    /// Entries whose file changed while it was scanned.
+    pub fn unstable(&self) -> impl Iterator<Item = &DirScanEntry> {
+        self.entries.iter().filter(|e| e.unstable)
+    }
+
+    /// Returns the entries in the order their scans finished. Unreadable
+    /// subdirectories come last.
+    pub fn iter_completion_order(&self) -> impl Iterator<Item = &DirScanEntry> {
+        let mut order: Vec<_> = (0..self.entries.len()).collect();
+        // entries added to the report by the caller count as finished last
+        order.sort_by_key(|&i| self.completion.get(i).copied().unwrap_or(u64::MAX));
+        order.into_iter().map(move |i| &self.entries[i])
+    }
+
+    /// Adds `entry` as the `completion`th finished file.
+    fn push(&mut self, entry: DirScanEntry, completion: u64) {
+        self.entries.push(entry);
+        self.completion.push(completion);
+    }
+
+    fn merge(&mut self, mut other: DirScanReport) {
+        self.entries.append(&mut other.entries);
+        self.completion.append(&mut other.completion);
+        self.skipped.append(&mut other.skipped);
+        self.suppressed.append(&mut other.suppressed);
+        for (file_type, stats) in other.by_type {
+            self.by_type.entry(file_type).or_default().merge(&stats);
+        }
+        self.budget_exhausted = self.budget_exhausted.or(other.budget_exhausted);
+        if self.resume.is_none() {
+            self.resume = other.resume;
+        }
+    }
+
+    /// Records that `limit` stopped the scan before `path`, the `sequence`th
+    /// file of the sorted walk, and lists it and the files `left` after it
+    /// as skipped.
+    fn stop<I: Iterator<Item = PathBuf>>(&mut self, limit: BudgetLimit, sequence: u64, path: PathBuf, left: I) {
+        self.budget_exhausted = Some(limit);
+        self.resume = Some(ResumeToken { position: sequence, path: path.clone() });
+        let left = std::iter::once(path).chain(left);
+        self.skipped.extend(left.map(|path| SkippedFile {
+            path,
+            reason: SkipReason::BudgetExhausted,
+        }));
+    }
+}
+
+/// Recursively collects all regular files below `dir`, sorted by path.
+///
+/// Symbolic links are not followed. Subdirectories which cannot be read are
+/// reported in the second element of the returned tuple.
+fn walk(dir: &Path) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    let mut unreadable = walk_with(dir, |path| {
+        files.push(path);
+        true
+    })?;
+
+    files.sort();
+    unreadable.sort();
+    Ok((files, unreadable))
+}
+
+/// Recursively visits all regular files below `dir` in no particular order.
+///
+/// The walk stops early if `on_file` returns false. Returns the subdirectories
+/// which could not be read.
+fn walk_with<F: FnMut(PathBuf) -> bool>(dir: &Path, mut on_file: F) -> io::Result<Vec<PathBuf>> {
+    let mut unreadable = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    let mut root = true;
+
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if root => return Err(err),
+            Err(_) => {
+                unreadable.push(dir);
+                continue;
+            }
+        };
+        root = false;
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => {
+                    unreadable.push(dir.clone());
+                    continue;
+                }
+            };
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => pending.push(entry.path()),
+                Ok(file_type) if file_type.is_file() && !on_file(entry.path()) => {
+                    return Ok(unreadable);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Ok(unreadable)
+}
+
+/// Points in the scan of a single file where tests can interfere
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// The walk found the file, which is about to be opened
+    Walked,
+    /// The file is open and about to be scanned
+    Opened,
+}
+
+/// Callback run at each [`Stage`] of every file
+type Hook<'a> = Option<&'a (dyn Fn(Stage, &Path) + Sync)>;
+
+fn run_hook(hook: Hook, stage: Stage, path: &Path) {
+    if let Some(hook) = hook {
+        hook(stage, path);
+    }
+}
+
+/// A file opened for scanning
+struct OpenedFile {
+    file: File,
+    /// Size and modification time when the file was opened
+    key: CacheKey,
+}
+
+/// What became of a file found by the walk when it was opened
+enum Opened {
+    File(OpenedFile),
+    Skipped(SkipReason),
+    Failed(ClamError),
+}
+
+/// Opens the file at `path` for scanning.
+///
+/// As in the walk, symbolic links are not followed. The file is opened
+/// non-blocking so that a FIFO put in its place cannot stall the scan.
+fn open_file(path: &Path) -> Opened {
+    let mut options = fs::OpenOptions::new();
+    options.read(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK);
+    }
+    let file = match options.open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Opened::Skipped(SkipReason::Vanished),
+        #[cfg(unix)]
+        Err(err) if err.raw_os_error() == Some(libc::ELOOP) => return Opened::Skipped(SkipReason::Replaced),
+        Err(_) => return Opened::Failed(ClamError::new(cl_error_t::CL_EOPEN)),
+    };
+    match file.metadata() {
+        Ok(metadata) if metadata.is_file() => Opened::File(OpenedFile {
+            file,
+            key: CacheKey::of(&metadata),
+        }),
+        Ok(_) => Opened::Skipped(SkipReason::Replaced),
+        Err(_) => Opened::Failed(ClamError::new(cl_error_t::CL_ESTAT)),
+    }
+}
+
+impl OpenedFile {
+    /// Scans the file through its descriptor, returning the outcome, the
+    /// file's type if `typed` is set, and whether the file changed while it
+    /// was scanned.
+    fn scan(
+        &self,
+        engine: &Engine,
+        path: &Path,
+        settings: &ScanSettings,
+        typed: bool,
+    ) -> (Result<ScanOutcome, ClamError>, Option<String>, bool) {
+        let (outcome, file_type) = if typed {
+            engine.scan_fileobj_typed(&self.file, settings, path.to_str())
+        } else {
+            (engine.scan_fileobj_outcome(&self.file, settings, path.to_str()), None)
+        };
+        let stable = matches!(self.file.metadata(), Ok(metadata) if CacheKey::of(&metadata) == self.key);
+        (outcome, file_type, !stable)
+    }
+}
+
+/// What stays the same for all files of a directory scan
+struct FileScanner<'a> {
+    engine: &'a Engine,
+    settings: &'a ScanSettings,
+    /// Whether to collect statistics by file type
+    typed: bool,
+    sink: Option<&'a dyn ResultSink>,
+    suppressions: Option<&'a SuppressionList>,
+    hook: Hook<'a>,
+    /// Number of files finished by all threads
+    completed: &'a AtomicU64,
+}
+
+impl FileScanner<'_> {
+    /// Scans the file at `path`, the `sequence`th file of the walk, which was
+    /// opened as `opened`, and adds it to `report`.
+    fn scan(&self, report: &mut DirScanReport, sequence: u64, path: PathBuf, opened: Opened) {
+        let file = match opened {
+            Opened::File(file) => file,
+            Opened::Skipped(reason) => {
+                report.skipped.push(SkippedFile { path, reason });
+                return;
+            }
+            Opened::Failed(err) => {
+                let entry = entry(self.sink, Some(sequence), path, Err(err), false);
+                report.push(entry, self.completed.fetch_add(1, Ordering::Relaxed));
+                return;
+            }
+        };
+        run_hook(self.hook, Stage::Opened, &path);
+        let started = Instant::now();
+        let (outcome, file_type, unstable) = file.scan(self.engine, &path, self.settings, self.typed);
+        let outcome = match self.suppressions {
+            // hash the descriptor which was scanned
+            Some(suppressions) => {
+                outcome.map(|outcome| suppressions.apply_outcome_hashed(outcome, || policy::sha256_file_hex(&file.file)))
+            }
+            None => outcome,
+        };
+        if let Ok(ScanOutcome { suppression: Some(record), .. }) = &outcome {
+            report.suppressed.push(SuppressedFile {
+                path: path.clone(),
+                record: record.clone(),
+            });
+        }
+        if self.typed {
+            count_type(&mut report.by_type, file_type, outcome.as_ref().ok(), started.elapsed());
+        }
+        let entry = entry(self.sink, Some(sequence), path, outcome, unstable);
+        report.push(entry, self.completed.fetch_add(1, Ordering::Relaxed));
+    }
+}
+
+/// Emits the result of one file to `sink` and converts it to a report entry.
+fn entry(
+    sink: Option<&dyn ResultSink>,
+    sequence: Option<u64>,
+    path: PathBuf,
+    outcome: Result<ScanOutcome, ClamError>,
+    unstable: bool,
+) -> DirScanEntry {
+    let record = ScanRecord {
+        sequence,
+        ..ScanRecord::new(Some(path), outcome)
+    };
+    if let Some(sink) = sink {
+        sink.emit(&record);
+    }
+    DirScanEntry {
+        path: record.path.expect("record has a path"),
+        result: record.outcome.map(|outcome| outcome.result),
+        cached: false,
+        unstable,
+    }
+}
+
+/// Recursively scans all regular files below `dir`.
+///
+/// Symbolic links are not followed. Errors scanning individual files, and
+/// subdirectories which cannot be read (reported as `CL_EOPEN`), are recorded
+/// in the report rather than aborting the scan. The report includes
+/// statistics by file type.
+///
+/// Each file is opened once and scanned through its descriptor. Files which
+/// disappear or are replaced by something other than a regular file before
+/// they are opened are listed in [`DirScanReport::skipped`], and files which
+/// change while they are scanned are marked as [`unstable`].
+///
+/// [`DirScanReport::skipped`]: struct.DirScanReport.html#structfield.skipped
+/// [`unstable`]: struct.DirScanEntry.html#structfield.unstable
+///
+/// # Errors
+///
+/// Returns an error if `dir` itself cannot be read.
+pub fn scan_directory(engine: &Engine, dir: &Path, settings: &ScanSettings) -> io::Result<DirScanReport> {
+    scan_directory_to(engine, dir, settings, &DirScanOptions::default(), None)
+}
+
+/// Same as [`scan_directory`], additionally emitting the result of each file
+/// to `sink` as soon as it is known.
+///
+/// Unreadable subdirectories are emitted after all files, and the sink is
+/// flushed before returning.
+///
+/// [`scan_directory`]: fn.scan_directory.html
+pub fn scan_directory_with_sink(
+    engine: &Engine,
+    dir: &Path,
+    settings: &ScanSettings,
+    sink: Arc<dyn ResultSink>,
+) -> io::Result<DirScanReport> {
+    let options = DirScanOptions::default().with_sink(sink);
+    scan_directory_to(engine, dir, settings, &options, None)
+}
+
+/// Same as [`scan_directory`], applying `suppressions` to the detections.
+///
+/// Suppressed detections are reported as `ScanResult::Suppressed` and
+/// recorded in [`DirScanReport::suppressed`]. Rules restricted to a SHA-256
+/// hash the file through the descriptor it was scanned through.
+///
+/// [`scan_directory`]: fn.scan_directory.html
+/// [`DirScanReport::suppressed`]: struct.DirScanReport.html#structfield.suppressed
+pub fn scan_directory_with_suppressions(
+    engine: &Engine,
+    dir: &Path,
+    settings: &ScanSettings,
+    suppressions: Arc<SuppressionList>,
+) -> io::Result<DirScanReport> {
+    let options = DirScanOptions {
+        suppressions: Some(suppressions),
+        ..Default::default()
+    };
+    scan_directory_to(engine, dir, settings, &options, None)
+}
+
+/// Limits on the work done by one run of [`scan_directory_with_budget`], or
+/// of a scan with [`DirScanOptions::budget`]
+///
+/// Limits are checked before each file, so the file which reaches a limit
+/// is still scanned completely. Unset limits do not apply.
+///
+/// [`scan_directory_with_budget`]: fn.scan_directory_with_budget.html
+/// [`DirScanOptions::budget`]: struct.DirScanOptions.html#structfield.budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchBudget {
+    /// Wall-clock time after which no further file is started
+    pub max_duration: Option<Duration>,
+    /// Total size of the opened files after which no further file is started
+    pub max_bytes: Option<u64>,
+    /// Number of files taken from the walk after which the scan stops
+    pub max_files: Option<u64>,
+}
+
+/// The limit of a [`BatchBudget`] which stopped a scan
+///
+/// [`BatchBudget`]: struct.BatchBudget.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetLimit {
+    Duration,
+    Bytes,
+    Files,
+}
+
+impl fmt::Display for BudgetLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BudgetLimit::Duration => write!(f, "duration"),
+            BudgetLimit::Bytes => write!(f, "bytes"),
+            BudgetLimit::Files => write!(f, "files"),
+        }
+    }
+}
+
+/// The work done so far by a budgeted scan
+struct BudgetUsage {
+    started: Instant,
+    bytes: u64,
+    files: u64,
+}
+
+impl BatchBudget {
+    /// Returns true if no limit is set.
+    fn is_unlimited(&self) -> bool {
+        *self == BatchBudget::default()
+    }
+
+    /// Returns the first limit `usage` has reached, if any.
+    fn exhausted(&self, usage: &BudgetUsage) -> Option<BudgetLimit> {
+        if matches!(self.max_files, Some(max) if usage.files >= max) {
+            Some(BudgetLimit::Files)
+        } else if matches!(self.max_bytes, Some(max) if usage.bytes >= max) {
+            Some(BudgetLimit::Bytes)
+        } else if matches!(self.max_duration, Some(max) if usage.started.elapsed() >= max) {
+            Some(BudgetLimit::Duration)
+        } else {
+            None
+        }
+    }
+}
+
+/// Position in the sorted walk where a scan stopped by its budget continues
+///
+/// The token holds the path of the first file which was not scanned, so a
+/// resumed scan starts at the right file even if files before it were added
+/// or removed in between.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResumeToken {
+    position: u64,
+    path: PathBuf,
+}
+
+impl ResumeToken {
+    /// Returns the position of the first file which was not scanned in the
+    /// walk of the stopped scan.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the path of the first file which was not scanned.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Returns the index of the first of the sorted `files` a scan resumed with
+/// `resume` starts at.
+fn resume_index(files: &[PathBuf], resume: Option<&ResumeToken>) -> usize {
+    resume.map_or(0, |token| files.partition_point(|path| *path < token.path))
+}
+
+/// Same as [`scan_directory`], stopping cleanly once `budget` is exhausted.
+///
+/// When a limit of the budget is reached, the files which were not scanned
+/// yet are listed in [`DirScanReport::skipped`] as
+/// [`SkipReason::BudgetExhausted`], the limit is recorded in
+/// [`budget_exhausted`] and [`resume`] holds a token. Passing the token as
+/// `resume` to the next run skips the files before it in the sorted walk.
+///
+/// [`scan_directory_parallel`] takes the budget and token as
+/// [`DirScanOptions::budget`] and [`DirScanOptions::resume`].
+///
+/// [`scan_directory`]: fn.scan_directory.html
+/// [`scan_directory_parallel`]: fn.scan_directory_parallel.html
+/// [`DirScanOptions::budget`]: struct.DirScanOptions.html#structfield.budget
+/// [`DirScanOptions::resume`]: struct.DirScanOptions.html#structfield.resume
+/// [`DirScanReport::skipped`]: struct.DirScanReport.html#structfield.skipped
+/// [`SkipReason::BudgetExhausted`]: enum.SkipReason.html#variant.BudgetExhausted
+/// [`budget_exhausted`]: struct.DirScanReport.html#structfield.budget_exhausted
+/// [`resume`]: struct.DirScanReport.html#structfield.resume
+///
+/// # Errors
+///
+/// Returns an error if `dir` itself cannot be read.
+pub fn scan_directory_with_budget(
+    engine: &Engine,
+    dir: &Path,
+    settings: &ScanSettings,
+    budget: &BatchBudget,
+    resume: Option<&ResumeToken>,
+) -> io::Result<DirScanReport> {
+    let options = DirScanOptions {
+        budget: *budget,
+        resume: resume.cloned(),
+        ..Default::default()
+    };
+    scan_directory_to(engine, dir, settings, &options, None)
+}
+
+/// Scans the files below `dir` on the calling thread, emitting to the sink,
+/// applying the suppressions and keeping to the budget of `options`.
+fn scan_directory_to(
+    engine: &Engine,
+    dir: &Path,
+    settings: &ScanSettings,
+    options: &DirScanOptions,
+    hook: Hook,
+) -> io::Result<DirScanReport> {
+    let sink = options.sink.as_deref();
+    let (mut files, unreadable) = walk(dir)?;
+    let start = resume_index(&files, options.resume.as_ref());
+    let files = files.split_off(start);
+    let completed = AtomicU64::new(0);
+    let scanner = FileScanner {
+        engine,
+        settings,
+        typed: true,
+        sink,
+        suppressions: options.suppressions.as_deref(),
+        hook,
+        completed: &completed,
+    };
+    let mut report = DirScanReport::default();
+    let mut usage = BudgetUsage { started: Instant::now(), bytes: 0, files: 0 };
+    let mut files = (start as u64..).zip(files);
+    while let Some((sequence, path)) = files.next() {
+        if let Some(limit) = options.budget.exhausted(&usage) {
+            report.stop(limit, sequence, path, files.map(|(_, path)| path));
+            break;
+        }
+        run_hook(hook, Stage::Walked, &path);
+        let opened = open_file(&path);
+        if let Opened::File(file) = &opened {
+            usage.bytes += file.key.size;
+        }
+        usage.files += 1;
+        scanner.scan(&mut report, sequence, path, opened);
+    }
+    finish(&mut report, unreadable, sink);
+    Ok(report)
+}
+
+/// Adds the unreadable subdirectories to `report`, sorts it and flushes `sink`.
+fn finish(report: &mut DirScanReport, unreadable: Vec<PathBuf>, sink: Option<&dyn ResultSink>) {
+    let completed = report.completion.iter().max().map_or(0, |last| last + 1);
+    for (completion, path) in (completed..).zip(unreadable) {
+        let entry = entry(sink, None, path, Err(ClamError::new(cl_error_t::CL_EOPEN)), false);
+        report.push(entry, completion);
+    }
+    let mut entries: Vec<_> = report.entries.drain(..).zip(report.completion.drain(..)).collect();
+    entries.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+    let (sorted, completion) = entries.into_iter().unzip();
+    report.entries = sorted;
+    report.completion = completion;
+    report.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+    report.suppressed.sort_by(|a, b| a.path.cmp(&b.path));
+    if let Some(sink) = sink {
+        sink.flush();
+    }
+}
+
+/// Token to cancel a running scan from another thread
+///
+/// Clones share the same cancellation state.
+#[derive(Debug, Clone, Default)]
+pub struct ScanToken(Arc<AtomicBool>);
+
+impl ScanToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Requests cancellation of the scans using this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Options of [`scan_directory_parallel`]
+///
+/// Build options with [`DirScanOptions::builder`], which checks them, or
+/// start from `DirScanOptions::default()`.
+///
+/// [`scan_directory_parallel`]: fn.scan_directory_parallel.html
+/// [`DirScanOptions::builder`]: #method.builder
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct DirScanOptions {
+    /// Number of threads scanning files. Values below 1 are treated as 1.
+    /// Defaults to [`Engine::recommended_concurrency`].
+    ///
+    /// [`Engine::recommended_concurrency`]: ../engine/struct.Engine.html#method.recommended_concurrency
+    pub threads: usize,
+    /// Number of threads opening files ahead of the scanning threads. Values
+    /// below 1 are treated as 1. Defaults to 2.
+    pub open_threads: usize,
+    /// Maximum number of opened files waiting for a scanning thread. Larger
+    /// values hide more IO latency at the cost of open descriptors; 0 hands
+    /// each file directly to a scanning thread. Defaults to 64.
+    pub prefetch: usize,
+    /// Token to cancel the scan. Files which are opened but not yet scanned
+    /// when the scan is cancelled are closed without being scanned. Defaults
+    /// to none.
+    pub cancel: Option<ScanToken>,
+    /// Whether to collect [`DirScanReport::by_type`], which needs a callback
+    /// for every scanned object. Defaults to true.
+    ///
+    /// [`DirScanReport::by_type`]: struct.DirScanReport.html#structfield.by_type
+    pub file_types: bool,
+    /// Sink receiving the result of each file as soon as it is scanned. The
+    /// sink is flushed before the scan returns. Records arrive in the order
+    /// the files finish, numbered in walk order; `JsonlSink::ordered` writes
+    /// them in walk order. Defaults to none.
+    pub sink: Option<Arc<dyn ResultSink>>,
+    /// Globs with `*` and `?` wildcards; if any are given, only files whose
+    /// name matches one of them are scanned. Defaults to none.
+    pub include: Vec<String>,
+    /// Globs with `*` and `?` wildcards; files whose name matches one of
+    /// them are not scanned, even if they match `include`. Defaults to none.
+    pub exclude: Vec<String>,
+    /// Suppression list applied to the detections, see
+    /// [`scan_directory_with_suppressions`]. Defaults to none.
+    ///
+    /// [`scan_directory_with_suppressions`]: fn.scan_directory_with_suppressions.html
+    pub suppressions: Option<Arc<SuppressionList>>,
+    /// Limits after which the scan stops, see
+    /// [`scan_directory_with_budget`]. Defaults to no limits.
+    ///
+    /// With a budget or a `resume` token, the parallel scan walks and sorts
+    /// the whole tree before scanning, so that files are started and
+    /// counted in the order of the sorted walk. Files opened ahead of the
+    /// scanning threads are counted against `max_bytes` once opened, so
+    /// that limit may be overshot by up to `prefetch` files.
+    ///
+    /// [`scan_directory_with_budget`]: fn.scan_directory_with_budget.html
+    pub budget: BatchBudget,
+    /// Token returned by a scan stopped by its budget, whose files before
+    /// the token are skipped. Defaults to none.
+    pub resume: Option<ResumeToken>,
+}
+
+impl DirScanOptions {
+    /// Returns a builder starting from the default options.
+    pub fn builder() -> DirScanOptionsBuilder {
+        DirScanOptionsBuilder::new()
+    }
+
+    /// Sets the sink receiving the results.
+    pub fn with_sink(mut self, sink: Arc<dyn ResultSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Returns whether the walk passes `path` on to be scanned, according to
+    /// the `include` and `exclude` globs.
+    fn admits(&self, path: &Path) -> bool {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return true;
+        }
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let matches = |glob: &String| policy::glob_matches(glob, &name);
+        !self.exclude.iter().any(matches) && (self.include.is_empty() || self.include.iter().any(matches))
+    }
+}
+
+/// Invalid [`DirScanOptions`], returned by [`DirScanOptionsBuilder::build`]
+///
+/// [`DirScanOptions`]: struct.DirScanOptions.html
+/// [`DirScanOptionsBuilder::build`]: struct.DirScanOptionsBuilder.html#method.build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DirScanOptionsError {
+    /// No scanning threads were requested
+    NoScanThreads,
+    /// No threads opening files were requested
+    NoOpenThreads,
+}
+
+impl fmt::Display for DirScanOptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DirScanOptionsError::NoScanThreads => write!(f, "at least one scanning thread is needed"),
+            DirScanOptionsError::NoOpenThreads => write!(f, "at least one thread opening files is needed"),
+        }
+    }
+}
+
+impl std::error::Error for DirScanOptionsError {}
+
+/// Builder for [`DirScanOptions`]
+///
+/// Unset options keep the defaults documented on [`DirScanOptions`].
+///
+/// [`DirScanOptions`]: struct.DirScanOptions.html
+#[derive(Debug, Clone, Default)]
+pub struct DirScanOptionsBuilder {
+    options: DirScanOptions,
+}
+
+impl DirScanOptionsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of scanning threads, see [`DirScanOptions::threads`].
+    ///
+    /// [`DirScanOptions::threads`]: struct.DirScanOptions.html#structfield.threads
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Sets the number of threads opening files, see
+    /// [`DirScanOptions::open_threads`].
+    ///
+    /// [`DirScanOptions::open_threads`]: struct.DirScanOptions.html#structfield.open_threads
+    pub fn open_threads(&mut self, threads: usize) -> &mut Self {
+        self.options.open_threads = threads;
+        self
+    }
+
+    /// Sets the number of files opened ahead, see [`DirScanOptions::prefetch`].
+    ///
+    /// [`DirScanOptions::prefetch`]: struct.DirScanOptions.html#structfield.prefetch
+    pub fn prefetch(&mut self, files: usize) -> &mut Self {
+        self.options.prefetch = files;
+        self
+    }
+
+    /// Sets the token to cancel the scan.
+    pub fn cancel(&mut self, token: ScanToken) -> &mut Self {
+        self.options.cancel = Some(token);
+        self
+    }
+
+    /// Sets whether to collect statistics by file type, see
+    /// [`DirScanOptions::file_types`].
+    ///
+    /// [`DirScanOptions::file_types`]: struct.DirScanOptions.html#structfield.file_types
+    pub fn file_types(&mut self, collect: bool) -> &mut Self {
+        self.options.file_types = collect;
+        self
+    }
+
+    /// Sets the sink receiving the results.
+    pub fn sink(&mut self, sink: Arc<dyn ResultSink>) -> &mut Self {
+        self.options.sink = Some(sink);
+        self
+    }
+
+    /// Adds a glob to [`DirScanOptions::include`].
+    ///
+    /// [`DirScanOptions::include`]: struct.DirScanOptions.html#structfield.include
+    pub fn include(&mut self, glob: &str) -> &mut Self {
+        self.options.include.push(glob.to_string());
+        self
+    }
+
+    /// Adds a glob to [`DirScanOptions::exclude`].
+    ///
+    /// [`DirScanOptions::exclude`]: struct.DirScanOptions.html#structfield.exclude
+    pub fn exclude(&mut self, glob: &str) -> &mut Self {
+        self.options.exclude.push(glob.to_string());
+        self
+    }
+
+    /// Sets the suppression list applied to the detections.
+    pub fn suppressions(&mut self, suppressions: Arc<SuppressionList>) -> &mut Self {
+        self.options.suppressions = Some(suppressions);
+        self
+    }
+
+    /// Sets the limits after which the scan stops, see
+    /// [`DirScanOptions::budget`].
+    ///
+    /// [`DirScanOptions::budget`]: struct.DirScanOptions.html#structfield.budget
+    pub fn budget(&mut self, budget: BatchBudget) -> &mut Self {
+        self.options.budget = budget;
+        self
+    }
+
+    /// Sets the token of a stopped scan to continue from.
+    pub fn resume(&mut self, token: ResumeToken) -> &mut Self {
+        self.options.resume = Some(token);
+        self
+    }
+
+    /// Checks the options and returns them.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`DirScanOptionsError`] if zero scanning threads or zero
+    /// threads opening files were set.
+    ///
+    /// [`DirScanOptionsError`]: enum.DirScanOptionsError.html
+    pub fn build(&self) -> Result<DirScanOptions, DirScanOptionsError> {
+        if self.options.threads == 0 {
+            return Err(DirScanOptionsError::NoScanThreads);
+        }
+        if self.options.open_threads == 0 {
+            return Err(DirScanOptionsError::NoOpenThreads);
+        }
+        Ok(self.options.clone())
+    }
+}
+
+impl fmt::Debug for DirScanOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DirScanOptions")
+            .field("threads", &self.threads)
+            .field("open_threads", &self.open_threads)
+            .field("prefetch", &self.prefetch)
+            .field("cancel", &self.cancel)
+            .field("file_types", &self.file_types)
+            .field("sink", &self.sink.as_ref().map(|_| "ResultSink"))
+            .field("include", &self.include)
+            .field("exclude", &self.exclude)
+            .field("suppressions", &self.suppressions)
+            .field("budget", &self.budget)
+            .field("resume", &self.resume)
+            .finish()
+    }
+}
+
+impl Default for DirScanOptions {
+    fn default() -> Self {
+        DirScanOptions {
+            threads: Engine::recommended_concurrency(),
+            open_threads: 2,
+            prefetch: 64,
+            cancel: None,
+            file_types: true,
+            sink: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            suppressions: None,
+            budget: BatchBudget::default(),
+            resume: None,
+        }
+    }
+}
+
+/// Recursively scans all regular files below `dir` with multiple threads.
+///
+/// The scan runs as a pipeline to overlap IO with scanning: one thread walks
+/// the directory tree, [`open_threads`] threads open the files ahead of time
+/// and up to [`prefetch`] opened files are queued for the [`threads`]
+/// scanning threads. Files are scanned through their descriptor, so a file
+/// renamed after it was opened is still scanned.
+///
+/// Errors, vanished and changing files are handled like in [`scan_directory`],
+/// and [`budget`] like in [`scan_directory_with_budget`]; the report is
+/// sorted by path.
+///
+/// # Errors
+///
+/// Returns an error if `dir` itself cannot be read.
+///
+/// [`open_threads`]: struct.DirScanOptions.html#structfield.open_threads
+/// [`prefetch`]: struct.DirScanOptions.html#structfield.prefetch
+/// [`threads`]: struct.DirScanOptions.html#structfield.threads
+/// [`scan_directory`]: fn.scan_directory.html
+/// [`budget`]: struct.DirScanOptions.html#structfield.budget
+/// [`scan_directory_with_budget`]: fn.scan_directory_with_budget.html
+pub fn scan_directory_parallel(
+    engine: &Engine,
+    dir: &Path,
+    settings: &ScanSettings,
+    options: &DirScanOptions,
+) -> io::Result<DirScanReport> {
+    scan_directory_parallel_to(engine, dir, settings, options, None)
+}
+
+fn scan_directory_parallel_to(
+    engine: &Engine,
+    dir: &Path,
+    settings: &ScanSettings,
+    options: &DirScanOptions,
+    hook: Hook,
+) -> io::Result<DirScanReport> {
+    let token = options.cancel.clone().unwrap_or_default();
+    let sink = options.sink.as_deref();
+    let suppressions = options.suppressions.as_deref();
+    let file_types = options.file_types;
+    let scan_options = settings.settings;
+    let completed = AtomicU64::new(0);
+    let opened_bytes = AtomicU64::new(0);
+    let started = Instant::now();
+    let (path_tx, path_rx) = mpsc::sync_channel::<(u64, PathBuf)>(options.prefetch);
+    let (file_tx, file_rx) = mpsc::sync_channel::<(u64, PathBuf, Opened)>(options.prefetch);
+    let path_rx = Mutex::new(path_rx);
+    let file_rx = Mutex::new(file_rx);
+
+    let (walked, scanned) = thread::scope(|s| {
+        let token = &token;
+        let completed = &completed;
+        let opened_bytes = &opened_bytes;
+        let path_rx = &path_rx;
+        let file_rx = &file_rx;
+
+        let walker = s.spawn(move || -> io::Result<(Vec<PathBuf>, DirScanReport)> {
+            let mut stopped = DirScanReport::default();
+            if options.budget.is_unlimited() && options.resume.is_none() {
+                let mut walked = 0;
+                let unreadable = walk_with(dir, |path| {
+                    if !options.admits(&path) {
+                        return !token.is_cancelled();
+                    }
+                    walked += 1;
+                    !token.is_cancelled() && path_tx.send((walked - 1, path)).is_ok()
+                })?;
+                return Ok((unreadable, stopped));
+            }
+            // budgets and resume tokens refer to positions in the sorted walk
+            let (files, unreadable) = walk(dir)?;
+            let files: Vec<_> = files.into_iter().filter(|path| options.admits(path)).collect();
+            let start = resume_index(&files, options.resume.as_ref());
+            let mut files = (start as u64..).zip(files.into_iter().skip(start));
+            while let Some((sequence, path)) = files.next() {
+                let usage = BudgetUsage {
+                    started,
+                    bytes: opened_bytes.load(Ordering::Relaxed),
+                    files: sequence - start as u64,
+                };
+                if let Some(limit) = options.budget.exhausted(&usage) {
+                    stopped.stop(limit, sequence, path, files.map(|(_, path)| path));
+                    break;
+                }
+                if token.is_cancelled() || path_tx.send((sequence, path)).is_err() {
+                    break;
+                }
+            }
+            Ok((unreadable, stopped))
+        });
+
+        for _ in 0..cmp::max(options.open_threads, 1) {
+            let file_tx = file_tx.clone();
+            s.spawn(move || loop {
+                let (sequence, path) = match path_rx.lock().unwrap().recv() {
+                    Ok(item) => item,
+                    Err(_) => break,
+                };
+                // Keep draining so that the walker notices the cancellation
+                if token.is_cancelled() {
+                    continue;
+                }
+                run_hook(hook, Stage::Walked, &path);
+                let opened = open_file(&path);
+                if let Opened::File(file) = &opened {
+                    opened_bytes.fetch_add(file.key.size, Ordering::Relaxed);
+                }
+                if file_tx.send((sequence, path, opened)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(file_tx);
+
+        let scanners: Vec<_> = (0..cmp::max(options.threads, 1))
+            .map(|_| {
+                s.spawn(move || {
+                    let settings = ScanSettings { settings: scan_options };
+                    let scanner = FileScanner {
+                        engine,
+                        settings: &settings,
+                        typed: file_types,
+                        sink,
+                        suppressions,
+                        hook,
+                        completed,
+                    };
+                    let mut report = DirScanReport::default();
+                    loop {
+                        let (sequence, path, opened) = match file_rx.lock().unwrap().recv() {
+                            Ok(item) => item,
+                            Err(_) => break,
+                        };
+                        // Dropping the file closes prefetched descriptors right away
+                        if token.is_cancelled() {
+                            continue;
+                        }
+                        scanner.scan(&mut report, sequence, path, opened);
+                    }
+                    report
+                })
+            })
+            .collect();
+
+        let walked = walker.join().unwrap();
+        let scanned: Vec<_> = scanners
+            .into_iter()
+            .map(|scanner| scanner.join().unwrap())
+            .collect();
+        (walked, scanned)
+    });
+
+    let (unreadable, stopped) = walked?;
+    let mut report = stopped;
+    for partial in scanned {
+        report.merge(partial);
+    }
+    finish(&mut report, unreadable, sink);
+    report.cancelled = token.is_cancelled();
+    if report.cancelled {
+        trace::cancelled(dir, report.entries.len());
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CacheKey {
+    size: u64,
+    mtime: Option<SystemTime>,
+}
+
+impl CacheKey {
+    fn of(metadata: &fs::Metadata) -> CacheKey {
+        CacheKey {
+            size: metadata.len(),
+            mtime: metadata.modified().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    key: CacheKey,
+    db_version: u32,
+    verdict: ScanResult,
+}
+
+/// Directory scanner which skips files that have not changed since the last scan
+///
+/// Verdicts are remembered per path together with the file's size, its
+/// modification time and the version of the database that produced them.
+/// A file is scanned again if its size or modification time changed, or if
+/// its verdict was produced by an older database than the one loaded in the
+/// engine. Only successful verdicts are cached; files which failed to scan
+/// or were not scanned at all, see `ScanResult::NotScanned`, are retried on
+/// every pass.
+///
+/// With the `cache-store` feature, the cache can be persisted with [`save`]
+/// and restored with [`load`] across runs.
+///
+/// [`save`]: #method.save
+/// [`load`]: #method.load
+#[derive(Debug, Clone, Default)]
+pub struct IncrementalScanner {
+    cache: HashMap<PathBuf, CacheEntry>,
+}
+
+impl IncrementalScanner {
+    /// Creates a scanner with an empty cache.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the number of cached verdicts.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns true if no verdicts are cached.
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// Forgets all cached verdicts.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Recursively scans all regular files below `dir`, reusing cached verdicts
+    /// for unchanged files.
+    ///
+    /// Reused verdicts are marked as `cached` in the report. Cache entries for
+    /// files below `dir` which no longer exist are removed. Files are opened
+    /// and classified as in [`scan_directory`](fn.scan_directory.html);
+    /// verdicts of unstable files are not cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dir` itself cannot be read.
+    pub fn scan_directory(
+        &mut self,
+        engine: &Engine,
+        dir: &Path,
+        settings: &ScanSettings,
+    ) -> io::Result<DirScanReport> {
+        let db_version = engine.database_version().unwrap_or(0);
+        let (files, unreadable) = walk(dir)?;
+        let mut report = DirScanReport::default();
+
+        self.cache
+            .retain(|path, _| !path.starts_with(dir) || files.binary_search(path).is_ok());
+
+        for path in files {
+            let file = match open_file(&path) {
+                Opened::File(file) => file,
+                Opened::Skipped(reason) => {
+                    self.cache.remove(&path);
+                    report.skipped.push(SkippedFile { path, reason });
+                    continue;
+                }
+                Opened::Failed(err) => {
+                    self.cache.remove(&path);
+                    report.push(entry(None, None, path, Err(err), false), report.completion.len() as u64);
+                    continue;
+                }
+            };
+
+            if let Some(entry) = self.cache.get(&path) {
+                if entry.key == file.key && entry.db_version >= db_version {
+                    let result = Ok(entry.verdict.clone());
+                    let entry = DirScanEntry { path, result, cached: true, unstable: false };
+                    report.push(entry, report.completion.len() as u64);
+                    continue;
+                }
+            }
+
+            let started = Instant::now();
+            let (outcome, file_type, unstable) = file.scan(engine, &path, settings, true);
+            count_type(&mut report.by_type, file_type, outcome.as_ref().ok(), started.elapsed());
+            let result = outcome.map(|outcome| outcome.result);
+            match &result {
+                // The verdict of a file which changed may not match its new key
+                Ok(verdict) if !unstable && is_cacheable(verdict) => {
+                    let entry = CacheEntry { key: file.key, db_version, verdict: verdict.clone() };
+                    self.cache.insert(path.clone(), entry);
+                }
+                _ => {
+                    self.cache.remove(&path);
+                }
+            }
+            let entry = DirScanEntry { path, result, cached: false, unstable };
+            report.push(entry, report.completion.len() as u64);
+        }
+
+        finish(&mut report, unreadable, None);
+        Ok(report)
+    }
+}
+
+/// Returns false for results which say nothing about the file's contents.
+///
+/// A database version of zero, as reported for local databases, would
+/// otherwise keep a `NotScanned` verdict alive once signatures are loaded.
+fn is_cacheable(verdict: &ScanResult) -> bool {
+    !matches!(verdict, ScanResult::NotScanned(_))
+}
+
+#[cfg(feature = "cache-store")]
+mod store {
+    //! On-disk format of the incremental scan cache.
+    //!
+    //! All integers are little endian:
+    //!
+    //! ```text
+    //! magic      b"CLRSIC01"
+    //! count      u64
+    //! entries    count times:
+    //!   path       u32 length + UTF-8 bytes
+    //!   size       u64
+    //!   mtime      u8 present flag, i64 seconds and u32 nanoseconds relative to UNIX_EPOCH
+    //!   db_version u32
+    //!   verdict    u8 tag followed by its payload:
+    //!                0 clean
+    //!                1 whitelisted
+    //!                2 virus, u32 length + raw name bytes
+    //!                3 suppressed, u32 length + raw name bytes of the original detection
+    //!                4 suspicious, u32 length + heuristic name of the exceeded limit
+    //!                5 encrypted, u32 length + heuristic name of the container
+    //!                6 not scanned, no database
+    //!                7 not scanned, no signatures loaded
+    //! ```
+    //!
+    //! Tags 6 and 7 are no longer written; entries carrying them are skipped
+    //! on load.
+
+    use std::convert::TryInto;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{is_cacheable, CacheEntry, CacheKey};
+    use crate::engine::{Detection, EncryptionKind, LimitKind, ScanResult};
+
+    const MAGIC: &[u8; 8] = b"CLRSIC01";
+
+    fn put_str(out: &mut Vec<u8>, value: &str) {
+        put_bytes(out, value.as_bytes());
+    }
+
+    fn put_bytes(out: &mut Vec<u8>, value: &[u8]) {
+        out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        out.extend_from_slice(value);
+    }
+
+    pub(super) fn encode<'a, I>(entries: I) -> Vec<u8>
+    where
+        I: Iterator<Item = (&'a str, &'a CacheEntry)>,
+    {
+        let entries: Vec<_> = entries.filter(|(_, entry)| is_cacheable(&entry.verdict)).collect();
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+        for (path, entry) in entries {
+            put_str(&mut out, path);
+            out.extend_from_slice(&entry.key.size.to_le_bytes());
+            let (present, secs, nanos) = match entry.key.mtime {
+                Some(mtime) => match mtime.duration_since(UNIX_EPOCH) {
+                    Ok(after) => (1u8, after.as_secs() as i64, after.subsec_nanos()),
+                    Err(err) => {
+                        let before = err.duration();
+                        (1u8, -(before.as_secs() as i64), before.subsec_nanos())
+                    }
+                },
+                None => (0u8, 0, 0),
+            };
+            out.push(present);
+            out.extend_from_slice(&secs.to_le_bytes());
+            out.extend_from_slice(&nanos.to_le_bytes());
+            out.extend_from_slice(&entry.db_version.to_le_bytes());
+            match &entry.verdict {
+                ScanResult::Clean => out.push(0),
+                ScanResult::Whitelisted => out.push(1),
+                ScanResult::Virus(detection) => {
+                    out.push(2);
+                    put_bytes(&mut out, &detection.raw_name);
+                }
+                ScanResult::Suppressed { original } => {
+                    out.push(3);
+                    put_bytes(&mut out, &original.raw_name);
+                }
+                ScanResult::Suspicious(kind) => {
+                    out.push(4);
+                    put_bytes(&mut out, kind.heuristic_name().as_bytes());
+                }
+                ScanResult::Encrypted { container } => {
+                    out.push(5);
+                    put_bytes(&mut out, container.heuristic_name().as_bytes());
+                }
+                ScanResult::NotScanned(_) => unreachable!("filtered above"),
+            }
+        }
+        out
+    }
+
+    struct Reader<'a>(&'a [u8]);
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+            if self.0.len() < len {
+                return None;
+            }
+            let (head, tail) = self.0.split_at(len);
+            self.0 = tail;
+            Some(head)
+        }
+
+        fn u8(&mut self) -> Option<u8> {
+            self.take(1).map(|bytes| bytes[0])
+        }
+
+        fn u32(&mut self) -> Option<u32> {
+            self.take(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        }
+
+        fn u64(&mut self) -> Option<u64> {
+            self.take(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        }
+
+        fn i64(&mut self) -> Option<i64> {
+            self.take(8).map(|bytes| i64::from_le_bytes(bytes.try_into().unwrap()))
+        }
+
+        fn bytes(&mut self) -> Option<&'a [u8]> {
+            let len = self.u32()? as usize;
+            self.take(len)
+        }
+
+        fn string(&mut self) -> Option<String> {
+            String::from_utf8(self.bytes()?.to_vec()).ok()
+        }
+    }
+
+    /// Decodes a cache file, returning None if it is corrupted in any way.
+    pub(super) fn decode(data: &[u8]) -> Option<Vec<(PathBuf, CacheEntry)>> {
+        let mut reader = Reader(data);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let count = reader.u64()?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let path = PathBuf::from(reader.string()?);
+            let size = reader.u64()?;
+            let present = reader.u8()?;
+            let secs = reader.i64()?;
+            let nanos = reader.u32()?;
+            let mtime = match present {
+                0 => None,
+                1 if nanos < 1_000_000_000 => {
+                    let offset = Duration::new(secs.unsigned_abs(), nanos);
+                    if secs >= 0 {
+                        UNIX_EPOCH.checked_add(offset)
+                    } else {
+                        UNIX_EPOCH.checked_sub(offset)
+                    }
+                }
+                _ => return None,
+            };
+            let db_version = reader.u32()?;
+            let verdict = match reader.u8()? {
+                0 => ScanResult::Clean,
+                1 => ScanResult::Whitelisted,
+                2 => ScanResult::Virus(Detection::from_raw(reader.bytes()?)),
+                3 => ScanResult::Suppressed { original: Detection::from_raw(reader.bytes()?) },
+                4 => ScanResult::Suspicious(LimitKind::from_detection(&Detection::from_raw(reader.bytes()?))?),
+                5 => ScanResult::Encrypted {
+                    container: EncryptionKind::from_detection(&Detection::from_raw(reader.bytes()?))?,
+                },
+                6 | 7 => continue,
+                _ => return None,
+            };
+            let key = CacheKey { size, mtime };
+            entries.push((path, CacheEntry { key, db_version, verdict }));
+        }
+        if reader.0.is_empty() {
+            Some(entries)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "cache-store")]
+impl IncrementalScanner {
+    /// Loads a cache previously written with [`save`].
+    ///
+    /// A missing, unreadable or corrupted cache file is not an error; the
+    /// scanner then simply starts with an empty cache.
+    ///
+    /// [`save`]: #method.save
+    pub fn load(path: &Path) -> Self {
+        let cache = fs::read(path)
+            .ok()
+            .and_then(|data| store::decode(&data))
+            .map(|entries| entries.into_iter().collect())
+            .unwrap_or_default();
+        IncrementalScanner { cache }
+    }
+
+    /// Writes the cache to `path`.
+    ///
+    /// The file is written to a temporary sibling first and then renamed, so
+    /// an interrupted save never leaves a truncated cache behind. Entries
+    /// whose path is not valid UTF-8 are not persisted.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let entries: Vec<_> = self
+            .cache
+            .iter()
+            .filter_map(|(path, entry)| path.to_str().map(|path| (path, entry)))
+            .collect();
+        let data = store::encode(entries.into_iter());
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// Partition table format of a disk image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PartitionScheme {
+    Mbr,
+    Gpt,
+}
+
+/// Options of [`scan_disk_image`]
+///
+/// [`scan_disk_image`]: fn.scan_disk_image.html
+#[derive(Debug, Clone)]
+pub struct DiskScanOptions {
+    /// Number of partitions libclamav and this function look at. The
+    /// engine's `CL_ENGINE_MAX_PARTITIONS` is raised to it if lower. Defaults to 128.
+    pub max_partitions: u32,
+    /// Number of bytes libclamav scans per window. The engine's
+    /// `CL_ENGINE_MAX_SCANSIZE` is raised to it if lower. Defaults to 4 GiB.
+    pub max_scansize: u64,
+    /// Window layout used for the image and each partition. Defaults to
+    /// 1 GiB windows overlapping by 1 MiB.
+    pub window: WindowConfig,
+}
+
+impl Default for DiskScanOptions {
+    fn default() -> Self {
+        DiskScanOptions {
+            max_partitions: 128,
+            max_scansize: 4 * 1024 * 1024 * 1024,
+            window: WindowConfig {
+                window_size: 1024 * 1024 * 1024,
+                overlap: 1024 * 1024,
+            },
+        }
+    }
+}
+
+/// Result of scanning one partition of a disk image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionScan {
+    pub scheme: PartitionScheme,
+    /// Position in the partition table, starting at 0
+    pub index: usize,
+    /// Offset of the partition in the image
+    pub offset: u64,
+    /// Length of the partition in bytes
+    pub len: u64,
+    /// Detections in the partition. Window offsets are relative to the image.
+    pub report: WindowedScanReport,
+}
+
+/// Result of [`scan_disk_image`]
+///
+/// [`scan_disk_image`]: fn.scan_disk_image.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskScanReport {
+    /// Detections in the whole image
+    pub image: WindowedScanReport,
+    /// Partitions found in the partition table, in table order
+    pub partitions: Vec<PartitionScan>,
+}
+
+impl DiskScanReport {
+    /// Returns the first detection in the image or any partition, or `Clean`.
+    pub fn result(&self) -> ScanResult {
+        std::iter::once(&self.image)
+            .chain(self.partitions.iter().map(|partition| &partition.report))
+            .map(WindowedScanReport::result)
+            .find(|result| *result != ScanResult::Clean)
+            .unwrap_or(ScanResult::Clean)
+    }
+
+    /// Returns the partitions with at least one detection.
+    pub fn infected_partitions(&self) -> impl Iterator<Item = &PartitionScan> {
+        self.partitions
+            .iter()
+            .filter(|partition| !partition.report.detections.is_empty())
+    }
+}
+
+/// Scans a raw disk image, the whole image as well as each partition.
+///
+/// The whole image is scanned with `CL_SCAN_HEURISTIC_PARTITION_INTXN`
+/// added to `settings`, so libclamav parses the partition table itself and
+/// reports intersecting partitions. libclamav does not tell the caller where
+/// a detection was found, so the MBR or GPT partition table is also read
+/// here and every partition is scanned on its own, which attributes
+/// detections to partitions. Logical partitions inside an extended MBR
+/// partition are only covered by the whole image scan.
+///
+/// Images and partitions are scanned in windows through the file
+/// descriptor, see [`Engine::scan_file_windowed`], so images larger than
+/// the engine's size limits are covered completely.
+///
+/// The engine's partition and scan size limits are raised to the values in
+/// `options` if they are lower. This affects all later scans with `engine`.
+///
+/// # Errors
+///
+/// Returns `CL_EARG` if the window overlap is not smaller than the window
+/// size, `CL_EOPEN` if the image cannot be opened, `CL_EREAD` if its
+/// partition table cannot be read, or the first error of a window scan.
+///
+/// [`Engine::scan_file_windowed`]: ../engine/struct.Engine.html#method.scan_file_windowed
+pub fn scan_disk_image(
+    engine: &Engine,
+    path: &Path,
+    settings: &ScanSettings,
+    options: &DiskScanOptions,
+) -> Result<DiskScanReport, ClamError> {
+    if engine.max_partitions()? < options.max_partitions {
+        engine.set_max_partitions(options.max_partitions)?;
+    }
+    if engine.max_scansize()? < options.max_scansize {
+        engine.set_max_scansize(options.max_scansize)?;
+    }
+    let mut settings = settings.clone();
+    settings.settings.heuristic |= CL_SCAN_HEURISTIC_PARTITION_INTXN;
+
+    let file = File::open(path).map_err(|_| ClamError::new(cl_error_t::CL_EOPEN))?;
+    let len = file
+        .metadata()
+        .map_err(|_| ClamError::new(cl_error_t::CL_ESTAT))?
+        .len();
+    let filename = path.to_str();
+    let image = engine.scan_region_windowed(&file, 0, len, filename, &settings, options.window)?;
+
+    let partitions = partition::read_partitions(&file, options.max_partitions as usize)
+        .map_err(|_| ClamError::new(cl_error_t::CL_EREAD))?
+        .into_iter()
+        .map(|partition| {
+            let report = engine.scan_region_windowed(
+                &file,
+                partition.offset,
+                partition.len,
+                filename,
+                &settings,
+                options.window,
+            )?;
+            Ok(PartitionScan {
+                scheme: partition.scheme,
+                index: partition.index,
+                offset: partition.offset,
+                len: partition.len,
+                report,
+            })
+        })
+        .collect::<Result<Vec<_>, ClamError>>()?;
+    Ok(DiskScanReport { image, partitions })
+}
+
+/// Options of [`scan_process_region_with`]
+///
+/// [`scan_process_region_with`]: fn.scan_process_region_with.html
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessScanOptions {
+    /// Size of the largest region to scan, in bytes. Defaults to 256 MiB.
+    pub max_region: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl Default for ProcessScanOptions {
+    fn default() -> Self {
+        ProcessScanOptions {
+            max_region: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Why a process memory region was only scanned in part
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionWarning {
+    /// The region was cut at `at`, the first address which is not mapped
+    /// readable or failed to read
+    Truncated { at: u64 },
+}
+
+#[cfg(target_os = "linux")]
+impl fmt::Display for RegionWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegionWarning::Truncated { at } => write!(f, "region truncated at unreadable address {:#x}", at),
+        }
+    }
+}
+
+/// Result of [`scan_process_region`]
+///
+/// [`scan_process_region`]: fn.scan_process_region.html
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessRegionScan {
+    pub outcome: ScanOutcome,
+    /// Addresses which were scanned, the readable start of the requested range
+    pub scanned: Range<u64>,
+    /// Set if less than the requested range was scanned
+    pub warning: Option<RegionWarning>,
+}
+
+/// Scans the memory of process `pid` at the addresses `range`.
+///
+/// Same as [`scan_process_region_with`] with the default options.
+///
+/// [`scan_process_region_with`]: fn.scan_process_region_with.html
+#[cfg(target_os = "linux")]
+pub fn scan_process_region(
+    engine: &Engine,
+    pid: u32,
+    range: Range<u64>,
+    settings: &ScanSettings,
+) -> Result<ProcessRegionScan, ClamError> {
+    scan_process_region_with(engine, pid, range, settings, &ProcessScanOptions::default())
+}
+
+/// Scans the memory of process `pid` at the addresses `range`.
+///
+/// The memory is read through `/proc/<pid>/mem`, which needs the same
+/// permission as attaching a debugger to the process. Reading addresses
+/// which are not mapped, or mapped without read permission, fails, so the
+/// region is cut at the first such address according to
+/// `/proc/<pid>/maps`, or at the first page which fails to read with `EIO`,
+/// and the scan reports a [`RegionWarning`]. A scan failing because pages
+/// were unmapped while it ran is repeated on the part still readable.
+///
+/// # Errors
+///
+/// Returns `CL_EARG` if `range` is empty or beyond the addresses `pread`
+/// accepts, `CL_EMAXSIZE` if it is larger than [`max_region`], `CL_EOPEN` if
+/// the process's memory cannot be opened, `CL_EREAD` if the start of
+/// `range` is not readable, or the error of the scan.
+///
+/// [`RegionWarning`]: enum.RegionWarning.html
+/// [`max_region`]: struct.ProcessScanOptions.html#structfield.max_region
+#[cfg(target_os = "linux")]
+pub fn scan_process_region_with(
+    engine: &Engine,
+    pid: u32,
+    range: Range<u64>,
+    settings: &ScanSettings,
+    options: &ProcessScanOptions,
+) -> Result<ProcessRegionScan, ClamError> {
+    use std::os::unix::io::AsRawFd;
+
+    if range.start >= range.end || range.end > i64::MAX as u64 {
+        return Err(ClamError::new(cl_error_t::CL_EARG));
+    }
+    if range.end - range.start > options.max_region {
+        return Err(ClamError::new(cl_error_t::CL_EMAXSIZE));
+    }
+    let mappings = procmaps::read_maps(pid).map_err(|_| ClamError::new(cl_error_t::CL_EOPEN))?;
+    let end = procmaps::readable_end(&mappings, &range);
+
+    let path = format!("/proc/{}/mem", pid);
+    let file = File::open(&path).map_err(|_| ClamError::new(cl_error_t::CL_EOPEN))?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+    let probe = |end| {
+        procmaps::probe_readable_end(&file, &(range.start..end), page_size)
+            .map_err(|_| ClamError::new(cl_error_t::CL_EREAD))
+    };
+    let mut end = probe(end)?;
+    let outcome = loop {
+        if end == range.start {
+            return Err(ClamError::new(cl_error_t::CL_EREAD));
+        }
+        // Memory of a running process can change, so pages are not aged out and read again
+        let map = Fmap::new_from_handle(file.as_raw_fd(), range.start, end - range.start, false)
+            .map_err(|_| ClamError::new(cl_error_t::CL_EMAP))?;
+        match engine.scan_map_outcome(&map, Some(&path), settings) {
+            Ok(outcome) => break outcome,
+            Err(err) => {
+                // pages unmapped during the scan end the region there
+                let probed = probe(end)?;
+                if probed == end {
+                    return Err(err);
+                }
+                end = probed;
+            }
+        }
+    };
+    Ok(ProcessRegionScan {
+        outcome,
+        scanned: range.start..end,
+        warning: if end < range.end {
+            Some(RegionWarning::Truncated { at: end })
+        } else {
+            None
+        },
+    })
+}
+
+/// Options of [`scan_attachments_with`]
+///
+/// [`scan_attachments_with`]: fn.scan_attachments_with.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentScanOptions {
+    /// Stop at the first part detected as a virus or exceeding a limit.
+    /// Defaults to false.
+    pub stop_on_detection: bool,
+    /// Maximum length in bytes of the file name passed to libclamav. Longer
+    /// names are truncated at a character boundary. Defaults to 255.
+    ///
+    /// NUL and other control characters are replaced as in
+    /// [`SanitizedName`], so the rest of the name, e.g. its extension, is
+    /// kept.
+    ///
+    /// [`SanitizedName`]: ../engine/struct.SanitizedName.html
+    pub max_filename_len: usize,
+}
+
+impl Default for AttachmentScanOptions {
+    fn default() -> Self {
+        AttachmentScanOptions {
+            stop_on_detection: false,
+            max_filename_len: 255,
+        }
+    }
+}
+
+/// Result of scanning one attachment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentOutcome {
+    /// File name as given by the caller
+    pub filename: String,
+    /// Outcome of the scan, or the error which prevented it
+    pub outcome: Result<ScanOutcome, ClamError>,
+}
+
+/// Result of scanning the attachments of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageScanResult {
+    /// Outcomes of the scanned parts, in the order they were given
+    pub parts: Vec<AttachmentOutcome>,
+    /// Worst verdict of the parts: a virus over an exceeded limit over a
+    /// part which failed to scan, reported as
+    /// `NotScanned(NotScannedReason::PartsFailed)`, over a suppressed
+    /// detection over `Clean`. `Whitelisted` only if all parts were
+    /// whitelisted.
+    pub verdict: ScanResult,
+    /// True if parts were left unscanned because of
+    /// [`AttachmentScanOptions::stop_on_detection`]
+    ///
+    /// [`AttachmentScanOptions::stop_on_detection`]: struct.AttachmentScanOptions.html#structfield.stop_on_detection
+    pub stopped_early: bool,
+}
+
+impl MessageScanResult {
+    /// Returns true if a part was detected as a virus or exceeded a limit.
+    pub fn found(&self) -> bool {
+        self.verdict.found()
+    }
+
+    /// Parts which could not be scanned.
+    pub fn errors(&self) -> impl Iterator<Item = &AttachmentOutcome> {
+        self.parts.iter().filter(|part| part.outcome.is_err())
+    }
+}
+
+/// Ranks verdicts for [`MessageScanResult::verdict`], higher is worse.
+fn severity(result: &ScanResult) -> u8 {
+    match result {
+        ScanResult::Whitelisted => 0,
+        ScanResult::Clean => 1,
+        ScanResult::Suppressed { .. } => 2,
+        ScanResult::NotScanned(_) => 3,
+        ScanResult::Suspicious(_) | ScanResult::Encrypted { .. } => 4,
+        ScanResult::Virus(_) => 5,
+    }
+}
+
+/// Cuts `filename` to at most `max_len` bytes. libclamav gets it sanitized
+/// by the scan.
+fn attachment_filename(filename: &str, max_len: usize) -> &str {
+    if filename.len() <= max_len {
+        return filename;
+    }
+    let mut end = max_len;
+    while !filename.is_char_boundary(end) {
+        end -= 1;
+    }
+    &filename[..end]
+}
+
+/// Scans attachments a mail pipeline already extracted, given as file name
+/// and content, and combines their verdicts.
+///
+/// Same as [`scan_attachments_with`] with the default options.
+///
+/// [`scan_attachments_with`]: fn.scan_attachments_with.html
+pub fn scan_attachments(engine: &Engine, parts: &[(String, &[u8])], settings: &ScanSettings) -> MessageScanResult {
+    scan_attachments_with(engine, parts, settings, &AttachmentScanOptions::default())
+}
+
+/// Scans attachments a mail pipeline already extracted, given as file name
+/// and content, and combines their verdicts.
+///
+/// Each part is scanned from memory with its file name, without
+/// reassembling the message. Empty parts are reported as clean without
+/// calling libclamav. Parts which fail to scan are recorded in
+/// [`MessageScanResult::parts`], and the verdict is at least
+/// `NotScanned(NotScannedReason::PartsFailed)` unless another part was
+/// detected, so a message is never reported clean without all of its parts
+/// being scanned.
+///
+/// [`MessageScanResult::parts`]: struct.MessageScanResult.html#structfield.parts
+pub fn scan_attachments_with(
+    engine: &Engine,
+    parts: &[(String, &[u8])],
+    settings: &ScanSettings,
+    options: &AttachmentScanOptions,
+) -> MessageScanResult {
+    let mut result = MessageScanResult {
+        parts: Vec::with_capacity(parts.len()),
+        verdict: ScanResult::Whitelisted,
+        stopped_early: false,
+    };
+    let failed = ScanResult::NotScanned(NotScannedReason::PartsFailed);
+    let mut any_part = false;
+    for (i, (filename, data)) in parts.iter().enumerate() {
+        let outcome = if data.is_empty() {
+            Ok(ScanOutcome {
+                result: ScanResult::Clean,
+                bytes_scanned: 0,
+                suppression: None,
+                performance: None,
+                from_cache: None,
+            })
+        } else {
+            let name = attachment_filename(filename, options.max_filename_len);
+            engine.scan_mem_outcome(data, Some(name).filter(|name| !name.is_empty()), settings)
+        };
+        let verdict = match &outcome {
+            Ok(outcome) => &outcome.result,
+            Err(_) => &failed,
+        };
+        if !any_part || severity(verdict) > severity(&result.verdict) {
+            result.verdict = verdict.clone();
+        }
+        any_part = true;
+        let found = verdict.found();
+        result.parts.push(AttachmentOutcome {
+            filename: filename.clone(),
+            outcome,
+        });
+        if found && options.stop_on_detection {
+            result.stopped_early = i + 1 < parts.len();
+            break;
+        }
+    }
+    if !any_part {
+        result.verdict = ScanResult::Clean;
+    }
+    result
+}
+
+/// Options of [`scan_oci_layer_with`]
+///
+/// [`scan_oci_layer_with`]: fn.scan_oci_layer_with.html
+#[cfg(feature = "oci")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerScanOptions {
+    /// Entries larger than this many bytes are copied to a temporary file of
+    /// the engine's [`TempStrategy`] and scanned from there instead of being
+    /// buffered in memory. Defaults to 16 MiB.
+    ///
+    /// [`TempStrategy`]: ../temp/trait.TempStrategy.html
+    pub spill_threshold: u64,
+}
+
+#[cfg(feature = "oci")]
+impl Default for LayerScanOptions {
+    fn default() -> Self {
+        LayerScanOptions {
+            spill_threshold: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Result of scanning a container image layer
+#[cfg(feature = "oci")]
+#[derive(Debug, Clone, Default)]
+pub struct LayerScanReport {
+    /// Verdict of each file, or the error which prevented scanning it, keyed by its path in the image, e.g. `etc/passwd`. If the layer
+    /// contains a path more than once, the last entry wins as it does when
+    /// the layer is unpacked.
+    pub entries: BTreeMap<PathBuf, Result<ScanResult, ClamError>>,
+    /// Whiteout entries (`.wh.` files) marking deletions from lower layers,
+    /// in layer order. They are not scanned.
+    pub whiteouts: Vec<PathBuf>,
+    /// Number of entries scanned from a temporary file because they exceeded
+    /// [`LayerScanOptions::spill_threshold`]
+    ///
+    /// [`LayerScanOptions::spill_threshold`]: struct.LayerScanOptions.html#structfield.spill_threshold
+    pub spilled: u64,
+}
+
+#[cfg(feature = "oci")]
+impl LayerScanReport {
+    /// Entries which were detected as viruses or exceeded a limit.
+    pub fn infected(&self) -> impl Iterator<Item = (&Path, &ScanResult)> {
+        self.entries.iter().filter_map(|(path, result)| match result {
+            Ok(result) if result.found() => Some((path.as_path(), result)),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(feature = "oci")]
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+#[cfg(feature = "oci")]
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Wraps `reader` in a decoder if it starts with a gzip or zstd header.
+#[cfg(feature = "oci")]
+fn decompress<'a, R: io::Read + 'a>(reader: R) -> io::Result<Box<dyn io::Read + 'a>> {
+    let mut reader = io::BufReader::new(reader);
+    let header = io::BufRead::fill_buf(&mut reader)?;
+    if header.starts_with(GZIP_MAGIC) {
+        #[cfg(feature = "flate2")]
+        return Ok(Box::new(flate2::read::MultiGzDecoder::new(reader)));
+        #[cfg(not(feature = "flate2"))]
+        return Err(unsupported_compression("gzip", "flate2"));
+    }
+    if header.starts_with(ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?));
+        #[cfg(not(feature = "zstd"))]
+        return Err(unsupported_compression("zstd", "zstd"));
+    }
+    Ok(Box::new(reader))
+}
+
+#[cfg(all(feature = "oci", not(all(feature = "flate2", feature = "zstd"))))]
+fn unsupported_compression(format: &str, feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} compressed layer, enable the {} feature", format, feature),
+    )
+}
+
+/// Returns the path of a layer entry in the image, without a leading `./`
+/// or `/`.
+#[cfg(feature = "oci")]
+fn image_path(path: &Path) -> PathBuf {
+    use std::path::Component;
+
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir | Component::RootDir))
+        .collect()
+}
+
+/// Returns true if `path` is a whiteout, including the opaque whiteout
+/// `.wh..wh..opq`.
+#[cfg(feature = "oci")]
+fn is_whiteout(path: &Path) -> bool {
+    matches!(path.file_name().and_then(|name| name.to_str()), Some(name) if name.starts_with(".wh."))
+}
+
+/// Returns true for tar entries without contents of their own: directories,
+/// links, devices, FIFOs and global PAX headers.
+#[cfg(feature = "oci")]
+fn is_contentless(entry_type: tar::EntryType) -> bool {
+    entry_type.is_dir()
+        || entry_type.is_symlink()
+        || entry_type.is_hard_link()
+        || entry_type.is_character_special()
+        || entry_type.is_block_special()
+        || entry_type.is_fifo()
+        || entry_type.is_pax_global_extensions()
+}
+
+/// Returns true for tar entries holding file contents: regular, contiguous
+/// and GNU sparse files, whose holes `tar` fills in with zeros.
+#[cfg(feature = "oci")]
+fn is_file_entry(entry_type: tar::EntryType) -> bool {
+    entry_type.is_file() || entry_type.is_contiguous() || entry_type.is_gnu_sparse()
+}
+
+/// Scans the regular files of a container image layer, a tar archive which
+/// may be gzip or zstd compressed, without unpacking it to disk.
+///
+/// Same as [`scan_oci_layer_with`] with default options.
+///
+/// [`scan_oci_layer_with`]: fn.scan_oci_layer_with.html
+#[cfg(feature = "oci")]
+pub fn scan_oci_layer<R: io::Read>(engine: &Engine, reader: R, settings: &ScanSettings) -> io::Result<LayerScanReport> {
+    scan_oci_layer_with(engine, reader, settings, &LayerScanOptions::default())
+}
+
+/// Scans the regular files of a container image layer, a tar archive which
+/// may be gzip or zstd compressed, without unpacking it to disk.
+///
+/// The compression is detected from the first bytes. Decompressing gzip
+/// needs the `flate2` feature and zstd the `zstd` feature. Entries are read
+/// one at a time: small ones are scanned from memory, entries above
+/// [`spill_threshold`] from a temporary file. Regular, contiguous and GNU
+/// sparse files are scanned. Whiteout entries are listed in
+/// [`LayerScanReport::whiteouts`] instead of being scanned. Directories,
+/// links, devices and FIFOs have no contents and are ignored.
+///
+/// Errors scanning individual entries are recorded in the report. Entries
+/// of any other type are not scanned and recorded as `CL_EFORMAT` errors.
+///
+/// # Errors
+///
+/// Returns an error if the layer cannot be read or decompressed, is not a
+/// valid tar archive, or uses a compression whose feature is disabled.
+///
+/// [`spill_threshold`]: struct.LayerScanOptions.html#structfield.spill_threshold
+/// [`LayerScanReport::whiteouts`]: struct.LayerScanReport.html#structfield.whiteouts
+#[cfg(feature = "oci")]
+pub fn scan_oci_layer_with<R: io::Read>(
+    engine: &Engine,
+    reader: R,
+    settings: &ScanSettings,
+    options: &LayerScanOptions,
+) -> io::Result<LayerScanReport> {
+    let mut archive = tar::Archive::new(decompress(reader)?);
+    let mut report = LayerScanReport::default();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if is_contentless(entry_type) {
+            continue;
+        }
+        let path = image_path(&entry.path()?);
+        if is_whiteout(&path) {
+            report.whiteouts.push(path);
+            continue;
+        }
+        if !is_file_entry(entry_type) {
+            report.entries.insert(path, Err(ClamError::new(cl_error_t::CL_EFORMAT)));
+            continue;
+        }
+        let name = path.to_str();
+        let size = entry.size();
+        let result = if size > options.spill_threshold {
+            report.spilled += 1;
+            engine.spill(".layer", &mut entry, size).and_then(|spilled| {
+                let mut file = spilled.file();
+                io::Seek::seek(&mut file, io::SeekFrom::Start(0))
+                    .map_err(|_| ClamError::new(cl_error_t::CL_ESEEK))?;
+                engine.scan_fileobj(file, settings, name)
+            })
+        } else {
+            let mut data = Vec::with_capacity(size as usize);
+            io::Read::read_to_end(&mut entry, &mut data)?;
+            if data.is_empty() {
+                Ok(ScanResult::Clean)
+            } else {
+                engine.scan_mem(&data, name, settings)
+            }
+        };
+        report.entries.insert(path, result);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::{JsonlSink, RecordReceiver};
+    use crate::test_support::{example_engine, GOOD_FILE_PATH, NAUGHTY_FILE_PATH};
+    use std::time::Duration;
+
+    #[test]
+    fn entry_format_line_matches_clamscan() {
+        crate::initialize().expect("initialize should succeed");
+        let entry = |result| DirScanEntry {
+            path: PathBuf::from("/tmp/files/eicar.com"),
+            result,
+            cached: false,
+            unstable: false,
+        };
+        assert_eq!(entry(Ok(ScanResult::Clean)).format_line(), "/tmp/files/eicar.com: OK");
+        assert_eq!(
+            entry(Ok(ScanResult::Virus("Eicar-Signature".into()))).format_line(),
+            "/tmp/files/eicar.com: Eicar-Signature FOUND"
+        );
+        assert_eq!(
+            entry(Err(ClamError::new(cl_error_t::CL_EOPEN))).format_line(),
+            "/tmp/files/eicar.com: Can't open file or directory ERROR"
+        );
+    }
+
+    fn fixture_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::copy(GOOD_FILE_PATH, dir.path().join("good_file")).unwrap();
+        fs::copy(NAUGHTY_FILE_PATH, dir.path().join("nested").join("naughty_file")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn scan_directory_finds_naughty_file() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        let settings: ScanSettings = Default::default();
+        let report = scan_directory(&engine, dir.path(), &settings).expect("scan should succeed");
+        assert_eq!(report.entries.len(), 2);
+        let infected: Vec<_> = report.infected().map(|e| e.path.clone()).collect();
+        assert_eq!(infected, vec![dir.path().join("nested").join("naughty_file")]);
+    }
+
+    #[test]
+    fn directory_scans_record_suppressed_detections() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        let naughty = dir.path().join("nested").join("naughty_file");
+        let sha256 = policy::sha256_hex(&fs::read(&naughty).unwrap()).unwrap();
+        let rule = policy::SuppressionRule::new("naughty_file.*").with_sha256(&sha256);
+        let suppressions = Arc::new(SuppressionList::new(vec![rule.clone()]));
+        let settings = ScanSettings::default();
+        let options = DirScanOptions::builder()
+            .threads(2)
+            .suppressions(Arc::clone(&suppressions))
+            .build()
+            .unwrap();
+        let sequential = scan_directory_with_suppressions(&engine, dir.path(), &settings, suppressions).unwrap();
+        let parallel = scan_directory_parallel(&engine, dir.path(), &settings, &options).unwrap();
+        for report in [sequential, parallel] {
+            assert_eq!(report.infected().count(), 0);
+            assert_eq!(report.suppressed.len(), 1);
+            let suppressed = &report.suppressed[0];
+            assert_eq!(suppressed.path, naughty);
+            assert_eq!(suppressed.record.rule, rule);
+            assert_eq!(suppressed.record.sha256.as_deref(), Some(sha256.as_str()));
+            let entry = report.entries.iter().find(|e| e.path == naughty).unwrap();
+            assert_eq!(
+                entry.result,
+                Ok(ScanResult::Suppressed { original: "naughty_file.UNOFFICIAL".into() })
+            );
+        }
+    }
+
+    #[test]
+    fn scan_directory_missing_dir_fails() {
+        let engine = example_engine();
+        let settings: ScanSettings = Default::default();
+        assert!(scan_directory(&engine, Path::new("/nonexistent/dir"), &settings).is_err());
+    }
+
+    #[test]
+    fn file_budget_skips_rest_and_resumes() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        fs::copy(GOOD_FILE_PATH, dir.path().join("another_file")).unwrap();
+        let settings: ScanSettings = Default::default();
+        let budget = BatchBudget {
+            max_files: Some(2),
+            ..Default::default()
+        };
+
+        let first = scan_directory_with_budget(&engine, dir.path(), &settings, &budget, None).unwrap();
+        let scanned: Vec<_> = first.entries.iter().map(|e| e.path.clone()).collect();
+        assert_eq!(scanned, vec![dir.path().join("another_file"), dir.path().join("good_file")]);
+        let skipped = SkippedFile {
+            path: dir.path().join("nested").join("naughty_file"),
+            reason: SkipReason::BudgetExhausted,
+        };
+        assert_eq!(first.skipped, vec![skipped.clone()]);
+        assert_eq!(first.budget_exhausted, Some(BudgetLimit::Files));
+        assert_eq!(crate::report::exit_code(&first), crate::report::EXIT_ERROR);
+        let token = first.resume.expect("stopped scan should return a resume token");
+        assert_eq!(token.position(), 2);
+        assert_eq!(token.path(), skipped.path);
+
+        let second = scan_directory_with_budget(&engine, dir.path(), &settings, &budget, Some(&token)).unwrap();
+        let scanned: Vec<_> = second.entries.iter().map(|e| e.path.clone()).collect();
+        assert_eq!(scanned, vec![skipped.path]);
+        assert!(second.skipped.is_empty());
+        assert_eq!(second.budget_exhausted, None);
+        assert!(second.resume.is_none());
+        assert_eq!(second.infected().count(), 1);
+    }
+
+    #[test]
+    fn parallel_scan_keeps_to_the_budget_of_its_options() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        fs::copy(GOOD_FILE_PATH, dir.path().join("another_file")).unwrap();
+        let settings: ScanSettings = Default::default();
+        let budget = BatchBudget {
+            max_files: Some(2),
+            ..Default::default()
+        };
+        let sequential = scan_directory_with_budget(&engine, dir.path(), &settings, &budget, None).unwrap();
+
+        let options = DirScanOptions::builder().threads(3).budget(budget).build().unwrap();
+        let first = scan_directory_parallel(&engine, dir.path(), &settings, &options).unwrap();
+        let paths = |report: &DirScanReport| -> Vec<PathBuf> { report.entries.iter().map(|e| e.path.clone()).collect() };
+        assert_eq!(paths(&first), paths(&sequential));
+        assert_eq!(first.skipped, sequential.skipped);
+        assert_eq!(first.budget_exhausted, Some(BudgetLimit::Files));
+        assert_eq!(first.resume, sequential.resume);
+
+        let token = first.resume.expect("stopped scan should return a resume token");
+        let options = DirScanOptions::builder().threads(3).budget(budget).resume(token).build().unwrap();
+        let second = scan_directory_parallel(&engine, dir.path(), &settings, &options).unwrap();
+        assert_eq!(paths(&second), vec![dir.path().join("nested").join("naughty_file")]);
+        assert!(second.skipped.is_empty());
+        assert!(second.resume.is_none());
+    }
+
+    #[test]
+    fn scan_directory_parallel_matches_sequential() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        for i in 0..8 {
+            fs::copy(GOOD_FILE_PATH, dir.path().join(format!("good_file_{}", i))).unwrap();
+        }
+        let settings: ScanSettings = Default::default();
+        let sequential = scan_directory(&engine, dir.path(), &settings).unwrap();
+
+        for &prefetch in &[0, 1, 64] {
+            let options = DirScanOptions {
+                threads: 3,
+                prefetch,
+                ..Default::default()
+            };
+            let parallel = scan_directory_parallel(&engine, dir.path(), &settings, &options).unwrap();
+            assert!(!parallel.cancelled);
+            let paths = |report: &DirScanReport| -> Vec<(PathBuf, Option<ScanResult>)> {
+                report.entries.iter().map(|e| (e.path.clone(), e.result.clone().ok())).collect()
+            };
+            assert_eq!(paths(&parallel), paths(&sequential));
+        }
+    }
+
+    /// Writer collecting everything written to it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parallel_scans_serialize_identically() {
+        let dir = fixture_tree();
+        for i in 0..24 {
+            let subdir = dir.path().join(format!("dir{}", i % 3));
+            fs::create_dir_all(&subdir).unwrap();
+            fs::copy(NAUGHTY_FILE_PATH, subdir.join(format!("naughty_{}", i))).unwrap();
+            fs::write(subdir.join(format!("text_{}", i)), format!("Plain text file number {}\n", i)).unwrap();
+        }
+        let settings: ScanSettings = Default::default();
+
+        let serialize = || {
+            // clean files are all different, and a new engine has an empty
+            // clean cache, so no run serves verdicts from it
+            let engine = example_engine();
+            let output = SharedBuffer::default();
+            let sink = JsonlSink::new(output.clone()).ordered(64);
+            let options = DirScanOptions {
+                threads: 4,
+                open_threads: 2,
+                prefetch: 8,
+                ..Default::default()
+            }
+            .with_sink(Arc::new(sink));
+            let report = scan_directory_parallel(&engine, dir.path(), &settings, &options).unwrap();
+            assert_eq!(report.iter_completion_order().count(), report.entries.len());
+            let mut completed: Vec<_> = report.iter_completion_order().map(|e| e.path.clone()).collect();
+            completed.sort();
+            assert_eq!(completed, report.entries.iter().map(|e| e.path.clone()).collect::<Vec<_>>());
+
+            let lines: Vec<_> = report.entries.iter().map(DirScanEntry::format_line).collect();
+            let jsonl = output.0.lock().unwrap().clone();
+            (lines.join("\n"), jsonl)
+        };
+
+        let (first_report, first_jsonl) = serialize();
+        assert_eq!(first_jsonl.iter().filter(|&&b| b == b'\n').count(), 50);
+        for _ in 0..10 {
+            let (report, jsonl) = serialize();
+            assert_eq!(report, first_report);
+            assert_eq!(jsonl, first_jsonl);
+        }
+    }
+
+    #[test]
+    fn sequential_completion_order_is_walk_order() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        let report = scan_directory(&engine, dir.path(), &Default::default()).unwrap();
+        let completed: Vec<_> = report.iter_completion_order().map(|e| e.path.clone()).collect();
+        let sorted: Vec<_> = report.entries.iter().map(|e| e.path.clone()).collect();
+        assert_eq!(completed, sorted);
+    }
+
+    #[test]
+    fn scan_directory_parallel_cancelled_scans_nothing() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        let token = ScanToken::new();
+        token.cancel();
+        let options = DirScanOptions {
+            cancel: Some(token),
+            ..Default::default()
+        };
+        let report = scan_directory_parallel(&engine, dir.path(), &Default::default(), &options).unwrap();
+        assert!(report.cancelled);
+        assert!(report.entries.is_empty());
+    }
+
+    #[test]
+    fn scan_directory_parallel_missing_dir_fails() {
+        let engine = example_engine();
+        let options = DirScanOptions::default();
+        let result = scan_directory_parallel(&engine, Path::new("/nonexistent/dir"), &Default::default(), &options);
+        assert!(result.is_err());
+    }
+
+    /// Records the paths received from `receiver` until the channel closes,
+    /// noting for each whether `done` was already set
+    fn consume(receiver: RecordReceiver, done: Arc<AtomicBool>) -> thread::JoinHandle<Vec<(PathBuf, bool)>> {
+        thread::spawn(move || {
+            receiver
+                .iter()
+                .map(|record| (record.path.unwrap(), done.load(Ordering::SeqCst)))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn sink_receives_results_while_scanning() {
+        use crate::sink::{ChannelSink, Overflow};
+
+        let engine = example_engine();
+        let dir = fixture_tree();
+        for i in 0..4 {
+            fs::copy(GOOD_FILE_PATH, dir.path().join(format!("good_file_{}", i))).unwrap();
+        }
+        let settings: ScanSettings = Default::default();
+
+        for &parallel in &[false, true] {
+            // with room for a single record, every further emit waits for the consumer
+            let (sink, receiver) = ChannelSink::new(1, Overflow::Block(Duration::from_secs(60)));
+            let sink = Arc::new(sink);
+            let done = Arc::new(AtomicBool::new(false));
+            let consumer = consume(receiver, done.clone());
+
+            let report = if parallel {
+                let options = DirScanOptions {
+                    threads: 2,
+                    ..Default::default()
+                }
+                .with_sink(sink.clone());
+                scan_directory_parallel(&engine, dir.path(), &settings, &options).unwrap()
+            } else {
+                scan_directory_with_sink(&engine, dir.path(), &settings, sink.clone()).unwrap()
+            };
+            done.store(true, Ordering::SeqCst);
+            drop(sink);
+
+            let mut streamed = consumer.join().unwrap();
+            assert!(streamed.iter().any(|&(_, done)| !done), "no result arrived during the scan");
+            streamed.sort();
+            let streamed: Vec<_> = streamed.into_iter().map(|(path, _)| path).collect();
+            let reported: Vec<_> = report.entries.iter().map(|e| e.path.clone()).collect();
+            assert_eq!(streamed, reported);
+        }
+    }
+
+    #[test]
+    fn batching_sink_is_flushed_when_scan_completes() {
+        use crate::sink::{BatchOptions, ChannelSink, Overflow, ResultSink};
+
+        let engine = example_engine();
+        let dir = fixture_tree();
+        let (channel, receiver) = ChannelSink::new(16, Overflow::Drop);
+        let options = BatchOptions {
+            batch_size: 100,
+            max_delay: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let sink = Arc::new(channel.batched(options));
+        let options = DirScanOptions::default().with_sink(sink.clone());
+        let report = scan_directory_parallel(&engine, dir.path(), &Default::default(), &options).unwrap();
+
+        let mut records: Vec<_> = receiver.try_iter().collect();
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(records.len(), report.entries.len());
+        let naughty = dir.path().join("nested").join("naughty_file");
+        for (record, entry) in records.iter().zip(&report.entries) {
+            assert_eq!(record.path.as_ref(), Some(&entry.path));
+            let result = record.outcome.clone().map(|outcome| outcome.result);
+            assert_eq!(result, entry.result.clone());
+        }
+        let found: Vec<_> = records
+            .iter()
+            .filter(|r| matches!(&r.outcome, Ok(outcome) if outcome.result.found()))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path.as_ref(), Some(&naughty));
+        assert_eq!(sink.dropped(), 0);
+    }
+
+    /// Text files, one of them naughty, a zip archive, a PDF and an empty file
+    fn mixed_type_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        // libclamav skips type detection for files of a few bytes like good_file
+        fs::write(dir.path().join("readme.txt"), b"Nothing to see here, just some plain text.\n").unwrap();
+        fs::copy(NAUGHTY_FILE_PATH, dir.path().join("nested").join("naughty_file")).unwrap();
+        fs::write(dir.path().join("archive.zip"), crate::test_support::zip_bomb(1024)).unwrap();
+        fs::write(
+            dir.path().join("nested").join("document.pdf"),
+            b"%PDF-1.4\n1 0 obj\n<< /Type /Catalog >>\nendobj\ntrailer\n<< /Root 1 0 R >>\n%%EOF\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("empty"), b"").unwrap();
+        dir
+    }
+
+    fn assert_mixed_type_stats(report: &DirScanReport) {
+        let counts: Vec<_> = report
+            .by_type
+            .iter()
+            .map(|(file_type, stats)| (file_type.as_str(), stats.scanned, stats.infected))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                (UNKNOWN_FILE_TYPE, 1, 0),
+                ("CL_TYPE_PDF", 1, 0),
+                ("CL_TYPE_TEXT_ASCII", 2, 1),
+                ("CL_TYPE_ZIP", 1, 0),
+            ],
+            "{:?}",
+            report.by_type
+        );
+        assert_eq!(report.by_type["CL_TYPE_TEXT_ASCII"].detection_rate(), 0.5);
+        assert!(report.by_type["CL_TYPE_ZIP"].bytes > 0);
+    }
+
+    #[test]
+    fn directory_scans_count_file_types() {
+        let engine = example_engine();
+        let dir = mixed_type_tree();
+        let settings = crate::scan_settings::ScanSettingsBuilder::new().enable_archive().enable_pdf().build();
+
+        let report = scan_directory(&engine, dir.path(), &settings).unwrap();
+        assert_mixed_type_stats(&report);
+
+        let options = DirScanOptions {
+            threads: 3,
+            ..Default::default()
+        };
+        let report = scan_directory_parallel(&engine, dir.path(), &settings, &options).unwrap();
+        assert_mixed_type_stats(&report);
+
+        let options = DirScanOptions {
+            file_types: false,
+            ..Default::default()
+        };
+        let report = scan_directory_parallel(&engine, dir.path(), &settings, &options).unwrap();
+        assert!(report.by_type.is_empty());
+        assert_eq!(report.entries.len(), 5);
+    }
+
+    #[test]
+    fn type_stats_display_and_rate() {
+        let stats = TypeStats {
+            scanned: 3,
+            infected: 1,
+            bytes: 12288,
+            time: Duration::from_micros(1500),
+        };
+        assert_eq!(stats.to_string(), "3 scanned, 1 infected, 12288 bytes in 1.5ms");
+        assert!((stats.detection_rate() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(TypeStats::default().detection_rate(), 0.0);
+    }
+
+    /// Returns a hook which runs `action` on another thread when the scan of
+    /// `victim` reaches `at`, and lets the scan continue once it is done.
+    fn interfere<'a>(
+        victim: &'a Path,
+        at: Stage,
+        action: &'a (dyn Fn(&Path) + Sync),
+    ) -> impl Fn(Stage, &Path) + Sync + 'a {
+        move |stage, path| {
+            if stage == at && path == victim {
+                thread::scope(|s| {
+                    s.spawn(|| action(path));
+                });
+            }
+        }
+    }
+
+    /// Runs the sequential and the parallel directory scan on a fresh
+    /// fixture tree each, with `hook` interfering with `victim`.
+    fn scan_with_hook<F>(victim: &str, at: Stage, action: &(dyn Fn(&Path) + Sync), check: F)
+    where
+        F: Fn(&Path, &DirScanReport),
+    {
+        let engine = example_engine();
+        let settings = ScanSettings::default();
+        for parallel in [false, true] {
+            let dir = fixture_tree();
+            let victim = dir.path().join(victim);
+            let hook = interfere(&victim, at, action);
+            let report = if parallel {
+                let options = DirScanOptions {
+                    threads: 2,
+                    ..Default::default()
+                };
+                scan_directory_parallel_to(&engine, dir.path(), &settings, &options, Some(&hook))
+            } else {
+                scan_directory_to(&engine, dir.path(), &settings, &DirScanOptions::default(), Some(&hook))
+            };
+            check(&victim, &report.unwrap());
+        }
+    }
+
+    #[test]
+    fn file_vanishing_before_open_is_skipped() {
+        let remove = |path: &Path| fs::remove_file(path).unwrap();
+        scan_with_hook("good_file", Stage::Walked, &remove, |victim, report| {
+            let skipped = SkippedFile {
+                path: victim.to_path_buf(),
+                reason: SkipReason::Vanished,
+            };
+            assert_eq!(report.skipped, vec![skipped]);
+            assert_eq!(report.entries.len(), 1);
+            assert!(report.entries.iter().all(|e| e.result.is_ok()), "{:?}", report.entries);
+            assert_eq!(crate::report::exit_code(report), crate::report::EXIT_FOUND);
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_replaced_by_symlink_is_skipped() {
+        let replace = |path: &Path| {
+            fs::remove_file(path).unwrap();
+            std::os::unix::fs::symlink(fs::canonicalize(NAUGHTY_FILE_PATH).unwrap(), path).unwrap();
+        };
+        scan_with_hook("good_file", Stage::Walked, &replace, |victim, report| {
+            assert_eq!(report.skipped.len(), 1);
+            assert_eq!(report.skipped[0].path, victim);
+            assert_eq!(report.skipped[0].reason, SkipReason::Replaced);
+            assert_eq!(report.entries.len(), 1);
+        });
+    }
+
+    #[test]
+    fn file_growing_during_scan_is_unstable() {
+        let append = |path: &Path| {
+            let mut file = fs::OpenOptions::new().append(true).open(path).unwrap();
+            io::Write::write_all(&mut file, b" and then some").unwrap();
+        };
+        scan_with_hook("good_file", Stage::Opened, &append, |victim, report| {
+            assert!(report.skipped.is_empty());
+            let unstable: Vec<_> = report.unstable().map(|e| e.path.as_path()).collect();
+            assert_eq!(unstable, vec![victim]);
+            let entry = report.unstable().next().unwrap();
+            assert_eq!(entry.result, Ok(ScanResult::Clean));
+        });
+    }
+
+    fn naughty() -> ScanResult {
+        ScanResult::Virus("naughty_file.UNOFFICIAL".into())
+    }
+
+    #[test]
+    fn attachments_report_parts_and_worst_verdict() {
+        let engine = example_engine();
+        let naughty_data = fs::read(NAUGHTY_FILE_PATH).unwrap();
+        let parts = vec![
+            ("notes.txt".to_string(), &b"Just a friendly reminder about the meeting."[..]),
+            ("invoice.doc".to_string(), &naughty_data[..]),
+            ("signature.txt".to_string(), &b"Regards"[..]),
+        ];
+        let result = scan_attachments(&engine, &parts, &ScanSettings::default());
+        let verdicts: Vec<_> = result
+            .parts
+            .iter()
+            .map(|part| (part.filename.as_str(), part.outcome.clone().unwrap().result))
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                ("notes.txt", ScanResult::Clean),
+                ("invoice.doc", naughty()),
+                ("signature.txt", ScanResult::Clean),
+            ]
+        );
+        assert_eq!(result.verdict, naughty());
+        assert!(result.found());
+        assert!(!result.stopped_early);
+
+        let options = AttachmentScanOptions {
+            stop_on_detection: true,
+            ..Default::default()
+        };
+        let result = scan_attachments_with(&engine, &parts, &ScanSettings::default(), &options);
+        assert_eq!(result.parts.len(), 2);
+        assert_eq!(result.verdict, naughty());
+        assert!(result.stopped_early);
+    }
+
+    #[test]
+    fn attachment_edge_cases() {
+        let engine = example_engine();
+        let long_name = format!("{}.txt", "ä".repeat(100_000));
+        let parts = vec![
+            ("empty.bin".to_string(), &b""[..]),
+            (long_name.clone(), &b"Nothing to see here, just some plain text."[..]),
+            ("nul\0name.txt".to_string(), &b"More plain text."[..]),
+        ];
+        let result = scan_attachments(&engine, &parts, &ScanSettings::default());
+        assert_eq!(result.parts.len(), 3);
+        assert_eq!(result.errors().count(), 0, "{:?}", result.parts);
+        assert_eq!(result.parts[0].outcome.as_ref().unwrap().bytes_scanned, 0);
+        assert_eq!(result.parts[1].filename, long_name);
+        assert_eq!(result.verdict, ScanResult::Clean);
+
+        let result = scan_attachments(&engine, &[], &ScanSettings::default());
+        assert!(result.parts.is_empty());
+        assert_eq!(result.verdict, ScanResult::Clean);
+    }
+
+    #[test]
+    fn attachment_filenames_are_truncated_at_char_boundaries() {
+        assert_eq!(attachment_filename("report.pdf", 255), "report.pdf");
+        assert_eq!(attachment_filename("äää", 4), "ää");
+        assert_eq!(attachment_filename("äää", 3), "ä");
+        assert_eq!(attachment_filename("evil\0.exe", 255), "evil\0.exe");
+        assert_eq!(crate::engine::SanitizedName::new("evil\0.exe").as_str(), "evil\u{FFFD}.exe");
+    }
+
+    #[test]
+    fn failed_attachments_are_not_reported_clean() {
+        crate::initialize().expect("initialize should succeed");
+        // scans fail on an engine which was never compiled
+        let engine = Engine::new().unwrap();
+        let parts = vec![
+            ("empty.bin".to_string(), &b""[..]),
+            ("notes.txt".to_string(), &b"Plain text."[..]),
+        ];
+        let result = scan_attachments(&engine, &parts, &ScanSettings::default());
+        assert_eq!(result.errors().count(), 1);
+        assert_eq!(result.verdict, ScanResult::NotScanned(NotScannedReason::PartsFailed));
+        assert!(!result.found());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn process_region_scan_detects_own_memory() {
+        let engine = example_engine();
+        let naughty_data = fs::read(NAUGHTY_FILE_PATH).unwrap();
+        let mut region = crate::test_support::GuardedRegion::new();
+        region.page_mut()[..naughty_data.len()].copy_from_slice(&naughty_data);
+        let (start, guard) = (region.start(), region.guard());
+        let page = guard - start;
+        let pid = std::process::id();
+        let settings = ScanSettings::default();
+
+        let scan = scan_process_region(&engine, pid, start..guard, &settings).unwrap();
+        assert_eq!(scan.outcome.result, naughty());
+        assert_eq!(scan.warning, None);
+
+        let scan = scan_process_region(&engine, pid, start..guard + page, &settings).unwrap();
+        assert_eq!(scan.outcome.result, naughty());
+        assert_eq!(scan.scanned, start..guard);
+        assert_eq!(scan.warning, Some(RegionWarning::Truncated { at: guard }));
+
+        let err = scan_process_region(&engine, pid, guard..guard + 16, &settings).unwrap_err();
+        assert_eq!(err, ClamError::new(cl_error_t::CL_EREAD));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn process_region_scan_checks_range() {
+        let engine = example_engine();
+        let pid = std::process::id();
+        let settings = ScanSettings::default();
+        let err = scan_process_region(&engine, pid, 4096..4096, &settings).unwrap_err();
+        assert_eq!(err, ClamError::new(cl_error_t::CL_EARG));
+
+        let options = ProcessScanOptions { max_region: 1024 };
+        let err = scan_process_region_with(&engine, pid, 4096..8192, &settings, &options).unwrap_err();
+        assert_eq!(err, ClamError::new(cl_error_t::CL_EMAXSIZE));
+    }
+
+    #[test]
+    fn dir_scan_options_defaults() {
+        let options = DirScanOptions::builder().build().unwrap();
+        assert_eq!(options.threads, Engine::recommended_concurrency());
+        assert_eq!(options.open_threads, 2);
+        assert_eq!(options.prefetch, 64);
+        assert!(options.cancel.is_none());
+        assert!(options.file_types);
+        assert!(options.budget.is_unlimited());
+        assert!(options.resume.is_none());
+        assert!(options.sink.is_none());
+    }
+
+    #[test]
+    fn dir_scan_options_builder_sets_values() {
+        let token = ScanToken::new();
+        let (sink, _results) = crate::sink::ChannelSink::new(1, crate::sink::Overflow::Drop);
+        let options = DirScanOptions::builder()
+            .threads(3)
+            .open_threads(1)
+            .prefetch(0)
+            .cancel(token.clone())
+            .file_types(false)
+            .sink(Arc::new(sink))
+            .build()
+            .unwrap();
+        assert_eq!((options.threads, options.open_threads, options.prefetch), (3, 1, 0));
+        token.cancel();
+        assert!(options.cancel.unwrap().is_cancelled());
+        assert!(!options.file_types);
+        assert!(options.sink.is_some());
+    }
+
+    #[test]
+    fn parallel_scan_applies_include_and_exclude_globs() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        fs::copy(GOOD_FILE_PATH, dir.path().join("nested").join("good_copy")).unwrap();
+        let settings: ScanSettings = Default::default();
+        let names = |options: &DirScanOptions| -> Vec<String> {
+            scan_directory_parallel(&engine, dir.path(), &settings, options)
+                .unwrap()
+                .entries
+                .iter()
+                .map(|entry| entry.path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let options = DirScanOptions::builder().include("good*").build().unwrap();
+        assert_eq!(names(&options), vec!["good_file", "good_copy"]);
+        let options = DirScanOptions::builder().include("good*").exclude("*copy").build().unwrap();
+        assert_eq!(names(&options), vec!["good_file"]);
+        let options = DirScanOptions::builder().exclude("naughty_?ile").build().unwrap();
+        assert_eq!(names(&options).len(), 2);
+    }
+
+    #[test]
+    fn dir_scan_options_builder_rejects_zero_threads() {
+        assert_eq!(
+            DirScanOptions::builder().threads(0).build().err(),
+            Some(DirScanOptionsError::NoScanThreads)
+        );
+        assert_eq!(
+            DirScanOptions::builder().open_threads(0).build().err(),
+            Some(DirScanOptionsError::NoOpenThreads)
+        );
+    }
+
+    #[test]
+    fn incremental_scan_rescans_only_changed_files() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        let settings: ScanSettings = Default::default();
+        let mut scanner = IncrementalScanner::new();
+
+        let first = scanner.scan_directory(&engine, dir.path(), &settings).unwrap();
+        assert_eq!(first.scanned().count(), 2);
+        assert_eq!(scanner.len(), 2);
+
+        let second = scanner.scan_directory(&engine, dir.path(), &settings).unwrap();
+        assert_eq!(second.scanned().count(), 0);
+        assert_eq!(second.infected().count(), 1);
+
+        let good_file = dir.path().join("good_file");
+        let mut contents = fs::read(&good_file).unwrap();
+        contents.extend_from_slice(b"changed");
+        fs::write(&good_file, contents).unwrap();
+
+        let third = scanner.scan_directory(&engine, dir.path(), &settings).unwrap();
+        let rescanned: Vec<_> = third.scanned().map(Path::to_path_buf).collect();
+        assert_eq!(rescanned, vec![good_file]);
+        assert_eq!(third.infected().count(), 1);
+    }
+
+    #[test]
+    fn incremental_scan_rescans_after_signatures_load() {
+        crate::initialize().expect("initialize should succeed");
+        let empty = Engine::new().unwrap();
+        empty.compile().expect("failed to compile");
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("eicar.com"), EICAR).unwrap();
+        let settings: ScanSettings = Default::default();
+        let mut scanner = IncrementalScanner::new();
+
+        let first = scanner.scan_directory(&empty, dir.path(), &settings).unwrap();
+        let not_scanned = ScanResult::NotScanned(NotScannedReason::EmptyEngine);
+        assert_eq!(first.entries[0].result, Ok(not_scanned));
+        assert!(scanner.is_empty());
+
+        // local databases report version zero, like the empty engine
+        let (engine, _db_dir) = eicar_engine();
+        assert_eq!(engine.database_version().unwrap_or(0), 0);
+        let second = scanner.scan_directory(&engine, dir.path(), &settings).unwrap();
+        assert_eq!(second.scanned().count(), 1);
+        assert_eq!(second.infected().count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "cache-store")]
+    fn incremental_cache_survives_save_and_load() {
+        let engine = example_engine();
+        let dir = fixture_tree();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join("cache.bin");
+        let settings: ScanSettings = Default::default();
+
+        let mut scanner = IncrementalScanner::new();
+        scanner.scan_directory(&engine, dir.path(), &settings).unwrap();
+        scanner.save(&cache_path).expect("save should succeed");
+
+        let mut restored = IncrementalScanner::load(&cache_path);
+        assert_eq!(restored.len(), 2);
+        let report = restored.scan_directory(&engine, dir.path(), &settings).unwrap();
+        assert_eq!(report.scanned().count(), 0);
+        assert_eq!(report.infected().count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "cache-store")]
+    fn incremental_corrupted_cache_is_ignored() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join("cache.bin");
+
+        fs::write(&cache_path, b"definitely not a cache").unwrap();
+        assert!(IncrementalScanner::load(&cache_path).is_empty());
+
+        let mut truncated = b"CLRSIC01".to_vec();
+        truncated.extend_from_slice(&5u64.to_le_bytes());
+        truncated.extend_from_slice(&[0xff; 3]);
+        fs::write(&cache_path, truncated).unwrap();
+        assert!(IncrementalScanner::load(&cache_path).is_empty());
+
+        assert!(IncrementalScanner::load(&cache_dir.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "cache-store")]
+    fn incremental_cache_skips_not_scanned_entries() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache_path = cache_dir.path().join("cache.bin");
+
+        let mut data = b"CLRSIC01".to_vec();
+        data.extend_from_slice(&2u64.to_le_bytes());
+        for (name, tag) in &[(b"clean", 0u8), (b"empty", 7u8)] {
+            data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            data.extend_from_slice(&name[..]);
+            data.extend_from_slice(&0u64.to_le_bytes());
+            data.push(0);
+            data.extend_from_slice(&0i64.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.push(*tag);
+        }
+        fs::write(&cache_path, data).unwrap();
+        assert_eq!(IncrementalScanner::load(&cache_path).len(), 1);
+    }
+
+    const EICAR: &[u8] = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+    /// Engine with a body signature matching EICAR at any offset
+    fn eicar_engine() -> (Engine, tempfile::TempDir) {
+        let db_dir = tempfile::tempdir().unwrap();
+        let hex: String = EICAR.iter().map(|b| format!("{:02x}", b)).collect();
+        fs::write(db_dir.path().join("disk.ndb"), format!("Disk.Test.Eicar:0:*:{}\n", hex)).unwrap();
+        crate::initialize().expect("initialize should succeed");
+        let engine = Engine::new().unwrap();
+        engine
+            .load_databases(db_dir.path().to_str().unwrap())
+            .expect("failed to load db");
+        engine.compile().expect("failed to compile");
+        (engine, db_dir)
+    }
+
+    fn small_windows() -> DiskScanOptions {
+        DiskScanOptions {
+            window: WindowConfig { window_size: 64 * 1024, overlap: 4096 },
+            ..Default::default()
+        }
+    }
+
+    fn assert_detected_in_partition(report: &DiskScanReport, scheme: PartitionScheme, index: usize) {
+        assert_eq!(report.result(), ScanResult::Virus("Disk.Test.Eicar".into()));
+        let infected: Vec<_> = report.infected_partitions().collect();
+        assert_eq!(infected.len(), 1, "partitions: {:?}", report.partitions);
+        assert_eq!(infected[0].scheme, scheme);
+        assert_eq!(infected[0].index, index);
+        assert_eq!(infected[0].offset, partition::tests::PARTITION_FIRST_SECTOR * 512);
+        let detection = &infected[0].report.detections[0];
+        assert!(detection.offset >= infected[0].offset);
+        assert_eq!(detection.name, "Disk.Test.Eicar");
+    }
+
+    #[test]
+    fn disk_image_mbr_partition_detection() {
+        let (engine, _db_dir) = eicar_engine();
+        let image = partition::tests::mbr_image(&partition::tests::fat12(b"EICAR   COM", EICAR));
+        let report = scan_disk_image(&engine, image.path(), &ScanSettings::default(), &small_windows())
+            .expect("scan should succeed");
+        assert_detected_in_partition(&report, PartitionScheme::Mbr, 0);
+    }
+
+    #[test]
+    fn disk_image_gpt_partition_detection() {
+        let (engine, _db_dir) = eicar_engine();
+        let image = partition::tests::gpt_image(&partition::tests::fat12(b"EICAR   COM", EICAR));
+        let report = scan_disk_image(&engine, image.path(), &ScanSettings::default(), &small_windows())
+            .expect("scan should succeed");
+        assert_detected_in_partition(&report, PartitionScheme::Gpt, 1);
+    }
+
+    #[test]
+    fn disk_image_clean_partition() {
+        let (engine, _db_dir) = eicar_engine();
+        let image = partition::tests::mbr_image(&partition::tests::fat12(b"README  TXT", b"hello"));
+        let report = scan_disk_image(&engine, image.path(), &ScanSettings::default(), &small_windows())
+            .expect("scan should succeed");
+        assert_eq!(report.result(), ScanResult::Clean);
+        assert_eq!(report.partitions.len(), 1);
+        assert_eq!(report.infected_partitions().count(), 0);
+    }
+
+    #[test]
+    fn disk_image_without_partition_table() {
+        let (engine, _db_dir) = eicar_engine();
+        let mut image = tempfile::NamedTempFile::new().unwrap();
+        io::Write::write_all(&mut image, &[0u8; 8192]).unwrap();
+        io::Write::write_all(&mut image, EICAR).unwrap();
+        let report = scan_disk_image(&engine, image.path(), &ScanSettings::default(), &small_windows())
+            .expect("scan should succeed");
+        assert!(report.partitions.is_empty());
+        assert_eq!(report.result(), ScanResult::Virus("Disk.Test.Eicar".into()));
+    }
+
+    #[test]
+    fn disk_image_raises_engine_limits() {
+        let (engine, _db_dir) = eicar_engine();
+        engine.set_max_partitions(2).unwrap();
+        engine.set_max_scansize(1024).unwrap();
+        let image = partition::tests::mbr_image(&partition::tests::fat12(b"README  TXT", b"hello"));
+        let options = DiskScanOptions { max_partitions: 64, max_scansize: 1 << 20, ..small_windows() };
+        scan_disk_image(&engine, image.path(), &ScanSettings::default(), &options).expect("scan should succeed");
+        assert_eq!(engine.max_partitions(), Ok(64));
+        assert_eq!(engine.max_scansize(), Ok(1 << 20));
+
+        // higher limits are kept
+        let options = DiskScanOptions { max_partitions: 8, max_scansize: 4096, ..small_windows() };
+        scan_disk_image(&engine, image.path(), &ScanSettings::default(), &options).expect("scan should succeed");
+        assert_eq!(engine.max_partitions(), Ok(64));
+        assert_eq!(engine.max_scansize(), Ok(1 << 20));
+    }
+
+    #[test]
+    fn disk_image_missing_file_fails() {
+        let (engine, _db_dir) = eicar_engine();
+        let result = scan_disk_image(
+            &engine,
+            Path::new("test_data/files/does_not_exist"),
+            &ScanSettings::default(),
+            &small_windows(),
+        );
+        assert_eq!(result.err().map(|e| e.code()), Some(cl_error_t::CL_EOPEN as i32));
+    }
+
+    #[cfg(feature = "oci")]
+    fn tar_layer(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[cfg(feature = "oci")]
+    #[test]
+    fn oci_layer_scans_contiguous_and_sparse_files() {
+        let (engine, _db_dir) = eicar_engine();
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut append = |path: &str, entry_type, contents: &[u8], sparse_at: Option<u64>| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(entry_type);
+            if let Some(offset) = sparse_at {
+                let gnu = header.as_gnu_mut().unwrap();
+                gnu.sparse[0].set_offset(offset);
+                gnu.sparse[0].set_length(contents.len() as u64);
+                gnu.set_real_size(offset + contents.len() as u64);
+            }
+            builder.append_data(&mut header, path, contents).unwrap();
+        };
+        append("bin", tar::EntryType::Directory, &[], None);
+        append("bin/sh", tar::EntryType::Symlink, &[], None);
+        append("opt/contiguous.com", tar::EntryType::Continuous, EICAR, None);
+        append("opt/sparse.bin", tar::EntryType::GNUSparse, EICAR, Some(4096));
+        append("opt/label", tar::EntryType::new(b'V'), EICAR, None);
+        let layer = builder.into_inner().unwrap();
+
+        let report = scan_oci_layer(&engine, &layer[..], &ScanSettings::default()).expect("scan should succeed");
+        let eicar = Ok(ScanResult::Virus("Disk.Test.Eicar".into()));
+        assert_eq!(report.entries.len(), 3);
+        assert_eq!(report.entries[Path::new("opt/contiguous.com")], eicar);
+        assert_eq!(report.entries[Path::new("opt/sparse.bin")], eicar);
+        assert_eq!(report.entries[Path::new("opt/label")], Err(ClamError::new(cl_error_t::CL_EFORMAT)));
+    }
+
+    #[cfg(all(feature = "oci", feature = "flate2"))]
+    #[test]
+    fn oci_layer_gzip_per_entry_verdicts() {
+        use flate2::write::GzEncoder;
+
+        let (engine, _db_dir) = eicar_engine();
+        let mut large = vec![b'A'; 4096];
+        large.extend_from_slice(EICAR);
+        let layer = tar_layer(&[
+            ("./etc/motd", &b"welcome"[..]),
+            ("./tmp/eicar.com", EICAR),
+            ("./tmp/.wh.removed", &[]),
+            ("./usr/.wh..wh..opq", &[]),
+            ("./var/large.bin", &large[..]),
+        ]);
+        let mut gzipped = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut gzipped, &layer).unwrap();
+        let gzipped = gzipped.finish().unwrap();
+
+        let options = LayerScanOptions { spill_threshold: 1024 };
+        let report = scan_oci_layer_with(&engine, &gzipped[..], &ScanSettings::default(), &options)
+            .expect("scan should succeed");
+        let verdicts: Vec<_> = report
+            .entries
+            .iter()
+            .map(|(path, result)| (path.to_str().unwrap(), result.clone().unwrap()))
+            .collect();
+        let eicar = || ScanResult::Virus("Disk.Test.Eicar".into());
+        assert_eq!(
+            verdicts,
+            vec![
+                ("etc/motd", ScanResult::Clean),
+                ("tmp/eicar.com", eicar()),
+                ("var/large.bin", eicar()),
+            ]
+        );
+        assert_eq!(report.whiteouts, vec![PathBuf::from("tmp/.wh.removed"), PathBuf::from("usr/.wh..wh..opq")]);
+        assert_eq!(report.spilled, 1);
+        assert_eq!(report.infected().count(), 2);
+        assert_eq!(engine.temp_bytes(), 0);
+    }
+
+    #[cfg(feature = "oci")]
+    #[test]
+    fn oci_layer_uncompressed_last_entry_wins() {
+        let (engine, _db_dir) = eicar_engine();
+        let layer = tar_layer(&[("app/run.sh", EICAR), ("app/run.sh", &b"#!/bin/sh\n"[..])]);
+        let report = scan_oci_layer(&engine, &layer[..], &ScanSettings::default()).expect("scan should succeed");
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(report.entries[Path::new("app/run.sh")], Ok(ScanResult::Clean));
+        assert_eq!(report.spilled, 0);
+    }
+}
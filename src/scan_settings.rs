@@ -1,5 +1,8 @@
 #![allow(dead_code)]
 
+use std::fmt;
+use std::str::FromStr;
+
 use clamav_sys::{
     cl_scan_options,
     CL_SCAN_GENERAL_ALLMATCHES,
@@ -115,7 +118,7 @@ bitflags! {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct ScanSettings {
     pub settings: cl_scan_options,
 }
@@ -131,82 +134,254 @@ impl ScanSettings {
     pub fn set_mail(&mut self, flags: MailFlags) {self.settings.mail = flags.bits();}
     pub fn dev(& self) -> DevFlags {DevFlags::from_bits(self.settings.dev).unwrap()}
     pub fn set_dev(&mut self, flags: DevFlags) {self.settings.dev = flags.bits();}
+
+    /// Returns the settings with the flags of both `self` and `other` set,
+    /// combining each flag group on its own.
+    pub fn union(&self, other: &ScanSettings) -> ScanSettings {
+        self.combine(other, |own, other| own | other)
+    }
+
+    /// Returns `self` with the flags set in `other` cleared.
+    pub fn difference(&self, other: &ScanSettings) -> ScanSettings {
+        self.combine(other, |own, other| own & !other)
+    }
+
+    fn combine(&self, other: &ScanSettings, op: fn(u32, u32) -> u32) -> ScanSettings {
+        ScanSettings {
+            settings: cl_scan_options {
+                general: op(self.settings.general, other.settings.general),
+                parse: op(self.settings.parse, other.settings.parse),
+                heuristic: op(self.settings.heuristic, other.settings.heuristic),
+                mail: op(self.settings.mail, other.settings.mail),
+                dev: op(self.settings.dev, other.settings.dev),
+            },
+        }
+    }
 }
 
-impl ToString for ScanSettings {
-    fn to_string(& self) -> String {
-        let mut flag_names = Vec::<String>::new();
-
-        let general_flags = vec![
-            (GeneralFlags::CL_SCAN_GENERAL_ALLMATCHES, "CL_SCAN_GENERAL_ALLMATCHES"),
-            (GeneralFlags::CL_SCAN_GENERAL_COLLECT_METADATA, "CL_SCAN_GENERAL_COLLECT_METADATA"),
-            (GeneralFlags::CL_SCAN_GENERAL_HEURISTICS, "CL_SCAN_GENERAL_HEURISTICS"),
-            (GeneralFlags::CL_SCAN_GENERAL_HEURISTIC_PRECEDENCE, "CL_SCAN_GENERAL_HEURISTIC_PRECEDENCE"),
-            (GeneralFlags::CL_SCAN_GENERAL_UNPRIVILEGED, "CL_SCAN_GENERAL_UNPRIVILEGED"),
-        ];
-        let parse_flags = vec![
-            (ParseFlags::CL_SCAN_PARSE_ARCHIVE, "CL_SCAN_PARSE_ARCHIVE"),
-            (ParseFlags::CL_SCAN_PARSE_ELF, "CL_SCAN_PARSE_ELF"),
-            (ParseFlags::CL_SCAN_PARSE_PDF, "CL_SCAN_PARSE_PDF"),
-            (ParseFlags::CL_SCAN_PARSE_SWF, "CL_SCAN_PARSE_SWF"),
-            (ParseFlags::CL_SCAN_PARSE_HWP3, "CL_SCAN_PARSE_HWP3"),
-            (ParseFlags::CL_SCAN_PARSE_XMLDOCS, "CL_SCAN_PARSE_XMLDOCS"),
-            (ParseFlags::CL_SCAN_PARSE_MAIL, "CL_SCAN_PARSE_MAIL"),
-            (ParseFlags::CL_SCAN_PARSE_OLE2, "CL_SCAN_PARSE_OLE2"),
-            (ParseFlags::CL_SCAN_PARSE_HTML, "CL_SCAN_PARSE_HTML"),
-            (ParseFlags::CL_SCAN_PARSE_PE, "CL_SCAN_PARSE_PE"),
-        ];
-        let heuristic_flags = vec![
-            (HeuristicFlags::CL_SCAN_HEURISTIC_BROKEN, "CL_SCAN_HEURISTIC_BROKEN"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_EXCEEDS_MAX, "CL_SCAN_HEURISTIC_EXCEEDS_MAX"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_PHISHING_SSL_MISMATCH, "CL_SCAN_HEURISTIC_PHISHING_SSL_MISMATCH"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_PHISHING_CLOAK, "CL_SCAN_HEURISTIC_PHISHING_CLOAK"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_MACROS, "CL_SCAN_HEURISTIC_MACROS"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE, "CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_DOC, "CL_SCAN_HEURISTIC_ENCRYPTED_DOC"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_PARTITION_INTXN, "CL_SCAN_HEURISTIC_PARTITION_INTXN"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_STRUCTURED, "CL_SCAN_HEURISTIC_STRUCTURED"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_STRUCTURED_SSN_NORMAL, "CL_SCAN_HEURISTIC_STRUCTURED_SSN_NORMAL"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_STRUCTURED_SSN_STRIPPED, "CL_SCAN_HEURISTIC_STRUCTURED_SSN_STRIPPED"),
-            (HeuristicFlags::CL_SCAN_HEURISTIC_STRUCTURED_CC, "CL_SCAN_HEURISTIC_STRUCTURED_CC"),
-        ];
-
-        let mail_flags = vec![
-            (MailFlags::CL_SCAN_MAIL_PARTIAL_MESSAGE, "CL_SCAN_MAIL_PARTIAL_MESSAGE"),
-        ]; 
-        
-        let dev_flags = vec![
-            (DevFlags::CL_SCAN_DEV_COLLECT_SHA, "CL_SCAN_DEV_COLLECT_SHA"),
-            (DevFlags::CL_SCAN_DEV_COLLECT_PERFORMANCE_INFO, "CL_SCAN_DEV_COLLECT_PERFORMANCE_INFO"),
-        ];
-
-        for (flag, name) in general_flags {
-            if self.general().contains(flag) {
-                flag_names.push(name.to_string());
+/// Generates `all_named` listing the flags of a group with their names
+macro_rules! named_flags {
+    ($flags:ident { $($name:ident),* $(,)? }) => {
+        impl $flags {
+            /// Returns every defined flag of this group with its name.
+            pub fn all_named() -> &'static [($flags, &'static str)] {
+                const ALL: &[($flags, &str)] = &[$(($flags::$name, stringify!($name))),*];
+                ALL
             }
         }
-        for (flag, name) in parse_flags {
-            if self.parse().contains(flag) {
-                flag_names.push(name.to_string());
-            }
+    };
+}
+
+named_flags!(GeneralFlags {
+    CL_SCAN_GENERAL_ALLMATCHES,
+    CL_SCAN_GENERAL_COLLECT_METADATA,
+    CL_SCAN_GENERAL_HEURISTICS,
+    CL_SCAN_GENERAL_HEURISTIC_PRECEDENCE,
+    CL_SCAN_GENERAL_UNPRIVILEGED,
+});
+
+named_flags!(ParseFlags {
+    CL_SCAN_PARSE_ARCHIVE,
+    CL_SCAN_PARSE_ELF,
+    CL_SCAN_PARSE_PDF,
+    CL_SCAN_PARSE_SWF,
+    CL_SCAN_PARSE_HWP3,
+    CL_SCAN_PARSE_XMLDOCS,
+    CL_SCAN_PARSE_MAIL,
+    CL_SCAN_PARSE_OLE2,
+    CL_SCAN_PARSE_HTML,
+    CL_SCAN_PARSE_PE,
+});
+
+named_flags!(HeuristicFlags {
+    CL_SCAN_HEURISTIC_BROKEN,
+    CL_SCAN_HEURISTIC_EXCEEDS_MAX,
+    CL_SCAN_HEURISTIC_PHISHING_SSL_MISMATCH,
+    CL_SCAN_HEURISTIC_PHISHING_CLOAK,
+    CL_SCAN_HEURISTIC_MACROS,
+    CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE,
+    CL_SCAN_HEURISTIC_ENCRYPTED_DOC,
+    CL_SCAN_HEURISTIC_PARTITION_INTXN,
+    CL_SCAN_HEURISTIC_STRUCTURED,
+    CL_SCAN_HEURISTIC_STRUCTURED_SSN_NORMAL,
+    CL_SCAN_HEURISTIC_STRUCTURED_SSN_STRIPPED,
+    CL_SCAN_HEURISTIC_STRUCTURED_CC,
+});
+
+named_flags!(MailFlags {
+    CL_SCAN_MAIL_PARTIAL_MESSAGE,
+});
+
+named_flags!(DevFlags {
+    CL_SCAN_DEV_COLLECT_SHA,
+    CL_SCAN_DEV_COLLECT_PERFORMANCE_INFO,
+});
+
+/// Lists the names of the flags of one group which are set in `bits`
+fn push_names<F: Copy>(names: &mut Vec<&'static str>, all_named: &[(F, &'static str)], bits: u32, flag_bits: fn(&F) -> u32) {
+    for &(flag, name) in all_named {
+        let flag = flag_bits(&flag);
+        if bits & flag == flag {
+            names.push(name);
         }
-        for (flag, name) in heuristic_flags {
-            if self.heuristic().contains(flag) {
-                flag_names.push(name.to_string());
+    }
+}
+
+/// Lists the set flags by name, separated by spaces
+impl fmt::Display for ScanSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = Vec::new();
+        push_names(&mut names, GeneralFlags::all_named(), self.settings.general, GeneralFlags::bits);
+        push_names(&mut names, ParseFlags::all_named(), self.settings.parse, ParseFlags::bits);
+        push_names(&mut names, HeuristicFlags::all_named(), self.settings.heuristic, HeuristicFlags::bits);
+        push_names(&mut names, MailFlags::all_named(), self.settings.mail, MailFlags::bits);
+        push_names(&mut names, DevFlags::all_named(), self.settings.dev, DevFlags::bits);
+        f.write_str(&names.join(" "))
+    }
+}
+
+/// Writes the set flags by name, e.g. `ScanSettings(CL_SCAN_PARSE_PDF)`
+impl fmt::Debug for ScanSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ScanSettings").field(&format_args!("{}", self)).finish()
+    }
+}
+
+/// Implements serde for a flag group as the list of the names of its set flags
+#[cfg(feature = "serde")]
+macro_rules! serde_flags {
+    ($($flags:ident),*) => {$(
+        impl serde::Serialize for $flags {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut names = Vec::new();
+                push_names(&mut names, $flags::all_named(), self.bits(), $flags::bits);
+                serializer.collect_seq(names)
             }
         }
-        for (flag, name) in mail_flags {
-            if self.mail().contains(flag) {
-                flag_names.push(name.to_string());
+
+        impl<'de> serde::Deserialize<'de> for $flags {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let mut flags = $flags::empty();
+                for name in Vec::<String>::deserialize(deserializer)? {
+                    match find_bits($flags::all_named(), &name, $flags::bits) {
+                        Some(bits) => flags |= $flags::from_bits_truncate(bits),
+                        None => return Err(serde::de::Error::custom(ParseSettingsError { name })),
+                    }
+                }
+                Ok(flags)
             }
         }
-        for (flag, name) in dev_flags {
-            if self.dev().contains(flag) {
-                flag_names.push(name.to_string());
-            }
+    )*};
+}
+
+#[cfg(feature = "serde")]
+serde_flags!(GeneralFlags, ParseFlags, HeuristicFlags, MailFlags, DevFlags);
+
+/// Serialized form of `ScanSettings`; a missing group has no flags set
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SettingsRepr {
+    #[serde(default = "GeneralFlags::empty")]
+    general: GeneralFlags,
+    #[serde(default = "ParseFlags::empty")]
+    parse: ParseFlags,
+    #[serde(default = "HeuristicFlags::empty")]
+    heuristic: HeuristicFlags,
+    #[serde(default = "MailFlags::empty")]
+    mail: MailFlags,
+    #[serde(default = "DevFlags::empty")]
+    dev: DevFlags,
+}
+
+/// Serializes each flag group as the list of the names of its set flags,
+/// e.g. `{"general": ["CL_SCAN_GENERAL_HEURISTICS"], "parse": [], ...}`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ScanSettings {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SettingsRepr {
+            general: GeneralFlags::from_bits_truncate(self.settings.general),
+            parse: ParseFlags::from_bits_truncate(self.settings.parse),
+            heuristic: HeuristicFlags::from_bits_truncate(self.settings.heuristic),
+            mail: MailFlags::from_bits_truncate(self.settings.mail),
+            dev: DevFlags::from_bits_truncate(self.settings.dev),
         }
+        .serialize(serializer)
+    }
+}
 
-        return flag_names.join(" ");
+/// Deserializes the format written by `Serialize`. Unknown flag names are
+/// an error, and flags which are not named are cleared.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ScanSettings {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = SettingsRepr::deserialize(deserializer)?;
+        Ok(ScanSettings {
+            settings: cl_scan_options {
+                general: repr.general.bits(),
+                parse: repr.parse.bits(),
+                heuristic: repr.heuristic.bits(),
+                mail: repr.mail.bits(),
+                dev: repr.dev.bits(),
+            },
+        })
+    }
+}
+
+/// A flag name not known to [`ScanSettings::from_str`]
+///
+/// [`ScanSettings::from_str`]: struct.ScanSettings.html#method.from_str
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSettingsError {
+    name: String,
+}
+
+impl fmt::Display for ParseSettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown scan option {}", self.name)
+    }
+}
+
+impl std::error::Error for ParseSettingsError {}
+
+fn find_bits<F: Copy>(all_named: &[(F, &str)], name: &str, flag_bits: fn(&F) -> u32) -> Option<u32> {
+    all_named
+        .iter()
+        .find(|(_, known)| *known == name)
+        .map(|(flag, _)| flag_bits(flag))
+}
+
+/// Parses the format written by `Display`: flag names separated by whitespace
+/// or commas, e.g. `CL_SCAN_PARSE_PDF,CL_SCAN_HEURISTIC_MACROS`.
+///
+/// Flags which are not named are cleared, so an empty string disables all options.
+impl FromStr for ScanSettings {
+    type Err = ParseSettingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = cl_scan_options {
+            general: 0,
+            parse: 0,
+            heuristic: 0,
+            mail: 0,
+            dev: 0,
+        };
+        for name in s.split(|c: char| c.is_whitespace() || c == ',').filter(|name| !name.is_empty()) {
+            if let Some(bits) = find_bits(GeneralFlags::all_named(), name, GeneralFlags::bits) {
+                settings.general |= bits;
+            } else if let Some(bits) = find_bits(ParseFlags::all_named(), name, ParseFlags::bits) {
+                settings.parse |= bits;
+            } else if let Some(bits) = find_bits(HeuristicFlags::all_named(), name, HeuristicFlags::bits) {
+                settings.heuristic |= bits;
+            } else if let Some(bits) = find_bits(MailFlags::all_named(), name, MailFlags::bits) {
+                settings.mail |= bits;
+            } else if let Some(bits) = find_bits(DevFlags::all_named(), name, DevFlags::bits) {
+                settings.dev |= bits;
+            } else {
+                return Err(ParseSettingsError { name: name.to_string() });
+            }
+        }
+        Ok(ScanSettings { settings })
     }
 }
 
@@ -222,6 +397,32 @@ impl ScanSettingsBuilder {
         }
     }
 
+    /// Starts from the preset for untrusted uploads: all common file formats
+    /// are parsed, heuristic alerts are on, and files exceeding a scan limit
+    /// or holding encrypted archives or documents are flagged.
+    ///
+    /// Use it together with tight engine limits, see
+    /// `Scanner::hardened_upload_profile`.
+    pub fn hardened_upload() -> Self {
+        let mut builder = Self::new();
+        builder
+            .enable_archive()
+            .enable_mail()
+            .enable_ole2()
+            .enable_html()
+            .enable_pe()
+            .enable_elf()
+            .enable_pdf()
+            .enable_swf()
+            .enable_xmldocs()
+            .enable_hwp3()
+            .block_max_limit()
+            .block_encrypted();
+        builder.current.general |= CL_SCAN_GENERAL_HEURISTICS;
+        builder.current.heuristic |= CL_SCAN_HEURISTIC_ENCRYPTED_DOC;
+        builder
+    }
+
     pub fn build(&self) -> ScanSettings {
         ScanSettings {
             settings: self.current,
@@ -254,7 +455,7 @@ impl ScanSettingsBuilder {
 
     /// With this flag the library will mark encrypted archives as viruses (Encrypted.Zip, Encrypted.RAR).
     pub fn block_encrypted(&mut self) -> &mut Self {
-        self.current.heuristic |= CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE | CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE;
+        self.current.heuristic |= CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE;
         self
     }
 
@@ -344,6 +545,15 @@ impl ScanSettingsBuilder {
         self
     }
 
+    /// Continue scanning after the first match and report every match.
+    ///
+    /// The scan functions still return a single name, the last one found;
+    /// libclamav keeps ownership of all names in this mode as well.
+    pub fn enable_all_matches(&mut self) -> &mut Self {
+        self.current.general |= CL_SCAN_GENERAL_ALLMATCHES;
+        self
+    }
+
     /// OLE2 containers, which contain VBA macros will be marked infected (Heuris-tics.OLE2.ContainsMacros).
     pub fn block_macros(&mut self) -> &mut Self {
         self.current.heuristic |= CL_SCAN_HEURISTIC_MACROS;
@@ -367,11 +577,201 @@ impl ScanSettingsBuilder {
         self.current.parse |= CL_SCAN_PARSE_HWP3;
         self
     }
+
+    /// Disable scanning of archives, see [`enable_archive`](#method.enable_archive).
+    pub fn disable_archive(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_ARCHIVE;
+        self
+    }
+
+    /// Disable support for mail files, see [`enable_mail`](#method.enable_mail).
+    pub fn disable_mail(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_MAIL;
+        self
+    }
+
+    /// Disable support for OLE2 containers, see [`enable_ole2`](#method.enable_ole2).
+    pub fn disable_ole2(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_OLE2;
+        self
+    }
+
+    /// Disable HTML normalisation, see [`enable_html`](#method.enable_html).
+    pub fn disable_html(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_HTML;
+        self
+    }
+
+    /// Disable deep scanning of Portable Executable files, see [`enable_pe`](#method.enable_pe).
+    pub fn disable_pe(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_PE;
+        self
+    }
+
+    /// Disable support for ELF files, see [`enable_elf`](#method.enable_elf).
+    pub fn disable_elf(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_ELF;
+        self
+    }
+
+    /// Disable scanning within PDF files, see [`enable_pdf`](#method.enable_pdf).
+    pub fn disable_pdf(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_PDF;
+        self
+    }
+
+    /// Disable scanning within SWF files, see [`enable_swf`](#method.enable_swf).
+    pub fn disable_swf(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_SWF;
+        self
+    }
+
+    /// Disable scanning of XML docs, see [`enable_xmldocs`](#method.enable_xmldocs).
+    pub fn disable_xmldocs(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_XMLDOCS;
+        self
+    }
+
+    /// Disable scanning of HWP3 files, see [`enable_hwp3`](#method.enable_hwp3).
+    pub fn disable_hwp3(&mut self) -> &mut Self {
+        self.current.parse &= !CL_SCAN_PARSE_HWP3;
+        self
+    }
+
+    /// Enable heuristic alerts, such as those of the phishing module and of
+    /// the `block_*` options.
+    pub fn enable_heuristics(&mut self) -> &mut Self {
+        self.current.general |= CL_SCAN_GENERAL_HEURISTICS;
+        self
+    }
+
+    /// Collect metadata of the scanned file and the objects within, as
+    /// written by `clamscan --gen-json`.
+    pub fn collect_metadata(&mut self) -> &mut Self {
+        self.current.general |= CL_SCAN_GENERAL_COLLECT_METADATA;
+        self
+    }
+
+    /// Tell libclamav that the scanner has no read access to the scanned
+    /// files, so it does not open them by name.
+    pub fn unprivileged(&mut self) -> &mut Self {
+        self.current.general |= CL_SCAN_GENERAL_UNPRIVILEGED;
+        self
+    }
+
+    /// Collect the time libclamav spends in each parser.
+    ///
+    /// The timings are attached to the `ScanOutcome` of the scan as `PerfInfo`.
+    pub fn collect_performance_info(&mut self) -> &mut Self {
+        self.current.dev |= CL_SCAN_DEV_COLLECT_PERFORMANCE_INFO;
+        self
+    }
+}
+
+/// Changes to the general and heuristic flags of a [`ScanSettings`] for a
+/// single scan
+///
+/// One shared settings object can serve all scans, with requests needing a
+/// variation passing overrides instead of building new settings, e.g. to
+/// rescan a file in all-match mode once it was flagged:
+///
+/// ```
+/// use clamav_rs::scan_settings::{Overrides, ScanSettings};
+///
+/// let base = ScanSettings::default();
+/// let rescan = Overrides::new().all_matches(true).heuristics(false).apply(&base);
+/// assert_ne!(rescan.to_string(), base.to_string());
+/// ```
+///
+/// Flags not mentioned keep the value of the base settings. Setting the
+/// same flag twice keeps the last value. Outcome-returning scan functions
+/// of `Engine` take overrides in their `_with` variants, e.g.
+/// `Engine::scan_file_outcome_with`.
+///
+/// [`ScanSettings`]: struct.ScanSettings.html
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Overrides {
+    general_set: u32,
+    general_clear: u32,
+    heuristic_set: u32,
+    heuristic_clear: u32,
+}
+
+impl Overrides {
+    /// Overrides changing nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn general(mut self, flag: u32, enabled: bool) -> Self {
+        if enabled {
+            self.general_set |= flag;
+            self.general_clear &= !flag;
+        } else {
+            self.general_clear |= flag;
+            self.general_set &= !flag;
+        }
+        self
+    }
+
+    /// Sets `CL_SCAN_GENERAL_ALLMATCHES`, see
+    /// `ScanSettingsBuilder::enable_all_matches`.
+    pub fn all_matches(self, enabled: bool) -> Self {
+        self.general(CL_SCAN_GENERAL_ALLMATCHES, enabled)
+    }
+
+    /// Sets `CL_SCAN_GENERAL_COLLECT_METADATA`.
+    pub fn collect_metadata(self, enabled: bool) -> Self {
+        self.general(CL_SCAN_GENERAL_COLLECT_METADATA, enabled)
+    }
+
+    /// Sets `CL_SCAN_GENERAL_HEURISTICS`, which enables heuristic alerts.
+    pub fn heuristics(self, enabled: bool) -> Self {
+        self.general(CL_SCAN_GENERAL_HEURISTICS, enabled)
+    }
+
+    /// Sets `CL_SCAN_GENERAL_HEURISTIC_PRECEDENCE`, see
+    /// `ScanSettingsBuilder::enable_heuristic_precedence`.
+    pub fn heuristic_precedence(self, enabled: bool) -> Self {
+        self.general(CL_SCAN_GENERAL_HEURISTIC_PRECEDENCE, enabled)
+    }
+
+    /// Sets `CL_SCAN_GENERAL_UNPRIVILEGED`.
+    pub fn unprivileged(self, enabled: bool) -> Self {
+        self.general(CL_SCAN_GENERAL_UNPRIVILEGED, enabled)
+    }
+
+    /// Sets the heuristic alerts in `flags`, e.g.
+    /// `HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE`.
+    pub fn heuristic_alerts(mut self, flags: HeuristicFlags, enabled: bool) -> Self {
+        if enabled {
+            self.heuristic_set |= flags.bits();
+            self.heuristic_clear &= !flags.bits();
+        } else {
+            self.heuristic_clear |= flags.bits();
+            self.heuristic_set &= !flags.bits();
+        }
+        self
+    }
+
+    /// Returns whether the overrides change nothing.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns a copy of `base` with the overrides applied.
+    pub fn apply(&self, base: &ScanSettings) -> ScanSettings {
+        let mut settings = base.clone();
+        settings.settings.general = (settings.settings.general & !self.general_clear) | self.general_set;
+        settings.settings.heuristic = (settings.settings.heuristic & !self.heuristic_clear) | self.heuristic_set;
+        settings
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quickcheck::quickcheck;
 
     #[test]
     fn builder_defaults_to_standard_opts() {
@@ -379,6 +779,18 @@ mod tests {
         assert_eq!(settings.settings, clamav_sys::cl_scan_options::default());
     }
 
+    #[test]
+    fn hardened_upload_preset() {
+        let settings = ScanSettingsBuilder::hardened_upload().build();
+        assert!(settings.general().contains(GeneralFlags::CL_SCAN_GENERAL_HEURISTICS));
+        assert!(settings.heuristic().contains(
+            HeuristicFlags::CL_SCAN_HEURISTIC_EXCEEDS_MAX
+                | HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE
+                | HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_DOC
+        ));
+        assert!(settings.parse().contains(ParseFlags::CL_SCAN_PARSE_ARCHIVE | ParseFlags::CL_SCAN_PARSE_PDF));
+    }
+
     #[test]
     fn builder_clear_success() {
         let settings = ScanSettingsBuilder::new().clear().build();
@@ -412,16 +824,22 @@ mod tests {
     #[test]
     fn display_settings_standard_options_success() {
         let string_settings = ScanSettings::default().to_string();
-        assert!(string_settings.contains("CL_SCAN_PARSE_ARCHIVE"));
-        assert!(string_settings.contains("CL_SCAN_PARSE_MAIL"));
-        assert!(string_settings.contains("CL_SCAN_PARSE_OLE2"));
-        assert!(string_settings.contains("CL_SCAN_PARSE_PDF"));
-        assert!(string_settings.contains("CL_SCAN_PARSE_HTML"));
-        assert!(string_settings.contains("CL_SCAN_PARSE_PE"));
-        assert!(string_settings.contains("CL_SCAN_PARSE_ELF"));
-        assert!(string_settings.contains("CL_SCAN_PARSE_SWF"));
-        assert!(string_settings.contains("CL_SCAN_PARSE_XMLDOCS"));
-        assert!(string_settings.contains("CL_SCAN_Parse_HWP3"));
+        // whole names, so that e.g. CL_SCAN_PARSE_PE is not found in a longer name
+        let names: Vec<&str> = string_settings.split(' ').collect();
+        for name in &[
+            "CL_SCAN_PARSE_ARCHIVE",
+            "CL_SCAN_PARSE_MAIL",
+            "CL_SCAN_PARSE_OLE2",
+            "CL_SCAN_PARSE_PDF",
+            "CL_SCAN_PARSE_HTML",
+            "CL_SCAN_PARSE_PE",
+            "CL_SCAN_PARSE_ELF",
+            "CL_SCAN_PARSE_SWF",
+            "CL_SCAN_PARSE_XMLDOCS",
+            "CL_SCAN_PARSE_HWP3",
+        ] {
+            assert!(names.contains(name), "{} missing in {:?}", name, names);
+        }
     }
 
     #[test]
@@ -429,4 +847,257 @@ mod tests {
         let settings: ScanSettings = Default::default();
         assert_eq!(settings.settings, cl_scan_options::default());
     }
+
+    fn zeroed() -> cl_scan_options {
+        cl_scan_options {
+            general: 0,
+            parse: 0,
+            heuristic: 0,
+            mail: 0,
+            dev: 0,
+        }
+    }
+
+    /// Settings with the defined flags of the given bits set
+    fn known_settings(general: u32, parse: u32, heuristic: u32, mail: u32, dev: u32) -> ScanSettings {
+        let mut settings = ScanSettings { settings: zeroed() };
+        settings.set_general(GeneralFlags::from_bits_truncate(general));
+        settings.set_parse(ParseFlags::from_bits_truncate(parse));
+        settings.set_heuristic(HeuristicFlags::from_bits_truncate(heuristic));
+        settings.set_mail(MailFlags::from_bits_truncate(mail));
+        settings.set_dev(DevFlags::from_bits_truncate(dev));
+        settings
+    }
+
+    fn check_group<F: Copy>(all_named: &[(F, &str)], flag_bits: fn(&F) -> u32, all: u32) {
+        let mut seen_bits = 0;
+        for (i, &(flag, name)) in all_named.iter().enumerate() {
+            let bits = flag_bits(&flag);
+            assert_eq!(bits.count_ones(), 1, "{} should be a single bit", name);
+            assert_eq!(seen_bits & bits, 0, "{} is listed twice", name);
+            seen_bits |= bits;
+            assert!(
+                all_named[i + 1..].iter().all(|&(_, other)| other != name),
+                "{} is named twice",
+                name
+            );
+        }
+        assert_eq!(seen_bits, all, "every flag should be named");
+    }
+
+    #[test]
+    fn named_flags_are_complete_and_unique() {
+        check_group(GeneralFlags::all_named(), GeneralFlags::bits, GeneralFlags::all().bits());
+        check_group(ParseFlags::all_named(), ParseFlags::bits, ParseFlags::all().bits());
+        check_group(HeuristicFlags::all_named(), HeuristicFlags::bits, HeuristicFlags::all().bits());
+        check_group(MailFlags::all_named(), MailFlags::bits, MailFlags::all().bits());
+        check_group(DevFlags::all_named(), DevFlags::bits, DevFlags::all().bits());
+    }
+
+    #[test]
+    fn accessors_read_set_bits() {
+        fn prop(general: u32, parse: u32, heuristic: u32, mail: u32, dev: u32) -> bool {
+            let settings = known_settings(general, parse, heuristic, mail, dev);
+            settings.general() == GeneralFlags::from_bits_truncate(general)
+                && settings.parse() == ParseFlags::from_bits_truncate(parse)
+                && settings.heuristic() == HeuristicFlags::from_bits_truncate(heuristic)
+                && settings.mail() == MailFlags::from_bits_truncate(mail)
+                && settings.dev() == DevFlags::from_bits_truncate(dev)
+        }
+        quickcheck(prop as fn(u32, u32, u32, u32, u32) -> bool);
+    }
+
+    #[test]
+    fn display_from_str_round_trips() {
+        fn prop(general: u32, parse: u32, heuristic: u32, mail: u32, dev: u32) -> bool {
+            let settings = known_settings(general, parse, heuristic, mail, dev);
+            match settings.to_string().parse::<ScanSettings>() {
+                Ok(parsed) => parsed.settings == settings.settings,
+                Err(_) => false,
+            }
+        }
+        quickcheck(prop as fn(u32, u32, u32, u32, u32) -> bool);
+    }
+
+    #[test]
+    fn builder_sets_documented_bits() {
+        type Setter = fn(&mut ScanSettingsBuilder) -> &mut ScanSettingsBuilder;
+        fn setters() -> Vec<(Setter, ScanSettings)> {
+            vec![
+                (ScanSettingsBuilder::enable_archive, known_settings(0, CL_SCAN_PARSE_ARCHIVE, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_mail, known_settings(0, CL_SCAN_PARSE_MAIL, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_ole2, known_settings(0, CL_SCAN_PARSE_OLE2, 0, 0, 0)),
+                (ScanSettingsBuilder::block_encrypted, known_settings(0, 0, CL_SCAN_HEURISTIC_ENCRYPTED_ARCHIVE, 0, 0)),
+                (ScanSettingsBuilder::enable_html, known_settings(0, CL_SCAN_PARSE_HTML, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_pe, known_settings(0, CL_SCAN_PARSE_PE, 0, 0, 0)),
+                (ScanSettingsBuilder::block_broken_executables, known_settings(0, 0, CL_SCAN_HEURISTIC_BROKEN, 0, 0)),
+                (ScanSettingsBuilder::block_max_limit, known_settings(0, 0, CL_SCAN_HEURISTIC_EXCEEDS_MAX, 0, 0)),
+                (ScanSettingsBuilder::enable_phishing_blockssl, known_settings(0, 0, CL_SCAN_HEURISTIC_PHISHING_SSL_MISMATCH, 0, 0)),
+                (ScanSettingsBuilder::enable_phishing_blockcloak, known_settings(0, 0, CL_SCAN_HEURISTIC_PHISHING_CLOAK, 0, 0)),
+                (ScanSettingsBuilder::enable_elf, known_settings(0, CL_SCAN_PARSE_ELF, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_pdf, known_settings(0, CL_SCAN_PARSE_PDF, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_structured, known_settings(0, 0, CL_SCAN_HEURISTIC_STRUCTURED, 0, 0)),
+                (ScanSettingsBuilder::enable_structured_ssn_normal, known_settings(0, 0, CL_SCAN_HEURISTIC_STRUCTURED_SSN_NORMAL, 0, 0)),
+                (ScanSettingsBuilder::enable_structured_ssn_stripped, known_settings(0, 0, CL_SCAN_HEURISTIC_STRUCTURED_SSN_STRIPPED, 0, 0)),
+                (ScanSettingsBuilder::enable_partial_message, known_settings(0, 0, 0, CL_SCAN_MAIL_PARTIAL_MESSAGE, 0)),
+                (ScanSettingsBuilder::enable_heuristic_precedence, known_settings(CL_SCAN_GENERAL_HEURISTIC_PRECEDENCE, 0, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_all_matches, known_settings(CL_SCAN_GENERAL_ALLMATCHES, 0, 0, 0, 0)),
+                (ScanSettingsBuilder::block_macros, known_settings(0, 0, CL_SCAN_HEURISTIC_MACROS, 0, 0)),
+                (ScanSettingsBuilder::enable_swf, known_settings(0, CL_SCAN_PARSE_SWF, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_xmldocs, known_settings(0, CL_SCAN_PARSE_XMLDOCS, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_hwp3, known_settings(0, CL_SCAN_PARSE_HWP3, 0, 0, 0)),
+                (ScanSettingsBuilder::enable_heuristics, known_settings(CL_SCAN_GENERAL_HEURISTICS, 0, 0, 0, 0)),
+                (ScanSettingsBuilder::collect_metadata, known_settings(CL_SCAN_GENERAL_COLLECT_METADATA, 0, 0, 0, 0)),
+                (ScanSettingsBuilder::unprivileged, known_settings(CL_SCAN_GENERAL_UNPRIVILEGED, 0, 0, 0, 0)),
+                (ScanSettingsBuilder::collect_performance_info, known_settings(0, 0, 0, 0, CL_SCAN_DEV_COLLECT_PERFORMANCE_INFO)),
+            ]
+        }
+        fn prop(selection: u32) -> bool {
+            let mut builder = ScanSettingsBuilder { current: zeroed() };
+            let mut expected = zeroed();
+            for (i, (set, bits)) in setters().into_iter().enumerate() {
+                if selection & (1 << i) != 0 {
+                    set(&mut builder);
+                    expected.general |= bits.settings.general;
+                    expected.parse |= bits.settings.parse;
+                    expected.heuristic |= bits.settings.heuristic;
+                    expected.mail |= bits.settings.mail;
+                    expected.dev |= bits.settings.dev;
+                }
+            }
+            builder.build().settings == expected
+        }
+        quickcheck(prop as fn(u32) -> bool);
+    }
+
+    #[test]
+    fn clone_keeps_bits_and_debug_names_flags() {
+        let template = ScanSettingsBuilder::new().enable_all_matches().disable_pe().build();
+        let copy = template.clone();
+        assert_eq!(copy.settings, template.settings);
+        let settings = known_settings(0, CL_SCAN_PARSE_PDF | CL_SCAN_PARSE_HWP3, 0, 0, 0);
+        assert_eq!(format!("{:?}", settings), "ScanSettings(CL_SCAN_PARSE_PDF CL_SCAN_PARSE_HWP3)");
+    }
+
+    #[test]
+    fn union_and_difference_combine_groups() {
+        let pdf = known_settings(CL_SCAN_GENERAL_ALLMATCHES, CL_SCAN_PARSE_PDF, 0, 0, 0);
+        let html = known_settings(0, CL_SCAN_PARSE_HTML, CL_SCAN_HEURISTIC_MACROS, 0, CL_SCAN_DEV_COLLECT_SHA);
+        let both = pdf.union(&html);
+        assert_eq!(
+            both.settings,
+            known_settings(
+                CL_SCAN_GENERAL_ALLMATCHES,
+                CL_SCAN_PARSE_PDF | CL_SCAN_PARSE_HTML,
+                CL_SCAN_HEURISTIC_MACROS,
+                0,
+                CL_SCAN_DEV_COLLECT_SHA
+            )
+            .settings
+        );
+        assert_eq!(both.difference(&html).settings, pdf.settings);
+        assert_eq!(pdf.difference(&pdf).settings, zeroed());
+    }
+
+    #[test]
+    fn disable_clears_one_parse_flag() {
+        let standard = ScanSettingsBuilder::new().build();
+        assert_ne!(standard.settings.parse & CL_SCAN_PARSE_PE, 0);
+        let settings = ScanSettingsBuilder::new().disable_pe().build();
+        assert_eq!(settings.settings.parse, standard.settings.parse & !CL_SCAN_PARSE_PE);
+        assert_eq!(settings.settings.heuristic, standard.settings.heuristic);
+        assert_eq!(settings.settings.general, standard.settings.general);
+
+        let settings = ScanSettingsBuilder::new().enable_pdf().disable_pdf().disable_archive().build();
+        assert_eq!(settings.settings.parse & (CL_SCAN_PARSE_PDF | CL_SCAN_PARSE_ARCHIVE), 0);
+    }
+
+    #[test]
+    fn overrides_change_a_copy_only() {
+        let base = ScanSettingsBuilder::hardened_upload().build();
+        let before = base.settings;
+        let overrides = Overrides::new()
+            .all_matches(true)
+            .heuristics(false)
+            .heuristic_alerts(HeuristicFlags::CL_SCAN_HEURISTIC_MACROS, true)
+            .heuristic_alerts(HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_DOC, false);
+        let applied = overrides.apply(&base);
+
+        assert_eq!(base.settings, before);
+        assert_eq!(applied.general(), (base.general() | GeneralFlags::CL_SCAN_GENERAL_ALLMATCHES) - GeneralFlags::CL_SCAN_GENERAL_HEURISTICS);
+        assert_eq!(
+            applied.heuristic(),
+            (base.heuristic() | HeuristicFlags::CL_SCAN_HEURISTIC_MACROS) - HeuristicFlags::CL_SCAN_HEURISTIC_ENCRYPTED_DOC
+        );
+        assert_eq!((applied.settings.parse, applied.settings.mail, applied.settings.dev), (before.parse, before.mail, before.dev));
+    }
+
+    #[test]
+    fn later_overrides_win() {
+        let base = ScanSettingsBuilder::new().clear().build();
+        let overrides = Overrides::new().heuristics(true).heuristics(false).all_matches(false).all_matches(true);
+        assert_eq!(overrides.apply(&base).general() & GeneralFlags::CL_SCAN_GENERAL_HEURISTICS, GeneralFlags::empty());
+        assert!(overrides.apply(&base).general().contains(GeneralFlags::CL_SCAN_GENERAL_ALLMATCHES));
+        assert!(Overrides::new().is_empty());
+        assert_eq!(Overrides::new().apply(&base).settings, base.settings);
+    }
+
+    #[test]
+    fn from_str_rejects_unknown_names() {
+        let result = "CL_SCAN_PARSE_PDF CL_SCAN_Parse_HWP3".parse::<ScanSettings>();
+        assert_eq!(
+            result.err().map(|e| e.to_string()),
+            Some("unknown scan option CL_SCAN_Parse_HWP3".to_string())
+        );
+        let empty: ScanSettings = "".parse().unwrap();
+        assert_eq!(empty.settings, zeroed());
+    }
+
+    #[test]
+    fn from_str_accepts_commas() {
+        let parsed: ScanSettings = "CL_SCAN_PARSE_PDF,CL_SCAN_HEURISTIC_MACROS, CL_SCAN_PARSE_HWP3".parse().unwrap();
+        let expected = known_settings(
+            0,
+            CL_SCAN_PARSE_PDF | CL_SCAN_PARSE_HWP3,
+            CL_SCAN_HEURISTIC_MACROS,
+            0,
+            0,
+        );
+        assert_eq!(parsed.settings, expected.settings);
+        assert_eq!(parsed.to_string(), "CL_SCAN_PARSE_PDF CL_SCAN_PARSE_HWP3 CL_SCAN_HEURISTIC_MACROS");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trips() {
+        let settings = ScanSettings::default();
+        let json = serde_json::to_string(&settings).unwrap();
+        let parsed: ScanSettings = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.settings, settings.settings);
+
+        fn prop(general: u32, parse: u32, heuristic: u32, mail: u32, dev: u32) -> bool {
+            let settings = known_settings(general, parse, heuristic, mail, dev);
+            let json = serde_json::to_string(&settings).unwrap();
+            match serde_json::from_str::<ScanSettings>(&json) {
+                Ok(parsed) => parsed.settings == settings.settings,
+                Err(_) => false,
+            }
+        }
+        quickcheck(prop as fn(u32, u32, u32, u32, u32) -> bool);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_uses_flag_names() {
+        let settings = known_settings(CL_SCAN_GENERAL_HEURISTICS, CL_SCAN_PARSE_PDF, 0, 0, 0);
+        assert_eq!(
+            serde_json::to_string(&settings).unwrap(),
+            r#"{"general":["CL_SCAN_GENERAL_HEURISTICS"],"parse":["CL_SCAN_PARSE_PDF"],"heuristic":[],"mail":[],"dev":[]}"#
+        );
+
+        let parsed: ScanSettings = serde_json::from_str(r#"{"parse": ["CL_SCAN_PARSE_PDF"]}"#).unwrap();
+        assert_eq!(parsed.settings, known_settings(0, CL_SCAN_PARSE_PDF, 0, 0, 0).settings);
+        let err = serde_json::from_str::<ScanSettings>(r#"{"parse": ["CL_SCAN_PARSE_PFD"]}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown scan option CL_SCAN_PARSE_PFD"), "{}", err);
+    }
 }
@@ -0,0 +1,1056 @@
+//! One object holding everything needed to scan.
+//!
+//! Most programs load one database directory, pick one set of scan options
+//! and scan with them for their whole lifetime. A [`Scanner`] bundles the
+//! compiled engine with the options, so only one value has to be passed
+//! around, e.g. as web framework state.
+//!
+//! [`Scanner`]: struct.Scanner.html
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use clamav_sys::cl_error_t;
+
+use crate::buffer::{BufferBudget, BufferLimits};
+use crate::control::{Controlled, ScannerControl};
+use crate::db;
+use crate::engine::{EncryptionKind, Engine, LimitKind, NotScannedReason, ScanOutcome, ScanResult};
+use crate::error::{ClamError, EngineStateError};
+use crate::fmap::Fmap;
+use crate::policy::{self, SuppressionList};
+use crate::request::{ScanError, ScanRequest};
+use crate::scan_settings::{ScanSettings, ScanSettingsBuilder};
+use crate::shared::SharedEngine;
+use crate::temp::TempStrategy;
+
+/// Everything needed to build the engine again on reload
+#[derive(Clone)]
+struct Config {
+    database_dir: String,
+    max_filesize: Option<u64>,
+    max_scansize: Option<u64>,
+    max_files: Option<u32>,
+    max_recursion: Option<u32>,
+    temp_strategy: Option<Arc<dyn TempStrategy>>,
+    temp_quota: Option<u64>,
+    allow_missing_database: bool,
+}
+
+impl Config {
+    fn build_engine(&self) -> Result<Engine, ClamError> {
+        let engine = self.configure_engine()?;
+        engine.load_databases(&self.database_dir)?;
+        engine.compile()?;
+        Ok(engine)
+    }
+
+    /// Builds an engine without databases, for a degraded scanner.
+    fn build_empty_engine(&self) -> Result<Engine, ClamError> {
+        let engine = self.configure_engine()?;
+        engine.compile()?;
+        Ok(engine)
+    }
+
+    fn configure_engine(&self) -> Result<Engine, ClamError> {
+        let engine = Engine::new()?;
+        if let Some(strategy) = &self.temp_strategy {
+            engine.set_temp_strategy(strategy.clone());
+        }
+        engine.set_temp_quota(self.temp_quota);
+        if let Some(max_filesize) = self.max_filesize {
+            engine.set_max_filesize(max_filesize)?;
+        }
+        if let Some(max_scansize) = self.max_scansize {
+            engine.set_max_scansize(max_scansize)?;
+        }
+        if let Some(max_files) = self.max_files {
+            engine.set_max_files(max_files)?;
+        }
+        if let Some(max_recursion) = self.max_recursion {
+            engine.set_max_recursion(max_recursion)?;
+        }
+        Ok(engine)
+    }
+
+    /// Returns false if databases are missing and that is allowed.
+    ///
+    /// A database file, or a directory which cannot be read, counts as
+    /// present, so loading reports what is wrong with it.
+    fn databases_present(&self) -> bool {
+        let path = Path::new(&self.database_dir);
+        !self.allow_missing_database || path.is_file() || db::contains_databases(path).unwrap_or(true)
+    }
+}
+
+/// Builder for a [`Scanner`]
+///
+/// [`Scanner`]: struct.Scanner.html
+#[derive(Default)]
+pub struct ScannerBuilder {
+    database_dir: Option<String>,
+    settings: ScanSettings,
+    max_filesize: Option<u64>,
+    max_scansize: Option<u64>,
+    max_files: Option<u32>,
+    max_recursion: Option<u32>,
+    report_limits: bool,
+    classify_encrypted: bool,
+    allow_missing_database: bool,
+    fail_degraded_scans: bool,
+    suppressions: Option<Arc<SuppressionList>>,
+    temp_strategy: Option<Arc<dyn TempStrategy>>,
+    temp_quota: Option<u64>,
+    buffer_limits: BufferLimits,
+}
+
+impl ScannerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory or file the databases are loaded from. Required.
+    pub fn database_dir(&mut self, path: &str) -> &mut Self {
+        self.database_dir = Some(path.to_string());
+        self
+    }
+
+    /// Sets the scan options. Defaults to `ScanSettings::default()`.
+    pub fn settings(&mut self, settings: ScanSettings) -> &mut Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Sets the size of the largest file to scan, see `Engine::set_max_filesize`.
+    /// Defaults to the libclamav default.
+    pub fn max_filesize(&mut self, bytes: u64) -> &mut Self {
+        self.max_filesize = Some(bytes);
+        self
+    }
+
+    /// Sets the number of bytes scanned per file, see `Engine::set_max_scansize`.
+    /// Defaults to the libclamav default.
+    pub fn max_scansize(&mut self, bytes: u64) -> &mut Self {
+        self.max_scansize = Some(bytes);
+        self
+    }
+
+    /// Sets the number of files extracted per file, see `Engine::set_max_files`.
+    /// Defaults to the libclamav default.
+    pub fn max_files(&mut self, files: u32) -> &mut Self {
+        self.max_files = Some(files);
+        self
+    }
+
+    /// Sets the nesting depth of containers, see `Engine::set_max_recursion`.
+    /// Defaults to the libclamav default.
+    pub fn max_recursion(&mut self, depth: u32) -> &mut Self {
+        self.max_recursion = Some(depth);
+        self
+    }
+
+    /// Reports exceeded limits as `ScanResult::Suspicious`, see
+    /// `LimitKind::classify`. Defaults to false, reporting them as libclamav
+    /// does: as `Heuristics.Limits.Exceeded` detections or as errors.
+    pub fn report_limits(&mut self, report: bool) -> &mut Self {
+        self.report_limits = report;
+        self
+    }
+
+    /// Reports encrypted archives and documents as `ScanResult::Encrypted`,
+    /// see `EncryptionKind::classify`, so callers can ask for a password
+    /// without matching signature names. Only has an effect with the
+    /// `ENCRYPTED_ARCHIVE` or `ENCRYPTED_DOC` heuristics enabled in the scan
+    /// settings. Defaults to false, reporting them as libclamav does: as
+    /// `Heuristics.Encrypted` detections.
+    pub fn classify_encrypted(&mut self, classify: bool) -> &mut Self {
+        self.classify_encrypted = classify;
+        self
+    }
+
+    /// Builds a degraded scanner instead of failing if the database
+    /// directory is missing or holds no databases, e.g. before freshclam
+    /// first ran.
+    ///
+    /// A degraded scanner does not scan: it reports every file as
+    /// `ScanResult::NotScanned(NotScannedReason::NoDatabase)`, or fails, see
+    /// [`fail_degraded_scans`]. [`Scanner::reload`] loads the databases once
+    /// they appear and the scanner, with all its clones, scans normally from
+    /// then on. See [`Scanner::is_degraded`]. Defaults to false.
+    ///
+    /// [`fail_degraded_scans`]: #method.fail_degraded_scans
+    /// [`Scanner::reload`]: struct.Scanner.html#method.reload
+    /// [`Scanner::is_degraded`]: struct.Scanner.html#method.is_degraded
+    pub fn allow_missing_database(&mut self, allow: bool) -> &mut Self {
+        self.allow_missing_database = allow;
+        self
+    }
+
+    /// Makes scans of a degraded scanner fail with
+    /// `EngineStateError::NoDatabase` instead of reporting the file as not
+    /// scanned. Defaults to false.
+    pub fn fail_degraded_scans(&mut self, fail: bool) -> &mut Self {
+        self.fail_degraded_scans = fail;
+        self
+    }
+
+    /// Sets the suppression list applied to every scan. Keep a clone of the
+    /// `Arc` to reload the list later. Defaults to no suppressions.
+    pub fn suppressions(&mut self, suppressions: Arc<SuppressionList>) -> &mut Self {
+        self.suppressions = Some(suppressions);
+        self
+    }
+
+    /// Sets how temporary files are created, see `Engine::set_temp_strategy`.
+    /// Defaults to `DefaultTempStrategy::new()`.
+    pub fn temp_strategy(&mut self, strategy: Arc<dyn TempStrategy>) -> &mut Self {
+        self.temp_strategy = Some(strategy);
+        self
+    }
+
+    /// Limits the bytes held in temporary files, see `Engine::set_temp_quota`.
+    /// Defaults to no limit.
+    pub fn temp_quota(&mut self, bytes: u64) -> &mut Self {
+        self.temp_quota = Some(bytes);
+        self
+    }
+
+    /// Limits the bytes buffered by [`Scanner::scan_reader`], per scan and
+    /// across all clones of the scanner. Defaults to no limits.
+    ///
+    /// [`Scanner::scan_reader`]: struct.Scanner.html#method.scan_reader
+    pub fn buffer_limits(&mut self, limits: BufferLimits) -> &mut Self {
+        self.buffer_limits = limits;
+        self
+    }
+
+    /// Loads and compiles the databases.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_ENULLARG` if no database directory was set, otherwise the
+    /// error of configuring, loading or compiling the engine. Missing
+    /// databases are no error with [`allow_missing_database`].
+    ///
+    /// [`allow_missing_database`]: #method.allow_missing_database
+    pub fn build(&self) -> Result<Scanner, ClamError> {
+        let database_dir = self
+            .database_dir
+            .clone()
+            .ok_or_else(|| ClamError::new(cl_error_t::CL_ENULLARG))?;
+        let config = Config {
+            database_dir,
+            max_filesize: self.max_filesize,
+            max_scansize: self.max_scansize,
+            max_files: self.max_files,
+            max_recursion: self.max_recursion,
+            temp_strategy: self.temp_strategy.clone(),
+            temp_quota: self.temp_quota,
+            allow_missing_database: self.allow_missing_database,
+        };
+        let degraded = !config.databases_present();
+        let engine = if degraded {
+            config.build_empty_engine()?
+        } else {
+            config.build_engine()?
+        };
+        Ok(Scanner {
+            engine: SharedEngine::new(engine),
+            degraded: Arc::new(AtomicBool::new(degraded)),
+            settings: self.settings.clone(),
+            published_settings: Arc::new(Mutex::new(PublishedSettings::default())),
+            settings_generation: 0,
+            config: Arc::new(config),
+            report_limits: self.report_limits,
+            classify_encrypted: self.classify_encrypted,
+            fail_degraded_scans: self.fail_degraded_scans,
+            suppressions: self.suppressions.clone(),
+            buffers: BufferBudget::new(self.buffer_limits),
+        })
+    }
+}
+
+/// Scan options set through a `ScannerControl`
+#[derive(Default)]
+struct PublishedSettings {
+    settings: ScanSettings,
+    /// Number of times options were set, 0 if never
+    generation: u64,
+}
+
+/// Compiled engine and scan options
+///
+/// The scanner is cheap to clone. Clones share the engine, so [`reload`] on
+/// any clone is seen by all of them, but each clone has its own scan options,
+/// so [`settings_mut`] only affects the clone it is called on. Options set
+/// through a [`ScannerControl`] replace those of all clones, until a clone
+/// changes its own again.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::prelude::*;
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let scanner = Scanner::builder()
+///     .database_dir("test_data/database/")
+///     .settings(ScanSettingsBuilder::new().enable_pdf().build())
+///     .build()
+///     .expect("failed to build scanner");
+///
+/// let outcome = scanner.scan_path("test_data/files/good_file").expect("scan failed");
+/// assert_eq!(outcome.result, ScanResult::Clean);
+/// ```
+///
+/// [`reload`]: #method.reload
+/// [`settings_mut`]: #method.settings_mut
+/// [`ScannerControl`]: ../control/struct.ScannerControl.html
+#[derive(Clone)]
+pub struct Scanner {
+    engine: SharedEngine,
+    /// Whether the engine has no databases, shared by all clones
+    degraded: Arc<AtomicBool>,
+    settings: ScanSettings,
+    /// Options set through a `ScannerControl`, shared by all clones
+    published_settings: Arc<Mutex<PublishedSettings>>,
+    /// `PublishedSettings::generation` when `settings` was last changed.
+    /// Published options newer than that override `settings`.
+    settings_generation: u64,
+    config: Arc<Config>,
+    report_limits: bool,
+    classify_encrypted: bool,
+    fail_degraded_scans: bool,
+    suppressions: Option<Arc<SuppressionList>>,
+    buffers: BufferBudget,
+}
+
+impl Scanner {
+    pub fn builder() -> ScannerBuilder {
+        ScannerBuilder::new()
+    }
+
+    /// Returns a builder preset for untrusted uploads.
+    ///
+    /// Scans use `ScanSettingsBuilder::hardened_upload`, the engine is limited
+    /// to 16 MiB per extracted file, 64 MiB scanned per upload, 500 extracted
+    /// files and 8 levels of nesting, and exceeded limits are reported as
+    /// `ScanResult::Suspicious`. Archive bombs are thereby flagged instead of
+    /// being expanded. Set the database directory and build as usual; any
+    /// value can still be overridden.
+    pub fn hardened_upload_profile() -> ScannerBuilder {
+        let mut builder = ScannerBuilder::new();
+        builder
+            .settings(ScanSettingsBuilder::hardened_upload().build())
+            .max_filesize(16 * 1024 * 1024)
+            .max_scansize(64 * 1024 * 1024)
+            .max_files(500)
+            .max_recursion(8)
+            .report_limits(true);
+        builder
+    }
+
+    /// Scans a file.
+    ///
+    /// If the detection matches a suppression rule restricted to a SHA-256,
+    /// the file is hashed through the descriptor it was scanned through.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EOPEN` if the file cannot be opened, or the error of the scan.
+    pub fn scan_path(&self, path: &str) -> Result<ScanOutcome, ClamError> {
+        if let Some(outcome) = self.degraded_outcome() {
+            return outcome;
+        }
+        let file = File::open(path).map_err(|_| ClamError::new(cl_error_t::CL_EOPEN))?;
+        let outcome = self
+            .engine
+            .get()
+            .scan_fileobj_outcome(&file, &self.scan_settings(), Some(path));
+        Ok(self.suppress(self.classify(outcome)?, || policy::sha256_file_hex(&file)))
+    }
+
+    /// Scans a memory buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EMAP` if the buffer cannot be mapped, or the error of the scan.
+    pub fn scan_bytes(&self, data: &[u8]) -> Result<ScanOutcome, ClamError> {
+        if let Some(outcome) = self.degraded_outcome() {
+            return outcome;
+        }
+        let map = Fmap::new_from_memory(data.as_ptr(), data.len() as u64)
+            .map_err(|_| ClamError::new(cl_error_t::CL_EMAP))?;
+        let outcome = self.engine.get().scan_map_outcome(&map, None, &self.scan_settings());
+        Ok(self.suppress(self.classify(outcome)?, || policy::sha256_hex(data)))
+    }
+
+    /// Returns the outcome of every scan while the scanner is degraded.
+    fn degraded_outcome(&self) -> Option<Result<ScanOutcome, ClamError>> {
+        if !self.is_degraded() {
+            return None;
+        }
+        if self.fail_degraded_scans {
+            return Some(Err(EngineStateError::NoDatabase.into()));
+        }
+        Some(Ok(ScanOutcome {
+            result: ScanResult::NotScanned(NotScannedReason::NoDatabase),
+            bytes_scanned: 0,
+            suppression: None,
+            performance: None,
+            from_cache: None,
+        }))
+    }
+
+    fn classify(&self, outcome: Result<ScanOutcome, ClamError>) -> Result<ScanOutcome, ClamError> {
+        let outcome = if self.report_limits {
+            LimitKind::classify(outcome)
+        } else {
+            outcome
+        };
+        if self.classify_encrypted {
+            EncryptionKind::classify(outcome)
+        } else {
+            outcome
+        }
+    }
+
+    /// Applies the suppression list, if any. `sha256` returns the hash of
+    /// the scanned data.
+    fn suppress<F>(&self, outcome: ScanOutcome, sha256: F) -> ScanOutcome
+    where
+        F: FnOnce() -> Option<String>,
+    {
+        match &self.suppressions {
+            Some(suppressions) => suppressions.apply_outcome_hashed(outcome, sha256),
+            None => outcome,
+        }
+    }
+
+    /// Reads `reader` to the end and scans its contents.
+    ///
+    /// The contents are buffered in memory within the scanner's
+    /// [`BufferLimits`] and released once the scan finishes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ScanError::BufferLimitExceeded` if the contents would exceed
+    /// the buffer limits, `CL_EREAD` if reading fails, otherwise the same
+    /// errors as [`scan_bytes`].
+    ///
+    /// [`BufferLimits`]: ../buffer/struct.BufferLimits.html
+    /// [`scan_bytes`]: #method.scan_bytes
+    pub fn scan_reader<R: Read>(&self, reader: R) -> Result<ScanOutcome, ScanError> {
+        let buffered = self.buffers.read(reader)?;
+        Ok(self.scan_bytes(buffered.data())?)
+    }
+
+    /// Returns the bytes currently buffered by `scan_reader` on this scanner
+    /// and its clones.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.buffers.buffered()
+    }
+
+    /// Scans the source of `request`, honouring its deadline and
+    /// cancellation token. See the [`request`] module for how interruptions
+    /// are handled.
+    ///
+    /// Suppressions are applied as in [`scan_path`] and [`scan_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `ScanError::DeadlineExceeded` or `ScanError::Cancelled` if the
+    /// request is interrupted, otherwise the errors of [`scan_path`] and
+    /// [`scan_bytes`].
+    ///
+    /// [`request`]: ../request/index.html
+    /// [`scan_path`]: #method.scan_path
+    /// [`scan_bytes`]: #method.scan_bytes
+    pub fn scan_request(&self, request: &ScanRequest) -> Result<ScanOutcome, ScanError> {
+        if let Some(outcome) = self.degraded_outcome() {
+            return Ok(outcome?);
+        }
+        let started = Instant::now();
+        let settings = request.settings.clone().unwrap_or_else(|| self.scan_settings());
+        request.run(started, || {
+            let source = request.open()?;
+            let outcome = self.classify(source.scan(&self.engine.get(), &settings, request.interrupt()).0)?;
+            Ok(self.suppress(outcome, || source.sha256()))
+        })
+    }
+
+    /// Loads and compiles the databases again and publishes the new engine
+    /// to all clones.
+    ///
+    /// Scans keep running on the previous engine while the new one is built.
+    /// If building fails, the previous engine stays in use.
+    ///
+    /// A degraded scanner stays degraded without error while its databases
+    /// are still missing, and scans normally once they were loaded.
+    pub fn reload(&self) -> Result<(), ClamError> {
+        if self.is_degraded() && !self.config.databases_present() {
+            return Ok(());
+        }
+        let engine = self.config.build_engine()?;
+        self.engine.swap(engine);
+        // scans seeing the flag cleared see the new engine
+        self.degraded.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns whether the scanner was built without databases and reports
+    /// files as not scanned, see `ScannerBuilder::allow_missing_database`.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Acquire)
+    }
+
+    /// Returns the scan options used for new scans of this scanner: those
+    /// set through a [`ScannerControl`] if they were set after the last
+    /// change through [`settings_mut`], otherwise this clone's own.
+    ///
+    /// [`ScannerControl`]: ../control/struct.ScannerControl.html
+    /// [`settings_mut`]: #method.settings_mut
+    pub fn settings(&self) -> ScanSettings {
+        self.scan_settings()
+    }
+
+    /// Returns the scan options of this scanner for modification, starting
+    /// from the options currently in use.
+    ///
+    /// The changes apply to this clone until options are set through a
+    /// [`ScannerControl`] again.
+    ///
+    /// [`ScannerControl`]: ../control/struct.ScannerControl.html
+    pub fn settings_mut(&mut self) -> &mut ScanSettings {
+        let published = self.published_settings.lock().unwrap();
+        if published.generation > self.settings_generation {
+            self.settings = published.settings.clone();
+            self.settings_generation = published.generation;
+        }
+        drop(published);
+        &mut self.settings
+    }
+
+    /// Returns the options used for new scans.
+    fn scan_settings(&self) -> ScanSettings {
+        let published = self.published_settings.lock().unwrap();
+        if published.generation > self.settings_generation {
+            published.settings.clone()
+        } else {
+            self.settings.clone()
+        }
+    }
+
+    /// Starts a control thread operating this scanner and its clones, and
+    /// returns a handle to it, see the [`control`] module.
+    ///
+    /// [`control`]: ../control/index.html
+    pub fn control(&self) -> ScannerControl {
+        ScannerControl::spawn(self.clone())
+    }
+
+    /// Returns the current engine.
+    pub fn engine(&self) -> Arc<Engine> {
+        self.engine.get()
+    }
+
+    /// Returns the suppression list applied to scans, if any.
+    pub fn suppressions(&self) -> Option<&Arc<SuppressionList>> {
+        self.suppressions.as_ref()
+    }
+}
+
+impl Controlled for Scanner {
+    fn reload(&mut self) -> Result<(), ClamError> {
+        Scanner::reload(self)
+    }
+
+    fn purge_cache(&mut self) -> Result<(), ClamError> {
+        // a new engine starts with an empty cache
+        Scanner::reload(self)
+    }
+
+    fn is_degraded(&self) -> bool {
+        Scanner::is_degraded(self)
+    }
+
+    fn settings(&self) -> ScanSettings {
+        self.scan_settings()
+    }
+
+    fn set_settings(&mut self, settings: ScanSettings) {
+        let mut published = self.published_settings.lock().unwrap();
+        published.settings = settings;
+        published.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferScope;
+    use crate::engine::ScanResult;
+    use crate::policy::SuppressionRule;
+    use crate::scan::ScanToken;
+    use std::thread;
+    use std::time::Duration;
+    use crate::scan_settings::ScanSettingsBuilder;
+    use crate::test_support::{EXAMPLE_DATABASE_PATH, GOOD_FILE_PATH, NAUGHTY_FILE_PATH};
+    use std::fs;
+    use std::io::Cursor;
+
+    fn example_scanner() -> Scanner {
+        crate::initialize().expect("initialize should succeed");
+        Scanner::builder()
+            .database_dir(EXAMPLE_DATABASE_PATH)
+            .build()
+            .expect("failed to build scanner")
+    }
+
+    fn naughty() -> ScanResult {
+        ScanResult::Virus("naughty_file.UNOFFICIAL".into())
+    }
+
+    #[test]
+    fn build_without_database_dir_fails() {
+        crate::initialize().expect("initialize should succeed");
+        let result = Scanner::builder().build();
+        assert_eq!(result.err().map(|e| e.code()), Some(cl_error_t::CL_ENULLARG as i32));
+    }
+
+    #[test]
+    fn build_with_missing_database_dir_fails() {
+        crate::initialize().expect("initialize should succeed");
+        let result = Scanner::builder()
+            .database_dir("test_data/does_not_exist")
+            .build();
+        assert!(result.is_err(), "missing databases should fail to load");
+    }
+
+    #[test]
+    fn build_applies_max_filesize() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Scanner::builder()
+            .database_dir(EXAMPLE_DATABASE_PATH)
+            .max_filesize(1234567)
+            .build()
+            .expect("failed to build scanner");
+        assert_eq!(scanner.engine().max_filesize(), Ok(1234567));
+    }
+
+    #[test]
+    fn scan_path_fixtures() {
+        let scanner = example_scanner();
+        assert_eq!(scanner.scan_path(GOOD_FILE_PATH).unwrap().result, ScanResult::Clean);
+        assert_eq!(scanner.scan_path(NAUGHTY_FILE_PATH).unwrap().result, naughty());
+    }
+
+    #[test]
+    fn scan_path_missing_file_fails() {
+        let scanner = example_scanner();
+        let result = scanner.scan_path("test_data/files/does_not_exist");
+        assert_eq!(result.err().map(|e| e.code()), Some(cl_error_t::CL_EOPEN as i32));
+    }
+
+    #[test]
+    fn scan_bytes_and_reader_fixtures() {
+        let scanner = example_scanner();
+        for &(path, ref expected) in &[(GOOD_FILE_PATH, ScanResult::Clean), (NAUGHTY_FILE_PATH, naughty())] {
+            let contents = fs::read(path).unwrap();
+            assert_eq!(&scanner.scan_bytes(&contents).unwrap().result, expected);
+            assert_eq!(&scanner.scan_reader(Cursor::new(contents)).unwrap().result, expected);
+        }
+    }
+
+    #[test]
+    fn scan_reader_enforces_buffer_limits() {
+        crate::initialize().expect("initialize should succeed");
+        let scanner = Scanner::builder()
+            .database_dir(EXAMPLE_DATABASE_PATH)
+            .buffer_limits(BufferLimits {
+                per_scan: 64,
+                global: 64,
+            })
+            .build()
+            .expect("failed to build scanner");
+        let contents = fs::read(NAUGHTY_FILE_PATH).unwrap();
+        assert_eq!(scanner.scan_reader(Cursor::new(&contents)).unwrap().result, naughty());
+        assert_eq!(scanner.buffered_bytes(), 0);
+
+        let err = scanner.scan_reader(Cursor::new(vec![0; 65])).err().unwrap();
+        match err {
+            ScanError::BufferLimitExceeded(err) => assert_eq!(err.scope, BufferScope::PerScan),
+            err => panic!("unexpected error {:?}", err),
+        }
+        assert_eq!(scanner.clone().buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn reload_is_seen_by_clones() {
+        let scanner = example_scanner();
+        let clone = scanner.clone();
+        let before = scanner.engine();
+        scanner.reload().expect("reload should succeed");
+        assert!(!Arc::ptr_eq(&before, &clone.engine()));
+        assert!(Arc::ptr_eq(&scanner.engine(), &clone.engine()));
+        assert_eq!(clone.scan_path(NAUGHTY_FILE_PATH).unwrap().result, naughty());
+    }
+
+    #[test]
+    fn control_reloads_and_replaces_settings_of_clones() {
+        let scanner = example_scanner();
+        let clone = scanner.clone();
+        let control = scanner.control();
+        let before = scanner.engine();
+
+        let reload = control.reload();
+        let settings = ScanSettingsBuilder::new().enable_pdf().build();
+        let set = control.set_settings(settings.clone());
+        let status = control.status().wait().expect("status failed");
+        reload.wait().expect("reload failed");
+        set.wait().expect("set_settings failed");
+
+        assert_eq!(status.reloads, 1);
+        assert!(!status.degraded);
+        assert_eq!(status.settings.to_string(), settings.to_string());
+        assert!(!Arc::ptr_eq(&before, &clone.engine()));
+        assert_eq!(clone.scan_settings().to_string(), settings.to_string());
+        assert_eq!(clone.scan_path(NAUGHTY_FILE_PATH).unwrap().result, naughty());
+    }
+
+    #[test]
+    fn settings_return_published_options_until_changed() {
+        let mut scanner = example_scanner();
+        let control = scanner.control();
+        let pdf = ScanSettingsBuilder::new().enable_pdf().build();
+        control.set_settings(pdf.clone()).wait().expect("set_settings failed");
+        assert_eq!(scanner.settings().to_string(), pdf.to_string());
+
+        // changes start from the options in use and apply to this clone
+        let clone = scanner.clone();
+        assert_eq!(scanner.settings_mut().to_string(), pdf.to_string());
+        *scanner.settings_mut() = ScanSettings::default();
+        assert_eq!(scanner.settings().to_string(), ScanSettings::default().to_string());
+        assert_eq!(clone.settings().to_string(), pdf.to_string());
+
+        control.set_settings(pdf.clone()).wait().expect("set_settings failed");
+        assert_eq!(scanner.settings().to_string(), pdf.to_string());
+    }
+
+    fn degraded_scanner(dir: &std::path::Path, fail: bool) -> Scanner {
+        crate::initialize().expect("initialize should succeed");
+        Scanner::builder()
+            .database_dir(dir.to_str().unwrap())
+            .allow_missing_database(true)
+            .fail_degraded_scans(fail)
+            .build()
+            .expect("failed to build degraded scanner")
+    }
+
+    #[test]
+    fn missing_database_degrades_until_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let scanner = degraded_scanner(dir.path(), false);
+        let clone = scanner.clone();
+        assert!(scanner.is_degraded());
+        let not_scanned = ScanResult::NotScanned(NotScannedReason::NoDatabase);
+        assert_eq!(scanner.scan_path(NAUGHTY_FILE_PATH).unwrap().result, not_scanned);
+        assert_eq!(scanner.scan_bytes(b"data").unwrap().result, not_scanned);
+        assert!(!not_scanned.found());
+
+        scanner.reload().expect("reload without databases should succeed");
+        assert!(clone.is_degraded());
+
+        fs::copy(EXAMPLE_DATABASE_PATH, dir.path().join("example.cud")).unwrap();
+        scanner.reload().expect("reload should succeed");
+        assert!(!scanner.is_degraded());
+        assert!(!clone.is_degraded());
+        assert_eq!(clone.scan_path(NAUGHTY_FILE_PATH).unwrap().result, naughty());
+        assert_eq!(scanner.scan_path(GOOD_FILE_PATH).unwrap().result, ScanResult::Clean);
+    }
+
+    #[test]
+    fn degraded_scans_can_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let scanner = degraded_scanner(&dir.path().join("not_downloaded_yet"), true);
+        assert!(scanner.is_degraded());
+        let err = scanner.scan_path(GOOD_FILE_PATH).unwrap_err();
+        assert_eq!(err.state_error(), Some(EngineStateError::NoDatabase));
+        match scanner.scan_request(&ScanRequest::bytes(b"data".to_vec())) {
+            Err(ScanError::Clam(err)) => assert_eq!(err.state_error(), Some(EngineStateError::NoDatabase)),
+            other => panic!("expected a state error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_database_fails_by_default() {
+        crate::initialize().expect("initialize should succeed");
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Scanner::builder().database_dir(dir.path().to_str().unwrap()).build().is_err());
+    }
+
+    #[test]
+    fn temp_settings_survive_reload() {
+        crate::initialize().expect("initialize should succeed");
+        let strategy: Arc<dyn TempStrategy> = Arc::new(crate::temp::DefaultTempStrategy::new());
+        let scanner = Scanner::builder()
+            .database_dir(EXAMPLE_DATABASE_PATH)
+            .temp_strategy(strategy.clone())
+            .temp_quota(4096)
+            .build()
+            .expect("failed to build scanner");
+        for _ in 0..2 {
+            let engine = scanner.engine();
+            assert_eq!(Arc::as_ptr(&engine.temp_strategy()) as *const (), Arc::as_ptr(&strategy) as *const ());
+            assert_eq!(engine.temp_quota(), Some(4096));
+            scanner.reload().expect("reload should succeed");
+        }
+    }
+
+    #[test]
+    fn settings_mut_only_changes_one_clone() {
+        let mut scanner = example_scanner();
+        let clone = scanner.clone();
+        *scanner.settings_mut() = ScanSettingsBuilder::new().enable_pdf().build();
+        assert_eq!(scanner.settings().to_string(), "CL_SCAN_PARSE_PDF");
+        assert_eq!(clone.settings().to_string(), ScanSettings::default().to_string());
+    }
+
+    fn suppressed_scanner(rule: SuppressionRule) -> Scanner {
+        crate::initialize().expect("initialize should succeed");
+        Scanner::builder()
+            .database_dir(EXAMPLE_DATABASE_PATH)
+            .suppressions(Arc::new(SuppressionList::new(vec![rule])))
+            .build()
+            .expect("failed to build scanner")
+    }
+
+    fn assert_suppressed(outcome: &ScanOutcome, rule: &SuppressionRule) {
+        assert_eq!(
+            outcome.result,
+            ScanResult::Suppressed { original: "naughty_file.UNOFFICIAL".into() }
+        );
+        let record = outcome.suppression.as_ref().expect("suppression should be recorded");
+        assert_eq!(record.detection, "naughty_file.UNOFFICIAL");
+        assert_eq!(&record.rule, rule);
+    }
+
+    #[test]
+    fn global_suppression_downgrades_detection() {
+        let rule = SuppressionRule::new("naughty_file.*");
+        let scanner = suppressed_scanner(rule.clone());
+        assert_suppressed(&scanner.scan_path(NAUGHTY_FILE_PATH).unwrap(), &rule);
+        assert_suppressed(&scanner.scan_bytes(&fs::read(NAUGHTY_FILE_PATH).unwrap()).unwrap(), &rule);
+        let clean = scanner.scan_path(GOOD_FILE_PATH).unwrap();
+        assert_eq!(clean.result, ScanResult::Clean);
+        assert_eq!(clean.suppression, None);
+    }
+
+    #[test]
+    fn suppression_by_hash_only_matches_that_data() {
+        crate::initialize().expect("initialize should succeed");
+        let contents = fs::read(NAUGHTY_FILE_PATH).unwrap();
+        let sha256 = crate::policy::sha256_hex(&contents).expect("hashing should succeed");
+        let rule = SuppressionRule::new("naughty_file.UNOFFICIAL").with_sha256(&sha256);
+        let scanner = suppressed_scanner(rule.clone());
+
+        let outcome = scanner.scan_path(NAUGHTY_FILE_PATH).unwrap();
+        assert_suppressed(&outcome, &rule);
+        assert_eq!(outcome.suppression.unwrap().sha256, Some(sha256.clone()));
+        let outcome = scanner.scan_request(&ScanRequest::path(NAUGHTY_FILE_PATH)).unwrap();
+        assert_suppressed(&outcome, &rule);
+        assert_eq!(outcome.suppression.unwrap().sha256, Some(sha256));
+
+        // a rule for other data does not suppress the detection
+        let other = crate::policy::sha256_hex(b"other data").expect("hashing should succeed");
+        let scanner = suppressed_scanner(SuppressionRule::new("naughty_file.UNOFFICIAL").with_sha256(&other));
+        let outcome = scanner.scan_bytes(&contents).unwrap();
+        assert_eq!(outcome.result, naughty());
+        assert_eq!(outcome.suppression, None);
+    }
+
+    #[test]
+    fn suppression_reload_is_seen_by_scanner() {
+        crate::initialize().expect("initialize should succeed");
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"Heuristics.*\n").unwrap();
+        let list = Arc::new(SuppressionList::load(file.path()).expect("list should load"));
+        let scanner = Scanner::builder()
+            .database_dir(EXAMPLE_DATABASE_PATH)
+            .suppressions(Arc::clone(&list))
+            .build()
+            .expect("failed to build scanner");
+        assert_eq!(scanner.scan_path(NAUGHTY_FILE_PATH).unwrap().result, naughty());
+
+        fs::write(file.path(), "naughty_file.UNOFFICIAL\n").unwrap();
+        list.reload().expect("reload should succeed");
+        assert!(!scanner.scan_path(NAUGHTY_FILE_PATH).unwrap().result.found());
+    }
+
+    #[test]
+    fn scan_request_without_limits_scans() {
+        let scanner = example_scanner();
+        let outcome = scanner
+            .scan_request(&ScanRequest::path(NAUGHTY_FILE_PATH))
+            .expect("scan should succeed");
+        assert_eq!(outcome.result, naughty());
+        let request = ScanRequest::bytes(fs::read(GOOD_FILE_PATH).unwrap()).timeout(Duration::from_secs(60));
+        assert_eq!(scanner.scan_request(&request).unwrap().result, ScanResult::Clean);
+    }
+
+    #[test]
+    fn scan_request_past_deadline_fails_fast() {
+        let scanner = example_scanner();
+        // the engine would fail with CL_EOPEN on this path
+        let request = ScanRequest::path("test_data/files/does_not_exist").deadline(Instant::now());
+        match scanner.scan_request(&request) {
+            Err(ScanError::DeadlineExceeded(progress)) => assert_eq!(progress.bytes_scanned, None),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let token = ScanToken::new();
+        token.cancel();
+        let request = ScanRequest::path("test_data/files/does_not_exist").cancellation(token);
+        assert!(matches!(scanner.scan_request(&request), Err(ScanError::Cancelled(_))));
+    }
+
+    #[test]
+    fn scan_request_cancelled_mid_scan() {
+        let scanner = example_scanner();
+        let token = ScanToken::new();
+        let request = ScanRequest::bytes(vec![0x41u8; 64 * 1024 * 1024]).cancellation(token.clone());
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(1));
+            token.cancel();
+        });
+        let result = scanner.scan_request(&request);
+        canceller.join().unwrap();
+        match result {
+            Err(ScanError::Cancelled(progress)) => assert!(progress.bytes_scanned.is_some()),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn scan_request_cancelled_from_callback_skips_contained_objects() {
+        use crate::engine::PreScanAction;
+        use std::sync::atomic::AtomicUsize;
+
+        let scanner = example_scanner();
+        let token = ScanToken::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (hook_token, hook_calls) = (token.clone(), Arc::clone(&calls));
+        scanner
+            .engine()
+            .set_pre_scan_callback(move |_, _| {
+                hook_calls.fetch_add(1, Ordering::SeqCst);
+                hook_token.cancel();
+                PreScanAction::Continue
+            })
+            .unwrap();
+        let request = ScanRequest::path("test_data/files/nested.zip").cancellation(token);
+        assert!(matches!(scanner.scan_request(&request), Err(ScanError::Cancelled(_))));
+        // without the check, inner.zip and its good_file would follow
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn scan_request_deadline_mid_scan() {
+        let scanner = example_scanner();
+        let data = vec![0x41u8; 64 * 1024 * 1024];
+        let request = ScanRequest::bytes(data).timeout(Duration::from_millis(1));
+        match scanner.scan_request(&request) {
+            Err(ScanError::DeadlineExceeded(progress)) => {
+                assert!(progress.elapsed >= Duration::from_millis(1));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn hardened_scanner() -> Scanner {
+        crate::initialize().expect("initialize should succeed");
+        Scanner::hardened_upload_profile()
+            .database_dir(EXAMPLE_DATABASE_PATH)
+            .build()
+            .expect("failed to build scanner")
+    }
+
+    #[test]
+    fn hardened_upload_profile_sets_limits() {
+        let scanner = hardened_scanner();
+        let engine = scanner.engine();
+        assert_eq!(engine.max_filesize(), Ok(16 * 1024 * 1024));
+        assert_eq!(engine.max_scansize(), Ok(64 * 1024 * 1024));
+        assert_eq!(engine.max_files(), Ok(500));
+        assert_eq!(engine.max_recursion(), Ok(8));
+        assert_eq!(
+            scanner.settings().to_string(),
+            ScanSettingsBuilder::hardened_upload().build().to_string()
+        );
+        assert_eq!(scanner.scan_path(GOOD_FILE_PATH).unwrap().result, ScanResult::Clean);
+        assert_eq!(scanner.scan_path(NAUGHTY_FILE_PATH).unwrap().result, naughty());
+    }
+
+    const ENCRYPTED_ZIP_PATH: &str = "test_data/files/encrypted.zip";
+    const ENCRYPTED_PDF_PATH: &str = "test_data/files/encrypted.pdf";
+
+    fn encrypted_scanner(classify: bool) -> Scanner {
+        crate::initialize().expect("initialize should succeed");
+        Scanner::builder()
+            .database_dir(EXAMPLE_DATABASE_PATH)
+            .settings(ScanSettingsBuilder::hardened_upload().build())
+            .classify_encrypted(classify)
+            .build()
+            .expect("failed to build scanner")
+    }
+
+    #[test]
+    fn classify_encrypted_reports_encrypted_content() {
+        let scanner = encrypted_scanner(true);
+        let outcome = scanner.scan_path(ENCRYPTED_ZIP_PATH).unwrap();
+        assert_eq!(outcome.result, ScanResult::Encrypted { container: EncryptionKind::Zip });
+        assert!(outcome.result.found());
+        assert_eq!(
+            outcome.format_line(std::path::Path::new("encrypted.zip")),
+            "encrypted.zip: Heuristics.Encrypted.Zip FOUND"
+        );
+        let outcome = scanner.scan_path(ENCRYPTED_PDF_PATH).unwrap();
+        assert_eq!(outcome.result, ScanResult::Encrypted { container: EncryptionKind::Pdf });
+        assert_eq!(scanner.scan_path(NAUGHTY_FILE_PATH).unwrap().result, naughty());
+    }
+
+    #[test]
+    fn encrypted_content_is_a_detection_by_default() {
+        let scanner = encrypted_scanner(false);
+        assert_eq!(
+            scanner.scan_path(ENCRYPTED_ZIP_PATH).unwrap().result,
+            ScanResult::Virus("Heuristics.Encrypted.Zip".into())
+        );
+        assert_eq!(
+            scanner.scan_path(ENCRYPTED_PDF_PATH).unwrap().result,
+            ScanResult::Virus("Heuristics.Encrypted.PDF".into())
+        );
+    }
+
+    #[test]
+    fn hardened_upload_profile_flags_zip_bomb() {
+        const UNCOMPRESSED: u64 = 32 * 1024 * 1024;
+        let bomb = crate::test_support::zip_bomb(UNCOMPRESSED);
+        assert!(UNCOMPRESSED / bomb.len() as u64 > 100, "compression ratio too low");
+
+        let scanner = hardened_scanner();
+        let outcome = scanner.scan_bytes(&bomb).expect("scan should succeed");
+        match outcome.result {
+            ScanResult::Suspicious(kind) => assert_eq!(kind, LimitKind::FileSize),
+            other => panic!("zip bomb should be suspicious, got {:?}", other),
+        }
+        assert!(outcome.result.found());
+        assert_eq!(
+            outcome.format_line(std::path::Path::new("bomb.zip")),
+            "bomb.zip: Heuristics.Limits.Exceeded.MaxFileSize FOUND"
+        );
+    }
+}
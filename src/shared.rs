@@ -0,0 +1,164 @@
+//! Engine handle that can be replaced while scans are running.
+//!
+//! Reloading definitions means building and compiling a new [`Engine`], which
+//! takes seconds. A [`SharedEngine`] lets the reloader publish the new engine
+//! in one step: scans started afterwards use it, scans already running keep
+//! the engine they started with, and the old engine is freed when the last of
+//! them finishes.
+//!
+//! [`Engine`]: ../engine/struct.Engine.html
+//! [`SharedEngine`]: struct.SharedEngine.html
+
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::engine::Engine;
+
+/// Replaceable, reference-counted engine
+///
+/// The handle is cheap to clone; clones publish to and read from the same
+/// slot. The internal read-write lock only guards cloning the `Arc` out and
+/// replacing it, and is never held during a scan or while an engine is
+/// dropped, so neither [`get`] nor [`swap`] waits for running scans.
+/// Concurrent calls of [`get`] share the read lock and don't wait for each
+/// other.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::prelude::*;
+/// use clamav_rs::shared::SharedEngine;
+///
+/// fn compiled() -> Engine {
+///     let engine = Engine::new().expect("failed to create engine");
+///     engine.load_databases("test_data/database/").expect("failed to load");
+///     engine.compile().expect("failed to compile");
+///     engine
+/// }
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let shared = SharedEngine::new(compiled());
+/// let engine = shared.get();
+/// // the reloader publishes a new engine, `engine` stays usable
+/// shared.swap(compiled());
+/// engine.scan_file("test_data/files/good_file", &Default::default()).expect("scan failed");
+/// ```
+///
+/// [`get`]: #method.get
+/// [`swap`]: #method.swap
+#[derive(Clone)]
+pub struct SharedEngine {
+    current: Arc<RwLock<Arc<Engine>>>,
+}
+
+impl SharedEngine {
+    /// Creates a handle publishing `engine`.
+    pub fn new(engine: Engine) -> Self {
+        Self::from_arc(Arc::new(engine))
+    }
+
+    /// Creates a handle publishing an already shared engine.
+    pub fn from_arc(engine: Arc<Engine>) -> Self {
+        SharedEngine {
+            current: Arc::new(RwLock::new(engine)),
+        }
+    }
+
+    /// Returns the current engine.
+    ///
+    /// Keep the returned `Arc` for the duration of a scan; the engine stays
+    /// alive until it is dropped, even if it is replaced in the meantime.
+    pub fn get(&self) -> Arc<Engine> {
+        // the slot always holds a valid engine, even after a panic elsewhere
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&current)
+    }
+
+    /// Publishes `engine` for all scans started from now on and returns the
+    /// previous engine.
+    ///
+    /// The previous engine is freed once the returned `Arc` and all guards
+    /// still held by running scans are dropped.
+    pub fn swap(&self, engine: Engine) -> Arc<Engine> {
+        self.swap_arc(Arc::new(engine))
+    }
+
+    /// Same as [`swap`], for an already shared engine.
+    ///
+    /// [`swap`]: #method.swap
+    pub fn swap_arc(&self, engine: Arc<Engine>) -> Arc<Engine> {
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        std::mem::replace(&mut *current, engine)
+    }
+}
+
+impl From<Engine> for SharedEngine {
+    fn from(engine: Engine) -> Self {
+        SharedEngine::new(engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ScanResult;
+    use crate::scan_settings::ScanSettings;
+    use crate::test_support::{example_engine, NAUGHTY_FILE_PATH};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn swap_returns_previous_engine() {
+        let shared = SharedEngine::new(example_engine());
+        let first = shared.get();
+        let previous = shared.swap(example_engine());
+        assert!(Arc::ptr_eq(&first, &previous));
+        assert!(!Arc::ptr_eq(&first, &shared.get()));
+    }
+
+    #[test]
+    fn scans_continue_across_swaps() {
+        let shared = SharedEngine::new(example_engine());
+        let old = Arc::downgrade(&shared.get());
+        let stop = Arc::new(AtomicBool::new(false));
+        let failures = Arc::new(AtomicUsize::new(0));
+        let scans = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                let stop = Arc::clone(&stop);
+                let failures = Arc::clone(&failures);
+                let scans = Arc::clone(&scans);
+                thread::spawn(move || {
+                    let settings: ScanSettings = Default::default();
+                    while !stop.load(Ordering::SeqCst) {
+                        let engine = shared.get();
+                        match engine.scan_file(NAUGHTY_FILE_PATH, &settings) {
+                            Ok(ScanResult::Virus(ref name)) if name == "naughty_file.UNOFFICIAL" => {}
+                            _ => {
+                                failures.fetch_add(1, Ordering::SeqCst);
+                            }
+                        }
+                        scans.fetch_add(1, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..3 {
+            let before = scans.load(Ordering::SeqCst);
+            while scans.load(Ordering::SeqCst) < before + 8 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            drop(shared.swap(example_engine()));
+        }
+        stop.store(true, Ordering::SeqCst);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(failures.load(Ordering::SeqCst), 0);
+        assert!(old.upgrade().is_none(), "old engine should have been dropped");
+    }
+}
@@ -0,0 +1,947 @@
+//! Streaming of scan results to external consumers.
+//!
+//! Long running scans can push every result to a [`ResultSink`] as soon as
+//! it is known, e.g. to an HTTP endpoint or a message queue, instead of
+//! collecting everything in memory first. Sinks are attached with
+//! `DirScanOptions::with_sink`, `scan_directory_with_sink` or
+//! `ScannerPool::with_sink`.
+//!
+//! `emit` is called on the scanning threads, so a slow sink slows down the
+//! scan. Sinks talking to the network or to slow storage should be wrapped in
+//! a [`BatchingSink`], which hands records to a background thread through a
+//! bounded queue and decides by its [`Overflow`] policy what happens when the
+//! consumer cannot keep up.
+//!
+//! [`ResultSink`]: trait.ResultSink.html
+//! [`BatchingSink`]: struct.BatchingSink.html
+//! [`Overflow`]: enum.Overflow.html
+
+#[cfg(feature = "serde")]
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+#[cfg(not(feature = "serde"))]
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::engine::ScanOutcome;
+use crate::error::ClamError;
+
+/// Result of one scan as delivered to a sink
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanRecord {
+    /// Path or name of the scanned data, if known
+    pub path: Option<PathBuf>,
+    /// Outcome, or the error which prevented the scan
+    pub outcome: Result<ScanOutcome, ClamError>,
+    /// Position of the file in the walk of a directory scan, counting from
+    /// 0, or `None` for other scans and for unreadable subdirectories
+    pub sequence: Option<u64>,
+}
+
+impl ScanRecord {
+    pub fn new(path: Option<PathBuf>, outcome: Result<ScanOutcome, ClamError>) -> Self {
+        ScanRecord {
+            path,
+            outcome,
+            sequence: None,
+        }
+    }
+
+    /// Formats the record as a single line JSON object, e.g.
+    /// `{"path":"/tmp/a","result":"OK","found":false,"bytes_scanned":4096}`
+    /// or `{"path":"/tmp/b","error":"Can't open file or directory","code":8}`.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let path = self.path.as_ref().map(|path| path.to_string_lossy());
+        let record = match &self.outcome {
+            Ok(outcome) => JsonRecord::Outcome {
+                path,
+                result: outcome.result.to_string(),
+                found: outcome.result.found(),
+                bytes_scanned: outcome.bytes_scanned,
+            },
+            Err(err) => JsonRecord::Error {
+                path,
+                error: err.string_error(),
+                code: err.code(),
+            },
+        };
+        serde_json::to_string(&record).expect("records serialize to JSON")
+    }
+
+    /// Formats the record as a single line JSON object, e.g.
+    /// `{"path":"/tmp/a","result":"OK","found":false,"bytes_scanned":4096}`
+    /// or `{"path":"/tmp/b","error":"Can't open file or directory","code":8}`.
+    #[cfg(not(feature = "serde"))]
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"path\":");
+        match &self.path {
+            Some(path) => push_json_string(&mut json, &path.to_string_lossy()),
+            None => json.push_str("null"),
+        }
+        match &self.outcome {
+            Ok(outcome) => {
+                json.push_str(",\"result\":");
+                push_json_string(&mut json, &outcome.result.to_string());
+                let _ = write!(
+                    json,
+                    ",\"found\":{},\"bytes_scanned\":{}",
+                    outcome.result.found(),
+                    outcome.bytes_scanned
+                );
+            }
+            Err(err) => {
+                json.push_str(",\"error\":");
+                push_json_string(&mut json, &err.string_error());
+                let _ = write!(json, ",\"code\":{}", err.code());
+            }
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Fields of the JSON form of a [`ScanRecord`], in output order
+///
+/// [`ScanRecord`]: struct.ScanRecord.html
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum JsonRecord<'a> {
+    Outcome {
+        path: Option<Cow<'a, str>>,
+        result: String,
+        found: bool,
+        bytes_scanned: u64,
+    },
+    Error {
+        path: Option<Cow<'a, str>>,
+        error: String,
+        code: i32,
+    },
+}
+
+/// Appends `value` as a JSON string, escaped the same way as by serde_json.
+#[cfg(not(feature = "serde"))]
+fn push_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            '\u{8}' => json.push_str("\\b"),
+            '\u{c}' => json.push_str("\\f"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Consumer of scan results
+pub trait ResultSink: Send + Sync {
+    /// Receives the result of one scan as soon as it is known.
+    fn emit(&self, record: &ScanRecord);
+
+    /// Receives several results at once. Sinks with a per-call overhead,
+    /// like a request per call, should override this.
+    fn emit_batch(&self, records: &[ScanRecord]) {
+        for record in records {
+            self.emit(record);
+        }
+    }
+
+    /// Called when a scan is complete, after its last result was emitted.
+    fn flush(&self) {}
+
+    /// Wraps the sink in a [`BatchingSink`].
+    ///
+    /// [`BatchingSink`]: struct.BatchingSink.html
+    fn batched(self, options: BatchOptions) -> BatchingSink
+    where
+        Self: Sized + 'static,
+    {
+        BatchingSink::new(Arc::new(self), options)
+    }
+}
+
+/// What a sink does with a record when its buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the record right away
+    Drop,
+    /// Wait up to the given time for space, then drop the record
+    Block(Duration),
+}
+
+impl Default for Overflow {
+    fn default() -> Self {
+        Overflow::Block(Duration::from_secs(1))
+    }
+}
+
+/// Sink sending records to a bounded channel
+///
+/// Records are dropped when the [`RecordReceiver`] is gone or the channel
+/// stays full longer than the overflow policy allows. A blocked `emit`
+/// continues as soon as the receiver takes a record.
+///
+/// [`RecordReceiver`]: struct.RecordReceiver.html
+pub struct ChannelSink {
+    shared: Arc<ChannelShared>,
+    overflow: Overflow,
+    dropped: AtomicU64,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    queue: VecDeque<ScanRecord>,
+    /// The sink was dropped
+    disconnected: bool,
+    /// The receiver was dropped
+    closed: bool,
+}
+
+struct ChannelShared {
+    capacity: usize,
+    state: Mutex<ChannelState>,
+    /// Signalled when a record is queued or taken and when either end is dropped
+    changed: Condvar,
+}
+
+impl ChannelShared {
+    /// Takes the oldest record and wakes a blocked sender.
+    fn take(&self, state: &mut MutexGuard<ChannelState>) -> Option<ScanRecord> {
+        let record = state.queue.pop_front();
+        if record.is_some() {
+            self.changed.notify_all();
+        }
+        record
+    }
+}
+
+impl ChannelSink {
+    /// Creates a sink and the receiving end of its channel, which buffers up
+    /// to `capacity` records. Values below 1 are treated as 1.
+    pub fn new(capacity: usize, overflow: Overflow) -> (ChannelSink, RecordReceiver) {
+        let shared = Arc::new(ChannelShared {
+            capacity: capacity.max(1),
+            state: Mutex::new(ChannelState::default()),
+            changed: Condvar::new(),
+        });
+        let sink = ChannelSink {
+            shared: Arc::clone(&shared),
+            overflow,
+            dropped: AtomicU64::new(0),
+        };
+        (sink, RecordReceiver { shared })
+    }
+
+    /// Number of records which were dropped.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl ResultSink for ChannelSink {
+    fn emit(&self, record: &ScanRecord) {
+        let shared = &self.shared;
+        let full = |state: &mut ChannelState| !state.closed && state.queue.len() >= shared.capacity;
+        let mut state = shared.state.lock().unwrap();
+        if let Overflow::Block(timeout) = self.overflow {
+            state = shared.changed.wait_timeout_while(state, timeout, full).unwrap().0;
+        }
+        if state.closed || state.queue.len() >= shared.capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        state.queue.push_back(record.clone());
+        shared.changed.notify_all();
+    }
+}
+
+impl Drop for ChannelSink {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().disconnected = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Receiving end of a [`ChannelSink`]
+///
+/// Like the receiver of a channel, `recv` fails once the sink was dropped
+/// and all records were received.
+///
+/// [`ChannelSink`]: struct.ChannelSink.html
+pub struct RecordReceiver {
+    shared: Arc<ChannelShared>,
+}
+
+impl RecordReceiver {
+    /// Waits for the next record.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RecvError` if the sink was dropped and no record is left.
+    pub fn recv(&self) -> Result<ScanRecord, RecvError> {
+        let state = self.shared.state.lock().unwrap();
+        let mut state = self
+            .shared
+            .changed
+            .wait_while(state, |state| state.queue.is_empty() && !state.disconnected)
+            .unwrap();
+        self.shared.take(&mut state).ok_or(RecvError)
+    }
+
+    /// Waits up to `timeout` for the next record.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RecvTimeoutError::Timeout` if no record arrived in time and
+    /// `RecvTimeoutError::Disconnected` if the sink was dropped and no record
+    /// is left.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<ScanRecord, RecvTimeoutError> {
+        let state = self.shared.state.lock().unwrap();
+        let mut state = self
+            .shared
+            .changed
+            .wait_timeout_while(state, timeout, |state| state.queue.is_empty() && !state.disconnected)
+            .unwrap()
+            .0;
+        match self.shared.take(&mut state) {
+            Some(record) => Ok(record),
+            None if state.disconnected => Err(RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Takes the next record without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TryRecvError::Empty` if no record is queued and
+    /// `TryRecvError::Disconnected` if the sink was dropped and no record is
+    /// left.
+    pub fn try_recv(&self) -> Result<ScanRecord, TryRecvError> {
+        let mut state = self.shared.state.lock().unwrap();
+        match self.shared.take(&mut state) {
+            Some(record) => Ok(record),
+            None if state.disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Returns an iterator waiting for records until the sink is dropped.
+    pub fn iter(&self) -> impl Iterator<Item = ScanRecord> + '_ {
+        iter::from_fn(move || self.recv().ok())
+    }
+
+    /// Returns an iterator over the records queued right now.
+    pub fn try_iter(&self) -> impl Iterator<Item = ScanRecord> + '_ {
+        iter::from_fn(move || self.try_recv().ok())
+    }
+}
+
+impl Drop for RecordReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        state.queue.clear();
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Sink writing one JSON object per line, see [`ScanRecord::to_json`]
+///
+/// Writes are not buffered across calls to `flush`, which is called at the
+/// end of each scan. Failed writes are counted and otherwise ignored, so a
+/// full disk does not abort the scan.
+///
+/// Parallel directory scans emit records in the order the files finish. A
+/// sink created with [`ordered`] writes them in walk order instead, so two
+/// scans of the same tree produce the same file.
+///
+/// [`ScanRecord::to_json`]: struct.ScanRecord.html#method.to_json
+/// [`ordered`]: #method.ordered
+pub struct JsonlSink {
+    writer: Mutex<Box<dyn Write + Send>>,
+    reorder: Option<Mutex<Reorder>>,
+    write_errors: AtomicU64,
+}
+
+impl JsonlSink {
+    /// Creates a sink writing to `writer`.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        JsonlSink {
+            writer: Mutex::new(Box::new(writer)),
+            reorder: None,
+            write_errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates or truncates the file at `path` and writes to it.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Writes the records of directory scans in the order of their
+    /// [`sequence`] numbers, holding back up to `window` records which
+    /// finished ahead of an earlier file.
+    ///
+    /// When more records are held back, e.g. because a file vanished before
+    /// it was scanned and its number never arrives, the gap is skipped and
+    /// the lowest held record is written. Records without a sequence number
+    /// and `flush` write all held records first. Sequence numbers restart at
+    /// 0 after `flush`.
+    ///
+    /// [`sequence`]: struct.ScanRecord.html#structfield.sequence
+    pub fn ordered(mut self, window: usize) -> Self {
+        self.reorder = Some(Mutex::new(Reorder {
+            window,
+            next: 0,
+            pending: BTreeMap::new(),
+        }));
+        self
+    }
+
+    /// Number of records or flushes which failed to write.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors.load(Ordering::Relaxed)
+    }
+
+    fn count_error(&self, result: io::Result<()>) {
+        if result.is_err() {
+            self.write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn write(&self, records: &[ScanRecord]) {
+        let mut lines = String::new();
+        match &self.reorder {
+            Some(reorder) => {
+                // held while writing, so released lines are written in order
+                let mut reorder = reorder.lock().unwrap();
+                for record in records {
+                    reorder.push(record, &mut lines);
+                }
+                self.write_lines(&lines);
+            }
+            None => {
+                for record in records {
+                    push_line(&mut lines, record.to_json());
+                }
+                self.write_lines(&lines);
+            }
+        }
+    }
+
+    fn write_lines(&self, lines: &str) {
+        if !lines.is_empty() {
+            let result = self.writer.lock().unwrap().write_all(lines.as_bytes());
+            self.count_error(result);
+        }
+    }
+}
+
+fn push_line(lines: &mut String, line: String) {
+    lines.push_str(&line);
+    lines.push('\n');
+}
+
+/// Records of an ordered [`JsonlSink`] waiting for earlier sequence numbers
+///
+/// [`JsonlSink`]: struct.JsonlSink.html
+struct Reorder {
+    /// Maximum number of records held back
+    window: usize,
+    /// Sequence number of the next record to write
+    next: u64,
+    pending: BTreeMap<u64, String>,
+}
+
+impl Reorder {
+    /// Queues `record` and appends the lines which are ready to `lines`.
+    fn push(&mut self, record: &ScanRecord, lines: &mut String) {
+        let line = record.to_json();
+        match record.sequence {
+            Some(sequence) if sequence >= self.next => {
+                self.pending.insert(sequence, line);
+            }
+            // its gap was skipped already
+            Some(_) => push_line(lines, line),
+            None => {
+                self.drain(lines);
+                push_line(lines, line);
+                return;
+            }
+        }
+        while let Some(&first) = self.pending.keys().next() {
+            if first != self.next && self.pending.len() <= self.window {
+                break;
+            }
+            let line = self.pending.remove(&first).expect("key exists");
+            push_line(lines, line);
+            self.next = first + 1;
+        }
+    }
+
+    /// Appends all held lines to `lines`.
+    fn drain(&mut self, lines: &mut String) {
+        while let Some((sequence, line)) = self.pending.pop_first() {
+            push_line(lines, line);
+            self.next = sequence + 1;
+        }
+    }
+}
+
+impl ResultSink for JsonlSink {
+    fn emit(&self, record: &ScanRecord) {
+        self.write(std::slice::from_ref(record));
+    }
+
+    fn emit_batch(&self, records: &[ScanRecord]) {
+        self.write(records);
+    }
+
+    fn flush(&self) {
+        if let Some(reorder) = &self.reorder {
+            let mut reorder = reorder.lock().unwrap();
+            let mut lines = String::new();
+            reorder.drain(&mut lines);
+            reorder.next = 0;
+            self.write_lines(&lines);
+        }
+        let result = self.writer.lock().unwrap().flush();
+        self.count_error(result);
+    }
+}
+
+/// Options of a [`BatchingSink`]
+///
+/// [`BatchingSink`]: struct.BatchingSink.html
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// Maximum number of records per `emit_batch` call. Values below 1 are
+    /// treated as 1. Defaults to 64.
+    pub batch_size: usize,
+    /// Maximum time a record waits for its batch to fill up. Defaults to one
+    /// second.
+    pub max_delay: Duration,
+    /// Number of records buffered before the overflow policy applies. Values
+    /// below 1 are treated as 1. Defaults to 4096.
+    pub capacity: usize,
+    /// What happens to records when the buffer is full
+    pub overflow: Overflow,
+    /// Maximum time `flush` waits for buffered records to be delivered.
+    /// Defaults to 30 seconds.
+    pub flush_timeout: Duration,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            batch_size: 64,
+            max_delay: Duration::from_secs(1),
+            capacity: 4096,
+            overflow: Overflow::default(),
+            flush_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Default)]
+struct BatchState {
+    queue: VecDeque<ScanRecord>,
+    /// Time the oldest queued record was emitted
+    oldest: Option<Instant>,
+    /// True while a batch is being delivered
+    delivering: bool,
+    /// Number of callers waiting in `flush`
+    flushing: usize,
+    dropped: u64,
+    closed: bool,
+}
+
+struct BatchShared {
+    inner: Arc<dyn ResultSink>,
+    options: BatchOptions,
+    state: Mutex<BatchState>,
+    changed: Condvar,
+}
+
+impl BatchShared {
+    fn batch_size(&self) -> usize {
+        self.options.batch_size.max(1)
+    }
+
+    /// Delivers batches until the sink is dropped and the queue is empty.
+    fn deliver(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let due = state.queue.len() >= self.batch_size()
+                || state.closed
+                || state.flushing > 0
+                || matches!(state.oldest, Some(oldest) if oldest.elapsed() >= self.options.max_delay);
+            if !state.queue.is_empty() && due {
+                let len = state.queue.len().min(self.batch_size());
+                let batch: Vec<_> = state.queue.drain(..len).collect();
+                state.oldest = if state.queue.is_empty() { None } else { Some(Instant::now()) };
+                state.delivering = true;
+                self.changed.notify_all();
+                drop(state);
+
+                self.inner.emit_batch(&batch);
+
+                state = self.state.lock().unwrap();
+                state.delivering = false;
+                self.changed.notify_all();
+                continue;
+            }
+            if state.closed {
+                return;
+            }
+            state = match state.oldest {
+                Some(oldest) => {
+                    let wait = self.options.max_delay.saturating_sub(oldest.elapsed());
+                    self.changed.wait_timeout(state, wait).unwrap().0
+                }
+                None => self.changed.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+/// Sink buffering records and delivering them in batches from a background
+/// thread
+///
+/// `emit` only waits if the buffer is full and the overflow policy blocks,
+/// and never longer than the policy allows. A batch is delivered when it is
+/// full, when its oldest record has waited for `max_delay` and on `flush`.
+/// Records still buffered when the sink is dropped are delivered by the
+/// background thread, which exits afterwards.
+pub struct BatchingSink {
+    shared: Arc<BatchShared>,
+}
+
+impl BatchingSink {
+    /// Creates a sink delivering batches to `inner`.
+    pub fn new(inner: Arc<dyn ResultSink>, options: BatchOptions) -> Self {
+        let shared = Arc::new(BatchShared {
+            inner,
+            options,
+            state: Mutex::new(BatchState::default()),
+            changed: Condvar::new(),
+        });
+        let worker = Arc::clone(&shared);
+        thread::spawn(move || worker.deliver());
+        BatchingSink { shared }
+    }
+
+    /// Number of records which were dropped because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.state.lock().unwrap().dropped
+    }
+}
+
+impl ResultSink for BatchingSink {
+    fn emit(&self, record: &ScanRecord) {
+        let shared = &self.shared;
+        let capacity = shared.options.capacity.max(1);
+        let mut state = shared.state.lock().unwrap();
+        if state.queue.len() >= capacity {
+            if let Overflow::Block(timeout) = shared.options.overflow {
+                state = shared
+                    .changed
+                    .wait_timeout_while(state, timeout, |state| state.queue.len() >= capacity)
+                    .unwrap()
+                    .0;
+            }
+            if state.queue.len() >= capacity {
+                state.dropped += 1;
+                return;
+            }
+        }
+        state.queue.push_back(record.clone());
+        if state.oldest.is_none() {
+            state.oldest = Some(Instant::now());
+        }
+        shared.changed.notify_all();
+    }
+
+    /// Delivers the buffered records, waiting up to `flush_timeout`, and
+    /// flushes the wrapped sink.
+    fn flush(&self) {
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap();
+        state.flushing += 1;
+        shared.changed.notify_all();
+        let (mut state, timeout) = shared
+            .changed
+            .wait_timeout_while(state, shared.options.flush_timeout, |state| {
+                !state.queue.is_empty() || state.delivering
+            })
+            .unwrap();
+        state.flushing -= 1;
+        drop(state);
+        if !timeout.timed_out() {
+            shared.inner.flush();
+        }
+    }
+}
+
+impl Drop for BatchingSink {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ScanResult;
+    use clamav_sys::cl_error_t;
+    use std::sync::mpsc::{self, Receiver};
+
+    fn record(name: &str) -> ScanRecord {
+        let outcome = ScanOutcome {
+            result: ScanResult::Clean,
+            bytes_scanned: 4096,
+            suppression: None,
+            performance: None,
+            from_cache: None,
+        };
+        ScanRecord::new(Some(PathBuf::from(name)), Ok(outcome))
+    }
+
+    /// Sink recording the batches it receives, optionally blocking until
+    /// released
+    #[derive(Default)]
+    struct Recorder {
+        batches: Mutex<Vec<Vec<ScanRecord>>>,
+        flushes: AtomicU64,
+        gate: Mutex<Option<Receiver<()>>>,
+    }
+
+    impl ResultSink for Recorder {
+        fn emit(&self, record: &ScanRecord) {
+            self.emit_batch(std::slice::from_ref(record));
+        }
+
+        fn emit_batch(&self, records: &[ScanRecord]) {
+            if let Some(gate) = self.gate.lock().unwrap().as_ref() {
+                let _ = gate.recv();
+            }
+            self.batches.lock().unwrap().push(records.to_vec());
+        }
+
+        fn flush(&self) {
+            self.flushes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        assert_eq!(
+            record("dir/\"a\"\n\u{8}\u{1}").to_json(),
+            r#"{"path":"dir/\"a\"\n\b\u0001","result":"OK","found":false,"bytes_scanned":4096}"#
+        );
+        let failed = ScanRecord::new(None, Err(ClamError::new(cl_error_t::CL_EOPEN)));
+        assert_eq!(
+            failed.to_json(),
+            format!(
+                r#"{{"path":null,"error":"{}","code":{}}}"#,
+                ClamError::new(cl_error_t::CL_EOPEN).string_error(),
+                cl_error_t::CL_EOPEN as i32
+            )
+        );
+    }
+
+    #[test]
+    fn jsonl_sink_writes_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.jsonl");
+        let sink = JsonlSink::create(&path).unwrap();
+        sink.emit(&record("a"));
+        sink.emit_batch(&[record("b"), record("c")]);
+        sink.flush();
+        let written = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(lines, vec![record("a").to_json(), record("b").to_json(), record("c").to_json()]);
+        assert_eq!(sink.write_errors(), 0);
+    }
+
+    fn numbered(name: &str, sequence: u64) -> ScanRecord {
+        ScanRecord {
+            sequence: Some(sequence),
+            ..record(name)
+        }
+    }
+
+    #[test]
+    fn ordered_jsonl_sink_writes_in_walk_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.jsonl");
+        let sink = JsonlSink::create(&path).unwrap().ordered(2);
+        sink.emit(&numbered("c", 2));
+        sink.emit_batch(&[numbered("b", 1), numbered("a", 0)]);
+        // 3 vanished: once more than two records wait, the gap is skipped
+        sink.emit(&numbered("e", 5));
+        sink.emit(&numbered("d", 4));
+        sink.emit(&numbered("f", 6));
+        sink.emit(&numbered("g", 8));
+        // records without a sequence number write the held ones first
+        sink.emit(&record("unreadable"));
+        sink.flush();
+        // numbers restart after flush
+        sink.emit(&numbered("h", 1));
+        sink.emit(&numbered("i", 0));
+        sink.flush();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let order: Vec<_> = written
+            .lines()
+            .map(|line| line.split('"').nth(3).unwrap().to_string())
+            .collect();
+        assert_eq!(order, vec!["a", "b", "c", "d", "e", "f", "g", "unreadable", "i", "h"]);
+        assert_eq!(sink.write_errors(), 0);
+    }
+
+    #[test]
+    fn channel_sink_drops_when_full() {
+        let (sink, receiver) = ChannelSink::new(1, Overflow::Drop);
+        sink.emit(&record("a"));
+        sink.emit(&record("b"));
+        assert_eq!(sink.dropped(), 1);
+        assert_eq!(receiver.try_recv(), Ok(record("a")));
+
+        let (sink, receiver) = ChannelSink::new(1, Overflow::Block(Duration::from_millis(20)));
+        sink.emit(&record("a"));
+        let started = Instant::now();
+        sink.emit(&record("b"));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert_eq!(sink.dropped(), 1);
+        drop(receiver);
+        sink.emit(&record("c"));
+        assert_eq!(sink.dropped(), 2);
+    }
+
+    #[test]
+    fn channel_sink_blocks_until_a_record_is_received() {
+        let (sink, receiver) = ChannelSink::new(1, Overflow::Block(Duration::from_secs(60)));
+        sink.emit(&record("a"));
+        let started = Instant::now();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                assert_eq!(receiver.recv(), Ok(record("a")));
+            });
+            sink.emit(&record("b"));
+        });
+        assert!(started.elapsed() < Duration::from_secs(60));
+        assert_eq!(sink.dropped(), 0);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(60)), Ok(record("b")));
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(1)), Err(RecvTimeoutError::Timeout));
+        drop(sink);
+        assert_eq!(receiver.recv(), Err(RecvError));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn batching_sink_flushes_partial_batch() {
+        let recorder = Arc::new(Recorder::default());
+        let options = BatchOptions {
+            batch_size: 2,
+            max_delay: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let sink = BatchingSink::new(recorder.clone(), options);
+        for name in &["a", "b", "c"] {
+            sink.emit(&record(name));
+        }
+        sink.flush();
+        assert_eq!(
+            *recorder.batches.lock().unwrap(),
+            vec![vec![record("a"), record("b")], vec![record("c")]]
+        );
+        assert_eq!(recorder.flushes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn batching_sink_delivers_after_max_delay() {
+        let recorder = Arc::new(Recorder::default());
+        let options = BatchOptions {
+            max_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let sink = BatchingSink::new(recorder.clone(), options);
+        sink.emit(&record("a"));
+        let started = Instant::now();
+        while recorder.batches.lock().unwrap().is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(10), "batch was never delivered");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(*recorder.batches.lock().unwrap(), vec![vec![record("a")]]);
+    }
+
+    #[test]
+    fn batching_sink_bounds_stalled_consumer() {
+        let (release, gate) = mpsc::channel();
+        let recorder = Arc::new(Recorder {
+            gate: Mutex::new(Some(gate)),
+            ..Default::default()
+        });
+        let options = BatchOptions {
+            batch_size: 1,
+            capacity: 1,
+            overflow: Overflow::Block(Duration::from_millis(20)),
+            flush_timeout: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let sink = BatchingSink::new(recorder.clone(), options);
+        // the first record is stuck in delivery, the second fills the buffer
+        sink.emit(&record("a"));
+        let started = Instant::now();
+        while !sink.shared.state.lock().unwrap().delivering {
+            assert!(started.elapsed() < Duration::from_secs(10), "delivery never started");
+            thread::sleep(Duration::from_millis(1));
+        }
+        sink.emit(&record("b"));
+        sink.emit(&record("c"));
+        assert_eq!(sink.dropped(), 1);
+        sink.flush();
+        assert_eq!(recorder.flushes.load(Ordering::SeqCst), 0);
+
+        drop(release);
+        sink.flush();
+        assert_eq!(
+            *recorder.batches.lock().unwrap(),
+            vec![vec![record("a")], vec![record("b")]]
+        );
+        assert_eq!(recorder.flushes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn sinks_are_object_safe() {
+        let (sink, receiver) = ChannelSink::new(4, Overflow::Drop);
+        let sinks: Vec<Arc<dyn ResultSink>> = vec![Arc::new(sink), Arc::new(JsonlSink::new(io::sink()))];
+        for sink in &sinks {
+            sink.emit(&record("a"));
+            sink.flush();
+        }
+        assert_eq!(receiver.try_recv(), Ok(record("a")));
+    }
+}
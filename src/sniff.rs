@@ -0,0 +1,241 @@
+//! Cheap file type sniffing to skip parsers which cannot apply.
+//!
+//! libclamav probes every enabled parser on every file. For some inputs, most
+//! notably plain text such as logs, CSV or source code, most of these probes
+//! can never succeed. [`sniff`] checks the whole content of a file with a small
+//! built-in matcher and [`FileKind::narrow`] removes the parse flags which are
+//! irrelevant for the detected kind.
+//!
+//! Only content which is text from its first to its last byte is classified as
+//! [`FileKind::Text`]. Looking at a prefix would not do: a text prefix followed
+//! by an archive or executable would hide the payload from the parsers which
+//! find it.
+//!
+//! The matcher is deliberately conservative: text which could contain embedded
+//! binary content that libclamav extracts (mail, MIME, uuencode, base64, RTF,
+//! XML, HTML, PDF) is classified as [`FileKind::Other`], which keeps all parse
+//! flags.
+//!
+//! Sniffing can be turned off per scan with [`SniffMode::Off`].
+//!
+//! [`sniff`]: fn.sniff.html
+//! [`FileKind::narrow`]: enum.FileKind.html#method.narrow
+//! [`FileKind::Text`]: enum.FileKind.html#variant.Text
+//! [`FileKind::Other`]: enum.FileKind.html#variant.Other
+//! [`SniffMode::Off`]: enum.SniffMode.html#variant.Off
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str;
+
+use clamav_sys::{
+    CL_SCAN_PARSE_ARCHIVE,
+    CL_SCAN_PARSE_ELF,
+    CL_SCAN_PARSE_HWP3,
+    CL_SCAN_PARSE_OLE2,
+    CL_SCAN_PARSE_PDF,
+    CL_SCAN_PARSE_PE,
+    CL_SCAN_PARSE_SWF,
+};
+
+/// Number of bytes read from a file at a time while sniffing
+pub const SNIFF_LEN: usize = 4096;
+
+/// Markers of text formats from which libclamav extracts embedded content
+const EMBEDDING_MARKERS: &[&[u8]] = &[
+    b"%pdf",
+    b"{\\rtf",
+    b"<?xml",
+    b"<?mso",
+    b"<html",
+    b"<!doctype",
+    b"<script",
+    b"content-type:",
+    b"mime-version:",
+    b"received:",
+    b"from ",
+    b"from:",
+    b"begin ",
+    b"base64",
+    b"data:",
+];
+
+/// Length of the longest marker, less one: the bytes of a chunk kept to find
+/// markers crossing into the next chunk
+const MARKER_OVERLAP: usize = 12;
+
+/// Whether [`Engine::scan_file_auto`] narrows the parse flags
+///
+/// [`Engine::scan_file_auto`]: ../engine/struct.Engine.html#method.scan_file_auto
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SniffMode {
+    /// Sniff the file and narrow the parse flags for its kind
+    Narrow,
+    /// Scan with the settings unchanged, like [`Engine::scan_file`]
+    ///
+    /// [`Engine::scan_file`]: ../engine/struct.Engine.html#method.scan_file
+    Off,
+}
+
+/// Kind of a file, as far as relevant for choosing parsers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// Plain text without markers of formats which embed other content
+    Text,
+    /// Anything else; all parsers stay enabled
+    Other,
+}
+
+impl FileKind {
+    /// Removes the parse flags which cannot apply to files of this kind.
+    pub fn narrow(self, parse: u32) -> u32 {
+        match self {
+            FileKind::Text => {
+                parse
+                    & !(CL_SCAN_PARSE_ARCHIVE
+                        | CL_SCAN_PARSE_ELF
+                        | CL_SCAN_PARSE_HWP3
+                        | CL_SCAN_PARSE_OLE2
+                        | CL_SCAN_PARSE_PDF
+                        | CL_SCAN_PARSE_PE
+                        | CL_SCAN_PARSE_SWF)
+            }
+            FileKind::Other => parse,
+        }
+    }
+}
+
+/// Classifies the complete content of a file.
+pub fn sniff(data: &[u8]) -> FileKind {
+    sniff_reader(data).unwrap_or(FileKind::Other)
+}
+
+/// Reads the whole file at `path` and classifies it.
+pub fn sniff_file(path: &Path) -> io::Result<FileKind> {
+    sniff_reader(File::open(path)?)
+}
+
+/// Reads `reader` to its end and classifies what it read.
+///
+/// Reading stops early at the first byte which rules out plain text.
+pub fn sniff_reader<R: Read>(mut reader: R) -> io::Result<FileKind> {
+    let mut chunk = vec![0; SNIFF_LEN];
+    // the end of the previous chunk: an incomplete UTF-8 character, and the
+    // bytes which may start a marker
+    let mut partial_char: Vec<u8> = Vec::new();
+    let mut marker_tail: Vec<u8> = Vec::new();
+    let mut empty = true;
+    loop {
+        let len = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        empty = false;
+        let chunk = &chunk[..len];
+        if !chunk.iter().all(|&b| is_text_byte(b)) {
+            return Ok(FileKind::Other);
+        }
+
+        partial_char.extend_from_slice(chunk);
+        match str::from_utf8(&partial_char) {
+            Ok(_) => partial_char.clear(),
+            // a character continued in the next chunk
+            Err(err) if err.error_len().is_none() => {
+                partial_char.drain(..err.valid_up_to());
+            }
+            Err(_) => return Ok(FileKind::Other),
+        }
+
+        marker_tail.extend_from_slice(chunk);
+        if has_embedding_marker(&marker_tail) {
+            return Ok(FileKind::Other);
+        }
+        let keep = marker_tail.len().saturating_sub(MARKER_OVERLAP);
+        marker_tail.drain(..keep);
+    }
+    if empty || !partial_char.is_empty() {
+        return Ok(FileKind::Other);
+    }
+    Ok(FileKind::Text)
+}
+
+fn is_text_byte(b: u8) -> bool {
+    b >= 0x20 || b == b'\t' || b == b'\n' || b == b'\r' || b == 0x0c
+}
+
+fn has_embedding_marker(data: &[u8]) -> bool {
+    let lowercase = data.to_ascii_lowercase();
+    EMBEDDING_MARKERS
+        .iter()
+        .any(|marker| lowercase.windows(marker.len()).any(|window| window == *marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clamav_sys::{CL_SCAN_PARSE_HTML, CL_SCAN_PARSE_MAIL};
+
+    #[test]
+    fn sniff_plain_text() {
+        assert_eq!(sniff(b"2021-01-01 12:00:00 INFO service started\n"), FileKind::Text);
+        assert_eq!(sniff("h\u{e9}llo w\u{f6}rld\r\n".as_bytes()), FileKind::Text);
+    }
+
+    #[test]
+    fn sniff_binary_is_other() {
+        assert_eq!(sniff(b"MZ\x90\x00\x03\x00\x00\x00"), FileKind::Other);
+        assert_eq!(sniff(b"PK\x03\x04"), FileKind::Other);
+        assert_eq!(sniff(b"\xff\xfe\xfd"), FileKind::Other);
+        assert_eq!(sniff(b""), FileKind::Other);
+    }
+
+    #[test]
+    fn sniff_embedding_text_is_other() {
+        assert_eq!(sniff(b"%PDF-1.4\n"), FileKind::Other);
+        assert_eq!(sniff(b"junk before the header %PDF-1.7"), FileKind::Other);
+        assert_eq!(sniff(b"From: a@example.com\nSubject: hi\n"), FileKind::Other);
+        assert_eq!(sniff(b"{\\rtf1\\ansi"), FileKind::Other);
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>"), FileKind::Other);
+        assert_eq!(sniff(b"<!DOCTYPE html>"), FileKind::Other);
+        assert_eq!(sniff(b"begin 644 payload.zip\n"), FileKind::Other);
+    }
+
+    #[test]
+    fn sniff_utf8_across_chunks_is_text() {
+        let mut data = vec![b'a'; SNIFF_LEN - 1];
+        data.extend_from_slice("\u{e9}t\u{e9}".as_bytes());
+        assert_eq!(sniff(&data), FileKind::Text);
+        assert_eq!(sniff(&data[..SNIFF_LEN]), FileKind::Other, "truncated character");
+    }
+
+    #[test]
+    fn sniff_checks_past_the_first_chunk() {
+        let mut data = b"2021-01-01 12:00:00 INFO service started\n".repeat(SNIFF_LEN);
+        assert_eq!(sniff(&data), FileKind::Text);
+        data.extend_from_slice(b"PK\x03\x04\x14\x00\x00\x00");
+        assert_eq!(sniff(&data), FileKind::Other, "archive after a text prefix");
+    }
+
+    #[test]
+    fn sniff_finds_markers_across_chunks() {
+        let mut data = vec![b' '; SNIFF_LEN - 3];
+        data.extend_from_slice(b"%PDF-1.7\n");
+        assert_eq!(sniff(&data), FileKind::Other);
+    }
+
+    #[test]
+    fn marker_overlap_fits_longest_marker() {
+        let longest = EMBEDDING_MARKERS.iter().map(|marker| marker.len()).max().unwrap();
+        assert_eq!(MARKER_OVERLAP, longest - 1);
+    }
+
+    #[test]
+    fn narrow_text_keeps_text_parsers() {
+        let all = CL_SCAN_PARSE_ARCHIVE | CL_SCAN_PARSE_PE | CL_SCAN_PARSE_MAIL | CL_SCAN_PARSE_HTML;
+        assert_eq!(FileKind::Text.narrow(all), CL_SCAN_PARSE_MAIL | CL_SCAN_PARSE_HTML);
+        assert_eq!(FileKind::Other.narrow(all), all);
+    }
+}
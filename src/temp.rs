@@ -0,0 +1,378 @@
+//! Temporary files created by the crate.
+//!
+//! Some APIs need scratch files, e.g. `Engine::load_yara_rules`, because
+//! libclamav only loads databases from files. These files are created
+//! through the engine's [`TempStrategy`], so deployments can put them on a
+//! tmpfs, into an encrypted scratch directory or create them in any other
+//! way. The default, [`DefaultTempStrategy`], creates them in the system's
+//! temporary directory.
+//!
+//! The bytes held in temporary files at the same time can be limited with
+//! `Engine::set_temp_quota`. Writes which would exceed the quota fail with a
+//! [`TempQuotaError`] before a file is created.
+//!
+//! Temporary files libclamav creates itself while scanning, e.g. when
+//! unpacking archives, are not affected; their directory is set with
+//! `Engine::set_tmp_dir`.
+//!
+//! [`TempStrategy`]: trait.TempStrategy.html
+//! [`DefaultTempStrategy`]: struct.DefaultTempStrategy.html
+//! [`TempQuotaError`]: ../struct.TempQuotaError.html
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use clamav_sys::cl_error_t;
+
+use crate::error::{ClamError, TempQuotaError};
+
+/// A named temporary file, removed when dropped
+#[derive(Debug)]
+pub struct TempFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Wraps a file opened for reading and writing at `path`. The file at
+    /// `path` is removed when the `TempFile` is dropped.
+    pub fn new(file: File, path: PathBuf) -> Self {
+        TempFile { file, path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Creates the temporary files of an engine
+pub trait TempStrategy: Send + Sync {
+    /// Creates a new, empty file for reading and writing whose name ends
+    /// with `suffix`, e.g. `.yara`.
+    ///
+    /// libclamav picks database parsers by the file extension, so the
+    /// suffix must be kept. The file must not be shared with other users.
+    fn create(&self, suffix: &str) -> io::Result<TempFile>;
+}
+
+/// Creates uniquely named files with the `tempfile` crate
+#[derive(Debug, Clone, Default)]
+pub struct DefaultTempStrategy {
+    dir: Option<PathBuf>,
+}
+
+impl DefaultTempStrategy {
+    /// Creates files in the system's temporary directory.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Creates files in `dir`.
+    pub fn in_dir<P: Into<PathBuf>>(dir: P) -> Self {
+        DefaultTempStrategy { dir: Some(dir.into()) }
+    }
+}
+
+impl TempStrategy for DefaultTempStrategy {
+    fn create(&self, suffix: &str) -> io::Result<TempFile> {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("clamav-rs-").suffix(suffix);
+        let file = match &self.dir {
+            Some(dir) => builder.tempfile_in(dir)?,
+            None => builder.tempfile()?,
+        };
+        let (file, path) = file.keep().map_err(|err| err.error)?;
+        Ok(TempFile::new(file, path))
+    }
+}
+
+/// Temporary files of one engine: how they are created and how many bytes
+/// they may hold
+pub(crate) struct TempSpace {
+    strategy: Mutex<Arc<dyn TempStrategy>>,
+    quota: Mutex<Option<u64>>,
+    used: Arc<AtomicU64>,
+}
+
+impl TempSpace {
+    pub(crate) fn new() -> Self {
+        TempSpace {
+            strategy: Mutex::new(Arc::new(DefaultTempStrategy::new())),
+            quota: Mutex::new(None),
+            used: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub(crate) fn strategy(&self) -> Arc<dyn TempStrategy> {
+        self.strategy.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_strategy(&self, strategy: Arc<dyn TempStrategy>) {
+        *self.strategy.lock().unwrap() = strategy;
+    }
+
+    pub(crate) fn quota(&self) -> Option<u64> {
+        *self.quota.lock().unwrap()
+    }
+
+    pub(crate) fn set_quota(&self, quota: Option<u64>) {
+        *self.quota.lock().unwrap() = quota;
+    }
+
+    /// Returns the bytes held in temporary files which are still in use.
+    pub(crate) fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Reserves `bytes` of the quota until the returned reservation is dropped.
+    fn reserve(&self, bytes: u64) -> Result<Reservation, TempQuotaError> {
+        let mut reservation = Reservation {
+            used: self.used.clone(),
+            bytes: 0,
+        };
+        self.grow(&mut reservation, bytes)?;
+        Ok(reservation)
+    }
+
+    /// Adds `bytes` of the quota to `reservation`.
+    fn grow(&self, reservation: &mut Reservation, bytes: u64) -> Result<(), TempQuotaError> {
+        let quota = self.quota();
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| match quota {
+                Some(quota) if used.saturating_add(bytes) > quota => None,
+                _ => Some(used + bytes),
+            })
+            .map_err(|used| TempQuotaError {
+                requested: reservation.bytes + bytes,
+                used: used - reservation.bytes,
+                quota: quota.unwrap_or(u64::MAX),
+            })?;
+        reservation.bytes += bytes;
+        Ok(())
+    }
+
+    /// Writes `contents` to a new temporary file whose name ends with
+    /// `suffix`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`TempQuotaError`] if the file would exceed the quota, or
+    /// `CL_ETMPFILE` if it cannot be created or written.
+    ///
+    /// [`TempQuotaError`]: ../struct.TempQuotaError.html
+    pub(crate) fn write(&self, suffix: &str, contents: &[u8]) -> Result<SpillFile, ClamError> {
+        self.copy(suffix, contents, contents.len() as u64)
+    }
+
+    /// Copies the `len` bytes of `reader` to a new temporary file whose name
+    /// ends with `suffix`, without buffering them in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`TempQuotaError`] if the file would exceed the quota,
+    /// `CL_EREAD` if `reader` fails or ends early, or `CL_ETMPFILE` if the
+    /// file cannot be created or written.
+    ///
+    /// [`TempQuotaError`]: ../struct.TempQuotaError.html
+    pub(crate) fn copy<R: Read>(&self, suffix: &str, reader: R, len: u64) -> Result<SpillFile, ClamError> {
+        let reservation = self.reserve(len)?;
+        let file = self
+            .strategy()
+            .create(suffix)
+            .map_err(|_| ClamError::new(cl_error_t::CL_ETMPFILE))?;
+        let mut reader = reader.take(len);
+        let mut writer = file.file();
+        let mut buf = [0u8; 64 * 1024];
+        let mut copied = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return Err(ClamError::new(cl_error_t::CL_EREAD)),
+            };
+            writer
+                .write_all(&buf[..n])
+                .map_err(|_| ClamError::new(cl_error_t::CL_ETMPFILE))?;
+            copied += n as u64;
+        }
+        if copied != len {
+            return Err(ClamError::new(cl_error_t::CL_EREAD));
+        }
+        writer.flush().map_err(|_| ClamError::new(cl_error_t::CL_ETMPFILE))?;
+        Ok(SpillFile {
+            file,
+            _reservation: reservation,
+        })
+    }
+
+    /// Writes `head` followed by the rest of `reader` to a new temporary
+    /// file whose name ends with `suffix`, for streams of unknown length.
+    /// The quota is reserved as the file grows.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`TempQuotaError`] once the file would exceed the quota,
+    /// `CL_EREAD` if `reader` fails, or `CL_ETMPFILE` if the file cannot be
+    /// created or written.
+    ///
+    /// [`TempQuotaError`]: ../struct.TempQuotaError.html
+    pub(crate) fn copy_stream<R: Read>(&self, suffix: &str, head: &[u8], mut reader: R) -> Result<SpillFile, ClamError> {
+        let mut reservation = self.reserve(head.len() as u64)?;
+        let file = self
+            .strategy()
+            .create(suffix)
+            .map_err(|_| ClamError::new(cl_error_t::CL_ETMPFILE))?;
+        let mut writer = file.file();
+        writer.write_all(head).map_err(|_| ClamError::new(cl_error_t::CL_ETMPFILE))?;
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return Err(ClamError::new(cl_error_t::CL_EREAD)),
+            };
+            self.grow(&mut reservation, n as u64)?;
+            writer
+                .write_all(&buf[..n])
+                .map_err(|_| ClamError::new(cl_error_t::CL_ETMPFILE))?;
+        }
+        writer.flush().map_err(|_| ClamError::new(cl_error_t::CL_ETMPFILE))?;
+        Ok(SpillFile {
+            file,
+            _reservation: reservation,
+        })
+    }
+}
+
+impl fmt::Debug for TempSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TempSpace")
+            .field("quota", &self.quota())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+/// Bytes of the quota held by a temporary file
+struct Reservation {
+    used: Arc<AtomicU64>,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+/// Temporary file written by [`TempSpace::write`], removed and released from
+/// the quota when dropped
+pub(crate) struct SpillFile {
+    file: TempFile,
+    _reservation: Reservation,
+}
+
+impl SpillFile {
+    pub(crate) fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        self.file.file()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Strategy counting the files it creates
+    #[derive(Debug, Default)]
+    pub(crate) struct CountingStrategy {
+        pub(crate) created: AtomicUsize,
+        inner: DefaultTempStrategy,
+    }
+
+    impl TempStrategy for CountingStrategy {
+        fn create(&self, suffix: &str) -> io::Result<TempFile> {
+            self.created.fetch_add(1, Ordering::SeqCst);
+            self.inner.create(suffix)
+        }
+    }
+
+    #[test]
+    fn default_strategy_creates_and_removes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = DefaultTempStrategy::in_dir(dir.path()).create(".yara").unwrap();
+        let path = file.path().to_path_buf();
+        assert!(path.starts_with(dir.path()));
+        assert!(path.to_str().unwrap().ends_with(".yara"));
+        assert!(path.exists());
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn writes_are_held_against_the_quota() {
+        let space = TempSpace::new();
+        let strategy = Arc::new(CountingStrategy::default());
+        space.set_strategy(strategy.clone());
+        space.set_quota(Some(10));
+
+        let first = space.write(".txt", b"123456").unwrap();
+        assert_eq!(fs::read(first.path()).unwrap(), b"123456");
+        assert_eq!(space.used(), 6);
+
+        let err = space.write(".txt", b"12345").err().expect("quota should be exceeded");
+        assert_eq!(
+            err.temp_quota_error(),
+            Some(TempQuotaError {
+                requested: 5,
+                used: 6,
+                quota: 10
+            })
+        );
+        assert_eq!(err.code(), cl_error_t::CL_ETMPFILE as i32);
+        assert_eq!(strategy.created.load(Ordering::SeqCst), 1);
+
+        drop(first);
+        assert_eq!(space.used(), 0);
+        let second = space.write(".txt", b"1234567890").unwrap();
+        assert_eq!(space.used(), 10);
+        assert_eq!(strategy.created.load(Ordering::SeqCst), 2);
+        drop(second);
+
+        space.set_quota(None);
+        space.write(".txt", &[0u8; 64]).unwrap();
+        assert_eq!(space.used(), 0);
+    }
+
+    #[test]
+    fn copy_fails_on_short_reader() {
+        let space = TempSpace::new();
+        let copied = space.copy(".bin", &b"1234567890"[..], 4).unwrap();
+        assert_eq!(fs::read(copied.path()).unwrap(), b"1234");
+        drop(copied);
+
+        let err = space.copy(".bin", &b"123"[..], 4).err().expect("short reader should fail");
+        assert_eq!(err.code(), cl_error_t::CL_EREAD as i32);
+        assert_eq!(space.used(), 0);
+    }
+}
@@ -0,0 +1,184 @@
+//! Fixtures shared by the unit tests.
+
+use crate::engine::Engine;
+
+pub(crate) const EXAMPLE_DATABASE_PATH: &str = "test_data/database/example.cud";
+pub(crate) const GOOD_FILE_PATH: &str = "test_data/files/good_file";
+pub(crate) const NAUGHTY_FILE_PATH: &str = "test_data/files/naughty_file";
+
+/// Compiled engine with the example database loaded
+pub(crate) fn example_engine() -> Engine {
+    crate::initialize().expect("initialize should succeed");
+    let engine = Engine::new().unwrap();
+    engine
+        .load_databases(EXAMPLE_DATABASE_PATH)
+        .expect("failed to load db");
+    engine.compile().expect("failed to compile");
+    engine
+}
+
+/// A writable page followed by a guard page past the end of the file both
+/// are mapped from. The maps list the guard page as readable, but reads of
+/// it through `/proc/self/mem` fail with `EIO`. Unmapped on drop.
+#[cfg(target_os = "linux")]
+pub(crate) struct GuardedRegion {
+    region: *mut libc::c_void,
+    page: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl GuardedRegion {
+    pub(crate) fn new() -> Self {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let fd = unsafe { libc::memfd_create(b"guarded\0".as_ptr().cast(), libc::MFD_CLOEXEC) };
+        assert!(fd >= 0);
+        let file = unsafe { <std::fs::File as std::os::unix::io::FromRawFd>::from_raw_fd(fd) };
+        file.set_len(page as u64).unwrap();
+        let region = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                2 * page,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE,
+                fd,
+                0,
+            )
+        };
+        assert_ne!(region, libc::MAP_FAILED);
+        GuardedRegion { region, page }
+    }
+
+    /// Address of the readable page
+    pub(crate) fn start(&self) -> u64 {
+        self.region as u64
+    }
+
+    /// Address of the guard page
+    pub(crate) fn guard(&self) -> u64 {
+        self.start() + self.page as u64
+    }
+
+    /// The readable page
+    pub(crate) fn page_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.region.cast::<u8>(), self.page) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for GuardedRegion {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.region, 2 * self.page);
+        }
+    }
+}
+
+/// Writes bits least significant bit first, as deflate streams do.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are stored most significant bit first
+    fn put_code(&mut self, code: u32, count: u32) {
+        self.put(code.reverse_bits() >> (32 - count), count);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// Deflates `len` zero bytes with fixed Huffman codes: one literal zero,
+/// then matches of 258 bytes at distance 1.
+fn deflate_zeros(len: u64) -> Vec<u8> {
+    let literal_zero = |w: &mut BitWriter| w.put_code(0x30, 8);
+    let mut w = BitWriter { out: Vec::new(), bits: 0, count: 0 };
+    w.put(1, 1); // final block
+    w.put(1, 2); // fixed Huffman codes
+    literal_zero(&mut w);
+    let rest = len - 1;
+    for _ in 0..rest / 258 {
+        w.put_code(0xc5, 8); // length 258
+        w.put_code(0, 5); // distance 1
+    }
+    for _ in 0..rest % 258 {
+        literal_zero(&mut w);
+    }
+    w.put_code(0, 7); // end of block
+    w.finish()
+}
+
+fn crc32_zeros(len: u64) -> u32 {
+    let table: Vec<u32> = (0..256u32)
+        .map(|n| (0..8).fold(n, |c, _| if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 }))
+        .collect();
+    !(0..len).fold(!0u32, |crc, _| table[(crc & 0xff) as usize] ^ (crc >> 8))
+}
+
+/// A zip archive holding a single file of `len` zero bytes
+pub(crate) fn zip_bomb(len: u64) -> Vec<u8> {
+    const NAME: &[u8] = b"zeros.bin";
+    let data = deflate_zeros(len);
+    let crc = crc32_zeros(len);
+    let entry = |out: &mut Vec<u8>| {
+        out.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        out.extend_from_slice(&0u16.to_le_bytes()); // flags
+        out.extend_from_slice(&8u16.to_le_bytes()); // deflate
+        out.extend_from_slice(&0u16.to_le_bytes()); // time
+        out.extend_from_slice(&0x21u16.to_le_bytes()); // 1980-01-01
+        out.extend_from_slice(&crc.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(len as u32).to_le_bytes());
+        out.extend_from_slice(&(NAME.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // extra field
+    };
+
+    let mut zip = b"PK\x03\x04".to_vec();
+    entry(&mut zip);
+    zip.extend_from_slice(NAME);
+    zip.extend_from_slice(&data);
+
+    let directory = zip.len();
+    zip.extend_from_slice(b"PK\x01\x02");
+    zip.extend_from_slice(&20u16.to_le_bytes()); // version made by
+    entry(&mut zip);
+    zip.extend_from_slice(&[0; 14]); // comment, disk, attributes, local header offset 0
+    zip.extend_from_slice(NAME);
+    let directory_len = zip.len() - directory;
+
+    zip.extend_from_slice(b"PK\x05\x06");
+    zip.extend_from_slice(&[0; 4]); // disk numbers
+    zip.extend_from_slice(&1u16.to_le_bytes());
+    zip.extend_from_slice(&1u16.to_le_bytes());
+    zip.extend_from_slice(&(directory_len as u32).to_le_bytes());
+    zip.extend_from_slice(&(directory as u32).to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes()); // comment
+    zip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_of_known_data() {
+        assert_eq!(crc32_zeros(0), 0);
+        assert_eq!(crc32_zeros(4), 0x2144_df1c);
+    }
+}
@@ -0,0 +1,182 @@
+//! Scan instrumentation with `tracing`, compiled away without the `tracing` feature.
+//!
+//! Every scan runs inside a `scan` span with the fields
+//!
+//! * `entry`: the public function called, e.g. `scan_file`
+//! * `path`: the path or file name passed to it, if any
+//! * `size`: the size of the scanned data, when known up front
+//! * `settings`: the enabled scan options, see `ScanSettings`'s `Display`
+//! * `verdict`: `OK`, `<signature> FOUND` or the error code
+//! * `bytes_scanned`: the bytes libclamav reports as scanned, when available
+//!
+//! and the events `virus_found` and `limit_exceeded` are emitted within it.
+//! Directory scans emit `cancelled` when they stop early.
+//!
+//! Without a subscriber interested in the span, creating it costs one atomic
+//! load and no field is formatted.
+
+#[cfg(feature = "tracing")]
+use crate::engine::LimitKind;
+use crate::engine::{ScanOutcome, ScanResult};
+use crate::error::ClamError;
+use crate::scan_settings::ScanSettings;
+
+/// Span of a single scan
+pub(crate) struct ScanSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl ScanSpan {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn new(entry: &'static str, path: Option<&str>, size: Option<u64>, settings: &ScanSettings) -> Self {
+        let span = tracing::info_span!(
+            "scan",
+            entry,
+            path,
+            size,
+            settings = %settings,
+            verdict = tracing::field::Empty,
+            bytes_scanned = tracing::field::Empty,
+        );
+        ScanSpan { span }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    #[inline(always)]
+    pub(crate) fn new(_entry: &'static str, _path: Option<&str>, _size: Option<u64>, _settings: &ScanSettings) -> Self {
+        ScanSpan {}
+    }
+
+    /// Same as `new`, looking up the size of the file at `path` if the span is recorded
+    #[cfg(feature = "tracing")]
+    pub(crate) fn for_path(entry: &'static str, path: &str, settings: &ScanSettings) -> Self {
+        let scan = Self::new(entry, Some(path), None, settings);
+        if !scan.span.is_disabled() {
+            if let Ok(metadata) = std::fs::metadata(path) {
+                scan.span.record("size", metadata.len());
+            }
+        }
+        scan
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    #[inline(always)]
+    pub(crate) fn for_path(_entry: &'static str, _path: &str, _settings: &ScanSettings) -> Self {
+        ScanSpan {}
+    }
+
+    /// Runs the scan inside the span
+    #[cfg(feature = "tracing")]
+    #[inline(always)]
+    pub(crate) fn in_scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        self.span.in_scope(f)
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    #[inline(always)]
+    pub(crate) fn in_scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        f()
+    }
+
+    /// Records the verdict and bytes scanned of an outcome
+    #[inline(always)]
+    pub(crate) fn finish_outcome(&self, outcome: &Result<ScanOutcome, ClamError>) {
+        match outcome {
+            Ok(outcome) => self.finish(Ok(&outcome.result), Some(outcome.bytes_scanned)),
+            Err(err) => self.finish(Err(err), None),
+        }
+    }
+
+    /// Records the verdict and emits the events it implies
+    #[cfg(feature = "tracing")]
+    pub(crate) fn finish(&self, result: Result<&ScanResult, &ClamError>, bytes_scanned: Option<u64>) {
+        if self.span.is_disabled() {
+            return;
+        }
+        if let Some(bytes_scanned) = bytes_scanned {
+            self.span.record("bytes_scanned", bytes_scanned);
+        }
+        match result {
+            Ok(verdict) => {
+                self.span.record("verdict", tracing::field::display(verdict));
+                if let ScanResult::Virus(detection) = verdict {
+                    tracing::info!(parent: &self.span, signature = %detection, "virus_found");
+                }
+            }
+            Err(err) => {
+                self.span.record("verdict", err.code());
+                if LimitKind::from_error(err).is_some() {
+                    tracing::warn!(parent: &self.span, code = err.code(), "limit_exceeded");
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    #[inline(always)]
+    pub(crate) fn finish(&self, _result: Result<&ScanResult, &ClamError>, _bytes_scanned: Option<u64>) {}
+}
+
+/// Emits the `cancelled` event for a directory scan which stopped early
+#[cfg(feature = "tracing")]
+pub(crate) fn cancelled(dir: &std::path::Path, scanned: usize) {
+    tracing::info!(dir = %dir.display(), scanned, "cancelled");
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+pub(crate) fn cancelled(_dir: &std::path::Path, _scanned: usize) {}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::scan_settings::{ScanSettings, ScanSettingsBuilder};
+    use crate::test_support::{example_engine, NAUGHTY_FILE_PATH};
+    use tracing_test::traced_test;
+
+    #[test]
+    #[traced_test]
+    fn naughty_file_scan_span_fields() {
+        let engine = example_engine();
+        let settings = ScanSettingsBuilder::new().enable_pdf().build();
+        engine.scan_file(NAUGHTY_FILE_PATH, &settings).expect("scan should succeed");
+
+        assert!(logs_contain("entry=\"scan_file\""));
+        assert!(logs_contain(&format!("path=\"{}\"", NAUGHTY_FILE_PATH)));
+        let size = std::fs::metadata(NAUGHTY_FILE_PATH).unwrap().len();
+        assert!(logs_contain(&format!("size={}", size)));
+        assert!(logs_contain("settings=CL_SCAN_PARSE_PDF"));
+        assert!(logs_contain("verdict=naughty_file.UNOFFICIAL FOUND"));
+        assert!(logs_contain("virus_found signature=naughty_file.UNOFFICIAL"));
+    }
+
+    #[test]
+    #[traced_test]
+    fn memory_scan_span_fields() {
+        let engine = example_engine();
+        let contents = std::fs::read(NAUGHTY_FILE_PATH).unwrap();
+        engine
+            .scan_mem(&contents, Some("upload.bin"), &ScanSettings::default())
+            .expect("scan should succeed");
+
+        assert!(logs_contain("entry=\"scan_mem\""));
+        assert!(logs_contain("path=\"upload.bin\""));
+        assert!(logs_contain(&format!("size={}", contents.len())));
+        assert!(logs_contain("bytes_scanned="));
+        assert!(logs_contain("virus_found"));
+    }
+
+    #[test]
+    #[traced_test]
+    fn memory_scan_names_are_sanitized() {
+        let engine = example_engine();
+        let contents = std::fs::read(NAUGHTY_FILE_PATH).unwrap();
+        engine
+            .scan_mem(&contents, Some("tenant-1/upload\nverdict=OK"), &ScanSettings::default())
+            .expect("scan should succeed");
+
+        assert!(logs_contain("path=\"tenant-1/upload\u{fffd}verdict=OK\""));
+        assert!(logs_contain("virus_found signature=naughty_file.UNOFFICIAL"));
+        assert!(!logs_contain("upload\nverdict"));
+    }
+}
@@ -1,4 +1,5 @@
 use std::ffi::CStr;
+use std::fmt;
 use std::str;
 
 /// Returns the database version level that the engine supports
@@ -11,7 +12,7 @@ pub fn flevel() -> u32 {
 /// # Example
 ///
 /// ```
-/// use clamav::{version};
+/// use clamav_rs::{version};
 ///
 /// println!("Running version {} flevel {}", version::version(), version::flevel());
 /// ```
@@ -26,6 +27,65 @@ pub fn version() -> String {
     }
 }
 
+/// The numeric part of a clamav version, see [`parsed`]
+///
+/// Versions compare by their numbers, so features can be gated on a
+/// minimum version.
+///
+/// [`parsed`]: fn.parsed.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// Parses `major.minor.patch`, ignoring a suffix such as `-beta` after
+    /// the patch number.
+    pub fn parse(version: &str) -> Option<Version> {
+        let end = version
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(version.len());
+        let mut numbers = version[..end].split('.').map(|number| number.parse().ok());
+        let parsed = Version {
+            major: numbers.next()??,
+            minor: numbers.next()??,
+            patch: numbers.next()??,
+        };
+        match numbers.next() {
+            None => Some(parsed),
+            Some(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Gets the clamav engine version as numbers, or `None` if [`version`]
+/// returns a string which is not of the form `major.minor.patch`, e.g. for
+/// a development build.
+///
+/// # Example
+///
+/// ```
+/// use clamav_rs::version::{self, Version};
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let file_inspection = version::parsed().map_or(false, |version| {
+///     version >= Version { major: 0, minor: 104, patch: 0 }
+/// });
+/// ```
+///
+/// [`version`]: fn.version.html
+pub fn parsed() -> Option<Version> {
+    Version::parse(&version())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,4 +101,28 @@ mod tests {
         crate::initialize().expect("initialize should succeed");
         assert!(flevel() > 0, "expected an flevel");
     }
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!(Version::parse("0.103.8"), Some(Version { major: 0, minor: 103, patch: 8 }));
+        assert_eq!(Version::parse("1.0.1"), Some(Version { major: 1, minor: 0, patch: 1 }));
+        assert_eq!(Version::parse("0.104.0-beta"), Some(Version { major: 0, minor: 104, patch: 0 }));
+        assert!(Version::parse("1.0.1") > Version::parse("0.103.8"));
+        assert_eq!(Version::parse("1.0.1").unwrap().to_string(), "1.0.1");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_versions() {
+        for malformed in &["", "devel-20230101", "1.0", "1..1", "1.0.1.2", "-1.0.1"] {
+            assert_eq!(Version::parse(malformed), None, "{:?}", malformed);
+        }
+    }
+
+    #[test]
+    fn parsed_matches_version() {
+        crate::initialize().expect("initialize should succeed");
+        if let Some(parsed) = parsed() {
+            assert!(version().starts_with(&parsed.to_string()));
+        }
+    }
 }
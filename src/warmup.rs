@@ -0,0 +1,338 @@
+//! Scanner which accepts scans while its engine is still being built.
+//!
+//! Loading and compiling full databases takes several seconds. Instead of
+//! refusing requests until the engine is ready, a [`WarmupScanner`] builds the
+//! engine on a background thread and queues incoming scans until it is done.
+//! Once the engine is ready, queued scans are released and later scans pass
+//! straight through.
+//!
+//! [`WarmupScanner`]: struct.WarmupScanner.html
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use clamav_sys::{cl_error_t, cl_scan_options};
+
+use crate::engine::{Engine, ScanResult};
+use crate::error::ClamError;
+use crate::fmap::Fmap;
+use crate::scan_settings::ScanSettings;
+
+/// What to do with a scan arriving while the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Reject the new scan with `CL_EBUSY`
+    RejectNew,
+    /// Reject the oldest queued scan with `CL_EBUSY` and queue the new one
+    RejectOldest,
+}
+
+/// Queueing behaviour of a [`WarmupScanner`] before its engine is ready
+///
+/// [`WarmupScanner`]: struct.WarmupScanner.html
+#[derive(Debug, Clone)]
+pub struct WarmupOptions {
+    /// Maximum number of scans waiting for the engine. Defaults to 1024.
+    pub queue_capacity: usize,
+    /// Policy applied when a scan arrives and the queue is full. Defaults to `RejectNew`.
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for WarmupOptions {
+    fn default() -> Self {
+        WarmupOptions {
+            queue_capacity: 1024,
+            overflow: OverflowPolicy::RejectNew,
+        }
+    }
+}
+
+struct State {
+    engine: Option<Result<Arc<Engine>, ClamError>>,
+    // Scans holding a ticket in first_queued..next_ticket are waiting for the engine
+    first_queued: u64,
+    next_ticket: u64,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    ready: Condvar,
+    options: WarmupOptions,
+    settings: cl_scan_options,
+}
+
+/// Scanner that queues scans until its engine has been loaded and compiled
+///
+/// The scanner is cheap to clone; clones share the same engine and queue.
+///
+/// If building the engine fails, all queued and future scans fail with the
+/// error that caused the failure.
+///
+/// # Examples
+///
+/// ```
+/// use clamav_rs::warmup::WarmupScanner;
+///
+/// clamav_rs::initialize().expect("failed to initialize");
+/// let scanner = WarmupScanner::new("test_data/database/", Default::default());
+/// // Blocks until the engine is ready, then scans
+/// let result = scanner.scan_file("test_data/files/good_file").expect("scan failed");
+/// ```
+#[derive(Clone)]
+pub struct WarmupScanner {
+    shared: Arc<Shared>,
+}
+
+impl WarmupScanner {
+    /// Starts loading and compiling the databases in `db_path` and returns immediately.
+    pub fn new(db_path: &str, settings: ScanSettings) -> Self {
+        Self::with_options(db_path, settings, WarmupOptions::default())
+    }
+
+    /// Like [`new`], with custom queueing options.
+    ///
+    /// [`new`]: #method.new
+    pub fn with_options(db_path: &str, settings: ScanSettings, options: WarmupOptions) -> Self {
+        let db_path = db_path.to_string();
+        Self::from_fn(settings, options, move || {
+            let engine = Engine::new()?;
+            engine.load_databases(&db_path)?;
+            engine.compile()?;
+            Ok(engine)
+        })
+    }
+
+    /// Builds the engine with a custom function, called on a background thread.
+    ///
+    /// The function must return a compiled engine. If it panics, all scans
+    /// fail with `CL_ERROR`.
+    pub fn from_fn<F>(settings: ScanSettings, options: WarmupOptions, build: F) -> Self
+    where
+        F: FnOnce() -> Result<Engine, ClamError> + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                engine: None,
+                first_queued: 0,
+                next_ticket: 0,
+            }),
+            ready: Condvar::new(),
+            options,
+            settings: settings.settings,
+        });
+        let thread_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            // queued scans must be woken even if the build panics
+            let engine = panic::catch_unwind(AssertUnwindSafe(build))
+                .unwrap_or_else(|_| Err(ClamError::new(cl_error_t::CL_ERROR)))
+                .map(Arc::new);
+            let mut state = thread_shared.state.lock().unwrap();
+            state.engine = Some(engine);
+            thread_shared.ready.notify_all();
+        });
+        WarmupScanner { shared }
+    }
+
+    /// Returns true once the engine has been built, successfully or not.
+    pub fn is_ready(&self) -> bool {
+        self.shared.state.lock().unwrap().engine.is_some()
+    }
+
+    /// Returns the number of scans currently waiting for the engine.
+    pub fn queued(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        match state.engine {
+            Some(_) => 0,
+            None => (state.next_ticket - state.first_queued) as usize,
+        }
+    }
+
+    /// Scans a file, waiting for the engine if it is not ready yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CL_EBUSY` if the scan was rejected by the queue, the error
+    /// which prevented building the engine, or the error of the scan itself.
+    pub fn scan_file(&self, path: &str) -> Result<ScanResult, ClamError> {
+        let engine = self.engine()?;
+        let settings = self.settings();
+        engine.scan_file(path, &settings)
+    }
+
+    /// Scans a memory buffer, waiting for the engine if it is not ready yet.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`scan_file`], and `CL_EMAP` if the buffer cannot be mapped.
+    ///
+    /// [`scan_file`]: #method.scan_file
+    pub fn scan_mem(&self, data: &[u8], filename: Option<&str>) -> Result<ScanResult, ClamError> {
+        let engine = self.engine()?;
+        let settings = self.settings();
+        let map = Fmap::new_from_memory(data.as_ptr(), data.len() as u64)
+            .map_err(|_| ClamError::new(cl_error_t::CL_EMAP))?;
+        engine.scan_map(&map, filename, &settings)
+    }
+
+    /// Returns the engine, waiting for it if it is not ready yet.
+    pub fn engine(&self) -> Result<Arc<Engine>, ClamError> {
+        let shared = &*self.shared;
+        let mut state = shared.state.lock().unwrap();
+        if let Some(engine) = &state.engine {
+            return engine.clone();
+        }
+
+        let queued = state.next_ticket - state.first_queued;
+        if queued >= shared.options.queue_capacity as u64 {
+            if shared.options.overflow == OverflowPolicy::RejectOldest && queued > 0 {
+                state.first_queued += 1;
+                shared.ready.notify_all();
+            } else {
+                return Err(ClamError::new(cl_error_t::CL_EBUSY));
+            }
+        }
+
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        loop {
+            if let Some(engine) = &state.engine {
+                return engine.clone();
+            }
+            if ticket < state.first_queued {
+                return Err(ClamError::new(cl_error_t::CL_EBUSY));
+            }
+            state = shared.ready.wait(state).unwrap();
+        }
+    }
+
+    fn settings(&self) -> ScanSettings {
+        ScanSettings {
+            settings: self.shared.settings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{EXAMPLE_DATABASE_PATH, GOOD_FILE_PATH, NAUGHTY_FILE_PATH};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn gated_scanner(options: WarmupOptions) -> (WarmupScanner, mpsc::Sender<()>) {
+        crate::initialize().expect("initialize should succeed");
+        let (open_gate, gate) = mpsc::channel();
+        let scanner = WarmupScanner::from_fn(Default::default(), options, move || {
+            gate.recv().unwrap();
+            let engine = Engine::new().unwrap();
+            engine.load_databases(EXAMPLE_DATABASE_PATH)?;
+            engine.compile()?;
+            Ok(engine)
+        });
+        (scanner, open_gate)
+    }
+
+    fn wait_for_queued(scanner: &WarmupScanner, count: usize) {
+        while scanner.queued() < count {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn queued_scans_complete_after_ready() {
+        let (scanner, open_gate) = gated_scanner(Default::default());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let scanner = scanner.clone();
+                thread::spawn(move || scanner.scan_file(NAUGHTY_FILE_PATH))
+            })
+            .collect();
+        wait_for_queued(&scanner, 4);
+        assert!(!scanner.is_ready());
+
+        open_gate.send(()).unwrap();
+        for handle in handles {
+            match handle.join().unwrap() {
+                Ok(ScanResult::Virus(name)) => assert_eq!(name, "naughty_file.UNOFFICIAL"),
+                _ => panic!("should have matched as a virus"),
+            }
+        }
+        assert!(scanner.is_ready());
+        match scanner.scan_file(GOOD_FILE_PATH) {
+            Ok(ScanResult::Clean) => {}
+            _ => panic!("should have been clean"),
+        }
+    }
+
+    #[test]
+    fn full_queue_rejects_new_scans() {
+        let options = WarmupOptions {
+            queue_capacity: 1,
+            overflow: OverflowPolicy::RejectNew,
+        };
+        let (scanner, open_gate) = gated_scanner(options);
+        let queued_scanner = scanner.clone();
+        let queued = thread::spawn(move || queued_scanner.scan_file(GOOD_FILE_PATH));
+        wait_for_queued(&scanner, 1);
+
+        let rejected = scanner.scan_file(GOOD_FILE_PATH).expect_err("scan should be rejected");
+        assert_eq!(rejected.code(), cl_error_t::CL_EBUSY as i32);
+
+        open_gate.send(()).unwrap();
+        assert!(queued.join().unwrap().is_ok(), "queued scan should succeed");
+    }
+
+    #[test]
+    fn full_queue_rejects_oldest_scan() {
+        let options = WarmupOptions {
+            queue_capacity: 1,
+            overflow: OverflowPolicy::RejectOldest,
+        };
+        let (scanner, open_gate) = gated_scanner(options);
+        let oldest_scanner = scanner.clone();
+        let oldest = thread::spawn(move || oldest_scanner.scan_file(GOOD_FILE_PATH));
+        wait_for_queued(&scanner, 1);
+
+        let newest_scanner = scanner.clone();
+        let newest = thread::spawn(move || newest_scanner.scan_file(GOOD_FILE_PATH));
+        let rejected = oldest.join().unwrap().expect_err("oldest scan should be rejected");
+        assert_eq!(rejected.code(), cl_error_t::CL_EBUSY as i32);
+
+        open_gate.send(()).unwrap();
+        assert!(newest.join().unwrap().is_ok(), "newest scan should succeed");
+    }
+
+    #[test]
+    fn build_failure_fails_all_scans() {
+        crate::initialize().expect("initialize should succeed");
+        let expected = match Engine::new().unwrap().load_databases("/dev/null") {
+            Err(err) => err,
+            Ok(_) => panic!("loading /dev/null should fail"),
+        };
+
+        let scanner = WarmupScanner::new("/dev/null", Default::default());
+        let queued_scanner = scanner.clone();
+        let queued = thread::spawn(move || queued_scanner.scan_file(GOOD_FILE_PATH));
+        assert_eq!(queued.join().unwrap().err(), Some(expected.clone()));
+        assert_eq!(scanner.scan_mem(b"data", None).err(), Some(expected));
+    }
+
+    #[test]
+    fn build_panic_fails_all_scans() {
+        let (open_gate, gate) = mpsc::channel::<()>();
+        let scanner = WarmupScanner::from_fn(Default::default(), Default::default(), move || {
+            gate.recv().unwrap();
+            panic!("build failed");
+        });
+        let queued_scanner = scanner.clone();
+        let queued = thread::spawn(move || queued_scanner.scan_file(GOOD_FILE_PATH));
+        wait_for_queued(&scanner, 1);
+
+        open_gate.send(()).unwrap();
+        let err = queued.join().unwrap().expect_err("queued scan should fail");
+        assert_eq!(err.code(), cl_error_t::CL_ERROR as i32);
+        assert!(scanner.is_ready());
+        assert_eq!(scanner.scan_file(GOOD_FILE_PATH).err(), Some(err));
+    }
+}
@@ -0,0 +1,72 @@
+//! Runs the `clamscan_rs` example against the bundled test data.
+
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+const DATABASE_PATH: &str = "test_data/database/";
+
+fn clamscan(args: &[&str]) -> Output {
+    Command::new(env!("CARGO"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["run", "--quiet", "--example", "clamscan_rs", "--", "-d", DATABASE_PATH])
+        .args(args)
+        .output()
+        .expect("failed to run the example")
+}
+
+fn stdout_lines(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect()
+}
+
+#[test]
+fn recursive_scan_reports_infected_file() {
+    let output = clamscan(&["-r", "test_data/files"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let lines = stdout_lines(&output);
+    assert!(lines.contains(&"test_data/files/naughty_file: naughty_file.UNOFFICIAL FOUND".to_string()), "{:?}", lines);
+    assert!(lines.contains(&"test_data/files/good_file: OK".to_string()), "{:?}", lines);
+}
+
+#[test]
+fn include_glob_limits_scanned_files() {
+    let output = clamscan(&["-r", "--include=good_*", "test_data/files"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert_eq!(stdout_lines(&output), vec!["test_data/files/good_file: OK"]);
+
+    let output = clamscan(&["-r", "-i", "--include", "good_*", "test_data/files"]);
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert!(stdout_lines(&output).is_empty());
+}
+
+#[test]
+fn single_file_and_missing_file() {
+    let output = clamscan(&["test_data/files/naughty_file"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert_eq!(stdout_lines(&output), vec!["test_data/files/naughty_file: naughty_file.UNOFFICIAL FOUND"]);
+
+    let output = clamscan(&["test_data/files/does_not_exist"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let lines = stdout_lines(&output);
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].starts_with("test_data/files/does_not_exist: ") && lines[0].ends_with(" ERROR"), "{:?}", lines);
+}
+
+#[test]
+fn jsonl_output_has_one_line_per_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let jsonl = dir.path().join("results.jsonl");
+    let output = clamscan(&[
+        "-r",
+        "--exclude=*.zip",
+        "--exclude=*.pdf",
+        &format!("--jsonl={}", jsonl.display()),
+        "test_data/files",
+    ]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+
+    let written = fs::read_to_string(&jsonl).unwrap();
+    let lines: Vec<_> = written.lines().collect();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines.iter().any(|line| line.contains("naughty_file.UNOFFICIAL")), "{:?}", lines);
+}
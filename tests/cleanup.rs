@@ -0,0 +1,32 @@
+//! Checks that `cleanup` tears down crypto and `initialize` sets it up again.
+//!
+//! Runs in its own test binary, as the cleanup would break concurrently
+//! running tests loading signed databases.
+
+use clamav_rs::engine::ScanResult;
+use clamav_rs::scan_settings::ScanSettings;
+
+mod common;
+
+use common::NAUGHTY_FILE_PATH;
+
+fn scan_naughty_file() -> ScanResult {
+    common::example_engine()
+        .scan_file(NAUGHTY_FILE_PATH, &ScanSettings::default())
+        .expect("scan failed")
+}
+
+#[test]
+fn initialize_after_cleanup() {
+    clamav_rs::initialize().expect("initialize to succeed");
+    assert!(scan_naughty_file().found());
+
+    // SAFETY: this test binary runs no other engine meanwhile.
+    unsafe {
+        clamav_rs::cleanup();
+        clamav_rs::cleanup();
+    }
+
+    clamav_rs::initialize().expect("initialize after cleanup to succeed");
+    assert!(scan_naughty_file().found());
+}
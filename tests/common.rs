@@ -1,3 +1,40 @@
+use clamav_rs::engine::Engine;
+
+#[allow(dead_code)]
+pub const EXAMPLE_DATABASE_PATH: &str = "test_data/database/example.cud";
+#[allow(dead_code)]
+pub const GOOD_FILE_PATH: &str = "test_data/files/good_file";
+#[allow(dead_code)]
+pub const NAUGHTY_FILE_PATH: &str = "test_data/files/naughty_file";
+
 pub fn setup() {
     clamav_rs::initialize().expect("initialize to succeed");
 }
+
+/// Compiled engine with the example database loaded
+#[allow(dead_code)]
+pub fn example_engine() -> Engine {
+    setup();
+    let engine = Engine::new().expect("failed to create engine");
+    engine
+        .load_databases(EXAMPLE_DATABASE_PATH)
+        .expect("failed to load db");
+    engine.compile().expect("failed to compile");
+    engine
+}
+
+/// Skips a test needing the system databases, which are missing.
+///
+/// With the `system-db-tests` feature, e.g. on CI machines running freshclam,
+/// missing databases fail the test instead.
+#[allow(dead_code)]
+pub fn skip_without_system_databases(test: &str) {
+    if cfg!(feature = "system-db-tests") {
+        panic!("{}: no databases in {}", test, clamav_rs::db::default_directory());
+    }
+    eprintln!(
+        "skipping {}: no databases in {}, run freshclam to enable it",
+        test,
+        clamav_rs::db::default_directory()
+    );
+}
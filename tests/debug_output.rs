@@ -0,0 +1,37 @@
+//! Checks that `set_debug` routes libclamav's debug messages.
+//!
+//! Runs in its own test binary, as debug output is enabled for the whole
+//! process.
+
+use std::sync::{Arc, Mutex};
+
+mod common;
+
+fn load_example_database() {
+    common::example_engine();
+}
+
+#[test]
+fn debug_messages_can_be_turned_on_and_off() {
+    common::setup();
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&messages);
+    clamav_rs::set_message_callback(move |_severity, message| {
+        sink.lock().unwrap().push(message.to_string());
+    });
+    let debug_messages = || {
+        let mut messages = messages.lock().unwrap();
+        let count = messages.iter().filter(|message| message.starts_with("LibClamAV debug")).count();
+        messages.clear();
+        count
+    };
+
+    clamav_rs::set_debug(true);
+    load_example_database();
+    assert!(debug_messages() > 0, "no debug messages while loading");
+
+    clamav_rs::set_debug(false);
+    load_example_database();
+    assert_eq!(debug_messages(), 0);
+    clamav_rs::clear_message_callback();
+}
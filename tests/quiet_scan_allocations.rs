@@ -0,0 +1,82 @@
+//! Checks that `Engine::scan_file_quiet` does not allocate for clean files.
+//!
+//! Runs in its own test binary, as it installs a counting global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use clamav_rs::engine::ScanResult;
+use clamav_rs::scan_settings::ScanSettings;
+
+mod common;
+
+use common::{GOOD_FILE_PATH, NAUGHTY_FILE_PATH};
+
+struct CountingAllocator;
+
+thread_local! {
+    /// Allocations made by this thread while counting, or `None`
+    static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get().map(|count| count + 1)));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get().map(|count| count + 1)));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the result of `f` and the number of allocations it made.
+fn count_allocations<T, F: FnOnce() -> T>(f: F) -> (T, usize) {
+    ALLOCATIONS.with(|count| count.set(Some(0)));
+    let result = f();
+    let count = ALLOCATIONS.with(|count| count.replace(None)).unwrap_or_default();
+    (result, count)
+}
+
+#[test]
+fn clean_quiet_scan_does_not_allocate() {
+    let engine = common::example_engine();
+    let settings = ScanSettings::default();
+
+    // the first scan on a thread sets up the path buffer
+    assert!(!engine.scan_file_quiet(GOOD_FILE_PATH, &settings).expect("scan failed"));
+
+    let (infected, allocations) = count_allocations(|| engine.scan_file_quiet(GOOD_FILE_PATH, &settings));
+    assert!(!infected.expect("scan failed"));
+    assert_eq!(allocations, 0, "clean quiet scan allocated");
+
+    // the full result names the detection, the quiet one does not
+    let (infected, quiet_allocations) = count_allocations(|| engine.scan_file_quiet(NAUGHTY_FILE_PATH, &settings));
+    assert!(infected.expect("scan failed"));
+    let (result, full_allocations) = count_allocations(|| engine.scan_file(NAUGHTY_FILE_PATH, &settings));
+    assert!(matches!(result.expect("scan failed"), ScanResult::Virus(_)));
+    assert!(
+        quiet_allocations < full_allocations,
+        "quiet scan made {} allocations, full scan {}",
+        quiet_allocations,
+        full_allocations
+    );
+}
+
+#[test]
+fn quiet_scan_rejects_nul_in_path() {
+    let engine = common::example_engine();
+
+    let error = engine
+        .scan_file_quiet("test_data/files/good\0file", &ScanSettings::default())
+        .expect_err("path with NUL should be rejected");
+    assert_eq!(error.code(), clamav_sys::cl_error_t::CL_EARG as i32);
+}
@@ -1,7 +1,7 @@
 use std::io::Write;
 
 use clamav_rs::scan_settings::ScanSettingsBuilder;
-use clamav_rs::{db, engine};
+use clamav_rs::engine;
 use tempfile::NamedTempFile;
 
 mod common;
@@ -9,6 +9,15 @@ mod common;
 #[test]
 fn scan_using_system_databases() {
     common::setup();
+    let scanner = engine::Engine::new().expect("failed to create engine");
+    let loaded = scanner
+        .try_load_system_databases()
+        .expect("load failed");
+    if loaded.is_none() {
+        common::skip_without_system_databases("scan_using_system_databases");
+        return;
+    }
+    scanner.compile().expect("compile failed");
 
     let mut test_file: NamedTempFile = NamedTempFile::new().unwrap();
     // Per http://www.eicar.org/86-0-Intended-use.html
@@ -16,17 +25,10 @@ fn scan_using_system_databases() {
     write!(test_file, r"-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*").unwrap();
     test_file.flush().unwrap();
 
-    let mut scan_settings = ScanSettingsBuilder::new().build();
-
-    clamav_rs::initialize().expect("initialize failed");
-    let scanner = engine::Engine::new();
-    scanner
-        .load_databases(&db::default_directory())
-        .expect("load failed");
-    scanner.compile().expect("compile failed");
+    let scan_settings = ScanSettingsBuilder::new().build();
 
     let result = scanner
-        .scan_file(test_file.path().to_str().unwrap(), &mut scan_settings)
+        .scan_file(test_file.path().to_str().unwrap(), &scan_settings)
         .unwrap();
     match result {
         engine::ScanResult::Virus(name) => assert_eq!(name, "Eicar-Test-Signature"),
@@ -0,0 +1,168 @@
+//! Concurrent scanning stress tests.
+//!
+//! Runs many threads scanning with one shared engine through a mix of
+//! `scan_file`, `scan_descriptor` and `scan_map`, and checks every verdict
+//! against the expected one. These tests take a while and are only built with
+//! the `stress-tests` feature:
+//!
+//! ```text
+//! cargo test --release --features stress-tests --test stress
+//! ```
+//!
+//! `all_match_scans_do_not_leak` repeats all-match scans while watching the
+//! resident set size; run it under valgrind for a stricter check.
+//!
+//! The number of threads and iterations can be tuned with the
+//! `CLAMAV_STRESS_THREADS` and `CLAMAV_STRESS_ITERATIONS` environment variables.
+#![cfg(feature = "stress-tests")]
+
+use std::env;
+use std::fs::{self, File};
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use clamav_rs::engine::{Engine, ScanResult};
+use clamav_rs::fmap::Fmap;
+use clamav_rs::scan_settings::{ScanSettings, ScanSettingsBuilder};
+
+mod common;
+
+use common::{example_engine, GOOD_FILE_PATH, NAUGHTY_FILE_PATH};
+
+fn env_or(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn expected(path: &str) -> ScanResult {
+    if path == NAUGHTY_FILE_PATH {
+        ScanResult::Virus("naughty_file.UNOFFICIAL".into())
+    } else {
+        ScanResult::Clean
+    }
+}
+
+fn scan(engine: &Engine, kind: usize, path: &str, contents: &[u8], settings: &ScanSettings) -> ScanResult {
+    match kind % 3 {
+        0 => engine.scan_file(path, settings),
+        1 => {
+            let file = File::open(path).expect("failed to open fixture");
+            engine.scan_fileobj(&file, settings, Some(path))
+        }
+        _ => {
+            let map = Fmap::new_from_memory(contents.as_ptr(), contents.len() as u64)
+                .expect("failed to map fixture");
+            engine.scan_map(&map, Some(path), settings)
+        }
+    }
+    .expect("scan should succeed")
+}
+
+#[test]
+fn concurrent_mixed_scans_are_stable() {
+    let threads = env_or("CLAMAV_STRESS_THREADS", 4 * Engine::recommended_concurrency());
+    let iterations = env_or("CLAMAV_STRESS_ITERATIONS", 500);
+
+    let engine = Arc::new(example_engine());
+    let fixtures: Arc<Vec<(&str, Vec<u8>)>> = Arc::new(
+        [GOOD_FILE_PATH, NAUGHTY_FILE_PATH]
+            .iter()
+            .map(|&path| (path, fs::read(path).expect("failed to read fixture")))
+            .collect(),
+    );
+    // Start all threads at once to maximize contention
+    let barrier = Arc::new(Barrier::new(threads));
+
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let engine = Arc::clone(&engine);
+            let fixtures = Arc::clone(&fixtures);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let settings = ScanSettings::default();
+                barrier.wait();
+                for i in 0..iterations {
+                    let (path, contents) = &fixtures[(t + i) % fixtures.len()];
+                    let result = scan(&engine, t + i / 2, path, contents, &settings);
+                    assert_eq!(result, expected(path), "thread {} iteration {}", t, i);
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("scan thread panicked");
+    }
+}
+
+#[test]
+fn concurrent_scans_of_one_buffer_are_stable() {
+    let threads = env_or("CLAMAV_STRESS_THREADS", 4 * Engine::recommended_concurrency());
+    let iterations = env_or("CLAMAV_STRESS_ITERATIONS", 500);
+
+    let engine = Arc::new(example_engine());
+    let contents = Arc::new(fs::read(NAUGHTY_FILE_PATH).expect("failed to read fixture"));
+
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let engine = Arc::clone(&engine);
+            let contents = Arc::clone(&contents);
+            thread::spawn(move || {
+                let settings = ScanSettings::default();
+                for _ in 0..iterations {
+                    let result = scan(&engine, 2, NAUGHTY_FILE_PATH, &contents, &settings);
+                    assert_eq!(result, expected(NAUGHTY_FILE_PATH));
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("scan thread panicked");
+    }
+}
+
+/// Resident set size of this process in bytes
+#[cfg(target_os = "linux")]
+fn resident_bytes() -> usize {
+    let statm = fs::read_to_string("/proc/self/statm").expect("failed to read statm");
+    let pages: usize = statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|value| value.parse().ok())
+        .expect("malformed statm");
+    // statm counts pages, which are not 4 KiB everywhere, e.g. on arm64
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    assert!(page_size > 0, "failed to query the page size");
+    pages * page_size as usize
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn all_match_scans_do_not_leak() {
+    let iterations = env_or("CLAMAV_STRESS_ITERATIONS", 5000);
+
+    let engine = example_engine();
+    let settings = ScanSettingsBuilder::new().enable_all_matches().build();
+    let contents = fs::read(NAUGHTY_FILE_PATH).expect("failed to read fixture");
+    let run = |count: usize| {
+        for i in 0..count {
+            let result = scan(&engine, i, NAUGHTY_FILE_PATH, &contents, &settings);
+            assert_eq!(result, expected(NAUGHTY_FILE_PATH), "iteration {}", i);
+        }
+    };
+
+    // Let allocator pools and libclamav's caches settle before measuring
+    run(iterations / 10);
+    let before = resident_bytes();
+    run(iterations);
+    let growth = resident_bytes().saturating_sub(before);
+    assert!(
+        growth < 8 * 1024 * 1024,
+        "resident set grew by {} bytes over {} all-match scans",
+        growth,
+        iterations
+    );
+}